@@ -72,11 +72,13 @@ mod wire {
     #[cfg(feature = "proto-ipv4")]
     fn bench_emit_ipv4(b: &mut test::Bencher) {
         let repr = Ipv4Repr {
-            src_addr:    Ipv4Address([192, 168, 1, 1]),
-            dst_addr:    Ipv4Address([192, 168, 1, 2]),
-            protocol:    IpProtocol::Tcp,
-            payload_len: 100,
-            hop_limit:   64
+            src_addr:      Ipv4Address([192, 168, 1, 1]),
+            dst_addr:      Ipv4Address([192, 168, 1, 2]),
+            protocol:      IpProtocol::Tcp,
+            payload_len:   100,
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
         };
         let mut bytes = vec![0xa5; repr.buffer_len()];
 
@@ -90,13 +92,14 @@ mod wire {
     #[cfg(feature = "proto-ipv6")]
     fn bench_emit_ipv6(b: &mut test::Bencher) {
         let repr = Ipv6Repr {
-            src_addr:    Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0,
-                                      0, 0, 0, 0, 0, 0, 0, 1]),
-            dst_addr:    Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0,
-                                      0, 0, 0, 0, 0, 0, 0, 2]),
-            next_header: IpProtocol::Tcp,
-            payload_len: 100,
-            hop_limit:   64
+            src_addr:      Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0,
+                                        0, 0, 0, 0, 0, 0, 0, 1]),
+            dst_addr:      Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0,
+                                        0, 0, 0, 0, 0, 0, 0, 2]),
+            next_header:   IpProtocol::Tcp,
+            payload_len:   100,
+            hop_limit:     64,
+            traffic_class: 0
         };
         let mut bytes = vec![0xa5; repr.buffer_len()];
 
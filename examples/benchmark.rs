@@ -108,6 +108,7 @@ fn main() {
     let mut processed = 0;
     while !CLIENT_DONE.load(Ordering::SeqCst) {
         let timestamp = Instant::now();
+        iface.device_mut().release(timestamp);
         match iface.poll(&mut sockets, timestamp) {
             Ok(_) => {},
             Err(e) => {
@@ -152,7 +153,7 @@ fn main() {
             }
         }
 
-        match iface.poll_at(&sockets, timestamp) {
+        match utils::poll_at(&iface, &sockets, timestamp) {
             Some(poll_at) if timestamp < poll_at => {
                 phy_wait(fd, Some(poll_at - timestamp)).expect("wait error");
             },
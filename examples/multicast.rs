@@ -9,7 +9,6 @@ mod utils;
 
 use std::collections::BTreeMap;
 use std::os::unix::io::AsRawFd;
-use smoltcp::phy::wait as phy_wait;
 use smoltcp::wire::{EthernetAddress, IpVersion, IpProtocol, IpAddress, IpCidr, Ipv4Address,
                     Ipv4Packet, IgmpPacket, IgmpRepr};
 use smoltcp::iface::{NeighborCache, EthernetInterfaceBuilder};
@@ -108,6 +107,6 @@ fn main() {
             }
         }
 
-        phy_wait(fd, iface.poll_delay(&sockets, timestamp)).expect("wait error");
+        utils::wait(fd, &mut iface, &sockets, timestamp).expect("wait error");
     }
 }
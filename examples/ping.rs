@@ -122,6 +122,7 @@ fn main() {
 
     loop {
         let timestamp = Instant::now();
+        iface.device_mut().release(timestamp);
         match iface.poll(&mut sockets, timestamp) {
             Ok(_) => {},
             Err(e) => {
@@ -200,7 +201,7 @@ fn main() {
         }
 
         let timestamp = Instant::now();
-        match iface.poll_at(&sockets, timestamp) {
+        match utils::poll_at(&iface, &sockets, timestamp) {
             Some(poll_at) if timestamp < poll_at => {
                 let resume_at = cmp::min(poll_at, send_at);
                 phy_wait(fd, Some(resume_at - timestamp)).expect("wait error");
@@ -1,20 +1,32 @@
 extern crate smoltcp;
 
 use std::env;
+use std::process::exit;
 use std::os::unix::io::AsRawFd;
 use smoltcp::phy::wait as phy_wait;
-use smoltcp::phy::{Device, RxToken, RawSocket};
+use smoltcp::phy::{Device, RxToken, RawSocket, Filter, FilterOp};
 use smoltcp::wire::{PrettyPrinter, EthernetFrame};
 use smoltcp::time::Instant;
 
 fn main() {
     let ifname = env::args().nth(1).unwrap();
+    let expr = env::args().skip(2).collect::<Vec<_>>().join(" ");
+    let filter = match Filter::parse(&expr, vec![FilterOp::EMPTY; 64]) {
+        Ok(filter) => filter,
+        Err(err) => {
+            eprintln!("invalid filter expression: {}", err);
+            exit(1)
+        }
+    };
+
     let mut socket = RawSocket::new(ifname.as_ref()).unwrap();
     loop {
         phy_wait(socket.as_raw_fd(), None).unwrap();
         let (rx_token, _) = socket.receive().unwrap();
         rx_token.consume(Instant::now(), |buffer| {
-            println!("{}", PrettyPrinter::<EthernetFrame<&[u8]>>::new("", &buffer));
+            if filter.matches(buffer) {
+                println!("{}", PrettyPrinter::<EthernetFrame<&[u8]>>::new("", &buffer));
+            }
             Ok(())
         }).unwrap();
     }
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use std::cmp;
 use std::cell::RefCell;
 use std::str::{self, FromStr};
 use std::rc::Rc;
@@ -8,17 +9,23 @@ use std::fs::File;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::env;
 use std::process;
+use std::os::unix::io::{AsRawFd, RawFd};
 #[cfg(feature = "log")]
 use log::{LogLevel, LogLevelFilter, LogRecord};
 #[cfg(feature = "log")]
 use env_logger::LogBuilder;
 use getopts::{Options, Matches};
 
-use smoltcp::phy::{Device, EthernetTracer, FaultInjector};
+use smoltcp::Result;
+use smoltcp::phy::{self, Device, DeviceCapabilities, EthernetTracer, FaultInjector};
 #[cfg(feature = "phy-tap_interface")]
 use smoltcp::phy::TapInterface;
+#[cfg(feature = "phy-udp_tunnel")]
+use smoltcp::phy::UdpTunnel;
 use smoltcp::phy::{PcapWriter, PcapSink, PcapMode, PcapLinkType};
 use smoltcp::phy::RawSocket;
+use smoltcp::iface::EthernetInterface;
+use smoltcp::socket::SocketSet;
 use smoltcp::time::{Duration, Instant};
 
 #[cfg(feature = "log")]
@@ -79,13 +86,116 @@ pub fn parse_options(options: &Options, free: Vec<&str>) -> Matches {
 }
 
 pub fn add_tap_options(_opts: &mut Options, free: &mut Vec<&str>) {
+    #[cfg(feature = "phy-udp_tunnel")]
+    _opts.optopt("", "udp-tunnel", "Exchange frames with PEER over UDP instead of using \
+                                    a TAP interface, with INTERFACE as the local address", "PEER");
     free.push("INTERFACE");
 }
 
 #[cfg(feature = "phy-tap_interface")]
-pub fn parse_tap_options(matches: &mut Matches) -> TapInterface {
+pub fn parse_tap_options(matches: &mut Matches) -> HostDevice {
     let interface = matches.free.remove(0);
-    TapInterface::new(&interface).unwrap()
+    #[cfg(feature = "phy-udp_tunnel")]
+    {
+        if let Some(peer) = matches.opt_str("udp-tunnel") {
+            return HostDevice::Tunnel(UdpTunnel::new(&interface, &peer).unwrap())
+        }
+    }
+    HostDevice::Tap(TapInterface::new(&interface).unwrap())
+}
+
+/// Either a TAP interface or a UDP tunnel, as selected on the command line.
+#[cfg(feature = "phy-tap_interface")]
+pub enum HostDevice {
+    Tap(TapInterface),
+    #[cfg(feature = "phy-udp_tunnel")]
+    Tunnel(UdpTunnel)
+}
+
+#[cfg(feature = "phy-tap_interface")]
+impl AsRawFd for HostDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            HostDevice::Tap(ref device) => device.as_raw_fd(),
+            #[cfg(feature = "phy-udp_tunnel")]
+            HostDevice::Tunnel(ref device) => device.as_raw_fd()
+        }
+    }
+}
+
+// Without tunnels, the tunnel tokens are never constructed.
+#[cfg(feature = "phy-udp_tunnel")]
+type TunnelDevice = UdpTunnel;
+#[cfg(all(feature = "phy-tap_interface", not(feature = "phy-udp_tunnel")))]
+type TunnelDevice = TapInterface;
+
+#[cfg(feature = "phy-tap_interface")]
+impl<'a> Device<'a> for HostDevice {
+    type RxToken = HostToken<<TapInterface as Device<'a>>::RxToken,
+                             <TunnelDevice as Device<'a>>::RxToken>;
+    type TxToken = HostToken<<TapInterface as Device<'a>>::TxToken,
+                             <TunnelDevice as Device<'a>>::TxToken>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        match *self {
+            HostDevice::Tap(ref device) => device.capabilities(),
+            #[cfg(feature = "phy-udp_tunnel")]
+            HostDevice::Tunnel(ref device) => device.capabilities()
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        match *self {
+            HostDevice::Tap(ref mut device) =>
+                device.receive().map(|(rx, tx)| (HostToken::Tap(rx), HostToken::Tap(tx))),
+            #[cfg(feature = "phy-udp_tunnel")]
+            HostDevice::Tunnel(ref mut device) =>
+                device.receive().map(|(rx, tx)| (HostToken::Tunnel(rx), HostToken::Tunnel(tx)))
+        }
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        match *self {
+            HostDevice::Tap(ref mut device) => device.transmit().map(HostToken::Tap),
+            #[cfg(feature = "phy-udp_tunnel")]
+            HostDevice::Tunnel(ref mut device) => device.transmit().map(HostToken::Tunnel)
+        }
+    }
+
+    fn flush(&mut self) {
+        match *self {
+            HostDevice::Tap(ref mut device) => device.flush(),
+            #[cfg(feature = "phy-udp_tunnel")]
+            HostDevice::Tunnel(ref mut device) => device.flush()
+        }
+    }
+}
+
+pub enum HostToken<T, U> {
+    Tap(T),
+    Tunnel(U)
+}
+
+impl<T: phy::RxToken, U: phy::RxToken> phy::RxToken for HostToken<T, U> {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
+        where F: FnOnce(&[u8]) -> Result<R>
+    {
+        match self {
+            HostToken::Tap(token) => token.consume(timestamp, f),
+            HostToken::Tunnel(token) => token.consume(timestamp, f)
+        }
+    }
+}
+
+impl<T: phy::TxToken, U: phy::TxToken> phy::TxToken for HostToken<T, U> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        match self {
+            HostToken::Tap(token) => token.consume(timestamp, len, f),
+            HostToken::Tunnel(token) => token.consume(timestamp, len, f)
+        }
+    }
 }
 
 pub fn parse_raw_socket_options(matches: &mut Matches) -> RawSocket {
@@ -95,8 +205,20 @@ pub fn parse_raw_socket_options(matches: &mut Matches) -> RawSocket {
 
 pub fn add_middleware_options(opts: &mut Options, _free: &mut Vec<&str>) {
     opts.optopt("", "pcap", "Write a packet capture file", "FILE");
+    opts.optflag("", "pcapng", "Write the packet capture file in the pcapng format");
     opts.optopt("", "drop-chance", "Chance of dropping a packet (%)", "CHANCE");
     opts.optopt("", "corrupt-chance", "Chance of corrupting a packet (%)", "CHANCE");
+    opts.optopt("", "reorder-chance", "Chance of reordering a delayed packet (%)", "CHANCE");
+    opts.optopt("", "duplicate-chance", "Chance of duplicating a packet (%)", "CHANCE");
+    opts.optopt("", "burst-enter-chance", "Chance of entering a loss burst (% per packet)",
+                "CHANCE");
+    opts.optopt("", "burst-leave-chance", "Chance of leaving a loss burst (% per packet)",
+                "CHANCE");
+    opts.optopt("", "burst-drop-chance", "Chance of dropping a packet in a loss burst (%)",
+                "CHANCE");
+    opts.optopt("", "delay", "Delay transmitted packets by a fixed interval (ms)", "DELAY");
+    opts.optopt("", "delay-jitter", "Delay transmitted packets by a further random interval \
+                                     up to the given one (ms)", "JITTER");
     opts.optopt("", "size-limit", "Drop packets larger than given size (octets)", "SIZE");
     opts.optopt("", "tx-rate-limit", "Drop packets after transmit rate exceeds given limit \
                                       (packets per interval)", "RATE");
@@ -113,6 +235,20 @@ pub fn parse_middleware_options<D>(matches: &mut Matches, device: D, loopback: b
                                   .unwrap_or(0);
     let corrupt_chance   = matches.opt_str("corrupt-chance").map(|s| u8::from_str(&s).unwrap())
                                   .unwrap_or(0);
+    let reorder_chance   = matches.opt_str("reorder-chance").map(|s| u8::from_str(&s).unwrap())
+                                  .unwrap_or(0);
+    let duplicate_chance = matches.opt_str("duplicate-chance").map(|s| u8::from_str(&s).unwrap())
+                                  .unwrap_or(0);
+    let burst_enter_chance = matches.opt_str("burst-enter-chance")
+                                    .map(|s| u8::from_str(&s).unwrap()).unwrap_or(0);
+    let burst_leave_chance = matches.opt_str("burst-leave-chance")
+                                    .map(|s| u8::from_str(&s).unwrap()).unwrap_or(0);
+    let burst_drop_chance  = matches.opt_str("burst-drop-chance")
+                                    .map(|s| u8::from_str(&s).unwrap()).unwrap_or(0);
+    let delay            = matches.opt_str("delay").map(|s| u64::from_str(&s).unwrap())
+                                  .unwrap_or(0);
+    let delay_jitter     = matches.opt_str("delay-jitter").map(|s| u64::from_str(&s).unwrap())
+                                  .unwrap_or(0);
     let size_limit       = matches.opt_str("size-limit").map(|s| usize::from_str(&s).unwrap())
                                   .unwrap_or(0);
     let tx_rate_limit    = matches.opt_str("tx-rate-limit").map(|s| u64::from_str(&s).unwrap())
@@ -131,9 +267,14 @@ pub fn parse_middleware_options<D>(matches: &mut Matches, device: D, loopback: b
 
     let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
 
-    let device = PcapWriter::new(device, Rc::new(RefCell::new(pcap_writer)) as Rc<PcapSink>,
-                                 if loopback { PcapMode::TxOnly } else { PcapMode::Both },
-                                 PcapLinkType::Ethernet);
+    let pcap_sink = Rc::new(RefCell::new(pcap_writer)) as Rc<PcapSink>;
+    let pcap_mode = if loopback { PcapMode::TxOnly } else { PcapMode::Both };
+    let device =
+        if matches.opt_present("pcapng") {
+            PcapWriter::new_pcapng(device, pcap_sink, pcap_mode, PcapLinkType::Ethernet, 0, None)
+        } else {
+            PcapWriter::new(device, pcap_sink, pcap_mode, PcapLinkType::Ethernet)
+        };
     let device = EthernetTracer::new(device, |_timestamp, _printer| {
         #[cfg(feature = "log")]
         trace!("{}", _printer);
@@ -141,9 +282,42 @@ pub fn parse_middleware_options<D>(matches: &mut Matches, device: D, loopback: b
     let mut device = FaultInjector::new(device, seed);
     device.set_drop_chance(drop_chance);
     device.set_corrupt_chance(corrupt_chance);
+    device.set_reorder_chance(reorder_chance);
+    device.set_duplicate_chance(duplicate_chance);
+    device.set_burst_enter_chance(burst_enter_chance);
+    device.set_burst_leave_chance(burst_leave_chance);
+    device.set_burst_drop_chance(burst_drop_chance);
+    device.set_delay(Duration::from_millis(delay));
+    device.set_delay_jitter(Duration::from_millis(delay_jitter));
+    device.set_delay_queue(vec![None; 16]);
     device.set_max_packet_size(size_limit);
     device.set_max_tx_rate(tx_rate_limit);
     device.set_max_rx_rate(rx_rate_limit);
     device.set_bucket_interval(Duration::from_millis(shaping_interval));
     device
 }
+
+/// Return the instant at which the interface needs to be polled, accounting for
+/// the packets held back by the fault injector.
+pub fn poll_at<D>(iface: &EthernetInterface<FaultInjector<D>>, sockets: &SocketSet,
+                  timestamp: Instant) -> Option<Instant>
+    where D: for<'a> Device<'a>
+{
+    match (iface.poll_at(sockets, timestamp), iface.device().poll_at()) {
+        (Some(poll_at), Some(release_at)) => Some(cmp::min(poll_at, release_at)),
+        (poll_at, release_at) => poll_at.or(release_at)
+    }
+}
+
+/// Wait until the interface needs to be polled, a packet held back by the fault injector
+/// is due, or `fd` becomes readable; then release the held packets that are due.
+pub fn wait<D>(fd: RawFd, iface: &mut EthernetInterface<FaultInjector<D>>,
+               sockets: &SocketSet, timestamp: Instant) -> io::Result<()>
+    where D: for<'a> Device<'a>
+{
+    let delay = poll_at(iface, sockets, timestamp).map(|poll_at|
+        if poll_at > timestamp { poll_at - timestamp } else { Duration::from_millis(0) });
+    phy::wait(fd, delay)?;
+    iface.device_mut().release(Instant::now());
+    Ok(())
+}
@@ -0,0 +1,433 @@
+// Heads up! Before working on this file you should read, at least,
+// RFC 5227 and the parts of RFC 3927 that discuss address selection.
+
+use time::{Duration, Instant};
+use wire::{EthernetAddress, Ipv4Address, ArpRepr, ArpOperation};
+use rand::Rand;
+
+/// The state of an IPv4 address undergoing conflict detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The address is being probed, and is not used yet.
+    Probing,
+    /// No conflict was detected while probing, and the address is being announced.
+    Announcing,
+    /// The address is in use, and is defended against conflicting hosts.
+    Bound,
+    /// Another host uses the address, and it is not used anymore.
+    Conflict,
+}
+
+/// An IPv4 address conflict detector.
+///
+/// A conflict detector probes an IPv4 address before it is used, announces it,
+/// and defends it against other hosts afterwards, as described in RFC 5227.
+#[derive(Debug, Clone, Copy)]
+pub struct Detector {
+    state:       State,
+    /// The number of probes or announcements sent in the current state.
+    sent:        u8,
+    /// When to send the next probe or announcement; `None` if the first probe
+    /// is not scheduled yet.
+    next_at:     Option<Instant>,
+    /// When a conflicting host was last seen.
+    defended_at: Option<Instant>,
+}
+
+impl Default for Detector {
+    fn default() -> Detector {
+        Detector::new()
+    }
+}
+
+impl Detector {
+    /// Maximum delay before sending the first probe.
+    const PROBE_WAIT: Duration = Duration { millis: 1_000 };
+
+    /// Number of probes sent.
+    const PROBE_NUM: u8 = 3;
+
+    /// Minimum delay between probes.
+    const PROBE_MIN: Duration = Duration { millis: 1_000 };
+
+    /// Maximum delay between probes.
+    const PROBE_MAX: Duration = Duration { millis: 2_000 };
+
+    /// Delay before sending the first announcement.
+    const ANNOUNCE_WAIT: Duration = Duration { millis: 2_000 };
+
+    /// Number of announcements sent.
+    const ANNOUNCE_NUM: u8 = 2;
+
+    /// Delay between announcements.
+    const ANNOUNCE_INTERVAL: Duration = Duration { millis: 2_000 };
+
+    /// Minimum interval between defensive announcements.
+    const DEFEND_INTERVAL: Duration = Duration { millis: 10_000 };
+
+    /// Create a detector that starts probing the next time it is polled.
+    pub fn new() -> Detector {
+        Detector {
+            state:       State::Probing,
+            sent:        0,
+            next_at:     None,
+            defended_at: None,
+        }
+    }
+
+    /// Create a detector that starts probing at the given timestamp.
+    pub(crate) fn new_at(timestamp: Instant) -> Detector {
+        Detector { next_at: Some(timestamp), ..Detector::new() }
+    }
+
+    /// Return the state of the address.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Query whether the address may be used.
+    pub fn is_usable(&self) -> bool {
+        match self.state {
+            State::Announcing | State::Bound => true,
+            State::Probing | State::Conflict => false,
+        }
+    }
+
+    /// Return the timestamp at which `egress` should be called next, if any.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        match self.state {
+            State::Probing | State::Announcing =>
+                Some(self.next_at.unwrap_or(Instant::from_millis(0))),
+            State::Bound | State::Conflict =>
+                None,
+        }
+    }
+
+    /// Return the ARP probe or announcement to broadcast for `addr`, if one is due,
+    /// without advancing the state machine.
+    pub(crate) fn packet(&self, addr: Ipv4Address, hardware_addr: EthernetAddress,
+                         timestamp: Instant) -> Option<ArpRepr> {
+        match self.next_at {
+            Some(next_at) if next_at <= timestamp => (),
+            _ => return None
+        }
+
+        match self.state {
+            State::Probing if self.sent < Self::PROBE_NUM =>
+                Some(ArpRepr::EthernetIpv4 {
+                    operation:            ArpOperation::Request,
+                    source_hardware_addr: hardware_addr,
+                    source_protocol_addr: Ipv4Address::UNSPECIFIED,
+                    target_hardware_addr: EthernetAddress([0; 6]),
+                    target_protocol_addr: addr,
+                }),
+            State::Probing | State::Announcing =>
+                Some(Self::announcement(addr, hardware_addr)),
+            State::Bound | State::Conflict => None
+        }
+    }
+
+    /// Advance the state machine, and return an ARP probe or announcement to broadcast
+    /// for `addr`, if one is due.
+    pub(crate) fn egress(&mut self, addr: Ipv4Address, hardware_addr: EthernetAddress,
+                         timestamp: Instant, rand: &mut Rand) -> Option<ArpRepr> {
+        let next_at = match self.next_at {
+            Some(next_at) => next_at,
+            None => {
+                self.next_at = Some(timestamp + rand.rand_duration(Duration::from_millis(0),
+                                                                   Self::PROBE_WAIT));
+                return None
+            }
+        };
+        if timestamp < next_at {
+            return None
+        }
+
+        let repr = self.packet(addr, hardware_addr, timestamp);
+        match self.state {
+            State::Probing if self.sent < Self::PROBE_NUM => {
+                self.sent += 1;
+                let delay = if self.sent < Self::PROBE_NUM {
+                    rand.rand_duration(Self::PROBE_MIN, Self::PROBE_MAX)
+                } else {
+                    Self::ANNOUNCE_WAIT
+                };
+                self.next_at = Some(timestamp + delay);
+                net_trace!("probing {}", addr);
+            }
+            State::Probing | State::Announcing => {
+                if self.state == State::Probing {
+                    net_debug!("no conflict for {}, announcing", addr);
+                    self.state = State::Announcing;
+                    self.sent = 0;
+                }
+                self.sent += 1;
+                if self.sent < Self::ANNOUNCE_NUM {
+                    self.next_at = Some(timestamp + Self::ANNOUNCE_INTERVAL);
+                } else {
+                    self.state = State::Bound;
+                }
+            }
+            State::Bound | State::Conflict => ()
+        }
+        repr
+    }
+
+    /// Check an incoming ARP packet for a conflict with `addr`, and defend the address
+    /// if necessary.
+    ///
+    /// Returns `true` if the address was lost to another host.
+    pub(crate) fn process(&mut self, addr: Ipv4Address, hardware_addr: EthernetAddress,
+                          arp_repr: &ArpRepr, timestamp: Instant) -> bool {
+        let (source_hardware_addr, source_protocol_addr, target_protocol_addr) =
+            match *arp_repr {
+                ArpRepr::EthernetIpv4 {
+                    source_hardware_addr, source_protocol_addr, target_protocol_addr, ..
+                } => (source_hardware_addr, source_protocol_addr, target_protocol_addr),
+                _ => return false
+            };
+        if source_hardware_addr == hardware_addr {
+            return false
+        }
+
+        match self.state {
+            State::Probing => {
+                // Another host either uses the address, or is probing for it as well.
+                if source_protocol_addr == addr ||
+                        (source_protocol_addr == Ipv4Address::UNSPECIFIED &&
+                         target_protocol_addr == addr) {
+                    net_debug!("address {} is used by {} while probing",
+                               addr, source_hardware_addr);
+                    self.state = State::Conflict;
+                    return true
+                }
+            }
+            State::Announcing | State::Bound => {
+                if source_protocol_addr == addr {
+                    match self.defended_at {
+                        Some(defended_at) if timestamp < defended_at + Self::DEFEND_INTERVAL => {
+                            net_debug!("address {} is used by {}, giving up",
+                                       addr, source_hardware_addr);
+                            self.state = State::Conflict;
+                            return true
+                        }
+                        _ => {
+                            net_debug!("address {} is used by {}, defending",
+                                       addr, source_hardware_addr);
+                            self.defended_at = Some(timestamp);
+                            self.state = State::Announcing;
+                            self.sent = Self::ANNOUNCE_NUM - 1;
+                            self.next_at = Some(timestamp);
+                        }
+                    }
+                }
+            }
+            State::Conflict => ()
+        }
+        false
+    }
+
+    /// Return an ARP announcement of `addr`.
+    pub(crate) fn announcement(addr: Ipv4Address, hardware_addr: EthernetAddress) -> ArpRepr {
+        ArpRepr::EthernetIpv4 {
+            operation:            ArpOperation::Request,
+            source_hardware_addr: hardware_addr,
+            source_protocol_addr: addr,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: addr,
+        }
+    }
+}
+
+/// IPv4 link-local address autoconfiguration, as described in RFC 3927.
+#[derive(Debug)]
+pub(crate) struct LinkLocal {
+    addr:      Ipv4Address,
+    detector:  Detector,
+    conflicts: u8,
+}
+
+impl LinkLocal {
+    /// Number of conflicts after which address selection is rate limited.
+    const MAX_CONFLICTS: u8 = 10;
+
+    /// Delay between address selections after too many conflicts.
+    const RATE_LIMIT_INTERVAL: Duration = Duration { millis: 60_000 };
+
+    pub(crate) fn new(rand: &mut Rand) -> LinkLocal {
+        LinkLocal {
+            addr:      Self::select_addr(rand),
+            detector:  Detector::new(),
+            conflicts: 0,
+        }
+    }
+
+    /// Pick an address from 169.254.1.0 to 169.254.254.255, inclusive.
+    fn select_addr(rand: &mut Rand) -> Ipv4Address {
+        let offset = rand.rand_u32() % (254 * 256);
+        Ipv4Address::new(169, 254, (1 + offset / 256) as u8, (offset % 256) as u8)
+    }
+
+    /// Return the address currently being probed or used.
+    pub(crate) fn candidate_addr(&self) -> Ipv4Address {
+        self.addr
+    }
+
+    /// Return the address, if it may be used.
+    pub(crate) fn addr(&self) -> Option<Ipv4Address> {
+        if self.detector.is_usable() {
+            Some(self.addr)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn state(&self) -> State {
+        self.detector.state()
+    }
+
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        self.detector.poll_at()
+    }
+
+    pub(crate) fn packet(&self, hardware_addr: EthernetAddress,
+                         timestamp: Instant) -> Option<ArpRepr> {
+        self.detector.packet(self.addr, hardware_addr, timestamp)
+    }
+
+    pub(crate) fn egress(&mut self, hardware_addr: EthernetAddress, timestamp: Instant,
+                         rand: &mut Rand) -> Option<ArpRepr> {
+        self.detector.egress(self.addr, hardware_addr, timestamp, rand)
+    }
+
+    pub(crate) fn process(&mut self, hardware_addr: EthernetAddress, arp_repr: &ArpRepr,
+                          timestamp: Instant, rand: &mut Rand) {
+        if !self.detector.process(self.addr, hardware_addr, arp_repr, timestamp) {
+            return
+        }
+
+        self.conflicts = self.conflicts.saturating_add(1);
+        self.addr = Self::select_addr(rand);
+        self.detector = if self.conflicts < Self::MAX_CONFLICTS {
+            Detector::new()
+        } else {
+            Detector::new_at(timestamp + Self::RATE_LIMIT_INTERVAL)
+        };
+        net_debug!("selected link-local address {}", self.addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HADDR_A: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 1]);
+    const HADDR_B: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 2]);
+    const ADDR: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+
+    fn arp(source_hardware_addr: EthernetAddress, source_protocol_addr: Ipv4Address,
+           target_protocol_addr: Ipv4Address) -> ArpRepr {
+        ArpRepr::EthernetIpv4 {
+            operation:            ArpOperation::Request,
+            source_hardware_addr, source_protocol_addr,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr
+        }
+    }
+
+    fn run(detector: &mut Detector, rand: &mut Rand, until: Instant) -> (usize, usize) {
+        let (mut probes, mut announcements) = (0, 0);
+        let mut timestamp = Instant::from_millis(0);
+        while timestamp < until {
+            match detector.egress(ADDR, HADDR_A, timestamp, rand) {
+                Some(ArpRepr::EthernetIpv4 { source_protocol_addr, .. })
+                        if source_protocol_addr == Ipv4Address::UNSPECIFIED =>
+                    probes += 1,
+                Some(_) => announcements += 1,
+                None => ()
+            }
+            timestamp += Duration::from_millis(100);
+        }
+        (probes, announcements)
+    }
+
+    #[test]
+    fn test_no_conflict() {
+        let mut rand = Rand::new(1);
+        let mut detector = Detector::new();
+        assert_eq!(detector.poll_at(), Some(Instant::from_millis(0)));
+        assert!(!detector.is_usable());
+
+        assert_eq!(run(&mut detector, &mut rand, Instant::from_millis(20_000)), (3, 2));
+        assert_eq!(detector.state(), State::Bound);
+        assert_eq!(detector.poll_at(), None);
+        assert!(detector.is_usable());
+    }
+
+    #[test]
+    fn test_probe_conflict() {
+        let mut rand = Rand::new(1);
+        let mut detector = Detector::new();
+        run(&mut detector, &mut rand, Instant::from_millis(1_500));
+        assert_eq!(detector.state(), State::Probing);
+
+        // Our own probes do not conflict.
+        assert!(!detector.process(ADDR, HADDR_A, &arp(HADDR_A, Ipv4Address::UNSPECIFIED, ADDR),
+                                  Instant::from_millis(1_500)));
+        // Another host probing for the same address does.
+        assert!(detector.process(ADDR, HADDR_A, &arp(HADDR_B, Ipv4Address::UNSPECIFIED, ADDR),
+                                 Instant::from_millis(1_500)));
+        assert_eq!(detector.state(), State::Conflict);
+        assert_eq!(detector.poll_at(), None);
+    }
+
+    #[test]
+    fn test_defend() {
+        let mut rand = Rand::new(1);
+        let mut detector = Detector::new();
+        run(&mut detector, &mut rand, Instant::from_millis(20_000));
+        assert_eq!(detector.state(), State::Bound);
+
+        let conflict = arp(HADDR_B, ADDR, Ipv4Address([192, 168, 1, 2]));
+        assert!(!detector.process(ADDR, HADDR_A, &conflict, Instant::from_millis(20_000)));
+        assert_eq!(detector.poll_at(), Some(Instant::from_millis(20_000)));
+        assert_eq!(detector.egress(ADDR, HADDR_A, Instant::from_millis(20_000), &mut rand),
+                   Some(Detector::announcement(ADDR, HADDR_A)));
+        assert_eq!(detector.state(), State::Bound);
+
+        assert!(!detector.process(ADDR, HADDR_A, &conflict, Instant::from_millis(40_000)));
+        assert!(detector.process(ADDR, HADDR_A, &conflict, Instant::from_millis(45_000)));
+        assert_eq!(detector.state(), State::Conflict);
+    }
+
+    #[test]
+    fn test_link_local_select() {
+        let mut rand = Rand::new(1);
+        for _ in 0..1000 {
+            let addr = LinkLocal::select_addr(&mut rand);
+            assert!(addr.is_link_local());
+            assert!(addr.0[2] >= 1 && addr.0[2] <= 254);
+        }
+    }
+
+    #[test]
+    fn test_link_local_conflict() {
+        let mut rand = Rand::new(1);
+        let mut link_local = LinkLocal::new(&mut rand);
+        let first_addr = link_local.candidate_addr();
+        assert_eq!(link_local.addr(), None);
+
+        let conflict = arp(HADDR_B, first_addr, first_addr);
+        link_local.process(HADDR_A, &conflict, Instant::from_millis(0), &mut rand);
+        assert!(link_local.candidate_addr() != first_addr);
+        assert_eq!(link_local.state(), State::Probing);
+        assert_eq!(link_local.poll_at(), Some(Instant::from_millis(0)));
+
+        for _ in 1..LinkLocal::MAX_CONFLICTS {
+            let conflict = arp(HADDR_B, link_local.candidate_addr(), link_local.candidate_addr());
+            link_local.process(HADDR_A, &conflict, Instant::from_millis(0), &mut rand);
+        }
+        assert_eq!(link_local.poll_at(), Some(Instant::from_millis(0) +
+                                              LinkLocal::RATE_LIMIT_INTERVAL));
+    }
+}
@@ -3,12 +3,14 @@
 // and RFCs 8200 and 4861 for any IPv6 and NDISC work.
 
 use core::cmp;
-use managed::{ManagedSlice, ManagedMap};
+use managed::ManagedSlice;
+#[cfg(feature = "proto-ipv4")]
+use managed::ManagedMap;
 #[cfg(not(feature = "proto-igmp"))]
 use core::marker::PhantomData;
 
 use {Error, Result};
-use phy::{Device, DeviceCapabilities, RxToken, TxToken};
+use phy::{Device, DeviceCapabilities, ChecksumCapabilities, RxToken, TxToken, TxOffload};
 use time::{Duration, Instant};
 use wire::pretty_print::PrettyPrinter;
 use wire::{EthernetAddress, EthernetProtocol, EthernetFrame};
@@ -16,7 +18,7 @@ use wire::{IpAddress, IpProtocol, IpRepr, IpCidr};
 #[cfg(feature = "proto-ipv6")]
 use wire::{Ipv6Address, Ipv6Packet, Ipv6Repr, IPV6_MIN_MTU};
 #[cfg(feature = "proto-ipv4")]
-use wire::{Ipv4Address, Ipv4Packet, Ipv4Repr, IPV4_MIN_MTU};
+use wire::{Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr, IPV4_MIN_MTU};
 #[cfg(feature = "proto-ipv4")]
 use wire::{ArpPacket, ArpRepr, ArpOperation};
 #[cfg(feature = "proto-ipv4")]
@@ -36,7 +38,7 @@ use wire::{NdiscNeighborFlags, NdiscRepr};
 #[cfg(all(feature = "proto-ipv6", feature = "socket-udp"))]
 use wire::Icmpv6DstUnreachable;
 #[cfg(feature = "socket-udp")]
-use wire::{UdpPacket, UdpRepr};
+use wire::{IpEndpoint, UdpPacket, UdpRepr};
 #[cfg(feature = "socket-tcp")]
 use wire::{TcpPacket, TcpRepr, TcpControl};
 
@@ -51,6 +53,17 @@ use socket::UdpSocket;
 use socket::TcpSocket;
 use super::{NeighborCache, NeighborAnswer};
 use super::Routes;
+use super::route::Flow;
+use super::select::{self, Candidate};
+#[cfg(feature = "proto-ipv4")]
+use super::{ConflictDetector, ConflictState};
+#[cfg(feature = "proto-ipv4")]
+use super::conflict::LinkLocal;
+#[cfg(feature = "proto-igmp")]
+use super::igmp::{self, Group as IgmpGroup, Membership as IgmpMembership,
+                  FilterMode as IgmpFilterMode};
+#[cfg(feature = "proto-ipv4")]
+use rand::Rand;
 
 /// An Ethernet network interface.
 ///
@@ -73,19 +86,58 @@ struct InterfaceInner<'b, 'c, 'e> {
     neighbor_cache:         NeighborCache<'b>,
     ethernet_addr:          EthernetAddress,
     ip_addrs:               ManagedSlice<'c, IpCidr>,
-    #[cfg(feature = "proto-ipv4")]
+    proxy_prefixes:         ManagedSlice<'c, IpCidr>,
+    deprecated_ip_addrs:    ManagedSlice<'c, IpAddress>,
     any_ip:                 bool,
     routes:                 Routes<'e>,
+    accept_redirects:       bool,
+    #[cfg(feature = "socket-udp")]
+    udp_unicast_policy:     UdpUnicastPolicy,
     #[cfg(feature = "proto-igmp")]
-    ipv4_multicast_groups:  ManagedMap<'e, Ipv4Address, ()>,
+    ipv4_multicast_groups:  ManagedMap<'e, Ipv4Address, IgmpGroup>,
     #[cfg(not(feature = "proto-igmp"))]
     _ipv4_multicast_groups: PhantomData<&'e ()>,
-    /// When to report for (all or) the next multicast group membership via IGMP
+    /// Until when an IGMPv1 querier is considered present
+    #[cfg(feature = "proto-igmp")]
+    igmp_v1_querier_until:  Option<Instant>,
+    /// Until when an IGMPv2 querier is considered present
     #[cfg(feature = "proto-igmp")]
-    igmp_report_state:      IgmpReportState,
+    igmp_v2_querier_until:  Option<Instant>,
+    #[cfg(feature = "proto-ipv4")]
+    ipv4_conflict_detectors: ManagedMap<'e, Ipv4Address, ConflictDetector>,
+    #[cfg(feature = "proto-ipv4")]
+    ipv4_link_local:        Option<LinkLocal>,
+    #[cfg(feature = "proto-ipv4")]
+    rand:                   Rand,
+    /// Index of the next address to announce via gratuitous ARP or an unsolicited
+    /// Neighbor Advertisement, if an announcement is pending
+    announce_index:         Option<usize>,
     device_capabilities:    DeviceCapabilities,
 }
 
+/// Which socket receives a unicast UDP datagram that several sockets accept.
+///
+/// Whatever the policy, a socket connected to the source endpoint receives the
+/// datagram in preference to an unconnected socket. Broadcast and multicast
+/// datagrams are always delivered to every socket that accepts them.
+#[cfg(feature = "socket-udp")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpUnicastPolicy {
+    /// The first socket in the socket set receives the datagram.
+    FirstMatch,
+    /// A socket bound to the destination address receives the datagram in preference
+    /// to a socket bound to the unspecified address; among equally specific sockets,
+    /// the first socket in the socket set receives it.
+    MostSpecific,
+}
+
+#[cfg(feature = "socket-udp")]
+impl Default for UdpUnicastPolicy {
+    fn default() -> UdpUnicastPolicy {
+        UdpUnicastPolicy::FirstMatch
+    }
+}
+
 /// A builder structure used for creating a Ethernet network
 /// interface.
 pub struct InterfaceBuilder <'b, 'c, 'e, DeviceT: for<'d> Device<'d>> {
@@ -93,14 +145,22 @@ pub struct InterfaceBuilder <'b, 'c, 'e, DeviceT: for<'d> Device<'d>> {
     ethernet_addr:          Option<EthernetAddress>,
     neighbor_cache:         Option<NeighborCache<'b>>,
     ip_addrs:               ManagedSlice<'c, IpCidr>,
-    #[cfg(feature = "proto-ipv4")]
+    proxy_prefixes:         ManagedSlice<'c, IpCidr>,
+    deprecated_ip_addrs:    ManagedSlice<'c, IpAddress>,
     any_ip:                 bool,
     routes:                 Routes<'e>,
+    accept_redirects:       bool,
+    #[cfg(feature = "socket-udp")]
+    udp_unicast_policy:     UdpUnicastPolicy,
     /// Does not share storage with `ipv6_multicast_groups` to avoid IPv6 size overhead.
     #[cfg(feature = "proto-igmp")]
-    ipv4_multicast_groups:  ManagedMap<'e, Ipv4Address, ()>,
+    ipv4_multicast_groups:  ManagedMap<'e, Ipv4Address, IgmpGroup>,
     #[cfg(not(feature = "proto-igmp"))]
     _ipv4_multicast_groups: PhantomData<&'e ()>,
+    #[cfg(feature = "proto-ipv4")]
+    ipv4_conflict_detectors: ManagedMap<'e, Ipv4Address, ConflictDetector>,
+    #[cfg(feature = "proto-ipv4")]
+    ipv4_link_local:        bool,
 }
 
 impl<'b, 'c, 'e, DeviceT> InterfaceBuilder<'b, 'c, 'e, DeviceT>
@@ -136,13 +196,21 @@ impl<'b, 'c, 'e, DeviceT> InterfaceBuilder<'b, 'c, 'e, DeviceT>
             ethernet_addr:       None,
             neighbor_cache:      None,
             ip_addrs:            ManagedSlice::Borrowed(&mut []),
-            #[cfg(feature = "proto-ipv4")]
+            proxy_prefixes:      ManagedSlice::Borrowed(&mut []),
+            deprecated_ip_addrs: ManagedSlice::Borrowed(&mut []),
             any_ip:              false,
-            routes:              Routes::new(ManagedMap::Borrowed(&mut [])),
+            routes:              Routes::new(ManagedSlice::Borrowed(&mut [])),
+            accept_redirects:    true,
+            #[cfg(feature = "socket-udp")]
+            udp_unicast_policy:  UdpUnicastPolicy::default(),
             #[cfg(feature = "proto-igmp")]
             ipv4_multicast_groups:   ManagedMap::Borrowed(&mut []),
             #[cfg(not(feature = "proto-igmp"))]
             _ipv4_multicast_groups:  PhantomData,
+            #[cfg(feature = "proto-ipv4")]
+            ipv4_conflict_detectors: ManagedMap::Borrowed(&mut []),
+            #[cfg(feature = "proto-ipv4")]
+            ipv4_link_local:     false,
         }
     }
 
@@ -175,20 +243,51 @@ impl<'b, 'c, 'e, DeviceT> InterfaceBuilder<'b, 'c, 'e, DeviceT>
         self
     }
 
+    /// Set the IP prefixes the interface will answer ARP requests and Neighbor
+    /// Solicitations for, with its own Ethernet address. See also [proxy_prefixes].
+    ///
+    /// Combined with [any_ip], this allows terminating traffic for a whole
+    /// subnet, without the hosts in it being reachable on the link.
+    ///
+    /// [proxy_prefixes]: struct.EthernetInterface.html#method.proxy_prefixes
+    /// [any_ip]: #method.any_ip
+    pub fn proxy_prefixes<T>(mut self, proxy_prefixes: T) -> Self
+        where T: Into<ManagedSlice<'c, IpCidr>>
+    {
+        self.proxy_prefixes = proxy_prefixes.into();
+        self
+    }
+
+    /// Set the IP addresses of the interface that are deprecated, and should not
+    /// be used as the source address of new communication if there is
+    /// an alternative. See also [deprecated_ip_addrs].
+    ///
+    /// [deprecated_ip_addrs]: struct.EthernetInterface.html#method.deprecated_ip_addrs
+    pub fn deprecated_ip_addrs<T>(mut self, deprecated_ip_addrs: T) -> Self
+        where T: Into<ManagedSlice<'c, IpAddress>>
+    {
+        self.deprecated_ip_addrs = deprecated_ip_addrs.into();
+        self
+    }
+
     /// Enable or disable the AnyIP capability, allowing packets to be received
-    /// locally on IPv4 addresses other than the interface's configured [ip_addrs].
+    /// locally on IP addresses other than the interface's configured [ip_addrs].
     /// When AnyIP is enabled and a route prefix in [routes] specifies one of
     /// the interface's [ip_addrs] as its gateway, the interface will accept
     /// packets addressed to that prefix.
     ///
     /// # IPv6
     ///
-    /// This option is not available or required for IPv6 as packets sent to
-    /// the interface are not filtered by IPv6 address.
+    /// Unicast IPv6 packets are filtered by destination address just as IPv4
+    /// packets are, so packets to addresses other than [ip_addrs] are dropped
+    /// unless AnyIP accepts them.
+    ///
+    /// Neighbor Solicitations for IPv6 addresses in such a prefix are answered
+    /// as well, so that the prefix does not have to be routed to the interface
+    /// by the neighbors.
     ///
     /// [routes]: struct.EthernetInterface.html#method.routes
     /// [ip_addrs]: struct.EthernetInterface.html#method.ip_addrs
-    #[cfg(feature = "proto-ipv4")]
     pub fn any_ip(mut self, enabled: bool) -> Self {
         self.any_ip = enabled;
         self
@@ -205,24 +304,83 @@ impl<'b, 'c, 'e, DeviceT> InterfaceBuilder<'b, 'c, 'e, DeviceT>
         self
     }
 
-    /// Provide storage for multicast groups.
+    /// Enable or disable processing of ICMP and NDISC Redirect messages.
+    ///
+    /// When enabled (the default), a valid redirect from the router currently
+    /// used for a destination installs a host route to that destination via
+    /// the indicated next hop in [routes], which expires after five minutes.
+    /// Such routes need room in the routing table storage.
+    ///
+    /// [routes]: #method.routes
+    pub fn accept_redirects(mut self, enabled: bool) -> Self {
+        self.accept_redirects = enabled;
+        self
+    }
+
+    /// Set which socket receives a unicast UDP datagram that several sockets accept.
+    /// The default is [FirstMatch].
+    ///
+    /// [FirstMatch]: enum.UdpUnicastPolicy.html#variant.FirstMatch
+    #[cfg(feature = "socket-udp")]
+    pub fn udp_unicast_policy(mut self, policy: UdpUnicastPolicy) -> Self {
+        self.udp_unicast_policy = policy;
+        self
+    }
+
+    /// Provide storage for multicast groups, and the source filters of their memberships.
     ///
-    /// Join multicast groups by calling [`join_multicast_group()`] on an `Interface`.
-    /// Using [`join_multicast_group()`] will send initial membership reports.
+    /// Join multicast groups by calling [`join_multicast_group()`] or
+    /// [`join_multicast_source_group()`] on an `Interface`.
+    /// Joining a group will send initial membership reports.
     ///
     /// A previously destroyed interface can be recreated by reusing the multicast group
     /// storage, i.e. providing a non-empty storage to `ipv4_multicast_groups()`.
     /// Note that this way initial membership reports are **not** sent.
     ///
     /// [`join_multicast_group()`]: struct.EthernetInterface.html#method.join_multicast_group
+    /// [`join_multicast_source_group()`]: struct.EthernetInterface.html#method.join_multicast_source_group
     #[cfg(feature = "proto-igmp")]
     pub fn ipv4_multicast_groups<T>(mut self, ipv4_multicast_groups: T) -> Self
-        where T: Into<ManagedMap<'e, Ipv4Address, ()>>
+        where T: Into<ManagedMap<'e, Ipv4Address, IgmpGroup>>
     {
         self.ipv4_multicast_groups = ipv4_multicast_groups.into();
         self
     }
 
+    /// Provide storage for IPv4 address conflict detection.
+    ///
+    /// Every IPv4 address configured with [ip_addrs] or [update_ip_addrs] for which
+    /// there is room in the storage is probed before it is used, then announced
+    /// and defended against other hosts, as described in RFC 5227. An address that
+    /// is found to be in use by another host is not used; see [ipv4_addr_state].
+    /// Addresses that do not fit in the storage are used immediately.
+    ///
+    /// [ip_addrs]: #method.ip_addrs
+    /// [update_ip_addrs]: struct.EthernetInterface.html#method.update_ip_addrs
+    /// [ipv4_addr_state]: struct.EthernetInterface.html#method.ipv4_addr_state
+    #[cfg(feature = "proto-ipv4")]
+    pub fn ipv4_conflict_detection<T>(mut self, storage: T) -> Self
+        where T: Into<ManagedMap<'e, Ipv4Address, ConflictDetector>>
+    {
+        self.ipv4_conflict_detectors = storage.into();
+        self
+    }
+
+    /// Enable or disable IPv4 link-local address autoconfiguration.
+    ///
+    /// When enabled, the interface picks an address from 169.254.0.0/16
+    /// pseudo-randomly based on its Ethernet address, probes and defends it
+    /// as described in RFC 3927, and picks another one on conflict. Once probing
+    /// succeeds, the address is used as the source address of IPv4 packets
+    /// if no other IPv4 address is configured; see [ipv4_link_local_addr].
+    ///
+    /// [ipv4_link_local_addr]: struct.EthernetInterface.html#method.ipv4_link_local_addr
+    #[cfg(feature = "proto-ipv4")]
+    pub fn ipv4_link_local(mut self, enabled: bool) -> Self {
+        self.ipv4_link_local = enabled;
+        self
+    }
+
     /// Set the Neighbor Cache the interface will use.
     pub fn neighbor_cache(mut self, neighbor_cache: NeighborCache<'b>) -> Self {
         self.neighbor_cache = Some(neighbor_cache);
@@ -245,22 +403,57 @@ impl<'b, 'c, 'e, DeviceT> InterfaceBuilder<'b, 'c, 'e, DeviceT>
             (Some(ethernet_addr), Some(neighbor_cache)) => {
                 let device_capabilities = self.device.capabilities();
 
-                Interface {
+                #[cfg(feature = "proto-ipv4")]
+                let mut rand = {
+                    let b = ethernet_addr.as_bytes();
+                    Rand::new(((b[2] as u32) << 24 | (b[3] as u32) << 16 |
+                               (b[4] as u32) << 8  | (b[5] as u32)) ^
+                              ((b[0] as u32) << 16 | (b[1] as u32) << 8))
+                };
+                #[cfg(feature = "proto-ipv4")]
+                let ipv4_link_local = if self.ipv4_link_local {
+                    Some(LinkLocal::new(&mut rand))
+                } else {
+                    None
+                };
+
+                let iface = Interface {
                     device: self.device,
                     inner: InterfaceInner {
                         ethernet_addr, device_capabilities, neighbor_cache,
                         ip_addrs: self.ip_addrs,
-                        #[cfg(feature = "proto-ipv4")]
+                        proxy_prefixes: self.proxy_prefixes,
+                        deprecated_ip_addrs: self.deprecated_ip_addrs,
                         any_ip: self.any_ip,
                         routes: self.routes,
+                        accept_redirects: self.accept_redirects,
+                        #[cfg(feature = "socket-udp")]
+                        udp_unicast_policy: self.udp_unicast_policy,
                         #[cfg(feature = "proto-igmp")]
                         ipv4_multicast_groups: self.ipv4_multicast_groups,
                         #[cfg(not(feature = "proto-igmp"))]
                         _ipv4_multicast_groups:  PhantomData,
                         #[cfg(feature = "proto-igmp")]
-                        igmp_report_state: IgmpReportState::Inactive,
+                        igmp_v1_querier_until: None,
+                        #[cfg(feature = "proto-igmp")]
+                        igmp_v2_querier_until: None,
+                        #[cfg(feature = "proto-ipv4")]
+                        ipv4_conflict_detectors: self.ipv4_conflict_detectors,
+                        #[cfg(feature = "proto-ipv4")]
+                        ipv4_link_local,
+                        #[cfg(feature = "proto-ipv4")]
+                        rand,
+                        announce_index: None,
                     }
-                }
+                };
+                // Probe the initial addresses as well.
+                #[cfg(feature = "proto-ipv4")]
+                let iface = {
+                    let mut iface = iface;
+                    iface.inner.update_ipv4_conflict_detectors();
+                    iface
+                };
+                iface
             },
             _ => panic!("a required option was not set"),
         }
@@ -275,7 +468,7 @@ enum Packet<'a> {
     #[cfg(feature = "proto-ipv4")]
     Icmpv4((Ipv4Repr, Icmpv4Repr<'a>)),
     #[cfg(feature = "proto-igmp")]
-    Igmp((Ipv4Repr, IgmpRepr)),
+    Igmp((Ipv4Repr, IgmpRepr<'a>)),
     #[cfg(feature = "proto-ipv6")]
     Icmpv6((Ipv6Repr, Icmpv6Repr<'a>)),
     #[cfg(feature = "socket-raw")]
@@ -321,21 +514,11 @@ fn icmp_reply_payload_len(len: usize, mtu: usize, header_len: usize) -> usize {
     cmp::min(len, mtu - header_len * 2 - 8)
 }
 
+/// How long an IGMPv1 or IGMPv2 querier is considered present after its last query,
+/// given the default robustness variable, query interval and query response interval
+/// of RFC 3376 § 8.
 #[cfg(feature = "proto-igmp")]
-enum IgmpReportState {
-    Inactive,
-    ToGeneralQuery {
-        version:    IgmpVersion,
-        timeout:    Instant,
-        interval:   Duration,
-        next_index: usize
-    },
-    ToSpecificQuery {
-        version:    IgmpVersion,
-        timeout:    Instant,
-        group:      Ipv4Address
-    },
-}
+const IGMP_OLDER_VERSION_QUERIER_PRESENT_TIMEOUT: Duration = Duration { millis: 260_000 };
 
 impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
         where DeviceT: for<'d> Device<'d> {
@@ -346,35 +529,78 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
 
     /// Set the Ethernet address of the interface.
     ///
+    /// If the address changes, the IP addresses of the interface are [announced]
+    /// on the next poll.
+    ///
     /// # Panics
     /// This function panics if the address is not unicast.
+    ///
+    /// [announced]: #method.announce
     pub fn set_ethernet_addr(&mut self, addr: EthernetAddress) {
-        self.inner.ethernet_addr = addr;
-        InterfaceInner::check_ethernet_addr(&self.inner.ethernet_addr);
+        InterfaceInner::check_ethernet_addr(&addr);
+        if self.inner.ethernet_addr != addr {
+            self.inner.ethernet_addr = addr;
+            self.announce();
+        }
+    }
+
+    /// Announce the IP addresses of the interface to the neighbors on the next poll.
+    ///
+    /// A gratuitous ARP request is broadcast for every IPv4 address, and an
+    /// unsolicited Neighbor Advertisement with the Override flag is sent to
+    /// the all-nodes multicast address for every IPv6 address, so that the neighbors
+    /// update their caches with the Ethernet address of the interface. Loopback
+    /// addresses, as well as IPv4 addresses still undergoing conflict detection,
+    /// are not announced.
+    ///
+    /// This is useful e.g. when taking over the addresses of another host.
+    pub fn announce(&mut self) {
+        self.inner.announce_index = Some(0);
     }
 
-    /// Add an address to a list of subscribed multicast IP addresses.
+    /// Add an address to a list of subscribed multicast IP addresses, receiving
+    /// datagrams from any source.
     ///
     /// Returns `Ok(announce_sent)` if the address was added successfully, where `annouce_sent`
     /// indicates whether an initial immediate announcement has been sent.
     pub fn join_multicast_group<T: Into<IpAddress>>(&mut self, addr: T, _timestamp: Instant) -> Result<bool> {
         match addr.into() {
             #[cfg(feature = "proto-igmp")]
-            IpAddress::Ipv4(addr) => {
-                let is_not_new = self.inner.ipv4_multicast_groups.insert(addr, ())
-                    .map_err(|_| Error::Exhausted)?
-                    .is_some();
-                if is_not_new {
-                    Ok(false)
-                } else if let Some(pkt) =
-                        self.inner.igmp_report_packet(IgmpVersion::Version2, addr) {
-                    // Send initial membership report
-                    let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
-                    self.inner.dispatch(tx_token, _timestamp, pkt)?;
-                    Ok(true)
-                } else {
-                    Ok(false)
+            IpAddress::Ipv4(addr) =>
+                self.change_multicast_group(addr, _timestamp, |group| {
+                    group.app_joined = true;
+                    match group.membership().cloned() {
+                        Some(membership) if membership.mode() == IgmpFilterMode::Exclude =>
+                            membership,
+                        _ => IgmpMembership::any_source()
+                    }
+                }),
+            // Multicast is not yet implemented for other address families
+            _ => Err(Error::Unaddressable)
+        }
+    }
+
+    /// Subscribe to datagrams sent to a multicast IP address by the given source,
+    /// as in source-specific multicast.
+    ///
+    /// Returns `Ok(announce_sent)` if the source was added successfully, where `annouce_sent`
+    /// indicates whether an immediate announcement has been sent. Returns `Err(Error::Illegal)`
+    /// if the address was joined for any source with [join_multicast_group].
+    ///
+    /// [join_multicast_group]: #method.join_multicast_group
+    pub fn join_multicast_source_group<T, U>(&mut self, addr: T, src_addr: U,
+                                             _timestamp: Instant) -> Result<bool>
+            where T: Into<IpAddress>, U: Into<IpAddress> {
+        match (addr.into(), src_addr.into()) {
+            #[cfg(feature = "proto-igmp")]
+            (IpAddress::Ipv4(addr), IpAddress::Ipv4(src_addr)) => {
+                let mut membership = self.multicast_membership(addr)
+                    .unwrap_or(IgmpMembership::none());
+                if membership.mode() == IgmpFilterMode::Exclude {
+                    return Err(Error::Illegal)
                 }
+                membership.add_source(src_addr)?;
+                self.update_multicast_membership(addr, membership, _timestamp)
             }
             // Multicast is not yet implemented for other address families
             _ => Err(Error::Unaddressable)
@@ -382,31 +608,122 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
     }
 
     /// Remove an address from the subscribed multicast IP addresses.
+    /// The group stays joined for any source as long as sockets are members of it.
     ///
     /// Returns `Ok(leave_sent)` if the address was removed successfully, where `leave_sent`
     /// indicates whether an immediate leave packet has been sent.
     pub fn leave_multicast_group<T: Into<IpAddress>>(&mut self, addr: T, _timestamp: Instant) -> Result<bool> {
         match addr.into() {
             #[cfg(feature = "proto-igmp")]
-            IpAddress::Ipv4(addr) => {
-                let was_not_present = self.inner.ipv4_multicast_groups.remove(&addr)
-                    .is_none();
-                if was_not_present {
-                    Ok(false)
-                } else if let Some(pkt) = self.inner.igmp_leave_packet(addr) {
-                    // Send group leave packet
-                    let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
-                    self.inner.dispatch(tx_token, _timestamp, pkt)?;
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
+            IpAddress::Ipv4(addr) =>
+                self.update_multicast_membership(addr, IgmpMembership::none(), _timestamp),
+            // Multicast is not yet implemented for other address families
+            _ => Err(Error::Unaddressable)
+        }
+    }
+
+    /// Unsubscribe from datagrams sent to a multicast IP address by the given source,
+    /// undoing [join_multicast_source_group]. The address is removed from the subscribed
+    /// multicast IP addresses when no sources are left.
+    ///
+    /// Returns `Ok(leave_sent)` if the source was removed successfully, where `leave_sent`
+    /// indicates whether an immediate announcement has been sent.
+    ///
+    /// [join_multicast_source_group]: #method.join_multicast_source_group
+    pub fn leave_multicast_source_group<T, U>(&mut self, addr: T, src_addr: U,
+                                              _timestamp: Instant) -> Result<bool>
+            where T: Into<IpAddress>, U: Into<IpAddress> {
+        match (addr.into(), src_addr.into()) {
+            #[cfg(feature = "proto-igmp")]
+            (IpAddress::Ipv4(addr), IpAddress::Ipv4(src_addr)) => {
+                let mut membership = match self.multicast_membership(addr) {
+                    Some(membership) if membership.mode() == IgmpFilterMode::Include =>
+                        membership,
+                    _ => return Ok(false)
+                };
+                membership.remove_source(src_addr);
+                self.update_multicast_membership(addr, membership, _timestamp)
             }
             // Multicast is not yet implemented for other address families
             _ => Err(Error::Unaddressable)
         }
     }
 
+    /// Get the membership of an IPv4 multicast group, if the group is subscribed to.
+    #[cfg(feature = "proto-igmp")]
+    pub fn multicast_membership(&self, addr: Ipv4Address) -> Option<IgmpMembership> {
+        self.inner.ipv4_multicast_groups.get(&addr)
+            .and_then(|group| group.membership().cloned())
+    }
+
+    /// Set the membership of an IPv4 multicast group, with an arbitrary source filter.
+    /// Setting an INCLUDE mode membership with no sources removes the group from
+    /// the subscribed multicast IP addresses, unless sockets are members of it,
+    /// in which case it stays joined for any source.
+    ///
+    /// Returns `Ok(announce_sent)` if the membership was updated successfully, where
+    /// `annouce_sent` indicates whether an immediate announcement has been sent.
+    /// The announcement is retransmitted once after a random delay; until then, a group
+    /// that was left still takes up room in the multicast group storage.
+    #[cfg(feature = "proto-igmp")]
+    pub fn update_multicast_membership(&mut self, addr: Ipv4Address, membership: IgmpMembership,
+                                       timestamp: Instant) -> Result<bool> {
+        self.change_multicast_group(addr, timestamp, |group| {
+            group.app_joined = !membership.is_none();
+            membership
+        })
+    }
+
+    /// Update the state of an IPv4 multicast group with `f`, which returns the new
+    /// membership, and report the change of the membership if there is one.
+    ///
+    /// A group that sockets are members of is never left.
+    #[cfg(feature = "proto-igmp")]
+    fn change_multicast_group<F>(&mut self, addr: Ipv4Address, timestamp: Instant,
+                                 f: F) -> Result<bool>
+            where F: FnOnce(&mut IgmpGroup) -> IgmpMembership {
+        let stored = self.inner.ipv4_multicast_groups.get(&addr).cloned();
+        let mut group = stored.unwrap_or(IgmpGroup::new());
+        let mut membership = f(&mut group);
+        if membership.is_none() && group.socket_refs > 0 {
+            membership = IgmpMembership::any_source();
+        }
+        if group.membership == membership {
+            if stored.is_some() {
+                self.inner.ipv4_multicast_groups.insert(addr, group)
+                    .map_err(|_| Error::Exhausted)?;
+            }
+            return Ok(false)
+        }
+
+        let version = self.inner.igmp_version(timestamp);
+        let retransmit_at = timestamp +
+            self.inner.rand.rand_duration(Duration::from_millis(0),
+                                          igmp::unsolicited_report_interval(version));
+        group.change(membership, retransmit_at);
+        self.inner.ipv4_multicast_groups.insert(addr, group)
+            .map_err(|_| Error::Exhausted)?;
+
+        let mut records = [0; igmp::MAX_RECORDS_LEN];
+        match self.inner.igmp_state_change_packet(version, addr, &group.changed_from,
+                                                  &group.membership, &mut records) {
+            Some(pkt) => {
+                let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
+                self.inner.dispatch(tx_token, timestamp, pkt)?;
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    /// Get the IGMP version used for membership reports at the given instant.
+    ///
+    /// This is IGMPv3, unless an IGMPv1 or IGMPv2 querier was seen recently.
+    #[cfg(feature = "proto-igmp")]
+    pub fn igmp_version(&self, timestamp: Instant) -> IgmpVersion {
+        self.inner.igmp_version(timestamp)
+    }
+
     /// Check whether the interface listens to given destination multicast IP address.
     pub fn has_multicast_group<T: Into<IpAddress>>(&self, addr: T) -> bool {
         self.inner.has_multicast_group(addr)
@@ -419,11 +736,57 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
 
     /// Update the IP addresses of the interface.
     ///
+    /// If [conflict detection] is enabled, new IPv4 addresses are probed
+    /// before they are used. The addresses are [announced] on the next poll.
+    ///
     /// # Panics
     /// This function panics if any of the addresses are not unicast.
+    ///
+    /// [conflict detection]: struct.EthernetInterfaceBuilder.html#method.ipv4_conflict_detection
+    /// [announced]: #method.announce
     pub fn update_ip_addrs<F: FnOnce(&mut ManagedSlice<'c, IpCidr>)>(&mut self, f: F) {
         f(&mut self.inner.ip_addrs);
-        InterfaceInner::check_ip_addrs(&self.inner.ip_addrs)
+        InterfaceInner::check_ip_addrs(&self.inner.ip_addrs);
+        #[cfg(feature = "proto-ipv4")]
+        self.inner.update_ipv4_conflict_detectors();
+        self.announce();
+    }
+
+    /// Get the IP prefixes the interface answers ARP requests and
+    /// Neighbor Solicitations for.
+    pub fn proxy_prefixes(&self) -> &[IpCidr] {
+        self.inner.proxy_prefixes.as_ref()
+    }
+
+    /// Update the IP prefixes the interface answers ARP requests and
+    /// Neighbor Solicitations for.
+    pub fn update_proxy_prefixes<F: FnOnce(&mut ManagedSlice<'c, IpCidr>)>(&mut self, f: F) {
+        f(&mut self.inner.proxy_prefixes);
+    }
+
+    /// Get the IP addresses of the interface that are deprecated.
+    pub fn deprecated_ip_addrs(&self) -> &[IpAddress] {
+        self.inner.deprecated_ip_addrs.as_ref()
+    }
+
+    /// Update the IP addresses of the interface that are deprecated.
+    pub fn update_deprecated_ip_addrs<F: FnOnce(&mut ManagedSlice<'c, IpAddress>)>(&mut self, f: F) {
+        f(&mut self.inner.deprecated_ip_addrs);
+    }
+
+    /// Select the source address to use for sending to `dst_addr`, as described in
+    /// RFC 6724 § 5. This address is used by sockets that are not bound to a local address.
+    ///
+    /// Returns `None` if the interface has no usable address of the same family.
+    pub fn select_src_addr(&self, dst_addr: IpAddress) -> Option<IpAddress> {
+        self.inner.select_src_addr(&dst_addr).map(|candidate| candidate.cidr.address())
+    }
+
+    /// Sort the destination addresses so that the most preferred one comes first,
+    /// as described in RFC 6724 § 6. This is useful for choosing among the addresses
+    /// a host name resolves to.
+    pub fn sort_dst_addrs(&self, dst_addrs: &mut [IpAddress]) {
+        select::sort_destinations(dst_addrs, |dst_addr| self.inner.select_src_addr(dst_addr))
     }
 
     /// Check whether the interface has the given IP address assigned.
@@ -437,6 +800,22 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
         self.inner.ipv4_address()
     }
 
+    /// Get the conflict detection state of an IPv4 address of the interface.
+    ///
+    /// Returns `None` if the address is not configured, or does not undergo
+    /// conflict detection.
+    #[cfg(feature = "proto-ipv4")]
+    pub fn ipv4_addr_state(&self, addr: Ipv4Address) -> Option<ConflictState> {
+        self.inner.ipv4_addr_state(addr)
+    }
+
+    /// Get the IPv4 link-local address of the interface, if it was autoconfigured
+    /// without a conflict.
+    #[cfg(feature = "proto-ipv4")]
+    pub fn ipv4_link_local_addr(&self) -> Option<Ipv4Address> {
+        self.inner.ipv4_link_local_addr()
+    }
+
     pub fn routes(&self) -> &Routes<'e> {
         &self.inner.routes
     }
@@ -445,6 +824,30 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
         &mut self.inner.routes
     }
 
+    /// Get a reference to the neighbor cache of the interface.
+    pub fn neighbor_cache(&self) -> &NeighborCache<'b> {
+        &self.inner.neighbor_cache
+    }
+
+    /// Get a mutable reference to the neighbor cache of the interface, e.g. to add
+    /// permanent entries or flush it.
+    pub fn neighbor_cache_mut(&mut self) -> &mut NeighborCache<'b> {
+        &mut self.inner.neighbor_cache
+    }
+
+    /// Get a reference to the device of the interface.
+    pub fn device(&self) -> &DeviceT {
+        &self.device
+    }
+
+    /// Get a mutable reference to the device of the interface.
+    ///
+    /// This is useful e.g. to drive a simulated device, such as
+    /// a [PcapReplay](../phy/struct.PcapReplay.html), between polls.
+    pub fn device_mut(&mut self) -> &mut DeviceT {
+        &mut self.device
+    }
+
     /// Transmit packets queued in the given sockets, and receive packets queued
     /// in the device.
     ///
@@ -452,6 +855,9 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
     /// processed or emitted, and thus, whether the readiness of any socket might
     /// have changed.
     ///
+    /// Frames queued by the device are [flushed](../phy/trait.Device.html#method.flush)
+    /// before this function returns, even if it returns an error.
+    ///
     /// # Errors
     /// This method will routinely return errors in response to normal network
     /// activity as well as certain boundary conditions such as buffer exhaustion.
@@ -463,14 +869,32 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
     /// a very common occurrence and on a production system it should not even
     /// be logged.
     pub fn poll(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> Result<bool> {
+        let result = self.poll_sockets(sockets, timestamp);
+        self.device.flush();
+        result
+    }
+
+    fn poll_sockets(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> Result<bool> {
         let mut readiness_may_have_changed = false;
         loop {
             let processed_any = self.socket_ingress(sockets, timestamp)?;
+
+            #[cfg(feature = "proto-igmp")]
+            self.socket_multicast_groups(sockets, timestamp);
+
+            #[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+            self.socket_multicast_loopback(sockets, timestamp);
+
             let emitted_any   = self.socket_egress(sockets, timestamp)?;
 
             #[cfg(feature = "proto-igmp")]
             self.igmp_egress(timestamp)?;
 
+            #[cfg(feature = "proto-ipv4")]
+            self.ipv4_conflict_egress(timestamp)?;
+
+            self.announce_egress(timestamp)?;
+
             if processed_any || emitted_any {
                 readiness_may_have_changed = true;
             } else {
@@ -489,6 +913,16 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
     /// [poll]: #method.poll
     /// [Instant]: struct.Instant.html
     pub fn poll_at(&self, sockets: &SocketSet, timestamp: Instant) -> Option<Instant> {
+        #[cfg(feature = "proto-ipv4")]
+        let conflict_poll_at = self.inner.ipv4_conflict_poll_at();
+        #[cfg(not(feature = "proto-ipv4"))]
+        let conflict_poll_at = None;
+        let announce_poll_at = self.inner.announce_poll_at();
+        #[cfg(feature = "proto-igmp")]
+        let igmp_poll_at = self.inner.igmp_poll_at();
+        #[cfg(not(feature = "proto-igmp"))]
+        let igmp_poll_at = None;
+
         sockets.iter().filter_map(|socket| {
             let socket_poll_at = socket.poll_at();
             match socket.meta().poll_at(socket_poll_at, |ip_addr|
@@ -497,7 +931,7 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
                     PollAt::Time(instant) => Some(instant),
                     PollAt::Now => Some(Instant::from_millis(0)),
             }
-        }).min()
+        }).chain(conflict_poll_at).chain(announce_poll_at).chain(igmp_poll_at).min()
     }
 
     /// Return an _advisory wait time_ for calling [poll] the next time.
@@ -528,8 +962,18 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
                 None => break,
                 Some(tokens) => tokens,
             };
+            let rx_offload = rx_token.offload();
             rx_token.consume(timestamp, |frame| {
-                inner.process_ethernet(sockets, timestamp, &frame).map_err(|err| {
+                // If the device has verified the checksums of this frame already, skip
+                // verifying them again, but only while processing this frame.
+                let checksum_caps = inner.device_capabilities.checksum.clone();
+                if rx_offload.checksum_valid {
+                    inner.device_capabilities.checksum = ChecksumCapabilities::ignored();
+                }
+                let result = inner.process_ethernet(sockets, timestamp, &frame);
+                inner.device_capabilities.checksum = checksum_caps;
+
+                result.map_err(|err| {
                     net_debug!("cannot process ingress packet: {}", err);
                     net_debug!("packet dump follows:\n{}",
                                PrettyPrinter::<EthernetFrame<&[u8]>>::new("", &frame));
@@ -549,6 +993,8 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
     fn socket_egress(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> Result<bool> {
         let mut caps = self.device.capabilities();
         caps.max_transmission_unit -= EthernetFrame::<&[u8]>::header_len();
+        caps.max_segmentation_size = caps.max_segmentation_size.and_then(|size|
+            size.checked_sub(EthernetFrame::<&[u8]>::header_len()));
 
         let mut emitted_any = false;
         for mut socket in sockets.iter_mut() {
@@ -562,11 +1008,12 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
             let &mut Self { ref mut device, ref mut inner } = self;
 
             macro_rules! respond {
-                ($response:expr) => ({
+                ($response:expr, $remote_mss:expr) => ({
                     let response = $response;
                     neighbor_addr = response.neighbor_addr();
                     let tx_token = device.transmit().ok_or(Error::Exhausted)?;
-                    device_result = inner.dispatch(tx_token, timestamp, response);
+                    device_result = inner.dispatch_segmented(tx_token, timestamp, response,
+                                                             $remote_mss);
                     device_result
                 })
             }
@@ -576,28 +1023,30 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
                     #[cfg(feature = "socket-raw")]
                     Socket::Raw(ref mut socket) =>
                         socket.dispatch(&caps.checksum, |response|
-                            respond!(Packet::Raw(response))),
+                            respond!(Packet::Raw(response), None)),
                     #[cfg(all(feature = "socket-icmp", any(feature = "proto-ipv4", feature = "proto-ipv6")))]
                     Socket::Icmp(ref mut socket) =>
                         socket.dispatch(&caps, |response| {
                             match response {
                                 #[cfg(feature = "proto-ipv4")]
                                 (IpRepr::Ipv4(ipv4_repr), IcmpRepr::Ipv4(icmpv4_repr)) =>
-                                    respond!(Packet::Icmpv4((ipv4_repr, icmpv4_repr))),
+                                    respond!(Packet::Icmpv4((ipv4_repr, icmpv4_repr)), None),
                                 #[cfg(feature = "proto-ipv6")]
                                 (IpRepr::Ipv6(ipv6_repr), IcmpRepr::Ipv6(icmpv6_repr)) =>
-                                    respond!(Packet::Icmpv6((ipv6_repr, icmpv6_repr))),
+                                    respond!(Packet::Icmpv6((ipv6_repr, icmpv6_repr)), None),
                                 _ => Err(Error::Unaddressable)
                             }
                         }),
                     #[cfg(feature = "socket-udp")]
                     Socket::Udp(ref mut socket) =>
                         socket.dispatch(|response|
-                            respond!(Packet::Udp(response))),
+                            respond!(Packet::Udp(response), None)),
                     #[cfg(feature = "socket-tcp")]
-                    Socket::Tcp(ref mut socket) =>
+                    Socket::Tcp(ref mut socket) => {
+                        let remote_mss = socket.remote_mss();
                         socket.dispatch(timestamp, &caps, |response|
-                            respond!(Packet::Tcp(response))),
+                            respond!(Packet::Tcp(response), Some(remote_mss)))
+                    }
                     Socket::__Nonexhaustive(_) => unreachable!()
                 };
 
@@ -624,52 +1073,232 @@ impl<'b, 'c, 'e, DeviceT> Interface<'b, 'c, 'e, DeviceT>
         Ok(emitted_any)
     }
 
-    /// Depending on `igmp_report_state` and the therein contained
-    /// timeouts, send IGMP membership reports.
+    /// Join and leave the multicast groups that sockets have joined or left since
+    /// the last poll, keeping count of the sockets that are members of each group.
+    /// The groups of sockets that have been removed from the set are left as well.
     #[cfg(feature = "proto-igmp")]
-    fn igmp_egress(&mut self, timestamp: Instant) -> Result<bool> {
-        match self.inner.igmp_report_state {
-            IgmpReportState::ToSpecificQuery { version, timeout, group }
-                    if timestamp >= timeout => {
-                if let Some(pkt) = self.inner.igmp_report_packet(version, group) {
-                    // Send initial membership report
-                    let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
-                    self.inner.dispatch(tx_token, timestamp, pkt)?;
+    fn socket_multicast_groups(&mut self, sockets: &mut SocketSet, timestamp: Instant) {
+        for mut socket in sockets.iter_mut() {
+            let handle = socket.handle();
+            let multicast = match socket.multicast_mut() {
+                Some(multicast) => multicast,
+                None => continue
+            };
+            while let Some((addr, join)) = multicast.pending_change() {
+                let result = if join {
+                    self.join_socket_multicast_group(addr, timestamp)
+                } else {
+                    self.leave_socket_multicast_group(addr, timestamp)
+                };
+                if let Err(err) = result {
+                    net_debug!("{}: cannot {} multicast group {}: {}",
+                               handle, if join { "join" } else { "leave" }, addr, err);
                 }
+                multicast.change_done(addr, result.is_ok());
+            }
+        }
 
-                self.inner.igmp_report_state = IgmpReportState::Inactive;
-                Ok(true)
+        // A group counting more sockets than the set has members of it was joined
+        // by sockets that have since been removed or pruned.
+        loop {
+            let stale = self.inner.ipv4_multicast_groups.iter().find(|&(addr, group)| {
+                let members = sockets.iter().filter(|socket| {
+                    socket.multicast().map(|multicast| multicast.has_group((*addr).into()))
+                                      .unwrap_or(false)
+                }).count();
+                group.socket_refs as usize > members
+            }).map(|(addr, _)| *addr);
+            let addr = match stale {
+                Some(addr) => addr,
+                None => break
+            };
+            if let Err(err) = self.leave_socket_multicast_group(addr.into(), timestamp) {
+                net_debug!("cannot leave multicast group {} of a removed socket: {}", addr, err);
             }
-            IgmpReportState::ToGeneralQuery { version, timeout, interval, next_index }
-                    if timestamp >= timeout => {
-                let addr = self.inner.ipv4_multicast_groups
-                    .iter()
-                    .nth(next_index)
-                    .map(|(addr, ())| *addr);
-
-                match addr {
-                    Some(addr) => {
-                        if let Some(pkt) = self.inner.igmp_report_packet(version, addr) {
-                            // Send initial membership report
-                            let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
-                            self.inner.dispatch(tx_token, timestamp, pkt)?;
-                        }
+        }
+    }
 
-                        let next_timeout = (timeout + interval).max(timestamp);
-                        self.inner.igmp_report_state = IgmpReportState::ToGeneralQuery {
-                            version, timeout: next_timeout, interval, next_index: next_index + 1
-                        };
-                        Ok(true)
-                    }
+    #[cfg(feature = "proto-igmp")]
+    fn join_socket_multicast_group(&mut self, addr: IpAddress, timestamp: Instant) -> Result<bool> {
+        let addr = match addr {
+            IpAddress::Ipv4(addr) => addr,
+            // Multicast is not yet implemented for other address families
+            _ => return Err(Error::Unaddressable)
+        };
+        let socket_refs = self.inner.ipv4_multicast_groups.get(&addr)
+            .map(|group| group.socket_refs).unwrap_or(0);
+        let result = self.change_multicast_group(addr, timestamp, |group| {
+            group.socket_refs += 1;
+            match group.membership().cloned() {
+                Some(membership) if membership.mode() == IgmpFilterMode::Exclude => membership,
+                _ => IgmpMembership::any_source()
+            }
+        });
+        // The group is joined even if the report could not be sent right away.
+        match self.inner.ipv4_multicast_groups.get(&addr) {
+            Some(group) if group.socket_refs > socket_refs => Ok(result.unwrap_or(false)),
+            _ => result
+        }
+    }
+
+    #[cfg(feature = "proto-igmp")]
+    fn leave_socket_multicast_group(&mut self, addr: IpAddress, timestamp: Instant) -> Result<bool> {
+        let addr = match addr {
+            IpAddress::Ipv4(addr) => addr,
+            _ => return Err(Error::Unaddressable)
+        };
+        match self.inner.ipv4_multicast_groups.get(&addr) {
+            Some(group) if group.socket_refs > 0 => (),
+            _ => return Ok(false)
+        }
+        self.change_multicast_group(addr, timestamp, |group| {
+            group.socket_refs -= 1;
+            if group.socket_refs == 0 && !group.app_joined {
+                IgmpMembership::none()
+            } else {
+                group.membership
+            }
+        })
+    }
 
-                    None => {
-                        self.inner.igmp_report_state = IgmpReportState::Inactive;
-                        Ok(false)
+    /// Deliver the multicast packets that sockets are about to send and that are
+    /// to be looped back to the other sockets of the same kind.
+    #[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+    fn socket_multicast_loopback(&mut self, sockets: &mut SocketSet, _timestamp: Instant) {
+        let inner = &self.inner;
+        sockets.for_each_with_others(|socket, others| {
+            match *socket {
+                #[cfg(feature = "socket-raw")]
+                Socket::Raw(ref mut socket) => socket.loop_back(|ip_repr, payload| {
+                    let ip_repr = match inner.multicast_loopback_repr(ip_repr) {
+                        Some(ip_repr) => ip_repr,
+                        None => return
+                    };
+                    let checksum_caps = &inner.device_capabilities.checksum;
+                    for mut raw_socket in others.filter_map(RawSocket::downcast) {
+                        if !raw_socket.accepts(&ip_repr) { continue }
+                        if let Err(err) = raw_socket.process(&ip_repr, payload, checksum_caps) {
+                            net_debug!("{}: cannot loop back multicast packet: {}",
+                                       raw_socket.handle(), err);
+                        }
                     }
-                }
+                }),
+                #[cfg(feature = "socket-udp")]
+                Socket::Udp(ref mut socket) => socket.loop_back(|ip_repr, udp_repr| {
+                    let ip_repr = match inner.multicast_loopback_repr(ip_repr) {
+                        Some(ip_repr) => ip_repr,
+                        None => return
+                    };
+                    for mut udp_socket in others.filter_map(UdpSocket::downcast) {
+                        if !udp_socket.accepts(&ip_repr, udp_repr) { continue }
+                        if let Err(err) = udp_socket.process(&ip_repr, udp_repr, _timestamp) {
+                            net_debug!("{}: cannot loop back multicast packet: {}",
+                                       udp_socket.handle(), err);
+                        }
+                    }
+                }),
+                _ => ()
+            }
+        })
+    }
+
+    /// Send the IGMP membership reports that are due, in response to queries
+    /// or as retransmissions of unsolicited reports.
+    #[cfg(feature = "proto-igmp")]
+    fn igmp_egress(&mut self, timestamp: Instant) -> Result<bool> {
+        let mut emitted_any = false;
+        loop {
+            let (addr, mut group) = match self.inner.ipv4_multicast_groups.iter()
+                    .find(|&(_, group)| group.poll_at().map(|at| at <= timestamp)
+                                                        .unwrap_or(false)) {
+                Some((addr, group)) => (*addr, *group),
+                None => break
+            };
+
+            let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
+            let version = self.inner.igmp_version(timestamp);
+            let is_query_response = group.report_at.map(|at| at <= timestamp).unwrap_or(false);
+
+            let mut records = [0; igmp::MAX_RECORDS_LEN];
+            let pkt = if is_query_response {
+                self.inner.igmp_report_packet(version, addr, &group.membership, &mut records)
+            } else {
+                self.inner.igmp_state_change_packet(version, addr, &group.changed_from,
+                                                    &group.membership, &mut records)
+            };
+            if let Some(pkt) = pkt {
+                self.inner.dispatch(tx_token, timestamp, pkt)?;
+                emitted_any = true;
+            }
+
+            // Only account for the report once it is sent, so that it is retried
+            // if it could not be.
+            if is_query_response {
+                group.report_at = None;
+            } else {
+                group.retransmissions_left -= 1;
+                group.retransmit_at = if group.retransmissions_left > 0 {
+                    Some(timestamp +
+                         self.inner.rand.rand_duration(Duration::from_millis(0),
+                                                       igmp::unsolicited_report_interval(version)))
+                } else {
+                    None
+                };
+            }
+            if group.membership().is_none() && group.poll_at().is_none() {
+                self.inner.ipv4_multicast_groups.remove(&addr);
+            } else {
+                self.inner.ipv4_multicast_groups.insert(addr, group)
+                    .map_err(|_| Error::Exhausted)?;
+            }
+        }
+        Ok(emitted_any)
+    }
+
+    /// Send the ARP probes and announcements that are due for the addresses
+    /// undergoing conflict detection.
+    #[cfg(feature = "proto-ipv4")]
+    fn ipv4_conflict_egress(&mut self, timestamp: Instant) -> Result<bool> {
+        let mut emitted_any = false;
+        loop {
+            match self.inner.ipv4_conflict_poll_at() {
+                Some(poll_at) if poll_at <= timestamp => (),
+                _ => break
+            }
+
+            if let Some(arp_repr) = self.inner.ipv4_conflict_packet(timestamp) {
+                let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
+                self.inner.dispatch(tx_token, timestamp, Packet::Arp(arp_repr))?;
+                emitted_any = true;
+            }
+            // Only advance the detector once its packet is sent, so that it is retried
+            // if it could not be.
+            self.inner.ipv4_conflict_advance(timestamp);
+        }
+        Ok(emitted_any)
+    }
+
+    /// Send the pending gratuitous ARP requests and unsolicited Neighbor
+    /// Advertisements, if any.
+    fn announce_egress(&mut self, timestamp: Instant) -> Result<bool> {
+        let mut emitted_any = false;
+        while let Some(index) = self.inner.announce_index {
+            // The slot past the configured addresses is the IPv4 link-local address.
+            if index > self.inner.ip_addrs.len() {
+                self.inner.announce_index = None;
+                break
+            }
+
+            if let Some(pkt) = self.inner.announce_packet(index) {
+                let tx_token = self.device.transmit().ok_or(Error::Exhausted)?;
+                self.inner.dispatch(tx_token, timestamp, pkt)?;
+                emitted_any = true;
             }
-            _ => Ok(false)
+            // Only move on to the next address once the announcement is sent, so that
+            // it is retried if it could not be.
+            self.inner.announce_index = Some(index + 1);
         }
+        Ok(emitted_any)
     }
 }
 
@@ -708,12 +1337,131 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
     }
 
     /// Check whether the interface has the given IP address assigned.
+    ///
+    /// IPv4 addresses that are being probed or were found to be in use
+    /// by another host are not considered assigned.
     fn has_ip_addr<T: Into<IpAddress>>(&self, addr: T) -> bool {
         let addr = addr.into();
-        self.ip_addrs.iter().any(|probe| probe.address() == addr)
+        match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(ipv4_addr) => {
+                if self.ipv4_link_local_addr() == Some(ipv4_addr) {
+                    return true
+                }
+                if self.is_ipv4_addr_unusable(ipv4_addr) {
+                    return false
+                }
+            }
+            _ => ()
+        }
+        self.ip_addrs.iter().any(|probe| probe.address() == addr)
     }
 
-    /// Get the first IPv4 address of the interface.
+    /// Check whether the given IPv4 address is being probed or was found to be
+    /// in use by another host, and so must not be used (RFC 5227 § 2.1).
+    #[cfg(feature = "proto-ipv4")]
+    fn is_ipv4_addr_unusable(&self, addr: Ipv4Address) -> bool {
+        match self.ipv4_conflict_detectors.get(&addr) {
+            Some(detector) => !detector.is_usable(),
+            None => false
+        }
+    }
+
+    /// Select the source address to use for sending to `dst_addr`.
+    ///
+    /// Loopback addresses are only used for loopback destinations, and IPv4 addresses
+    /// that are not usable yet are not used at all; the autoconfigured IPv4 link-local
+    /// address is a candidate as well.
+    fn select_src_addr(&self, dst_addr: &IpAddress) -> Option<Candidate> {
+        #[cfg(feature = "proto-ipv4")]
+        let link_local = self.ipv4_link_local_addr()
+            .map(|addr| IpCidr::Ipv4(Ipv4Cidr::new(addr, 16)));
+        #[cfg(not(feature = "proto-ipv4"))]
+        let link_local = None;
+
+        let dst_is_loopback = Self::is_loopback(dst_addr);
+        let candidates = self.ip_addrs.iter()
+            .cloned()
+            .filter(|cidr| Self::is_loopback(&cidr.address()) == dst_is_loopback)
+            .filter(|cidr| self.has_ip_addr(cidr.address()))
+            .chain(link_local)
+            .map(|cidr| Candidate {
+                cidr:       cidr,
+                deprecated: self.deprecated_ip_addrs.iter().any(|addr| *addr == cidr.address())
+            });
+        select::select_source(dst_addr, candidates)
+    }
+
+    fn is_loopback(addr: &IpAddress) -> bool {
+        match *addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(addr) => addr.is_loopback(),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(addr) => addr.is_loopback(),
+            _ => false
+        }
+    }
+
+    /// Check whether AnyIP is enabled, and the given IP address is in a prefix
+    /// routed via one of the addresses of the interface.
+    fn is_any_ip_routed<T: Into<IpAddress>>(&self, addr: T, timestamp: Instant) -> bool {
+        if !self.any_ip { return false }
+
+        let addr = addr.into();
+        if !addr.is_unicast() { return false }
+        self.routes.any_next_hop(&addr, timestamp, |router_addr| self.has_ip_addr(router_addr))
+    }
+
+    /// Install a host route to `dst_addr` via `target_addr`, in response to a redirect
+    /// sent by `router_addr`, if the redirect is valid.
+    ///
+    /// Returns whether the route was installed.
+    fn process_redirect(&mut self, timestamp: Instant, router_addr: IpAddress,
+                        dst_addr: IpAddress, target_addr: IpAddress) -> bool {
+        if !self.accept_redirects {
+            net_debug!("ignoring redirect for {}", dst_addr);
+            return false
+        }
+        if !dst_addr.is_unicast() || !target_addr.is_unicast() {
+            net_debug!("redirect for {} to {}: not unicast", dst_addr, target_addr);
+            return false
+        }
+
+        // Only the router that we currently use for the destination may redirect us,
+        // and only to a next hop on the link.
+        if self.in_same_network(&dst_addr) ||
+                !self.routes.any_next_hop(&dst_addr, timestamp, |next_hop|
+                    next_hop == router_addr && next_hop != dst_addr) {
+            net_debug!("redirect for {}: not sent by its first hop {}", dst_addr, router_addr);
+            return false
+        }
+        if target_addr != dst_addr && !self.in_same_network(&target_addr) {
+            net_debug!("redirect for {}: {} is not on-link", dst_addr, target_addr);
+            return false
+        }
+
+        match self.routes.add_redirect(dst_addr, target_addr, timestamp) {
+            Ok(()) => {
+                net_debug!("redirected {} to {}", dst_addr, target_addr);
+                true
+            }
+            Err(_) => {
+                net_debug!("no room for redirect of {} to {}", dst_addr, target_addr);
+                false
+            }
+        }
+    }
+
+    /// Check whether the interface answers address resolution requests for
+    /// the given IP address on behalf of another host.
+    fn is_proxied_addr<T: Into<IpAddress>>(&self, addr: T) -> bool {
+        let addr = addr.into();
+        !self.ip_addrs.iter().any(|cidr| cidr.address() == addr) &&
+            self.proxy_prefixes.iter().any(|cidr| cidr.contains_addr(&addr))
+    }
+
+    /// Get the first IPv4 address of the interface, falling back
+    /// to the link-local address.
     #[cfg(feature = "proto-ipv4")]
     pub fn ipv4_address(&self) -> Option<Ipv4Address> {
         self.ip_addrs.iter()
@@ -722,7 +1470,191 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                     &IpCidr::Ipv4(cidr) => Some(cidr.address()),
                     _ => None,
                 })
+            .filter(|addr| self.has_ip_addr(*addr))
             .next()
+            .or_else(|| self.ipv4_link_local_addr())
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    fn ipv4_link_local_addr(&self) -> Option<Ipv4Address> {
+        self.ipv4_link_local.as_ref().and_then(|link_local| link_local.addr())
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    fn ipv4_addr_state(&self, addr: Ipv4Address) -> Option<ConflictState> {
+        match self.ipv4_link_local {
+            Some(ref link_local) if link_local.candidate_addr() == addr =>
+                return Some(link_local.state()),
+            _ => ()
+        }
+        self.ipv4_conflict_detectors.get(&addr).map(|detector| detector.state())
+    }
+
+    /// Start conflict detection for new IPv4 addresses, and stop it for removed ones.
+    #[cfg(feature = "proto-ipv4")]
+    fn update_ipv4_conflict_detectors(&mut self) {
+        loop {
+            let removed_addr = self.ipv4_conflict_detectors.iter()
+                .map(|(addr, _)| *addr)
+                .find(|addr| !self.ip_addrs.iter().any(|cidr|
+                    cidr.address() == IpAddress::Ipv4(*addr)));
+            match removed_addr {
+                Some(addr) => { self.ipv4_conflict_detectors.remove(&addr); }
+                None => break
+            }
+        }
+
+        for cidr in self.ip_addrs.iter() {
+            let addr = match *cidr {
+                IpCidr::Ipv4(cidr) => cidr.address(),
+                _ => continue
+            };
+            if self.ipv4_conflict_detectors.get(&addr).is_some() {
+                continue
+            }
+            match self.ipv4_conflict_detectors.insert(addr, ConflictDetector::new()) {
+                Ok(_) => (),
+                Err(_) => {
+                    net_debug!("no room for conflict detection of {}, using it immediately",
+                               addr);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    fn ipv4_conflict_poll_at(&self) -> Option<Instant> {
+        self.ipv4_conflict_detectors.iter()
+            .filter_map(|(_, detector)| detector.poll_at())
+            .chain(self.ipv4_link_local.as_ref().and_then(|link_local| link_local.poll_at()))
+            .min()
+    }
+
+    /// Return the next ARP probe or announcement that is due, if any, without
+    /// advancing the conflict detector it belongs to.
+    #[cfg(feature = "proto-ipv4")]
+    fn ipv4_conflict_packet(&self, timestamp: Instant) -> Option<ArpRepr> {
+        if let Some(ref link_local) = self.ipv4_link_local {
+            match link_local.poll_at() {
+                Some(poll_at) if poll_at <= timestamp =>
+                    return link_local.packet(self.ethernet_addr, timestamp),
+                _ => ()
+            }
+        }
+        for (addr, detector) in self.ipv4_conflict_detectors.iter() {
+            match detector.poll_at() {
+                Some(poll_at) if poll_at <= timestamp =>
+                    return detector.packet(*addr, self.ethernet_addr, timestamp),
+                _ => ()
+            }
+        }
+        None
+    }
+
+    /// Advance the conflict detector whose packet `ipv4_conflict_packet` returns.
+    #[cfg(feature = "proto-ipv4")]
+    fn ipv4_conflict_advance(&mut self, timestamp: Instant) {
+        let &mut InterfaceInner {
+            ethernet_addr, ref mut ipv4_conflict_detectors, ref mut ipv4_link_local,
+            ref mut rand, ..
+        } = self;
+
+        if let Some(ref mut link_local) = *ipv4_link_local {
+            match link_local.poll_at() {
+                Some(poll_at) if poll_at <= timestamp => {
+                    link_local.egress(ethernet_addr, timestamp, rand);
+                    return
+                }
+                _ => ()
+            }
+        }
+        for (addr, detector) in ipv4_conflict_detectors.iter_mut() {
+            match detector.poll_at() {
+                Some(poll_at) if poll_at <= timestamp => {
+                    detector.egress(*addr, ethernet_addr, timestamp, rand);
+                    return
+                }
+                _ => ()
+            }
+        }
+    }
+
+    /// Return the address in the given announcement slot, if it should be announced.
+    fn announce_addr(&self, index: usize) -> Option<IpAddress> {
+        let addr = match self.ip_addrs.get(index) {
+            Some(cidr) => cidr.address(),
+            #[cfg(feature = "proto-ipv4")]
+            None => match self.ipv4_link_local_addr() {
+                Some(addr) => addr.into(),
+                None => return None
+            },
+            #[cfg(not(feature = "proto-ipv4"))]
+            None => return None
+        };
+        match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(ipv4_addr) if ipv4_addr.is_loopback() => None,
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(ipv6_addr) if ipv6_addr.is_loopback() => None,
+            _ if self.has_ip_addr(addr) => Some(addr),
+            _ => None
+        }
+    }
+
+    fn announce_poll_at(&self) -> Option<Instant> {
+        match self.announce_index {
+            Some(next_index) if (next_index..self.ip_addrs.len() + 1)
+                    .any(|index| self.announce_addr(index).is_some()) =>
+                Some(Instant::from_millis(0)),
+            _ => None
+        }
+    }
+
+    /// Return the gratuitous ARP request or unsolicited Neighbor Advertisement
+    /// for the address in the given announcement slot, if any.
+    fn announce_packet<'any>(&self, index: usize) -> Option<Packet<'any>> {
+        match self.announce_addr(index) {
+            #[cfg(feature = "proto-ipv4")]
+            Some(IpAddress::Ipv4(addr)) => {
+                net_debug!("announcing {}", addr);
+                Some(Packet::Arp(ConflictDetector::announcement(addr, self.ethernet_addr)))
+            }
+            #[cfg(feature = "proto-ipv6")]
+            Some(IpAddress::Ipv6(addr)) => {
+                net_debug!("announcing {}", addr);
+                let advert = Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+                    flags:       NdiscNeighborFlags::OVERRIDE,
+                    target_addr: addr,
+                    lladdr:      Some(self.ethernet_addr)
+                });
+                let ip_repr = Ipv6Repr {
+                    src_addr:      addr,
+                    dst_addr:      Ipv6Address::LINK_LOCAL_ALL_NODES,
+                    next_header:   IpProtocol::Icmpv6,
+                    payload_len:   advert.buffer_len(),
+                    hop_limit:     0xff,
+                    traffic_class: 0
+                };
+                Some(Packet::Icmpv6((ip_repr, advert)))
+            }
+            _ => None
+        }
+    }
+
+    /// Look for other hosts using or probing for our IPv4 addresses.
+    #[cfg(feature = "proto-ipv4")]
+    fn process_ipv4_conflicts(&mut self, arp_repr: &ArpRepr, timestamp: Instant) {
+        let &mut InterfaceInner {
+            ethernet_addr, ref mut ipv4_conflict_detectors, ref mut ipv4_link_local,
+            ref mut rand, ..
+        } = self;
+
+        if let Some(ref mut link_local) = *ipv4_link_local {
+            link_local.process(ethernet_addr, arp_repr, timestamp, rand);
+        }
+        for (addr, detector) in ipv4_conflict_detectors.iter_mut() {
+            detector.process(*addr, ethernet_addr, arp_repr, timestamp);
+        }
     }
 
     /// Check whether the interface listens to given destination multicast IP address.
@@ -734,12 +1666,50 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             #[cfg(feature = "proto-igmp")]
             IpAddress::Ipv4(key) =>
                 key == Ipv4Address::MULTICAST_ALL_SYSTEMS ||
-                self.ipv4_multicast_groups.get(&key).is_some(),
+                self.ipv4_multicast_groups.get(&key)
+                    .and_then(|group| group.membership()).is_some(),
             _ =>
                 false,
         }
     }
 
+    /// Return the IP header of a multicast packet looped back to the sockets, or `None`
+    /// if the interface is not a member of the group or has no suitable source address.
+    #[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+    fn multicast_loopback_repr(&self, ip_repr: &IpRepr) -> Option<IpRepr> {
+        let dst_addr = ip_repr.dst_addr();
+        if !self.has_multicast_group(dst_addr) { return None }
+
+        let src_addr = if ip_repr.src_addr().is_unspecified() {
+            self.select_src_addr(&dst_addr)?.cidr.address()
+        } else {
+            ip_repr.src_addr()
+        };
+        IpRepr::Unspecified {
+            src_addr,
+            dst_addr,
+            protocol:      ip_repr.protocol(),
+            payload_len:   ip_repr.payload_len(),
+            hop_limit:     ip_repr.hop_limit(),
+            traffic_class: ip_repr.traffic_class(),
+        }.lower(&[]).ok()
+    }
+
+    /// Check whether the interface receives datagrams sent to the given multicast
+    /// IPv4 address by the given source. IGMP messages are received regardless
+    /// of the source filter.
+    #[cfg(feature = "proto-ipv4")]
+    fn has_multicast_source(&self, addr: Ipv4Address, _src_addr: Ipv4Address,
+                            _protocol: IpProtocol) -> bool {
+        #[cfg(feature = "proto-igmp")]
+        match self.ipv4_multicast_groups.get(&addr).and_then(|group| group.membership()) {
+            Some(membership) if _protocol != IpProtocol::Igmp =>
+                return membership.accepts(_src_addr),
+            _ => ()
+        }
+        self.has_multicast_group(addr)
+    }
+
     fn process_ethernet<'frame, T: AsRef<[u8]>>
                        (&mut self, sockets: &mut SocketSet, timestamp: Instant, frame: &'frame T) ->
                        Result<Packet<'frame>>
@@ -784,17 +1754,30 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             ArpRepr::EthernetIpv4 {
                 operation, source_hardware_addr, source_protocol_addr, target_protocol_addr, ..
             } => {
-                if source_protocol_addr.is_unicast() && source_hardware_addr.is_unicast() {
-                    self.neighbor_cache.fill(source_protocol_addr.into(),
-                                             source_hardware_addr,
-                                             timestamp);
-                } else {
+                // ARP probes have an all-zeroes source protocol address.
+                if !source_hardware_addr.is_unicast() ||
+                        !(source_protocol_addr.is_unicast() ||
+                          source_protocol_addr == Ipv4Address::UNSPECIFIED) {
                     // Discard packets with non-unicast source addresses.
                     net_debug!("non-unicast source address");
                     return Err(Error::Malformed)
                 }
 
-                if operation == ArpOperation::Request && self.has_ip_addr(target_protocol_addr) {
+                self.process_ipv4_conflicts(&arp_repr, timestamp);
+
+                if source_protocol_addr.is_unicast() {
+                    self.neighbor_cache.fill(source_protocol_addr.into(),
+                                             source_hardware_addr,
+                                             timestamp);
+                }
+
+                // Probes and announcements are not answered on behalf of other hosts.
+                let is_proxied = source_protocol_addr.is_unicast() &&
+                    source_protocol_addr != target_protocol_addr &&
+                    self.is_proxied_addr(target_protocol_addr);
+
+                if operation == ArpOperation::Request &&
+                        (self.has_ip_addr(target_protocol_addr) || is_proxied) {
                     Ok(Packet::Arp(ArpRepr::EthernetIpv4 {
                         operation: ArpOperation::Reply,
                         source_hardware_addr: self.ethernet_addr,
@@ -857,6 +1840,13 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             }
         }
 
+        if !ipv6_repr.dst_addr.is_multicast() && !self.has_ip_addr(ipv6_repr.dst_addr) &&
+                !self.is_any_ip_routed(ipv6_repr.dst_addr, timestamp) {
+            // Ignore IP packets not directed at us, unless AnyIP is enabled
+            // and the packet is routed locally.
+            return Ok(Packet::None);
+        }
+
         let ip_payload = ipv6_packet.payload();
 
         #[cfg(feature = "socket-raw")]
@@ -882,7 +1872,7 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
 
             #[cfg(feature = "socket-udp")]
             IpProtocol::Udp =>
-                self.process_udp(sockets, ipv6_repr.into(), ip_payload),
+                self.process_udp(sockets, timestamp, ipv6_repr.into(), ip_payload),
 
             #[cfg(feature = "socket-tcp")]
             IpProtocol::Tcp =>
@@ -941,22 +1931,19 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         #[cfg(feature = "socket-raw")]
         let handled_by_raw_socket = self.raw_socket_filter(sockets, &ip_repr, ip_payload);
 
-        if !self.has_ip_addr(ipv4_repr.dst_addr) && !self.has_multicast_group(ipv4_repr.dst_addr) {
-            // Ignore IP packets not directed at us or any of the multicast groups
-            // If AnyIP is enabled, also check if the packet is routed locally.
-            if !self.any_ip {
-                return Ok(Packet::None);
-            } else if match self.routes.lookup(&IpAddress::Ipv4(ipv4_repr.dst_addr), timestamp) {
-                Some(router_addr) => !self.has_ip_addr(router_addr),
-                None => true,
-            } {
-                return Ok(Packet::None);
-            }
+        if !self.has_ip_addr(ipv4_repr.dst_addr) &&
+                !self.has_multicast_source(ipv4_repr.dst_addr, ipv4_repr.src_addr,
+                                           ipv4_repr.protocol) &&
+                !self.is_broadcast(&ipv4_repr.dst_addr.into()) &&
+                !self.is_any_ip_routed(ipv4_repr.dst_addr, timestamp) {
+            // Ignore IP packets not directed at us, any of the multicast groups or
+            // a broadcast address, unless AnyIP is enabled and the packet is routed locally.
+            return Ok(Packet::None);
         }
 
         match ipv4_repr.protocol {
             IpProtocol::Icmp =>
-                self.process_icmpv4(sockets, ip_repr, ip_payload),
+                self.process_icmpv4(sockets, timestamp, ip_repr, ip_payload),
 
             #[cfg(feature = "proto-igmp")]
             IpProtocol::Igmp =>
@@ -964,7 +1951,7 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
 
             #[cfg(feature = "socket-udp")]
             IpProtocol::Udp =>
-                self.process_udp(sockets, ip_repr, ip_payload),
+                self.process_udp(sockets, timestamp, ip_repr, ip_payload),
 
             #[cfg(feature = "socket-tcp")]
             IpProtocol::Tcp =>
@@ -988,60 +1975,118 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         }
     }
 
-    /// Host duties of the **IGMPv2** protocol.
+    /// Return the earliest instant an IGMP membership report is to be sent at.
+    #[cfg(feature = "proto-igmp")]
+    fn igmp_poll_at(&self) -> Option<Instant> {
+        self.ipv4_multicast_groups.iter()
+            .filter_map(|(_, group)| group.poll_at())
+            .min()
+    }
+
+    /// Return the IGMP version to use for membership reports, depending on the versions
+    /// of the queriers seen recently, see RFC 3376 § 7.2.1.
+    #[cfg(feature = "proto-igmp")]
+    fn igmp_version(&self, timestamp: Instant) -> IgmpVersion {
+        let is_present = |until: Option<Instant>| until.map(|until| timestamp < until)
+                                                         .unwrap_or(false);
+        if is_present(self.igmp_v1_querier_until) {
+            IgmpVersion::Version1
+        } else if is_present(self.igmp_v2_querier_until) {
+            IgmpVersion::Version2
+        } else {
+            IgmpVersion::Version3
+        }
+    }
+
+    /// Host duties of the **IGMPv3** protocol, falling back to IGMPv1 and IGMPv2
+    /// when older queriers are present.
     ///
-    /// Sets up `igmp_report_state` for responding to IGMP general/specific membership queries.
-    /// Membership must not be reported immediately in order to avoid flooding the network
-    /// after a query is broadcasted by a router; this is not currently done.
+    /// Schedules the membership reports in response to IGMP general/specific membership
+    /// queries at a random point within the maximum response time, so that the hosts
+    /// on the network do not all report at once. When falling back to IGMPv1 or IGMPv2,
+    /// a scheduled report is suppressed if another host reports first.
+    ///
+    /// Group-and-source-specific queries are answered with the current state of the whole
+    /// group, if any of the queried sources is received.
     #[cfg(feature = "proto-igmp")]
     fn process_igmp<'frame>(&mut self, timestamp: Instant, ipv4_repr: Ipv4Repr,
                             ip_payload: &'frame [u8]) -> Result<Packet<'frame>> {
         let igmp_packet = IgmpPacket::new_checked(ip_payload)?;
         let igmp_repr = IgmpRepr::parse(&igmp_packet)?;
 
-        // FIXME: report membership after a delay
-        match igmp_repr {
+        let (group_addr, max_resp_time, source_addrs) = match igmp_repr {
             IgmpRepr::MembershipQuery { group_addr, version, max_resp_time } => {
-                // General query
-                if group_addr.is_unspecified() &&
-                        ipv4_repr.dst_addr == Ipv4Address::MULTICAST_ALL_SYSTEMS {
-                    // Are we member in any groups?
-                    if self.ipv4_multicast_groups.iter().next().is_some() {
-                        let interval = match version {
-                            IgmpVersion::Version1 =>
-                                Duration::from_millis(100),
-                            IgmpVersion::Version2 => {
-                                // No dependence on a random generator
-                                // (see [#24](https://github.com/m-labs/smoltcp/issues/24))
-                                // but at least spread reports evenly across max_resp_time.
-                                let intervals = self.ipv4_multicast_groups.len() as u32 + 1;
-                                max_resp_time / intervals
-                            }
-                        };
-                        self.igmp_report_state = IgmpReportState::ToGeneralQuery {
-                            version, timeout: timestamp + interval, interval, next_index: 0
-                        };
+                let until = Some(timestamp + IGMP_OLDER_VERSION_QUERIER_PRESENT_TIMEOUT);
+                match version {
+                    IgmpVersion::Version1 => {
+                        self.igmp_v1_querier_until = until;
+                        (group_addr, igmp::V1_MAX_RESP_TIME, &[][..])
                     }
-                } else {
-                    // Group-specific query
-                    if self.has_multicast_group(group_addr) && ipv4_repr.dst_addr == group_addr {
-                        // Don't respond immediately
-                        let timeout = max_resp_time / 4;
-                        self.igmp_report_state = IgmpReportState::ToSpecificQuery {
-                            version, timeout: timestamp + timeout, group: group_addr
-                        };
+                    _ => {
+                        self.igmp_v2_querier_until = until;
+                        (group_addr, max_resp_time, &[][..])
                     }
                 }
-            },
-            // Ignore membership reports
-            IgmpRepr::MembershipReport { .. } => (),
+            }
+            IgmpRepr::MembershipQueryV3 { group_addr, max_resp_time, source_addrs, .. } =>
+                (group_addr, max_resp_time, source_addrs),
+            // Another host reported the membership for us, see RFC 2236 § 3.
+            // IGMPv3 reports are not suppressed.
+            IgmpRepr::MembershipReport { group_addr, .. } => {
+                if self.igmp_version(timestamp) != IgmpVersion::Version3 {
+                    if let Some(group) = self.ipv4_multicast_groups.get_mut(&group_addr) {
+                        group.report_at = None;
+                    }
+                }
+                return Ok(Packet::None)
+            }
+            // Ignore IGMPv3 membership reports
+            IgmpRepr::MembershipReportV3 { .. } => return Ok(Packet::None),
             // Ignore hosts leaving groups
-            IgmpRepr::LeaveGroup{ .. } => (),
+            IgmpRepr::LeaveGroup{ .. } => return Ok(Packet::None),
+        };
+
+        let &mut InterfaceInner { ref mut ipv4_multicast_groups, ref mut rand, .. } = self;
+        let mut schedule_report = |group: &mut IgmpGroup| {
+            let report_at = timestamp + rand.rand_duration(Duration::from_millis(0),
+                                                           max_resp_time);
+            group.schedule_report(timestamp, max_resp_time, report_at);
+        };
+
+        if group_addr.is_unspecified() &&
+                ipv4_repr.dst_addr == Ipv4Address::MULTICAST_ALL_SYSTEMS {
+            // General query
+            for (_, group) in ipv4_multicast_groups.iter_mut() {
+                if group.membership().is_some() {
+                    schedule_report(group);
+                }
+            }
+        } else if ipv4_repr.dst_addr == group_addr &&
+                InterfaceInner::is_igmp_queried(ipv4_multicast_groups, group_addr,
+                                                source_addrs) {
+            // Group-specific query
+            if let Some(group) = ipv4_multicast_groups.get_mut(&group_addr) {
+                schedule_report(group);
+            }
         }
 
         Ok(Packet::None)
     }
 
+    /// Check whether a group-specific query, or a group-and-source-specific query
+    /// with the given source addresses, applies to our membership of the group.
+    #[cfg(feature = "proto-igmp")]
+    fn is_igmp_queried(groups: &ManagedMap<'e, Ipv4Address, IgmpGroup>,
+                       group_addr: Ipv4Address, source_addrs: &[u8]) -> bool {
+        match groups.get(&group_addr).and_then(|group| group.membership()) {
+            Some(_) if source_addrs.is_empty() => true,
+            Some(membership) =>
+                source_addrs.chunks(4)
+                    .any(|source_addr| membership.accepts(Ipv4Address::from_bytes(source_addr))),
+            None => false
+        }
+    }
+
     #[cfg(feature = "proto-ipv6")]
     fn process_icmpv6<'frame>(&mut self, _sockets: &mut SocketSet, timestamp: Instant,
                               ip_repr: IpRepr, ip_payload: &'frame [u8]) -> Result<Packet<'frame>>
@@ -1087,6 +2132,16 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             // Ignore any echo replies.
             Icmpv6Repr::EchoReply { .. } => Ok(Packet::None),
 
+            // Report unreachable ports to the UDP sockets connected to them.
+            #[cfg(feature = "socket-udp")]
+            Icmpv6Repr::DstUnreachable {
+                reason: Icmpv6DstUnreachable::PortUnreachable, header, data
+            } if header.next_header == IpProtocol::Udp => {
+                Self::process_udp_unreachable(_sockets, header.src_addr.into(),
+                                              header.dst_addr.into(), data);
+                Ok(Packet::None)
+            }
+
             // Forward any NDISC packets to the ndisc packet handler
             Icmpv6Repr::Ndisc(repr) if ip_repr.hop_limit() == 0xff => match ip_repr {
                 IpRepr::Ipv6(ipv6_repr) => self.process_ndisc(timestamp, ipv6_repr, repr),
@@ -1123,6 +2178,26 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                 }
                 Ok(Packet::None)
             }
+            // See RFC 4861 § 8.1 for the validity checks.
+            NdiscRepr::Redirect { target_addr, dest_addr, lladdr, .. } => {
+                if !ip_repr.src_addr.is_link_local() || ip_repr.hop_limit != 0xff {
+                    net_debug!("redirect not from a link-local address on the link");
+                    return Ok(Packet::None)
+                }
+                if !target_addr.is_link_local() && target_addr != dest_addr {
+                    net_debug!("redirect target {} is not link-local", target_addr);
+                    return Ok(Packet::None)
+                }
+
+                let redirected = self.process_redirect(timestamp, ip_repr.src_addr.into(),
+                                                       dest_addr.into(), target_addr.into());
+                match lladdr {
+                    Some(lladdr) if redirected && lladdr.is_unicast() =>
+                        self.neighbor_cache.fill(target_addr.into(), lladdr, timestamp),
+                    _ => ()
+                }
+                Ok(Packet::None)
+            }
             NdiscRepr::NeighborSolicit { target_addr, lladdr, .. } => {
                 match lladdr {
                     Some(lladdr) if lladdr.is_unicast() && target_addr.is_unicast() => {
@@ -1130,7 +2205,11 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                     },
                     _ => (),
                 }
-                if self.has_solicited_node(ip_repr.dst_addr) && self.has_ip_addr(target_addr) {
+                let is_any_ip = (ip_repr.dst_addr == target_addr.solicited_node() ||
+                                 ip_repr.dst_addr == target_addr) &&
+                                self.is_any_ip_routed(target_addr, timestamp);
+                if (self.has_solicited_node(ip_repr.dst_addr) && self.has_ip_addr(target_addr)) ||
+                        is_any_ip {
                     let advert = Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
                         flags: NdiscNeighborFlags::SOLICITED,
                         target_addr: target_addr,
@@ -1141,6 +2220,35 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                         dst_addr: ip_repr.src_addr,
                         next_header: IpProtocol::Icmpv6,
                         hop_limit: 0xff,
+                        traffic_class: 0,
+                        payload_len: advert.buffer_len()
+                    };
+                    Ok(Packet::Icmpv6((ip_repr, advert)))
+                } else if (ip_repr.dst_addr == target_addr.solicited_node() ||
+                           ip_repr.dst_addr == target_addr) &&
+                          ip_repr.src_addr != target_addr &&
+                          self.is_proxied_addr(target_addr) {
+                    // Proxy advertisements do not override the cache entries created by
+                    // the advertisements of the host itself, see RFC 4861 § 7.2.8.
+                    let advert = Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+                        flags: NdiscNeighborFlags::SOLICITED,
+                        target_addr: target_addr,
+                        lladdr: Some(self.ethernet_addr)
+                    });
+                    let src_addr = self.ip_addrs.iter()
+                        .filter_map(|cidr| match *cidr {
+                            IpCidr::Ipv6(cidr) if !cidr.address().is_loopback() =>
+                                Some(cidr.address()),
+                            _ => None
+                        })
+                        .next()
+                        .unwrap_or(target_addr);
+                    let ip_repr = Ipv6Repr {
+                        src_addr: src_addr,
+                        dst_addr: ip_repr.src_addr,
+                        next_header: IpProtocol::Icmpv6,
+                        hop_limit: 0xff,
+                        traffic_class: 0,
                         payload_len: advert.buffer_len()
                     };
                     Ok(Packet::Icmpv6((ip_repr, advert)))
@@ -1185,8 +2293,8 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
     }
 
     #[cfg(feature = "proto-ipv4")]
-    fn process_icmpv4<'frame>(&self, _sockets: &mut SocketSet, ip_repr: IpRepr,
-                              ip_payload: &'frame [u8]) -> Result<Packet<'frame>>
+    fn process_icmpv4<'frame>(&mut self, _sockets: &mut SocketSet, timestamp: Instant,
+                              ip_repr: IpRepr, ip_payload: &'frame [u8]) -> Result<Packet<'frame>>
     {
         let icmp_packet = Icmpv4Packet::new_checked(ip_payload)?;
         let checksum_caps = self.device_capabilities.checksum.clone();
@@ -1227,6 +2335,25 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             // Ignore any echo replies.
             Icmpv4Repr::EchoReply { .. } => Ok(Packet::None),
 
+            // Report unreachable ports to the UDP sockets connected to them.
+            #[cfg(feature = "socket-udp")]
+            Icmpv4Repr::DstUnreachable {
+                reason: Icmpv4DstUnreachable::PortUnreachable, header, data
+            } if header.protocol == IpProtocol::Udp => {
+                Self::process_udp_unreachable(_sockets, header.src_addr.into(),
+                                              header.dst_addr.into(), data);
+                Ok(Packet::None)
+            }
+
+            // Use a better first hop, if the redirect is about one of our packets.
+            Icmpv4Repr::Redirect { gateway, header, .. } => {
+                if self.has_ip_addr(header.src_addr) {
+                    self.process_redirect(timestamp, ip_repr.src_addr(),
+                                          header.dst_addr.into(), gateway.into());
+                }
+                Ok(Packet::None)
+            }
+
             // Don't report an error if a packet with unknown type
             // has been handled by an ICMP socket
             #[cfg(feature = "socket-icmp")]
@@ -1242,13 +2369,15 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                    (&self, ipv4_repr: Ipv4Repr, icmp_repr: Icmpv4Repr<'icmp>) ->
                    Packet<'frame>
     {
-        if ipv4_repr.dst_addr.is_unicast() {
+        if ipv4_repr.dst_addr.is_unicast() && !self.is_broadcast(&ipv4_repr.dst_addr.into()) {
             let ipv4_reply_repr = Ipv4Repr {
-                src_addr:    ipv4_repr.dst_addr,
-                dst_addr:    ipv4_repr.src_addr,
-                protocol:    IpProtocol::Icmp,
-                payload_len: icmp_repr.buffer_len(),
-                hop_limit:   64
+                src_addr:      ipv4_repr.dst_addr,
+                dst_addr:      ipv4_repr.src_addr,
+                protocol:      IpProtocol::Icmp,
+                payload_len:   icmp_repr.buffer_len(),
+                hop_limit:     64,
+                traffic_class: 0,
+                router_alert:  false
             };
             Packet::Icmpv4((ipv4_reply_repr, icmp_repr))
         } else {
@@ -1264,11 +2393,12 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
     {
         if ipv6_repr.dst_addr.is_unicast() {
             let ipv6_reply_repr = Ipv6Repr {
-                src_addr:    ipv6_repr.dst_addr,
-                dst_addr:    ipv6_repr.src_addr,
-                next_header: IpProtocol::Icmpv6,
-                payload_len: icmp_repr.buffer_len(),
-                hop_limit:   64
+                src_addr:      ipv6_repr.dst_addr,
+                dst_addr:      ipv6_repr.src_addr,
+                next_header:   IpProtocol::Icmpv6,
+                payload_len:   icmp_repr.buffer_len(),
+                hop_limit:     64,
+                traffic_class: 0
             };
             Packet::Icmpv6((ipv6_reply_repr, icmp_repr))
         } else {
@@ -1278,7 +2408,7 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
     }
 
     #[cfg(feature = "socket-udp")]
-    fn process_udp<'frame>(&self, sockets: &mut SocketSet,
+    fn process_udp<'frame>(&self, sockets: &mut SocketSet, timestamp: Instant,
                            ip_repr: IpRepr, ip_payload: &'frame [u8]) ->
                           Result<Packet<'frame>>
     {
@@ -1287,14 +2417,47 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         let checksum_caps = self.device_capabilities.checksum.clone();
         let udp_repr = UdpRepr::parse(&udp_packet, &src_addr, &dst_addr, &checksum_caps)?;
 
-        for mut udp_socket in sockets.iter_mut().filter_map(UdpSocket::downcast) {
-            if !udp_socket.accepts(&ip_repr, &udp_repr) { continue }
-
-            match udp_socket.process(&ip_repr, &udp_repr) {
-                // The packet is valid and handled by socket.
-                Ok(()) => return Ok(Packet::None),
-                // The packet is malformed, or the socket buffer is full.
-                Err(e) => return Err(e)
+        if dst_addr.is_multicast() || self.is_broadcast(&dst_addr) {
+            // Broadcast and multicast datagrams are delivered to every accepting socket.
+            let mut result = None;
+            for mut udp_socket in sockets.iter_mut().filter_map(UdpSocket::downcast) {
+                if !udp_socket.accepts(&ip_repr, &udp_repr) { continue }
+
+                // The datagram is handled if at least one socket received it;
+                // otherwise, report why the first socket did not.
+                match udp_socket.process(&ip_repr, &udp_repr, timestamp) {
+                    Ok(()) => result = Some(Ok(Packet::None)),
+                    Err(e) => if result.is_none() { result = Some(Err(e)) }
+                }
+            }
+            if let Some(result) = result {
+                return result
+            }
+        } else {
+            // A connected socket always wins over the unconnected ones.
+            let specificity = |udp_socket: &UdpSocket| match self.udp_unicast_policy {
+                UdpUnicastPolicy::FirstMatch =>
+                    (udp_socket.is_connected() as u8) << 1,
+                UdpUnicastPolicy::MostSpecific =>
+                    (udp_socket.is_connected() as u8) << 1 |
+                    (udp_socket.endpoint().addr == dst_addr) as u8,
+            };
+            let max_specificity = sockets.iter_mut()
+                .filter_map(UdpSocket::downcast)
+                .filter(|udp_socket| udp_socket.accepts(&ip_repr, &udp_repr))
+                .map(|udp_socket| specificity(&udp_socket))
+                .max();
+
+            for mut udp_socket in sockets.iter_mut().filter_map(UdpSocket::downcast) {
+                if !udp_socket.accepts(&ip_repr, &udp_repr) { continue }
+                if Some(specificity(&udp_socket)) != max_specificity { continue }
+
+                match udp_socket.process(&ip_repr, &udp_repr, timestamp) {
+                    // The packet is valid and handled by socket.
+                    Ok(()) => return Ok(Packet::None),
+                    // The packet is malformed, or the socket buffer is full.
+                    Err(e) => return Err(e)
+                }
             }
         }
 
@@ -1327,6 +2490,22 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         }
     }
 
+    /// Report an ICMP port unreachable message about a datagram sent from `src_addr`
+    /// to `dst_addr`, whose first octets are `udp_data`, to the connected UDP sockets.
+    #[cfg(feature = "socket-udp")]
+    fn process_udp_unreachable(sockets: &mut SocketSet, src_addr: IpAddress, dst_addr: IpAddress,
+                               udp_data: &[u8]) {
+        // Only the ports are needed, and not the whole header.
+        if udp_data.len() < 4 { return }
+        let udp_packet = UdpPacket::new_unchecked(udp_data);
+        let local_endpoint  = IpEndpoint::new(src_addr, udp_packet.src_port());
+        let remote_endpoint = IpEndpoint::new(dst_addr, udp_packet.dst_port());
+
+        for mut udp_socket in sockets.iter_mut().filter_map(UdpSocket::downcast) {
+            udp_socket.process_unreachable(local_endpoint, remote_endpoint);
+        }
+    }
+
     #[cfg(feature = "socket-tcp")]
     fn process_tcp<'frame>(&self, sockets: &mut SocketSet, timestamp: Instant,
                            ip_repr: IpRepr, ip_payload: &'frame [u8]) ->
@@ -1337,6 +2516,12 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         let checksum_caps = self.device_capabilities.checksum.clone();
         let tcp_repr = TcpRepr::parse(&tcp_packet, &src_addr, &dst_addr, &checksum_caps)?;
 
+        if !dst_addr.is_unicast() || self.is_broadcast(&dst_addr) {
+            // TCP is unicast only; do not answer broadcast or multicast segments, not even
+            // with a TCP RST packet.
+            return Ok(Packet::None)
+        }
+
         for mut tcp_socket in sockets.iter_mut().filter_map(TcpSocket::downcast) {
             if !tcp_socket.accepts(&ip_repr, &tcp_repr) { continue }
 
@@ -1361,6 +2546,15 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
     fn dispatch<Tx>(&mut self, tx_token: Tx, timestamp: Instant,
                     packet: Packet) -> Result<()>
         where Tx: TxToken
+    {
+        self.dispatch_segmented(tx_token, timestamp, packet, None)
+    }
+
+    /// Dispatch a packet; a TCP segment larger than the MTU is split by the device
+    /// into segments no larger than `remote_mss`, if given, as well.
+    fn dispatch_segmented<Tx>(&mut self, tx_token: Tx, timestamp: Instant,
+                              packet: Packet, _remote_mss: Option<usize>) -> Result<()>
+        where Tx: TxToken
     {
         let checksum_caps = self.device_capabilities.checksum.clone();
         match packet {
@@ -1368,11 +2562,14 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             Packet::Arp(arp_repr) => {
                 let dst_hardware_addr =
                     match arp_repr {
+                        ArpRepr::EthernetIpv4 { operation: ArpOperation::Request, .. } =>
+                            EthernetAddress::BROADCAST,
                         ArpRepr::EthernetIpv4 { target_hardware_addr, .. } => target_hardware_addr,
                         _ => unreachable!()
                     };
 
-                self.dispatch_ethernet(tx_token, timestamp, arp_repr.buffer_len(), |mut frame| {
+                self.dispatch_ethernet(tx_token, timestamp, arp_repr.buffer_len(),
+                                       TxOffload::default(), |mut frame| {
                     frame.set_dst_addr(dst_hardware_addr);
                     frame.set_ethertype(EthernetProtocol::Arp);
 
@@ -1389,9 +2586,11 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             }
             #[cfg(feature = "proto-igmp")]
             Packet::Igmp((ipv4_repr, igmp_repr)) => {
+                let mut result = Ok(());
                 self.dispatch_ip(tx_token, timestamp, IpRepr::Ipv4(ipv4_repr), |_ip_repr, payload| {
-                    igmp_repr.emit(&mut IgmpPacket::new_unchecked(payload));
-                })
+                    result = igmp_repr.emit(&mut IgmpPacket::new_unchecked(payload));
+                })?;
+                result
             }
             #[cfg(feature = "proto-ipv6")]
             Packet::Icmpv6((ipv6_repr, icmpv6_repr)) => {
@@ -1409,7 +2608,9 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             }
             #[cfg(feature = "socket-udp")]
             Packet::Udp((ip_repr, udp_repr)) => {
-                self.dispatch_ip(tx_token, timestamp, ip_repr, |ip_repr, payload| {
+                let ports = Some((udp_repr.src_port, udp_repr.dst_port));
+                self.dispatch_ip_offload(tx_token, timestamp, ip_repr, ports, None,
+                                         |ip_repr, payload| {
                     udp_repr.emit(&mut UdpPacket::new_unchecked(payload),
                                   &ip_repr.src_addr(), &ip_repr.dst_addr(),
                                   &checksum_caps);
@@ -1418,7 +2619,11 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             #[cfg(feature = "socket-tcp")]
             Packet::Tcp((ip_repr, mut tcp_repr)) => {
                 let caps = self.device_capabilities.clone();
-                self.dispatch_ip(tx_token, timestamp, ip_repr, |ip_repr, payload| {
+                let tcp_header_len = tcp_repr.header_len();
+                let ports = Some((tcp_repr.src_port, tcp_repr.dst_port));
+                self.dispatch_ip_offload(tx_token, timestamp, ip_repr, ports,
+                                         Some((tcp_header_len, _remote_mss)),
+                                         |ip_repr, payload| {
                     // This is a terrible hack to make TCP performance more acceptable on systems
                     // where the TCP buffers are significantly larger than network buffers,
                     // e.g. a 64 kB TCP receive buffer (and so, when empty, a 64k window)
@@ -1448,11 +2653,11 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
     }
 
     fn dispatch_ethernet<Tx, F>(&mut self, tx_token: Tx, timestamp: Instant,
-                                buffer_len: usize, f: F) -> Result<()>
+                                buffer_len: usize, offload: TxOffload, f: F) -> Result<()>
         where Tx: TxToken, F: FnOnce(EthernetFrame<&mut [u8]>)
     {
         let tx_len = EthernetFrame::<&[u8]>::buffer_len(buffer_len);
-        tx_token.consume(timestamp, tx_len, |tx_buffer| {
+        tx_token.consume_with_offload(timestamp, tx_len, offload, |tx_buffer| {
             debug_assert!(tx_buffer.as_ref().len() == tx_len);
             let mut frame = EthernetFrame::new_unchecked(tx_buffer.as_mut());
             frame.set_src_addr(self.ethernet_addr);
@@ -1463,42 +2668,77 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         })
     }
 
+    /// Check whether the given address is the limited broadcast address, or
+    /// the broadcast address of one of the IPv4 subnets of the interface.
+    #[cfg(any(feature = "proto-ipv4", feature = "socket-udp", feature = "socket-tcp"))]
+    fn is_broadcast(&self, addr: &IpAddress) -> bool {
+        match *addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(addr) =>
+                addr.is_broadcast() ||
+                self.ip_addrs.iter().any(|cidr| match *cidr {
+                    IpCidr::Ipv4(cidr) => cidr.broadcast() == Some(addr),
+                    _ => false
+                }),
+            _ => false
+        }
+    }
+
     fn in_same_network(&self, addr: &IpAddress) -> bool {
+        match *addr {
+            // Link-local destinations are always on-link, see RFC 3927 § 2.6.
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(addr) if addr.is_link_local() &&
+                                     self.ipv4_link_local_addr().is_some() =>
+                return true,
+            // So are IPv6 link-local destinations, see RFC 4861 § 5.2.
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(addr) if addr.is_link_local() =>
+                return true,
+            _ => ()
+        }
+
         self.ip_addrs
             .iter()
             .find(|cidr| cidr.contains_addr(addr))
             .is_some()
     }
 
-    fn route(&self, addr: &IpAddress, timestamp: Instant) -> Result<IpAddress> {
+    fn route(&self, flow: &Flow, timestamp: Instant) -> Result<IpAddress> {
+        let addr = &flow.dst_addr;
         // Send directly.
         if self.in_same_network(addr) || addr.is_broadcast() {
             return Ok(*addr)
         }
 
         // Route via a router.
-        match self.routes.lookup(addr, timestamp) {
+        match self.routes.lookup(flow, timestamp) {
             Some(router_addr) => Ok(router_addr),
             None => Err(Error::Unaddressable),
         }
     }
 
     fn has_neighbor<'a>(&self, addr: &'a IpAddress, timestamp: Instant) -> bool {
-        match self.route(addr, timestamp) {
-            Ok(routed_addr) => {
-                self.neighbor_cache
-                    .lookup_pure(&routed_addr, timestamp)
-                    .is_some()
-            }
-            Err(_) => false
+        let has_neighbor = |routed_addr: IpAddress|
+            self.neighbor_cache
+                .lookup_pure(&routed_addr, timestamp)
+                .is_some();
+
+        // Flows to the destination may be spread over several routes.
+        if self.in_same_network(addr) || addr.is_broadcast() {
+            has_neighbor(*addr)
+        } else if addr.is_unicast() {
+            self.routes.any_next_hop(addr, timestamp, has_neighbor)
+        } else {
+            false
         }
     }
 
     fn lookup_hardware_addr<Tx>(&mut self, tx_token: Tx, timestamp: Instant,
-                                src_addr: &IpAddress, dst_addr: &IpAddress) ->
-                               Result<(EthernetAddress, Tx)>
+                                flow: &Flow) -> Result<(EthernetAddress, Tx)>
         where Tx: TxToken
     {
+        let (src_addr, dst_addr) = (&flow.src_addr, &flow.dst_addr);
         if dst_addr.is_multicast() {
             let b = dst_addr.as_bytes();
             let hardware_addr =
@@ -1532,7 +2772,7 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
             }
         }
 
-        let dst_addr = self.route(dst_addr, timestamp)?;
+        let dst_addr = self.route(flow, timestamp)?;
 
         match self.neighbor_cache.lookup(&dst_addr, timestamp) {
             NeighborAnswer::Found(hardware_addr) =>
@@ -1556,7 +2796,8 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                     target_protocol_addr: dst_addr,
                 };
 
-                self.dispatch_ethernet(tx_token, timestamp, arp_repr.buffer_len(), |mut frame| {
+                self.dispatch_ethernet(tx_token, timestamp, arp_repr.buffer_len(),
+                                       TxOffload::default(), |mut frame| {
                     frame.set_dst_addr(EthernetAddress::BROADCAST);
                     frame.set_ethertype(EthernetProtocol::Arp);
 
@@ -1583,7 +2824,8 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                     dst_addr: dst_addr.solicited_node(),
                     next_header: IpProtocol::Icmpv6,
                     payload_len: solicit.buffer_len(),
-                    hop_limit: 0xff
+                    hop_limit: 0xff,
+                    traffic_class: 0
                 });
 
                 self.dispatch_ip(tx_token, timestamp, ip_repr, |ip_repr, payload| {
@@ -1602,14 +2844,48 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
                           ip_repr: IpRepr, f: F) -> Result<()>
         where Tx: TxToken, F: FnOnce(IpRepr, &mut [u8])
     {
-        let ip_repr = ip_repr.lower(&self.ip_addrs)?;
+        self.dispatch_ip_offload(tx_token, timestamp, ip_repr, None, None, f)
+    }
+
+    /// Dispatch an IP packet, which may carry a TCP segment with a header of
+    /// `tcp_header_len` octets that is larger than the MTU if the device
+    /// segments it itself, into segments no larger than the remote MSS. The source and destination `ports` of the packet,
+    /// if any, are used to route it.
+    fn dispatch_ip_offload<Tx, F>(&mut self, tx_token: Tx, timestamp: Instant,
+                                  ip_repr: IpRepr, ports: Option<(u16, u16)>,
+                                  tcp: Option<(usize, Option<usize>)>, f: F) -> Result<()>
+        where Tx: TxToken, F: FnOnce(IpRepr, &mut [u8])
+    {
+        let ip_repr = if ip_repr.src_addr().is_unspecified() {
+            match self.select_src_addr(&ip_repr.dst_addr()) {
+                Some(candidate) => ip_repr.lower(&[candidate.cidr])?,
+                None => return Err(Error::Unaddressable)
+            }
+        } else {
+            // A packet from an address that may not be used yet is dropped, as if it were
+            // lost; sockets retransmit what they have to once the address is usable.
+            #[cfg(feature = "proto-ipv4")]
+            match ip_repr.src_addr() {
+                IpAddress::Ipv4(src_addr) if self.is_ipv4_addr_unusable(src_addr) => {
+                    net_debug!("dropping a packet from {}: the address is not usable", src_addr);
+                    return Ok(())
+                }
+                _ => ()
+            }
+            ip_repr.lower(&[])?
+        };
         let checksum_caps = self.device_capabilities.checksum.clone();
 
+        let flow = Flow {
+            protocol: Some(ip_repr.protocol()),
+            ports:    ports,
+            ..Flow::new(ip_repr.src_addr(), ip_repr.dst_addr())
+        };
         let (dst_hardware_addr, tx_token) =
-            self.lookup_hardware_addr(tx_token, timestamp,
-                                      &ip_repr.src_addr(), &ip_repr.dst_addr())?;
+            self.lookup_hardware_addr(tx_token, timestamp, &flow)?;
 
-        self.dispatch_ethernet(tx_token, timestamp, ip_repr.total_len(), |mut frame| {
+        let offload = self.tx_offload(&ip_repr, tcp);
+        self.dispatch_ethernet(tx_token, timestamp, ip_repr.total_len(), offload, |mut frame| {
             frame.set_dst_addr(dst_hardware_addr);
             match ip_repr {
                 #[cfg(feature = "proto-ipv4")]
@@ -1626,54 +2902,120 @@ impl<'b, 'c, 'e> InterfaceInner<'b, 'c, 'e> {
         })
     }
 
+    fn tx_offload(&self, ip_repr: &IpRepr, tcp: Option<(usize, Option<usize>)>) -> TxOffload {
+        let caps = &self.device_capabilities;
+        let header_len = EthernetFrame::<&[u8]>::header_len() + ip_repr.buffer_len();
+
+        let mut offload = TxOffload::default();
+        match ip_repr.protocol() {
+            // The checksum field is at offset 16 of a TCP header, and 6 of an UDP header.
+            IpProtocol::Tcp if caps.checksum.tcp.partial() => {
+                offload.checksum_start  = Some(header_len);
+                offload.checksum_offset = 16;
+            }
+            IpProtocol::Udp if caps.checksum.udp.partial() => {
+                offload.checksum_start  = Some(header_len);
+                offload.checksum_offset = 6;
+            }
+            _ => ()
+        }
+
+        if let Some((tcp_header_len, remote_mss)) = tcp {
+            let max_ip_len = caps.max_transmission_unit
+                .saturating_sub(EthernetFrame::<&[u8]>::header_len());
+            if ip_repr.total_len() > max_ip_len {
+                let local_mss = max_ip_len.saturating_sub(ip_repr.buffer_len() + tcp_header_len);
+                let segment_size = match remote_mss {
+                    Some(remote_mss) if remote_mss > 0 => cmp::min(local_mss, remote_mss),
+                    _ => local_mss
+                };
+                if segment_size > 0 {
+                    offload.segment_size = Some(segment_size);
+                    offload.header_len   = header_len + tcp_header_len;
+                }
+            }
+        }
+        offload
+    }
+
     #[cfg(feature = "proto-igmp")]
-    fn igmp_report_packet<'any>(&self, version: IgmpVersion, group_addr: Ipv4Address) -> Option<Packet<'any>> {
+    fn igmp_packet<'any>(&self, dst_addr: Ipv4Address, igmp_repr: IgmpRepr<'any>) -> Option<Packet<'any>> {
         let iface_addr = self.ipv4_address()?;
-        let igmp_repr = IgmpRepr::MembershipReport {
-            group_addr,
-            version,
-        };
         let pkt = Packet::Igmp((Ipv4Repr {
-            src_addr:    iface_addr,
-            // Send to the group being reported
-            dst_addr:    group_addr,
-            protocol:    IpProtocol::Igmp,
-            payload_len: igmp_repr.buffer_len(),
-            hop_limit:   1,
-            // TODO: add Router Alert IPv4 header option. See
-            // [#183](https://github.com/m-labs/smoltcp/issues/183).
+            src_addr:      iface_addr,
+            dst_addr:      dst_addr,
+            protocol:      IpProtocol::Igmp,
+            payload_len:   igmp_repr.buffer_len(),
+            hop_limit:     1,
+            traffic_class: 0,
+            // See RFC 2236 § 2 and RFC 3376 § 4.
+            router_alert:  true,
         }, igmp_repr));
         Some(pkt)
     }
 
+    /// Return a report of the current membership of a group, using `records`
+    /// as storage for IGMPv3 group records.
     #[cfg(feature = "proto-igmp")]
-    fn igmp_leave_packet<'any>(&self, group_addr: Ipv4Address) -> Option<Packet<'any>> {
-        self.ipv4_address().map(|iface_addr| {
-            let igmp_repr = IgmpRepr::LeaveGroup { group_addr };
-            let pkt = Packet::Igmp((Ipv4Repr {
-                src_addr:    iface_addr,
-                dst_addr:    Ipv4Address::MULTICAST_ALL_ROUTERS,
-                protocol:    IpProtocol::Igmp,
-                payload_len: igmp_repr.buffer_len(),
-                hop_limit:   1,
-            }, igmp_repr));
-            pkt
-        })
+    fn igmp_report_packet<'any>(&self, version: IgmpVersion, group_addr: Ipv4Address,
+                                membership: &IgmpMembership,
+                                records: &'any mut [u8]) -> Option<Packet<'any>> {
+        if membership.is_none() { return None }
+        match version {
+            IgmpVersion::Version3 => {
+                let (num_records, len) =
+                    igmp::emit_current_state(records, group_addr, membership);
+                self.igmp_packet(Ipv4Address::MULTICAST_ALL_IGMPV3_ROUTERS,
+                                 IgmpRepr::MembershipReportV3 {
+                                     num_records, records: &records[..len]
+                                 })
+            }
+            // Send to the group being reported
+            _ => self.igmp_packet(group_addr, IgmpRepr::MembershipReport { group_addr, version })
+        }
+    }
+
+    /// Return a report of a change of the membership of a group, or `None` if nothing
+    /// is to be reported, using `records` as storage for IGMPv3 group records.
+    #[cfg(feature = "proto-igmp")]
+    fn igmp_state_change_packet<'any>(&self, version: IgmpVersion, group_addr: Ipv4Address,
+                                      old_membership: &IgmpMembership,
+                                      new_membership: &IgmpMembership,
+                                      records: &'any mut [u8]) -> Option<Packet<'any>> {
+        match (version, old_membership.is_none(), new_membership.is_none()) {
+            (IgmpVersion::Version3, _, _) => {
+                let (num_records, len) = igmp::emit_state_change(records, group_addr,
+                                                                 old_membership, new_membership);
+                if num_records == 0 { return None }
+                self.igmp_packet(Ipv4Address::MULTICAST_ALL_IGMPV3_ROUTERS,
+                                 IgmpRepr::MembershipReportV3 {
+                                     num_records, records: &records[..len]
+                                 })
+            }
+            // Older versions only report joining and leaving the group.
+            (_, true, false) =>
+                self.igmp_report_packet(version, group_addr, new_membership, records),
+            // IGMPv1 has no leave message.
+            (IgmpVersion::Version2, false, true) =>
+                self.igmp_packet(Ipv4Address::MULTICAST_ALL_ROUTERS,
+                                 IgmpRepr::LeaveGroup { group_addr }),
+            _ => None
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    #[cfg(feature = "proto-igmp")]
     use std::vec::Vec;
     use std::collections::BTreeMap;
     use {Result, Error};
 
-    use super::InterfaceBuilder;
+    use super::{InterfaceBuilder, Flow};
     use iface::{NeighborCache, EthernetInterface};
     use phy::{self, Loopback, ChecksumCapabilities};
+    use phy::{Device, RxToken};
     #[cfg(feature = "proto-igmp")]
-    use phy::{Device, RxToken, TxToken};
+    use phy::TxToken;
     use time::Instant;
     use socket::SocketSet;
     #[cfg(feature = "proto-ipv4")]
@@ -1687,11 +3029,15 @@ mod test {
     #[cfg(feature = "proto-ipv4")]
     use wire::{Icmpv4Repr, Icmpv4DstUnreachable};
     #[cfg(feature = "proto-igmp")]
-    use wire::{IgmpPacket, IgmpRepr, IgmpVersion};
+    use wire::{IgmpPacket, IgmpRepr, IgmpVersion, IgmpGroupRecord, IgmpRecordType};
+    #[cfg(feature = "proto-igmp")]
+    use iface::{IgmpMembership, IgmpFilterMode};
+    #[cfg(feature = "proto-igmp")]
+    use time::Duration;
     #[cfg(all(feature = "socket-udp", any(feature = "proto-ipv4", feature = "proto-ipv6")))]
     use wire::{UdpPacket, UdpRepr};
     #[cfg(feature = "proto-ipv6")]
-    use wire::{Ipv6Address, Ipv6Repr};
+    use wire::{Ipv6Address, Ipv6Packet, Ipv6Repr};
     #[cfg(feature = "proto-ipv6")]
     use wire::{Icmpv6Packet, Icmpv6Repr, Icmpv6ParamProblem};
     #[cfg(feature = "proto-ipv6")]
@@ -1727,7 +3073,6 @@ mod test {
         (iface, SocketSet::new(vec![]))
     }
 
-    #[cfg(feature = "proto-igmp")]
     fn recv_all<'b>(iface: &mut EthernetInterface<'static, 'b, 'static, Loopback>, timestamp: Instant) -> Vec<Vec<u8>> {
         let mut pkts = Vec::new();
         while let Some((rx, _tx)) = iface.device.receive() {
@@ -1768,19 +3113,22 @@ mod test {
         // response. See RFC 1122 § 3.2.2.
         #[cfg(all(feature = "proto-ipv4", not(feature = "proto-ipv6")))]
         let repr = IpRepr::Ipv4(Ipv4Repr {
-            src_addr:    Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
-            dst_addr:    Ipv4Address::BROADCAST,
-            protocol:    IpProtocol::Unknown(0x0c),
-            payload_len: 0,
-            hop_limit:   0x40
+            src_addr:      Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
+            dst_addr:      Ipv4Address::BROADCAST,
+            protocol:      IpProtocol::Unknown(0x0c),
+            payload_len:   0,
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
         });
         #[cfg(feature = "proto-ipv6")]
         let repr = IpRepr::Ipv6(Ipv6Repr {
-            src_addr:    Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
-            dst_addr:    Ipv6Address::LINK_LOCAL_ALL_NODES,
-            next_header: IpProtocol::Unknown(0x0c),
-            payload_len: 0,
-            hop_limit:   0x40
+            src_addr:      Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            dst_addr:      Ipv6Address::LINK_LOCAL_ALL_NODES,
+            next_header:   IpProtocol::Unknown(0x0c),
+            payload_len:   0,
+            hop_limit:     0x40,
+            traffic_class: 0
         });
 
         let frame = {
@@ -1813,11 +3161,13 @@ mod test {
 
         // Unknown Ipv4 Protocol with no payload
         let repr = IpRepr::Ipv4(Ipv4Repr {
-            src_addr:    Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
-            dst_addr:    Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
-            protocol:    IpProtocol::Unknown(0x0c),
-            payload_len: 0,
-            hop_limit:   0x40
+            src_addr:      Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
+            dst_addr:      Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
+            protocol:      IpProtocol::Unknown(0x0c),
+            payload_len:   0,
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
         });
 
         // emit the above repr to a frame
@@ -1839,7 +3189,9 @@ mod test {
                 dst_addr: Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
                 protocol: IpProtocol::Unknown(12),
                 payload_len: 0,
-                hop_limit: 64
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
             },
             data: &NO_BYTES
         };
@@ -1850,7 +3202,9 @@ mod test {
                 dst_addr: Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
                 protocol: IpProtocol::Icmp,
                 payload_len: icmp_repr.buffer_len(),
-                hop_limit: 64
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
             },
             icmp_repr
         ));
@@ -1883,11 +3237,13 @@ mod test {
         };
 
         let ip_repr = IpRepr::Ipv4(Ipv4Repr {
-            src_addr:    Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
-            dst_addr:    Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
-            protocol:    IpProtocol::Udp,
-            payload_len: udp_repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
+            dst_addr:      Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
+            protocol:      IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
         });
 
         // Emit the representations to a packet
@@ -1905,7 +3261,9 @@ mod test {
                 dst_addr: Ipv4Address([0x7f, 0x00, 0x00, 0x01]),
                 protocol: IpProtocol::Udp,
                 payload_len: udp_repr.buffer_len(),
-                hop_limit: 64
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
             },
             data: &data
         };
@@ -1915,22 +3273,26 @@ mod test {
                 dst_addr: Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
                 protocol: IpProtocol::Icmp,
                 payload_len: icmp_repr.buffer_len(),
-                hop_limit: 64
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
             },
             icmp_repr
         ));
 
         // Ensure that the unknown protocol triggers an error response.
         // And we correctly handle no payload.
-        assert_eq!(iface.inner.process_udp(&mut socket_set, ip_repr, data),
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0), ip_repr, data),
                    Ok(expected_repr));
 
         let ip_repr = IpRepr::Ipv4(Ipv4Repr {
-            src_addr:    Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
-            dst_addr:    Ipv4Address::BROADCAST,
-            protocol:    IpProtocol::Udp,
-            payload_len: udp_repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      Ipv4Address([0x7f, 0x00, 0x00, 0x02]),
+            dst_addr:      Ipv4Address::BROADCAST,
+            protocol:      IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
         });
 
         // Emit the representations to a packet
@@ -1941,7 +3303,7 @@ mod test {
         // Ensure that the port unreachable error does not trigger an
         // ICMP error response when the destination address is a
         // broadcast address and no socket is bound to the port.
-        assert_eq!(iface.inner.process_udp(&mut socket_set, ip_repr,
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0), ip_repr,
                    packet_broadcast.into_inner()), Ok(Packet::None));
     }
 
@@ -1978,19 +3340,22 @@ mod test {
 
         #[cfg(feature = "proto-ipv6")]
         let ip_repr = IpRepr::Ipv6(Ipv6Repr {
-            src_addr:    src_ip,
-            dst_addr:    Ipv6Address::LINK_LOCAL_ALL_NODES,
-            next_header: IpProtocol::Udp,
-            payload_len: udp_repr.buffer_len(),
-            hop_limit:   0x40
+            src_addr:      src_ip,
+            dst_addr:      Ipv6Address::LINK_LOCAL_ALL_NODES,
+            next_header:   IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0
         });
         #[cfg(all(not(feature = "proto-ipv6"), feature = "proto-ipv4"))]
         let ip_repr = IpRepr::Ipv4(Ipv4Repr {
-            src_addr:    src_ip,
-            dst_addr:    Ipv4Address::BROADCAST,
-            protocol:    IpProtocol::Udp,
-            payload_len: udp_repr.buffer_len(),
-            hop_limit:   0x40
+            src_addr:      src_ip,
+            dst_addr:      Ipv4Address::BROADCAST,
+            protocol:      IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
         });
 
         {
@@ -2005,7 +3370,8 @@ mod test {
                       &ChecksumCapabilities::default());
 
         // Packet should be handled by bound UDP socket
-        assert_eq!(iface.inner.process_udp(&mut socket_set, ip_repr, packet.into_inner()),
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr, packet.into_inner()),
                    Ok(Packet::None));
 
         {
@@ -2017,6 +3383,339 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(feature = "socket-udp")]
+    fn test_handle_udp_broadcast_shared() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+        use wire::IpEndpoint;
+
+        static UDP_PAYLOAD: [u8; 5] = [0x48, 0x65, 0x6c, 0x6c, 0x6f];
+
+        let (iface, mut socket_set) = create_loopback();
+
+        let mut socket_handles = vec![];
+        for _ in 0..2 {
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind(68), Ok(()));
+            socket_handles.push(socket_set.add(udp_socket));
+        }
+
+        #[cfg(feature = "proto-ipv6")]
+        let src_ip = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        #[cfg(all(not(feature = "proto-ipv6"), feature = "proto-ipv4"))]
+        let src_ip = Ipv4Address::new(0x7f, 0x00, 0x00, 0x02);
+
+        let udp_repr = UdpRepr {
+            src_port: 67,
+            dst_port: 68,
+            payload:  &UDP_PAYLOAD
+        };
+
+        #[cfg(feature = "proto-ipv6")]
+        let ip_repr = IpRepr::Ipv6(Ipv6Repr {
+            src_addr:      src_ip,
+            dst_addr:      Ipv6Address::LINK_LOCAL_ALL_NODES,
+            next_header:   IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0
+        });
+        #[cfg(all(not(feature = "proto-ipv6"), feature = "proto-ipv4"))]
+        let ip_repr = IpRepr::Ipv4(Ipv4Repr {
+            src_addr:      src_ip,
+            dst_addr:      Ipv4Address::BROADCAST,
+            protocol:      IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
+        });
+
+        let mut udp_bytes = vec![0u8; 13];
+        let mut packet = UdpPacket::new_unchecked(&mut udp_bytes);
+        udp_repr.emit(&mut packet, &ip_repr.src_addr(), &ip_repr.dst_addr(),
+                      &ChecksumCapabilities::default());
+        let packet = packet.into_inner();
+
+        // Every bound socket receives a copy of the datagram
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.clone(), packet),
+                   Ok(Packet::None));
+        for handle in &socket_handles {
+            let mut socket = socket_set.get::<UdpSocket>(*handle);
+            assert_eq!(socket.recv(), Ok((&UDP_PAYLOAD[..], IpEndpoint::new(src_ip.into(), 67))));
+        }
+
+        // A full socket does not keep the datagram from the other sockets
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.clone(), packet),
+                   Ok(Packet::None));
+        {
+            let mut socket = socket_set.get::<UdpSocket>(socket_handles[1]);
+            assert!(socket.recv().is_ok());
+        }
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.clone(), packet),
+                   Ok(Packet::None));
+        {
+            let mut socket = socket_set.get::<UdpSocket>(socket_handles[1]);
+            assert!(socket.recv().is_ok());
+        }
+
+        // But the datagram is dropped if every socket is full
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.clone(), packet),
+                   Ok(Packet::None));
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr, packet),
+                   Err(Error::Exhausted));
+    }
+
+    #[test]
+    #[cfg(all(feature = "socket-udp", feature = "proto-ipv4"))]
+    fn test_udp_broadcast_ingress() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+        use wire::IpEndpoint;
+
+        static UDP_PAYLOAD: [u8; 5] = [0x48, 0x65, 0x6c, 0x6c, 0x6f];
+
+        let (mut iface, mut socket_set) = create_loopback();
+
+        let mut socket_handles = vec![];
+        for _ in 0..2 {
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind(68), Ok(()));
+            socket_handles.push(socket_set.add(udp_socket));
+        }
+
+        let src_addr = Ipv4Address::new(127, 0, 0, 2);
+        let mut process = |socket_set: &mut SocketSet, dst_addr, protocol, payload: &[u8]| {
+            let ipv4_repr = Ipv4Repr {
+                src_addr:      src_addr,
+                dst_addr:      dst_addr,
+                protocol:      protocol,
+                payload_len:   payload.len(),
+                hop_limit:     0x40,
+                traffic_class: 0,
+                router_alert:  false
+            };
+            let mut eth_bytes = vec![0u8; 14 + ipv4_repr.buffer_len() + payload.len()];
+            let mut frame = EthernetFrame::new_unchecked(&mut eth_bytes);
+            frame.set_dst_addr(EthernetAddress::BROADCAST);
+            frame.set_src_addr(EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]));
+            frame.set_ethertype(EthernetProtocol::Ipv4);
+            {
+                let mut packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+                ipv4_repr.emit(&mut packet, &ChecksumCapabilities::default());
+                packet.payload_mut().copy_from_slice(payload);
+            }
+            // Whether the frame was processed without a reply.
+            iface.inner.process_ethernet(socket_set, Instant::from_millis(0),
+                                         frame.into_inner()) == Ok(Packet::None)
+        };
+        let udp_bytes = |dst_addr: Ipv4Address, dst_port| {
+            let udp_repr = UdpRepr { src_port: 67, dst_port, payload: &UDP_PAYLOAD };
+            let mut bytes = vec![0u8; udp_repr.buffer_len()];
+            udp_repr.emit(&mut UdpPacket::new_unchecked(&mut bytes),
+                          &src_addr.into(), &dst_addr.into(), &ChecksumCapabilities::default());
+            bytes
+        };
+
+        // The limited broadcast address, and the broadcast address of 127.0.0.1/8.
+        for &dst_addr in &[Ipv4Address::BROADCAST, Ipv4Address::new(127, 255, 255, 255)] {
+            // Every bound socket receives a copy of a broadcast datagram
+            assert!(process(&mut socket_set, dst_addr, IpProtocol::Udp,
+                            &udp_bytes(dst_addr, 68)));
+            for handle in &socket_handles {
+                let mut socket = socket_set.get::<UdpSocket>(*handle);
+                assert_eq!(socket.recv(),
+                           Ok((&UDP_PAYLOAD[..], IpEndpoint::new(src_addr.into(), 67))));
+            }
+
+            // And no port unreachable message is sent if there is no socket
+            assert!(process(&mut socket_set, dst_addr, IpProtocol::Udp,
+                            &udp_bytes(dst_addr, 69)));
+
+            // Nor is a TCP segment to a broadcast address answered with a reset
+            #[cfg(feature = "socket-tcp")]
+            {
+                use wire::{TcpPacket, TcpRepr, TcpControl, TcpSeqNumber};
+
+                let tcp_repr = TcpRepr {
+                    src_port:     67,
+                    dst_port:     69,
+                    control:      TcpControl::Syn,
+                    seq_number:   TcpSeqNumber(0),
+                    ack_number:   None,
+                    window_len:   256,
+                    window_scale: None,
+                    max_seg_size: None,
+                    sack_permitted: false,
+                    sack_ranges:  [None, None, None],
+                    ece:          false,
+                    cwr:          false,
+                    payload:      &[]
+                };
+                let mut tcp_bytes = vec![0u8; tcp_repr.buffer_len()];
+                tcp_repr.emit(&mut TcpPacket::new_unchecked(&mut tcp_bytes),
+                              &src_addr.into(), &dst_addr.into(),
+                              &ChecksumCapabilities::default());
+                assert!(process(&mut socket_set, dst_addr, IpProtocol::Tcp, &tcp_bytes));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "socket-udp", feature = "proto-ipv4"))]
+    fn test_udp_unicast_policy() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+        use super::UdpUnicastPolicy;
+
+        static UDP_PAYLOAD: [u8; 5] = [0x48, 0x65, 0x6c, 0x6c, 0x6f];
+
+        let (mut iface, mut socket_set) = create_loopback();
+
+        let mut socket_handles = vec![];
+        for &addr in &[Ipv4Address::UNSPECIFIED, Ipv4Address::new(127, 0, 0, 1)] {
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 2], vec![0; 30]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind((addr, 68)), Ok(()));
+            socket_handles.push(socket_set.add(udp_socket));
+        }
+
+        let udp_repr = UdpRepr {
+            src_port: 67,
+            dst_port: 68,
+            payload:  &UDP_PAYLOAD
+        };
+        let ip_repr = IpRepr::Ipv4(Ipv4Repr {
+            src_addr:      Ipv4Address::new(127, 0, 0, 2),
+            dst_addr:      Ipv4Address::new(127, 0, 0, 1),
+            protocol:      IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
+        });
+
+        let mut udp_bytes = vec![0u8; 13];
+        let mut packet = UdpPacket::new_unchecked(&mut udp_bytes);
+        udp_repr.emit(&mut packet, &ip_repr.src_addr(), &ip_repr.dst_addr(),
+                      &ChecksumCapabilities::default());
+        let packet = packet.into_inner();
+
+        let recv_counts = |socket_set: &mut SocketSet| {
+            socket_handles.iter().map(|handle| {
+                let mut socket = socket_set.get::<UdpSocket>(*handle);
+                let mut count = 0;
+                while socket.recv().is_ok() { count += 1 }
+                count
+            }).collect::<Vec<_>>()
+        };
+
+        // Only the first socket receives the datagram by default
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.clone(), packet),
+                   Ok(Packet::None));
+        assert_eq!(recv_counts(&mut socket_set), vec![1, 0]);
+
+        // The socket bound to the destination address is preferred
+        iface.inner.udp_unicast_policy = UdpUnicastPolicy::MostSpecific;
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.clone(), packet),
+                   Ok(Packet::None));
+        assert_eq!(recv_counts(&mut socket_set), vec![0, 1]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "socket-udp", feature = "proto-ipv4"))]
+    fn test_udp_connected() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+        use wire::Icmpv4Packet;
+
+        let local_addr  = Ipv4Address::new(127, 0, 0, 1);
+        let remote_addr = Ipv4Address::new(127, 0, 0, 2);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        let mut socket_handles = vec![];
+        for &connected in &[false, true] {
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind(68), Ok(()));
+            if connected {
+                assert_eq!(udp_socket.connect((remote_addr, 67)), Ok(()));
+            }
+            socket_handles.push(socket_set.add(udp_socket));
+        }
+
+        let udp_repr = UdpRepr {
+            src_port: 67,
+            dst_port: 68,
+            payload:  b"hello"
+        };
+        let ipv4_repr = Ipv4Repr {
+            src_addr:      remote_addr,
+            dst_addr:      local_addr,
+            protocol:      IpProtocol::Udp,
+            payload_len:   udp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
+        };
+        let mut udp_bytes = vec![0u8; udp_repr.buffer_len()];
+        udp_repr.emit(&mut UdpPacket::new_unchecked(&mut udp_bytes),
+                      &remote_addr.into(), &local_addr.into(), &ChecksumCapabilities::default());
+
+        // The connected socket receives datagrams from its remote endpoint, even though
+        // an unconnected socket comes first and the policy is the default one.
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           IpRepr::Ipv4(ipv4_repr), &udp_bytes),
+                   Ok(Packet::None));
+        assert_eq!(socket_set.get::<UdpSocket>(socket_handles[0]).recv(), Err(Error::Exhausted));
+        assert_eq!(socket_set.get::<UdpSocket>(socket_handles[1]).recv(),
+                   Ok((&b"hello"[..], (remote_addr, 67).into())));
+
+        // A port unreachable message about a datagram sent to the remote endpoint
+        // is reported to the connected socket.
+        let mut udp_bytes = vec![0u8; udp_repr.buffer_len()];
+        let udp_repr = UdpRepr { src_port: 68, dst_port: 67, ..udp_repr };
+        udp_repr.emit(&mut UdpPacket::new_unchecked(&mut udp_bytes),
+                      &local_addr.into(), &remote_addr.into(), &ChecksumCapabilities::default());
+        let icmp_repr = Icmpv4Repr::DstUnreachable {
+            reason: Icmpv4DstUnreachable::PortUnreachable,
+            header: Ipv4Repr {
+                src_addr: local_addr,
+                dst_addr: remote_addr,
+                ..ipv4_repr
+            },
+            data:   &udp_bytes
+        };
+        let mut icmp_bytes = vec![0u8; icmp_repr.buffer_len()];
+        icmp_repr.emit(&mut Icmpv4Packet::new_unchecked(&mut icmp_bytes),
+                       &ChecksumCapabilities::default());
+        let ip_repr = IpRepr::Ipv4(Ipv4Repr {
+            src_addr:      remote_addr,
+            dst_addr:      local_addr,
+            protocol:      IpProtocol::Icmp,
+            payload_len:   icmp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0,
+            router_alert:  false
+        });
+        assert_eq!(iface.inner.process_icmpv4(&mut socket_set, Instant::from_millis(0),
+                                              ip_repr, &icmp_bytes),
+                   Ok(Packet::None));
+        assert_eq!(socket_set.get::<UdpSocket>(socket_handles[0]).recv(), Err(Error::Exhausted));
+        assert_eq!(socket_set.get::<UdpSocket>(socket_handles[1]).recv(),
+                   Err(Error::Unreachable));
+    }
+
     #[test]
     #[cfg(feature = "socket-udp")]
     fn test_icmp_reply_size() {
@@ -2059,6 +3758,8 @@ mod test {
             dst_addr: dst_addr,
             protocol: IpProtocol::Udp,
             hop_limit: 64,
+            traffic_class: 0,
+            router_alert: false,
             payload_len: udp_repr.buffer_len()
         };
         #[cfg(feature = "proto-ipv6")]
@@ -2067,6 +3768,7 @@ mod test {
             dst_addr: dst_addr,
             next_header: IpProtocol::Udp,
             hop_limit: 64,
+            traffic_class: 0,
             payload_len: udp_repr.buffer_len()
         };
         let payload = packet.into_inner();
@@ -2084,6 +3786,7 @@ mod test {
             dst_addr: src_addr,
             next_header: IpProtocol::Icmpv6,
             hop_limit: 64,
+            traffic_class: 0,
             payload_len: expected_icmp_repr.buffer_len()
         };
         #[cfg(all(feature = "proto-ipv4", not(feature = "proto-ipv6")))]
@@ -2098,6 +3801,8 @@ mod test {
             dst_addr: src_addr,
             protocol: IpProtocol::Icmp,
             hop_limit: 64,
+            traffic_class: 0,
+            router_alert: false,
             payload_len: expected_icmpv4_repr.buffer_len()
         };
 
@@ -2105,10 +3810,12 @@ mod test {
         assert_eq!(expected_ip_repr.buffer_len() + expected_icmp_repr.buffer_len(), MIN_MTU);
         // The expected packet and the generated packet are equal
         #[cfg(all(feature = "proto-ipv4", not(feature = "proto-ipv6")))]
-        assert_eq!(iface.inner.process_udp(&mut socket_set, ip_repr.into(), payload),
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.into(), payload),
                    Ok(Packet::Icmpv4((expected_ip_repr, expected_icmp_repr))));
         #[cfg(feature = "proto-ipv6")]
-        assert_eq!(iface.inner.process_udp(&mut socket_set, ip_repr.into(), payload),
+        assert_eq!(iface.inner.process_udp(&mut socket_set, Instant::from_millis(0),
+                                           ip_repr.into(), payload),
                    Ok(Packet::Icmpv6((expected_ip_repr, expected_icmp_repr))));
     }
 
@@ -2153,7 +3860,7 @@ mod test {
 
         // Ensure the address of the requestor was entered in the cache
         assert_eq!(iface.inner.lookup_hardware_addr(MockTxToken, Instant::from_secs(0),
-            &IpAddress::Ipv4(local_ip_addr), &IpAddress::Ipv4(remote_ip_addr)),
+            &Flow::new(IpAddress::Ipv4(local_ip_addr), IpAddress::Ipv4(remote_ip_addr))),
             Ok((remote_hw_addr, MockTxToken)));
     }
 
@@ -2178,6 +3885,7 @@ mod test {
             dst_addr: local_ip_addr.solicited_node(),
             next_header: IpProtocol::Icmpv6,
             hop_limit: 0xff,
+            traffic_class: 0,
             payload_len: solicit.buffer_len()
         });
 
@@ -2204,6 +3912,7 @@ mod test {
             dst_addr: remote_ip_addr,
             next_header: IpProtocol::Icmpv6,
             hop_limit: 0xff,
+            traffic_class: 0,
             payload_len: icmpv6_expected.buffer_len()
         };
 
@@ -2213,10 +3922,415 @@ mod test {
 
         // Ensure the address of the requestor was entered in the cache
         assert_eq!(iface.inner.lookup_hardware_addr(MockTxToken, Instant::from_secs(0),
-            &IpAddress::Ipv6(local_ip_addr), &IpAddress::Ipv6(remote_ip_addr)),
+            &Flow::new(IpAddress::Ipv6(local_ip_addr), IpAddress::Ipv6(remote_ip_addr))),
             Ok((remote_hw_addr, MockTxToken)));
     }
 
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_handle_proxy_arp_request() {
+        let (mut iface, mut socket_set) = create_loopback();
+        iface.update_proxy_prefixes(|prefixes| {
+            *prefixes = From::from(vec![IpCidr::new(IpAddress::v4(10, 0, 0, 0), 24)]);
+        });
+
+        let proxied_ip_addr = Ipv4Address([10, 0, 0, 5]);
+        let remote_ip_addr = Ipv4Address([10, 0, 0, 2]);
+        let local_hw_addr = EthernetAddress([0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let remote_hw_addr = EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]);
+
+        let mut process_request = |source_protocol_addr, target_protocol_addr| {
+            let repr = ArpRepr::EthernetIpv4 {
+                operation: ArpOperation::Request,
+                source_hardware_addr: remote_hw_addr,
+                source_protocol_addr: source_protocol_addr,
+                target_hardware_addr: EthernetAddress::default(),
+                target_protocol_addr: target_protocol_addr,
+            };
+
+            let mut eth_bytes = vec![0u8; 42];
+            let mut frame = EthernetFrame::new_unchecked(&mut eth_bytes);
+            frame.set_dst_addr(EthernetAddress::BROADCAST);
+            frame.set_src_addr(remote_hw_addr);
+            frame.set_ethertype(EthernetProtocol::Arp);
+            repr.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+            match iface.inner.process_ethernet(&mut socket_set, Instant::from_millis(0),
+                                               frame.into_inner()) {
+                Ok(Packet::Arp(arp_repr)) => Some(arp_repr),
+                Ok(Packet::None) => None,
+                result => panic!("unexpected result {:?}", result)
+            }
+        };
+
+        // Ensure an ARP Request for a proxied address triggers an ARP Reply
+        assert_eq!(process_request(remote_ip_addr, proxied_ip_addr),
+                   Some(ArpRepr::EthernetIpv4 {
+                       operation: ArpOperation::Reply,
+                       source_hardware_addr: local_hw_addr,
+                       source_protocol_addr: proxied_ip_addr,
+                       target_hardware_addr: remote_hw_addr,
+                       target_protocol_addr: remote_ip_addr
+                   }));
+
+        // Ensure probes and announcements are not answered
+        assert_eq!(process_request(Ipv4Address::UNSPECIFIED, proxied_ip_addr), None);
+        assert_eq!(process_request(remote_ip_addr, remote_ip_addr), None);
+
+        // Ensure addresses outside of the proxied prefixes are not answered
+        assert_eq!(process_request(remote_ip_addr, Ipv4Address([10, 0, 1, 5])), None);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_handle_proxy_ndisc_request() {
+        let (mut iface, mut socket_set) = create_loopback();
+        iface.update_proxy_prefixes(|prefixes| {
+            *prefixes = From::from(vec![
+                IpCidr::new(IpAddress::v6(0xfdbe, 0, 0, 1, 0, 0, 0, 0), 64)
+            ]);
+        });
+
+        let mut eth_bytes = vec![0u8; 86];
+
+        let local_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 0, 0, 0, 0, 1);
+        let proxied_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 1, 0, 0, 0, 5);
+        let remote_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 0, 0, 0, 0, 2);
+        let local_hw_addr = EthernetAddress([0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let remote_hw_addr = EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]);
+
+        let solicit = Icmpv6Repr::Ndisc(NdiscRepr::NeighborSolicit {
+            target_addr: proxied_ip_addr,
+            lladdr: Some(remote_hw_addr),
+        });
+        let ip_repr = IpRepr::Ipv6(Ipv6Repr {
+            src_addr: remote_ip_addr,
+            dst_addr: proxied_ip_addr.solicited_node(),
+            next_header: IpProtocol::Icmpv6,
+            hop_limit: 0xff,
+            traffic_class: 0,
+            payload_len: solicit.buffer_len()
+        });
+
+        let mut frame = EthernetFrame::new_unchecked(&mut eth_bytes);
+        frame.set_dst_addr(EthernetAddress([0x33, 0x33, 0xff, 0x00, 0x00, 0x05]));
+        frame.set_src_addr(remote_hw_addr);
+        frame.set_ethertype(EthernetProtocol::Ipv6);
+        {
+            ip_repr.emit(frame.payload_mut(), &ChecksumCapabilities::default());
+            solicit.emit(&remote_ip_addr.into(), &proxied_ip_addr.solicited_node().into(),
+                         &mut Icmpv6Packet::new_unchecked(
+                            &mut frame.payload_mut()[ip_repr.buffer_len()..]),
+                         &ChecksumCapabilities::default());
+        }
+
+        let icmpv6_expected = Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+            flags: NdiscNeighborFlags::SOLICITED,
+            target_addr: proxied_ip_addr,
+            lladdr: Some(local_hw_addr)
+        });
+
+        let ipv6_expected = Ipv6Repr {
+            src_addr: local_ip_addr,
+            dst_addr: remote_ip_addr,
+            next_header: IpProtocol::Icmpv6,
+            hop_limit: 0xff,
+            traffic_class: 0,
+            payload_len: icmpv6_expected.buffer_len()
+        };
+
+        // Ensure a Neighbor Solicitation for a proxied address triggers
+        // a Neighbor Advertisement without the Override flag
+        assert_eq!(iface.inner.process_ethernet(&mut socket_set, Instant::from_millis(0), frame.into_inner()),
+                   Ok(Packet::Icmpv6((ipv6_expected, icmpv6_expected))));
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_any_ip_ipv6() {
+        use iface::{Route, Routes};
+
+        let local_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 0, 0, 0, 0, 1);
+        let routed_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 1, 0, 0, 0, 5);
+        let remote_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 0, 0, 0, 0, 2);
+        let local_hw_addr = EthernetAddress([0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let remote_hw_addr = EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]);
+
+        let create_iface = |any_ip| {
+            let mut routes = Routes::new(vec![]);
+            routes.add(Route::new_via_router(IpCidr::new(IpAddress::v6(0xfdbe, 0, 0, 1, 0, 0, 0, 0), 64),
+                                             local_ip_addr.into())).unwrap();
+            InterfaceBuilder::new(Loopback::new())
+                .ethernet_addr(local_hw_addr)
+                .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+                .ip_addrs([IpCidr::new(local_ip_addr.into(), 64)])
+                .routes(routes)
+                .any_ip(any_ip)
+                .finalize()
+        };
+        let mut socket_set = SocketSet::new(vec![]);
+
+        let solicit = Icmpv6Repr::Ndisc(NdiscRepr::NeighborSolicit {
+            target_addr: routed_ip_addr,
+            lladdr: Some(remote_hw_addr),
+        });
+        let echo = Icmpv6Repr::EchoRequest { ident: 0x1234, seq_no: 0xabcd, data: &[] };
+
+        let mut process_icmpv6 = |iface: &mut EthernetInterface<Loopback>,
+                                  dst_addr: Ipv6Address, icmp_repr: &Icmpv6Repr| {
+            let ip_repr = IpRepr::Ipv6(Ipv6Repr {
+                src_addr: remote_ip_addr,
+                dst_addr: dst_addr,
+                next_header: IpProtocol::Icmpv6,
+                hop_limit: 0xff,
+                traffic_class: 0,
+                payload_len: icmp_repr.buffer_len()
+            });
+
+            let mut eth_bytes = vec![0u8; 14 + ip_repr.total_len()];
+            let mut frame = EthernetFrame::new_unchecked(&mut eth_bytes);
+            frame.set_dst_addr(EthernetAddress([0x33, 0x33, 0x00, 0x00, 0x00, 0x00]));
+            frame.set_src_addr(remote_hw_addr);
+            frame.set_ethertype(EthernetProtocol::Ipv6);
+            ip_repr.emit(frame.payload_mut(), &ChecksumCapabilities::default());
+            icmp_repr.emit(&remote_ip_addr.into(), &dst_addr.into(),
+                           &mut Icmpv6Packet::new_unchecked(
+                              &mut frame.payload_mut()[ip_repr.buffer_len()..]),
+                           &ChecksumCapabilities::default());
+
+            match iface.inner.process_ethernet(&mut socket_set, Instant::from_millis(0),
+                                               frame.into_inner()) {
+                Ok(Packet::Icmpv6((ipv6_repr, Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+                    target_addr, lladdr, ..
+                })))) => {
+                    assert_eq!(ipv6_repr.src_addr, routed_ip_addr);
+                    assert_eq!(target_addr, routed_ip_addr);
+                    assert_eq!(lladdr, Some(local_hw_addr));
+                    true
+                }
+                Ok(Packet::Icmpv6((ipv6_repr, Icmpv6Repr::EchoReply { .. }))) => {
+                    assert_eq!(ipv6_repr.src_addr, routed_ip_addr);
+                    true
+                }
+                Ok(Packet::None) => false,
+                result => panic!("unexpected result {:?}", result)
+            }
+        };
+
+        // Ensure packets to routed addresses are ignored without AnyIP
+        let mut iface = create_iface(false);
+        assert!(!process_icmpv6(&mut iface, routed_ip_addr.solicited_node(), &solicit));
+        assert!(!process_icmpv6(&mut iface, routed_ip_addr, &echo));
+
+        // Ensure they are accepted, and solicitations for them answered, with AnyIP
+        let mut iface = create_iface(true);
+        assert!(process_icmpv6(&mut iface, routed_ip_addr.solicited_node(), &solicit));
+        assert!(process_icmpv6(&mut iface, routed_ip_addr, &echo));
+
+        // Ensure addresses outside of the routed prefix are still ignored
+        let other_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 2, 0, 0, 0, 5);
+        assert!(!process_icmpv6(&mut iface, other_ip_addr, &echo));
+
+        // Ensure sockets bound to routed addresses receive datagrams, and reply from them
+        #[cfg(feature = "socket-udp")]
+        {
+            use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+            use wire::IpEndpoint;
+
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind((routed_ip_addr, 68)), Ok(()));
+            let handle = socket_set.add(udp_socket);
+
+            let udp_repr = UdpRepr { src_port: 67, dst_port: 68, payload: b"hello" };
+            let ip_repr = IpRepr::Ipv6(Ipv6Repr {
+                src_addr: remote_ip_addr,
+                dst_addr: routed_ip_addr,
+                next_header: IpProtocol::Udp,
+                hop_limit: 64,
+                traffic_class: 0,
+                payload_len: udp_repr.buffer_len()
+            });
+
+            let mut eth_bytes = vec![0u8; 14 + ip_repr.total_len()];
+            let mut frame = EthernetFrame::new_unchecked(&mut eth_bytes);
+            frame.set_dst_addr(local_hw_addr);
+            frame.set_src_addr(remote_hw_addr);
+            frame.set_ethertype(EthernetProtocol::Ipv6);
+            ip_repr.emit(frame.payload_mut(), &ChecksumCapabilities::default());
+            udp_repr.emit(&mut UdpPacket::new_unchecked(
+                              &mut frame.payload_mut()[ip_repr.buffer_len()..]),
+                          &remote_ip_addr.into(), &routed_ip_addr.into(),
+                          &ChecksumCapabilities::default());
+            assert_eq!(iface.inner.process_ethernet(&mut socket_set, Instant::from_millis(0),
+                                                    frame.into_inner()),
+                       Ok(Packet::None));
+
+            let remote_endpoint = IpEndpoint::new(remote_ip_addr.into(), 67);
+            {
+                let mut socket = socket_set.get::<UdpSocket>(handle);
+                assert_eq!(socket.recv(), Ok((&b"hello"[..], remote_endpoint)));
+                assert_eq!(socket.send_slice(b"world", remote_endpoint), Ok(()));
+            }
+            assert_eq!(iface.socket_egress(&mut socket_set, Instant::from_millis(0)), Ok(true));
+
+            let frames = recv_all(&mut iface, Instant::from_millis(0));
+            assert_eq!(frames.len(), 1);
+            let eth_frame = EthernetFrame::new_checked(&frames[0]).unwrap();
+            assert_eq!(eth_frame.dst_addr(), remote_hw_addr);
+            let ipv6_packet = Ipv6Packet::new_checked(eth_frame.payload()).unwrap();
+            assert_eq!(ipv6_packet.src_addr(), routed_ip_addr);
+            assert_eq!(ipv6_packet.dst_addr(), remote_ip_addr);
+            let udp_packet = UdpPacket::new_checked(ipv6_packet.payload()).unwrap();
+            assert_eq!(udp_packet.src_port(), 68);
+            assert_eq!(udp_packet.payload(), b"world");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_neighbor_cache_access() {
+        let (mut iface, _) = create_loopback();
+
+        let local_ip_addr = Ipv4Address([0x7f, 0x00, 0x00, 0x01]);
+        let remote_ip_addr = Ipv4Address([0x7f, 0x00, 0x00, 0x02]);
+        let remote_hw_addr = EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]);
+        let flow = Flow::new(local_ip_addr.into(), remote_ip_addr.into());
+
+        iface.neighbor_cache_mut().fill_permanent(remote_ip_addr.into(), remote_hw_addr).unwrap();
+        iface.neighbor_cache_mut().flush();
+        assert_eq!(iface.neighbor_cache().iter().count(), 1);
+        assert_eq!(iface.inner.lookup_hardware_addr(MockTxToken, Instant::from_secs(3600), &flow),
+                   Ok((remote_hw_addr, MockTxToken)));
+
+        assert!(iface.neighbor_cache_mut().remove(&remote_ip_addr.into()).is_some());
+        assert_eq!(iface.neighbor_cache().iter().count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_icmpv4_redirect() {
+        use iface::Routes;
+        use wire::{Icmpv4Packet, Icmpv4Redirect};
+
+        let local_ip_addr = Ipv4Address([192, 168, 1, 1]);
+        let router_ip_addr = Ipv4Address([192, 168, 1, 254]);
+        let other_router_ip_addr = Ipv4Address([192, 168, 1, 253]);
+        let remote_ip_addr = Ipv4Address([10, 0, 0, 1]);
+        let flow = Flow::new(local_ip_addr.into(), remote_ip_addr.into());
+
+        let mut routes = Routes::new(vec![]);
+        routes.add_default_ipv4_route(router_ip_addr).unwrap();
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs([IpCidr::new(local_ip_addr.into(), 24)])
+            .routes(routes)
+            .finalize();
+        let mut socket_set = SocketSet::new(vec![]);
+
+        let mut process_redirect = |iface: &mut EthernetInterface<Loopback>, src_addr: Ipv4Address| {
+            let data = [0u8; 8];
+            let icmp_repr = Icmpv4Repr::Redirect {
+                reason: Icmpv4Redirect::Host,
+                gateway: other_router_ip_addr,
+                header: Ipv4Repr {
+                    src_addr: local_ip_addr,
+                    dst_addr: remote_ip_addr,
+                    protocol: IpProtocol::Udp,
+                    payload_len: data.len(),
+                    hop_limit: 64,
+                    traffic_class: 0,
+                    router_alert: false
+                },
+                data: &data
+            };
+            let ip_repr = IpRepr::Ipv4(Ipv4Repr {
+                src_addr: src_addr,
+                dst_addr: local_ip_addr,
+                protocol: IpProtocol::Icmp,
+                payload_len: icmp_repr.buffer_len(),
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
+            });
+            let mut bytes = vec![0u8; icmp_repr.buffer_len()];
+            icmp_repr.emit(&mut Icmpv4Packet::new_unchecked(&mut bytes[..]),
+                           &ChecksumCapabilities::default());
+            assert_eq!(iface.inner.process_icmpv4(&mut socket_set, Instant::from_millis(0),
+                                                  ip_repr, &bytes),
+                       Ok(Packet::None));
+            iface.inner.route(&flow, Instant::from_millis(0))
+        };
+
+        // Ensure redirects are ignored when disabled, or when not sent by the first hop
+        iface.inner.accept_redirects = false;
+        assert_eq!(process_redirect(&mut iface, router_ip_addr), Ok(router_ip_addr.into()));
+        iface.inner.accept_redirects = true;
+        assert_eq!(process_redirect(&mut iface, other_router_ip_addr), Ok(router_ip_addr.into()));
+
+        // Ensure a valid redirect installs a host route
+        assert_eq!(process_redirect(&mut iface, router_ip_addr), Ok(other_router_ip_addr.into()));
+        assert_eq!(iface.inner.route(&flow, Instant::from_millis(300_001)),
+                   Ok(router_ip_addr.into()));
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_ndisc_redirect() {
+        use iface::Routes;
+
+        let local_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 0, 0, 0, 0, 1);
+        let router_ip_addr = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let other_router_ip_addr = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+        let other_router_hw_addr = EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x02]);
+        let remote_ip_addr = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let flow = Flow::new(local_ip_addr.into(), remote_ip_addr.into());
+
+        let mut routes = Routes::new(vec![]);
+        routes.add_default_ipv6_route(router_ip_addr).unwrap();
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs([IpCidr::new(local_ip_addr.into(), 64)])
+            .routes(routes)
+            .finalize();
+
+        let redirect = NdiscRepr::Redirect {
+            target_addr: other_router_ip_addr,
+            dest_addr: remote_ip_addr,
+            lladdr: Some(other_router_hw_addr),
+            redirected_hdr: None
+        };
+        let ip_repr = |src_addr, hop_limit| Ipv6Repr {
+            src_addr: src_addr,
+            dst_addr: local_ip_addr,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: Icmpv6Repr::Ndisc(redirect).buffer_len(),
+            hop_limit: hop_limit,
+            traffic_class: 0
+        };
+
+        // Ensure redirects from other hosts or from beyond the link are ignored
+        assert_eq!(iface.inner.process_ndisc(Instant::from_millis(0),
+                                             ip_repr(other_router_ip_addr, 0xff), redirect),
+                   Ok(Packet::None));
+        assert_eq!(iface.inner.process_ndisc(Instant::from_millis(0),
+                                             ip_repr(router_ip_addr, 0x40), redirect),
+                   Ok(Packet::None));
+        assert_eq!(iface.inner.route(&flow, Instant::from_millis(0)),
+                   Ok(router_ip_addr.into()));
+
+        // Ensure a valid redirect installs a host route and fills the neighbor cache
+        assert_eq!(iface.inner.process_ndisc(Instant::from_millis(0),
+                                             ip_repr(router_ip_addr, 0xff), redirect),
+                   Ok(Packet::None));
+        assert_eq!(iface.inner.route(&flow, Instant::from_millis(0)),
+                   Ok(other_router_ip_addr.into()));
+        assert_eq!(iface.inner.lookup_hardware_addr(MockTxToken, Instant::from_millis(0), &flow),
+                   Ok((other_router_hw_addr, MockTxToken)));
+    }
+
     #[test]
     #[cfg(feature = "proto-ipv4")]
     fn test_handle_other_arp_request() {
@@ -2250,18 +4364,251 @@ mod test {
 
         // Ensure the address of the requestor was entered in the cache
         assert_eq!(iface.inner.lookup_hardware_addr(MockTxToken, Instant::from_secs(0),
-            &IpAddress::Ipv4(Ipv4Address([0x7f, 0x00, 0x00, 0x01])),
-            &IpAddress::Ipv4(remote_ip_addr)),
+            &Flow::new(IpAddress::Ipv4(Ipv4Address([0x7f, 0x00, 0x00, 0x01])),
+                       IpAddress::Ipv4(remote_ip_addr))),
             Ok((remote_hw_addr, MockTxToken)));
     }
 
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_ipv4_conflict_detection() {
+        use iface::ConflictState;
+
+        let local_ip_addr = Ipv4Address([192, 168, 1, 1]);
+        let remote_hw_addr = EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]);
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs([IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)])
+            .ipv4_conflict_detection(BTreeMap::new())
+            .finalize();
+        let mut socket_set = SocketSet::new(vec![]);
+
+        iface.update_ip_addrs(|addrs| {
+            *addrs = From::from(vec![IpCidr::new(local_ip_addr.into(), 24)]);
+        });
+        assert_eq!(iface.ipv4_addr_state(local_ip_addr), Some(ConflictState::Probing));
+        assert_eq!(iface.ipv4_addr_state(Ipv4Address([127, 0, 0, 1])), None);
+        assert!(!iface.has_ip_addr(local_ip_addr));
+        assert_eq!(iface.poll_at(&socket_set, Instant::from_millis(0)),
+                   Some(Instant::from_millis(0)));
+
+        // Another host probes for the same address.
+        let repr = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: remote_hw_addr,
+            source_protocol_addr: Ipv4Address::UNSPECIFIED,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: local_ip_addr,
+        };
+        let mut eth_bytes = vec![0u8; 42];
+        let mut frame = EthernetFrame::new_unchecked(&mut eth_bytes);
+        frame.set_dst_addr(EthernetAddress::BROADCAST);
+        frame.set_src_addr(remote_hw_addr);
+        frame.set_ethertype(EthernetProtocol::Arp);
+        repr.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+
+        // The tentative address is not answered for.
+        assert_eq!(iface.inner.process_ethernet(&mut socket_set, Instant::from_millis(0), frame.into_inner()),
+                   Ok(Packet::None));
+        assert_eq!(iface.ipv4_addr_state(local_ip_addr), Some(ConflictState::Conflict));
+        assert!(!iface.has_ip_addr(local_ip_addr));
+        assert_eq!(iface.poll_at(&socket_set, Instant::from_millis(0)), None);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_ipv4_conflict_egress() {
+        let local_ip_addr = Ipv4Address([192, 168, 1, 1]);
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs([IpCidr::new(local_ip_addr.into(), 24)])
+            .ipv4_conflict_detection(BTreeMap::new())
+            .finalize();
+
+        // Scheduling the first probe does not produce a packet.
+        let timestamp = Instant::from_millis(0);
+        assert_eq!(iface.inner.ipv4_conflict_poll_at(), Some(timestamp));
+        assert_eq!(iface.inner.ipv4_conflict_packet(timestamp), None);
+        iface.inner.ipv4_conflict_advance(timestamp);
+
+        // The probe is only consumed once the detector is advanced.
+        let timestamp = iface.inner.ipv4_conflict_poll_at().unwrap();
+        let probe = iface.inner.ipv4_conflict_packet(timestamp);
+        match probe {
+            Some(ArpRepr::EthernetIpv4 { source_protocol_addr, target_protocol_addr, .. }) => {
+                assert_eq!(source_protocol_addr, Ipv4Address::UNSPECIFIED);
+                assert_eq!(target_protocol_addr, local_ip_addr);
+            }
+            _ => panic!("expected a probe, got {:?}", probe)
+        }
+        assert_eq!(iface.inner.ipv4_conflict_packet(timestamp), probe);
+        iface.inner.ipv4_conflict_advance(timestamp);
+        assert!(iface.inner.ipv4_conflict_poll_at().unwrap() > timestamp);
+    }
+
+    #[test]
+    #[cfg(all(feature = "proto-ipv4", feature = "socket-udp"))]
+    fn test_ipv4_conflict_src_addr() {
+        use iface::ConflictState;
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+
+        let local_ip_addr = Ipv4Address([192, 168, 1, 1]);
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs([IpCidr::new(local_ip_addr.into(), 24)])
+            .ipv4_conflict_detection(BTreeMap::new())
+            .finalize();
+        let mut socket_set = SocketSet::new(vec![]);
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+        let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+        assert_eq!(udp_socket.bind((local_ip_addr, 67)), Ok(()));
+        let handle = socket_set.add(udp_socket);
+
+        fn count_ipv4(frames: Vec<Vec<u8>>) -> usize {
+            frames.iter().filter(|frame| {
+                EthernetFrame::new_checked(frame).unwrap().ethertype() == EthernetProtocol::Ipv4
+            }).count()
+        }
+
+        // Nothing is sent from an address that is being probed,
+        let mut timestamp = Instant::from_millis(0);
+        assert_eq!(socket_set.get::<UdpSocket>(handle)
+                       .send_slice(b"hello", (Ipv4Address::BROADCAST, 68).into()), Ok(()));
+        assert_eq!(iface.socket_egress(&mut socket_set, timestamp), Ok(true));
+        assert_eq!(iface.ipv4_addr_state(local_ip_addr), Some(ConflictState::Probing));
+        assert_eq!(count_ipv4(recv_all(&mut iface, timestamp)), 0);
+
+        // until probing succeeds.
+        while let Some(poll_at) = iface.poll_at(&socket_set, timestamp) {
+            timestamp = timestamp.max(poll_at);
+            iface.poll(&mut socket_set, timestamp).unwrap();
+        }
+        assert_eq!(iface.ipv4_addr_state(local_ip_addr), Some(ConflictState::Bound));
+        recv_all(&mut iface, timestamp);
+        assert_eq!(socket_set.get::<UdpSocket>(handle)
+                       .send_slice(b"hello", (Ipv4Address::BROADCAST, 68).into()), Ok(()));
+        assert_eq!(iface.socket_egress(&mut socket_set, timestamp), Ok(true));
+        assert_eq!(count_ipv4(recv_all(&mut iface, timestamp)), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_announce_ipv4() {
+        let local_ip_addr = Ipv4Address([192, 168, 1, 1]);
+        let local_hw_addr = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        iface.update_ip_addrs(|addrs| {
+            *addrs = From::from(vec![
+                IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8),
+                IpCidr::new(local_ip_addr.into(), 24),
+            ]);
+        });
+        iface.set_ethernet_addr(local_hw_addr);
+        assert_eq!(iface.poll_at(&socket_set, Instant::from_millis(0)),
+                   Some(Instant::from_millis(0)));
+        assert_eq!(iface.poll(&mut socket_set, Instant::from_millis(0)), Ok(false));
+        assert_eq!(iface.poll_at(&socket_set, Instant::from_millis(0)), None);
+
+        // Only the non-loopback address is announced, and only once.
+        let frames = recv_all(&mut iface, Instant::from_millis(0));
+        assert_eq!(frames.len(), 1);
+        let eth_frame = EthernetFrame::new_checked(&frames[0]).unwrap();
+        assert_eq!(eth_frame.dst_addr(), EthernetAddress::BROADCAST);
+        assert_eq!(eth_frame.src_addr(), local_hw_addr);
+        let arp_packet = ArpPacket::new_checked(eth_frame.payload()).unwrap();
+        assert_eq!(ArpRepr::parse(&arp_packet), Ok(ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: local_hw_addr,
+            source_protocol_addr: local_ip_addr,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: local_ip_addr,
+        }));
+
+        // Setting the same address does not announce again.
+        iface.set_ethernet_addr(local_hw_addr);
+        assert_eq!(iface.poll_at(&socket_set, Instant::from_millis(0)), None);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_announce_ipv6() {
+        let (mut iface, mut socket_set) = create_loopback();
+        let local_ip_addr = Ipv6Address::new(0xfdbe, 0, 0, 0, 0, 0, 0, 1);
+
+        iface.announce();
+        assert_eq!(iface.poll(&mut socket_set, Instant::from_millis(0)), Ok(false));
+
+        let checksum_caps = iface.device.capabilities().checksum;
+        let frames = recv_all(&mut iface, Instant::from_millis(0));
+        let adverts = frames.iter().filter_map(|frame| {
+            let eth_frame = EthernetFrame::new_checked(frame).ok()?;
+            let ipv6_packet = Ipv6Packet::new_checked(eth_frame.payload()).ok()?;
+            let ipv6_repr = Ipv6Repr::parse(&ipv6_packet).ok()?;
+            let icmp_packet = Icmpv6Packet::new_checked(ipv6_packet.payload()).ok()?;
+            let icmp_repr = Icmpv6Repr::parse(&ipv6_repr.src_addr.into(),
+                                              &ipv6_repr.dst_addr.into(),
+                                              &icmp_packet, &checksum_caps).ok()?;
+            match icmp_repr {
+                Icmpv6Repr::Ndisc(ndisc_repr) => Some((ipv6_repr, ndisc_repr)),
+                _ => None
+            }
+        }).collect::<Vec<_>>();
+
+        // The loopback address is not announced.
+        assert_eq!(adverts.len(), 1);
+        assert_eq!(adverts[0].0.src_addr, local_ip_addr);
+        assert_eq!(adverts[0].0.dst_addr, Ipv6Address::LINK_LOCAL_ALL_NODES);
+        assert_eq!(adverts[0].0.hop_limit, 0xff);
+        assert_eq!(adverts[0].1, NdiscRepr::NeighborAdvert {
+            flags: NdiscNeighborFlags::OVERRIDE,
+            target_addr: local_ip_addr,
+            lladdr: Some(EthernetAddress::default())
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_ipv4_link_local() {
+        use iface::ConflictState;
+
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ipv4_link_local(true)
+            .finalize();
+        let mut socket_set = SocketSet::new(vec![]);
+        assert_eq!(iface.ipv4_link_local_addr(), None);
+        assert_eq!(iface.ipv4_address(), None);
+
+        // Our own probes and announcements come back through the loopback,
+        // and must not be mistaken for conflicts.
+        let mut timestamp = Instant::from_millis(0);
+        while let Some(poll_at) = iface.poll_at(&socket_set, timestamp) {
+            timestamp = timestamp.max(poll_at);
+            iface.poll(&mut socket_set, timestamp).unwrap();
+        }
+
+        let addr = iface.ipv4_link_local_addr().unwrap();
+        assert!(addr.is_link_local());
+        assert_eq!(iface.ipv4_addr_state(addr), Some(ConflictState::Bound));
+        assert_eq!(iface.ipv4_address(), Some(addr));
+        assert!(iface.has_ip_addr(addr));
+        assert!(iface.inner.in_same_network(&IpAddress::v4(169, 254, 42, 42)));
+    }
+
     #[test]
     #[cfg(all(feature = "socket-icmp", feature = "proto-ipv4"))]
     fn test_icmpv4_socket() {
         use socket::{IcmpSocket, IcmpEndpoint, IcmpSocketBuffer, IcmpPacketMetadata};
         use wire::Icmpv4Packet;
 
-        let (iface, mut socket_set) = create_loopback();
+        let (mut iface, mut socket_set) = create_loopback();
 
         let rx_buffer = IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY], vec![0; 24]);
         let tx_buffer = IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY], vec![0; 24]);
@@ -2288,11 +4635,13 @@ mod test {
         let icmp_data = &packet.into_inner()[..];
 
         let ipv4_repr = Ipv4Repr {
-            src_addr:    Ipv4Address::new(0x7f, 0x00, 0x00, 0x02),
-            dst_addr:    Ipv4Address::new(0x7f, 0x00, 0x00, 0x01),
-            protocol:    IpProtocol::Icmp,
-            payload_len: 24,
-            hop_limit:   64
+            src_addr:      Ipv4Address::new(0x7f, 0x00, 0x00, 0x02),
+            dst_addr:      Ipv4Address::new(0x7f, 0x00, 0x00, 0x01),
+            protocol:      IpProtocol::Icmp,
+            payload_len:   24,
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
         };
         let ip_repr = IpRepr::Ipv4(ipv4_repr);
 
@@ -2309,7 +4658,7 @@ mod test {
             dst_addr: ipv4_repr.src_addr,
             ..ipv4_repr
         };
-        assert_eq!(iface.inner.process_icmpv4(&mut socket_set, ip_repr, icmp_data),
+        assert_eq!(iface.inner.process_icmpv4(&mut socket_set, Instant::from_millis(0), ip_repr, icmp_data),
                    Ok(Packet::Icmpv4((ipv4_reply, echo_reply))));
 
         {
@@ -2348,11 +4697,12 @@ mod test {
         let payload = [0x12, 0x34, 0x56, 0x78];
 
         let ipv6_repr = Ipv6Repr {
-            src_addr:    remote_ip_addr,
-            dst_addr:    Ipv6Address::LOOPBACK,
-            next_header: IpProtocol::HopByHop,
-            payload_len: 12,
-            hop_limit:   0x40,
+            src_addr:      remote_ip_addr,
+            dst_addr:      Ipv6Address::LOOPBACK,
+            next_header:   IpProtocol::HopByHop,
+            payload_len:   12,
+            hop_limit:     0x40,
+            traffic_class: 0,
         };
 
         let frame = {
@@ -2390,11 +4740,12 @@ mod test {
         };
 
         let reply_ipv6_repr = Ipv6Repr {
-            src_addr:    Ipv6Address::LOOPBACK,
-            dst_addr:    remote_ip_addr,
-            next_header: IpProtocol::Icmpv6,
-            payload_len: reply_icmp_repr.buffer_len(),
-            hop_limit:   0x40,
+            src_addr:      Ipv6Address::LOOPBACK,
+            dst_addr:      remote_ip_addr,
+            next_header:   IpProtocol::Icmpv6,
+            payload_len:   reply_icmp_repr.buffer_len(),
+            hop_limit:     0x40,
+            traffic_class: 0,
         };
 
         // Ensure the unknown next header causes a ICMPv6 Parameter Problem
@@ -2404,30 +4755,80 @@ mod test {
 
         // Ensure the address of the requestor was entered in the cache
         assert_eq!(iface.inner.lookup_hardware_addr(MockTxToken, Instant::from_secs(0),
-            &IpAddress::Ipv6(Ipv6Address::LOOPBACK),
-            &IpAddress::Ipv6(remote_ip_addr)),
+            &Flow::new(IpAddress::Ipv6(Ipv6Address::LOOPBACK),
+                       IpAddress::Ipv6(remote_ip_addr))),
             Ok((remote_hw_addr, MockTxToken)));
     }
 
-    #[test]
     #[cfg(feature = "proto-igmp")]
-    fn test_handle_igmp() {
-        fn recv_igmp<'b>(mut iface: &mut EthernetInterface<'static, 'b, 'static, Loopback>, timestamp: Instant) -> Vec<(Ipv4Repr, IgmpRepr)> {
-            let checksum_caps = &iface.device.capabilities().checksum;
-            recv_all(&mut iface, timestamp)
-                .iter()
-                .filter_map(|frame| {
-                    let eth_frame = EthernetFrame::new_checked(frame).ok()?;
-                    let ipv4_packet = Ipv4Packet::new_checked(eth_frame.payload()).ok()?;
-                    let ipv4_repr = Ipv4Repr::parse(&ipv4_packet, &checksum_caps).ok()?;
-                    let ip_payload = ipv4_packet.payload();
-                    let igmp_packet = IgmpPacket::new_checked(ip_payload).ok()?;
-                    let igmp_repr = IgmpRepr::parse(&igmp_packet).ok()?;
-                    Some((ipv4_repr, igmp_repr))
-                })
-                .collect::<Vec<_>>()
+    fn recv_igmp<'b>(mut iface: &mut EthernetInterface<'static, 'b, 'static, Loopback>,
+                     timestamp: Instant) -> Vec<(Ipv4Repr, Vec<u8>)> {
+        let checksum_caps = &iface.device.capabilities().checksum;
+        recv_all(&mut iface, timestamp)
+            .iter()
+            .filter_map(|frame| {
+                let eth_frame = EthernetFrame::new_checked(frame).ok()?;
+                let ipv4_packet = Ipv4Packet::new_checked(eth_frame.payload()).ok()?;
+                let ipv4_repr = Ipv4Repr::parse(&ipv4_packet, &checksum_caps).ok()?;
+                if ipv4_repr.protocol != IpProtocol::Igmp {
+                    return None
+                }
+                Some((ipv4_repr, ipv4_packet.payload().to_vec()))
+            })
+            .collect::<Vec<_>>()
+    }
+
+    #[cfg(feature = "proto-igmp")]
+    fn parse_igmp<'a>(payload: &'a [u8]) -> IgmpRepr<'a> {
+        IgmpRepr::parse(&IgmpPacket::new_checked(payload).unwrap()).unwrap()
+    }
+
+    #[cfg(feature = "proto-igmp")]
+    fn parse_igmp_records(payload: &[u8]) -> Vec<(IgmpRecordType, Ipv4Address, Vec<Ipv4Address>)> {
+        match parse_igmp(payload) {
+            IgmpRepr::MembershipReportV3 { num_records, records } => {
+                let mut offset = 0;
+                (0..num_records).map(|_| {
+                    let record = IgmpGroupRecord::new_checked(&records[offset..]).unwrap();
+                    offset += record.total_len();
+                    (record.record_type(), record.group_addr(),
+                     (0..record.num_sources() as usize).map(|i| record.source_addr(i)).collect())
+                }).collect()
+            }
+            repr => panic!("not an IGMPv3 membership report: {}", repr)
+        }
+    }
+
+    #[cfg(feature = "proto-igmp")]
+    fn send_igmp<'b>(iface: &mut EthernetInterface<'static, 'b, 'static, Loopback>,
+                     socket_set: &mut SocketSet, timestamp: Instant,
+                     dst_addr: Ipv4Address, igmp_repr: IgmpRepr) {
+        let ipv4_repr = Ipv4Repr {
+            src_addr:      Ipv4Address::new(192, 168, 1, 1),
+            dst_addr:      dst_addr,
+            protocol:      IpProtocol::Igmp,
+            payload_len:   igmp_repr.buffer_len(),
+            hop_limit:     1,
+            traffic_class: 0,
+            router_alert:  false,
+        };
+        let mut bytes = vec![0u8; 14 + ipv4_repr.buffer_len() + igmp_repr.buffer_len()];
+        {
+            let mut frame = EthernetFrame::new_unchecked(&mut bytes);
+            frame.set_dst_addr(EthernetAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]));
+            frame.set_src_addr(EthernetAddress([0x52, 0x54, 0x00, 0x00, 0x00, 0x00]));
+            frame.set_ethertype(EthernetProtocol::Ipv4);
+            let mut packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+            ipv4_repr.emit(&mut packet, &ChecksumCapabilities::default());
+            igmp_repr.emit(&mut IgmpPacket::new_unchecked(packet.payload_mut())).unwrap();
         }
+        assert_eq!(iface.inner.process_ethernet(socket_set, timestamp, &bytes),
+                   Ok(Packet::None));
+    }
 
+    #[test]
+    #[cfg(feature = "proto-igmp")]
+    fn test_handle_igmp() {
         let groups = [
             Ipv4Address::new(224, 0, 0, 22),
             Ipv4Address::new(224, 0, 0, 56),
@@ -2446,11 +4847,9 @@ mod test {
         assert_eq!(reports.len(), 2);
         for (i, group_addr) in groups.iter().enumerate() {
             assert_eq!(reports[i].0.protocol, IpProtocol::Igmp);
-            assert_eq!(reports[i].0.dst_addr, *group_addr);
-            assert_eq!(reports[i].1, IgmpRepr::MembershipReport {
-                group_addr: *group_addr,
-                version: IgmpVersion::Version2,
-            });
+            assert_eq!(reports[i].0.dst_addr, Ipv4Address::MULTICAST_ALL_IGMPV3_ROUTERS);
+            assert_eq!(parse_igmp_records(&reports[i].1),
+                       vec![(IgmpRecordType::ChangeToExclude, *group_addr, vec![])]);
         }
 
         // General query
@@ -2481,6 +4880,9 @@ mod test {
         // pkts that could be checked.
         iface.socket_ingress(&mut socket_set, timestamp).unwrap();
 
+        // The query is an IGMPv3 query, so IGMPv3 is still used.
+        assert_eq!(iface.igmp_version(timestamp), IgmpVersion::Version3);
+
         // Leave multicast groups
         let timestamp = Instant::now();
         for group in &groups {
@@ -2492,8 +4894,428 @@ mod test {
         assert_eq!(leaves.len(), 2);
         for (i, group_addr) in groups.iter().cloned().enumerate() {
             assert_eq!(leaves[i].0.protocol, IpProtocol::Igmp);
-            assert_eq!(leaves[i].0.dst_addr, Ipv4Address::MULTICAST_ALL_ROUTERS);
-            assert_eq!(leaves[i].1, IgmpRepr::LeaveGroup { group_addr });
+            assert!(leaves[i].0.router_alert);
+            assert_eq!(leaves[i].0.dst_addr, Ipv4Address::MULTICAST_ALL_IGMPV3_ROUTERS);
+            assert_eq!(parse_igmp_records(&leaves[i].1),
+                       vec![(IgmpRecordType::ChangeToInclude, group_addr, vec![])]);
         }
     }
+
+    #[test]
+    #[cfg(feature = "proto-igmp")]
+    fn test_handle_igmp_older_querier() {
+        let group_addr = Ipv4Address::new(224, 0, 0, 56);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        // An IGMPv2 querier is present.
+        send_igmp(&mut iface, &mut socket_set, Instant::from_secs(0),
+                  Ipv4Address::MULTICAST_ALL_SYSTEMS, IgmpRepr::MembershipQuery {
+                      max_resp_time: Duration::from_secs(10),
+                      group_addr:    Ipv4Address::UNSPECIFIED,
+                      version:       IgmpVersion::Version2,
+                  });
+        assert_eq!(iface.igmp_version(Instant::from_secs(0)), IgmpVersion::Version2);
+
+        iface.join_multicast_group(group_addr, Instant::from_secs(1)).unwrap();
+        let reports = recv_igmp(&mut iface, Instant::from_secs(1));
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0.dst_addr, group_addr);
+        assert_eq!(parse_igmp(&reports[0].1), IgmpRepr::MembershipReport {
+            group_addr, version: IgmpVersion::Version2
+        });
+
+        iface.leave_multicast_group(group_addr, Instant::from_secs(2)).unwrap();
+        let leaves = recv_igmp(&mut iface, Instant::from_secs(2));
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].0.dst_addr, Ipv4Address::MULTICAST_ALL_ROUTERS);
+        assert_eq!(parse_igmp(&leaves[0].1), IgmpRepr::LeaveGroup { group_addr });
+
+        // An IGMPv1 querier takes precedence, and IGMPv1 has no leave message.
+        send_igmp(&mut iface, &mut socket_set, Instant::from_secs(3),
+                  Ipv4Address::MULTICAST_ALL_SYSTEMS, IgmpRepr::MembershipQuery {
+                      max_resp_time: Duration::from_secs(0),
+                      group_addr:    Ipv4Address::UNSPECIFIED,
+                      version:       IgmpVersion::Version1,
+                  });
+        assert_eq!(iface.igmp_version(Instant::from_secs(3)), IgmpVersion::Version1);
+        iface.join_multicast_group(group_addr, Instant::from_secs(4)).unwrap();
+        iface.leave_multicast_group(group_addr, Instant::from_secs(4)).unwrap();
+        let reports = recv_igmp(&mut iface, Instant::from_secs(4));
+        assert_eq!(reports.len(), 1);
+        assert_eq!(parse_igmp(&reports[0].1), IgmpRepr::MembershipReport {
+            group_addr, version: IgmpVersion::Version1
+        });
+
+        // Without further queries, the interface falls back to IGMPv3.
+        assert_eq!(iface.igmp_version(Instant::from_secs(262)), IgmpVersion::Version1);
+        assert_eq!(iface.igmp_version(Instant::from_secs(263)), IgmpVersion::Version3);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-igmp")]
+    fn test_igmp_report_timers() {
+        let group_addr = Ipv4Address::new(224, 0, 0, 56);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        // The unsolicited report is retransmitted once within a second.
+        iface.join_multicast_group(group_addr, Instant::from_secs(0)).unwrap();
+        assert_eq!(recv_igmp(&mut iface, Instant::from_secs(0)).len(), 1);
+        let poll_at = iface.poll_at(&socket_set, Instant::from_secs(0)).unwrap();
+        assert!(poll_at <= Instant::from_secs(1));
+        assert_eq!(iface.igmp_egress(poll_at), Ok(true));
+        let reports = recv_igmp(&mut iface, poll_at);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::ChangeToExclude, group_addr, vec![])]);
+        assert_eq!(iface.poll_at(&socket_set, poll_at), None);
+
+        // A general query is answered at a random point within the maximum response time.
+        send_igmp(&mut iface, &mut socket_set, Instant::from_secs(2),
+                  Ipv4Address::MULTICAST_ALL_SYSTEMS, IgmpRepr::MembershipQueryV3 {
+                      max_resp_time:   Duration::from_secs(10),
+                      group_addr:      Ipv4Address::UNSPECIFIED,
+                      suppress_router: false,
+                      robustness:      2,
+                      query_interval:  Duration::from_secs(125),
+                      source_addrs:    &[],
+                  });
+        let poll_at = iface.poll_at(&socket_set, Instant::from_secs(2)).unwrap();
+        assert!(poll_at >= Instant::from_secs(2) && poll_at <= Instant::from_secs(12));
+        assert_eq!(iface.igmp_egress(poll_at - Duration::from_millis(1)), Ok(false));
+        assert_eq!(iface.igmp_egress(poll_at), Ok(true));
+        let reports = recv_igmp(&mut iface, poll_at);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::ModeIsExclude, group_addr, vec![])]);
+        assert_eq!(iface.poll_at(&socket_set, poll_at), None);
+
+        // The left group is kept until the leave report is retransmitted.
+        iface.leave_multicast_group(group_addr, Instant::from_secs(20)).unwrap();
+        assert_eq!(recv_igmp(&mut iface, Instant::from_secs(20)).len(), 1);
+        assert!(!iface.has_multicast_group(group_addr));
+        assert_eq!(iface.inner.ipv4_multicast_groups.len(), 1);
+        assert_eq!(iface.igmp_egress(Instant::from_secs(21)), Ok(true));
+        let reports = recv_igmp(&mut iface, Instant::from_secs(21));
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::ChangeToInclude, group_addr, vec![])]);
+        assert_eq!(iface.inner.ipv4_multicast_groups.len(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-igmp")]
+    fn test_igmp_report_suppression() {
+        let group_addr = Ipv4Address::new(224, 0, 0, 56);
+        let (mut iface, mut socket_set) = create_loopback();
+        iface.join_multicast_group(group_addr, Instant::from_secs(0)).unwrap();
+        iface.igmp_egress(Instant::from_secs(1)).unwrap();
+        recv_igmp(&mut iface, Instant::from_secs(1));
+
+        // A group-specific IGMPv2 query schedules a report,
+        send_igmp(&mut iface, &mut socket_set, Instant::from_secs(2),
+                  group_addr, IgmpRepr::MembershipQuery {
+                      max_resp_time: Duration::from_secs(1),
+                      group_addr,
+                      version:       IgmpVersion::Version2,
+                  });
+        let poll_at = iface.poll_at(&socket_set, Instant::from_secs(2)).unwrap();
+        assert!(poll_at <= Instant::from_secs(3));
+
+        // which is suppressed when another host reports first.
+        send_igmp(&mut iface, &mut socket_set, Instant::from_secs(2),
+                  group_addr, IgmpRepr::MembershipReport {
+                      group_addr,
+                      version: IgmpVersion::Version2,
+                  });
+        assert_eq!(iface.poll_at(&socket_set, Instant::from_secs(2)), None);
+        assert_eq!(iface.igmp_egress(Instant::from_secs(3)), Ok(false));
+    }
+
+    #[test]
+    #[cfg(feature = "proto-igmp")]
+    fn test_igmp_source_specific() {
+        let group_addr = Ipv4Address::new(232, 1, 1, 1);
+        let source_a = Ipv4Address::new(10, 0, 0, 1);
+        let source_b = Ipv4Address::new(10, 0, 0, 2);
+        let timestamp = Instant::from_secs(0);
+        let (mut iface, _socket_set) = create_loopback();
+
+        assert_eq!(iface.join_multicast_source_group(group_addr, source_a, timestamp), Ok(true));
+        assert_eq!(iface.join_multicast_source_group(group_addr, source_b, timestamp), Ok(true));
+        assert_eq!(iface.join_multicast_source_group(group_addr, source_b, timestamp), Ok(false));
+        let reports = recv_igmp(&mut iface, timestamp);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::AllowNewSources, group_addr, vec![source_a])]);
+        assert_eq!(parse_igmp_records(&reports[1].1),
+                   vec![(IgmpRecordType::AllowNewSources, group_addr, vec![source_b])]);
+        assert_eq!(iface.multicast_membership(group_addr),
+                   Some(IgmpMembership::new(IgmpFilterMode::Include,
+                                            &[source_a, source_b]).unwrap()));
+
+        // Only datagrams from the included sources are received, except for IGMP.
+        assert!(iface.inner.has_multicast_source(group_addr, source_a, IpProtocol::Udp));
+        assert!(!iface.inner.has_multicast_source(group_addr, Ipv4Address::new(10, 0, 0, 3),
+                                                  IpProtocol::Udp));
+        assert!(iface.inner.has_multicast_source(group_addr, Ipv4Address::new(10, 0, 0, 3),
+                                                 IpProtocol::Igmp));
+
+        assert_eq!(iface.leave_multicast_source_group(group_addr, source_a, timestamp), Ok(true));
+        assert_eq!(iface.leave_multicast_source_group(group_addr, source_b, timestamp), Ok(true));
+        assert_eq!(iface.leave_multicast_source_group(group_addr, source_b, timestamp), Ok(false));
+        let reports = recv_igmp(&mut iface, timestamp);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::BlockOldSources, group_addr, vec![source_a])]);
+        assert_eq!(parse_igmp_records(&reports[1].1),
+                   vec![(IgmpRecordType::BlockOldSources, group_addr, vec![source_b])]);
+        assert!(!iface.has_multicast_group(group_addr));
+
+        // A group joined for any source cannot be joined for a specific source.
+        iface.join_multicast_group(group_addr, timestamp).unwrap();
+        assert_eq!(iface.join_multicast_source_group(group_addr, source_a, timestamp),
+                   Err(Error::Illegal));
+    }
+
+    #[test]
+    #[cfg(all(feature = "proto-igmp", feature = "socket-udp"))]
+    fn test_socket_multicast() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+
+        let group_addr = Ipv4Address::new(224, 0, 0, 56);
+        let timestamp = Instant::from_secs(0);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        let mut socket_handles = vec![];
+        for _ in 0..2 {
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind(5353), Ok(()));
+            assert_eq!(udp_socket.join_multicast_group(group_addr), Ok(true));
+            socket_handles.push(socket_set.add(udp_socket));
+        }
+
+        // The group is joined once for both sockets.
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(iface.has_multicast_group(group_addr));
+        assert_eq!(recv_igmp(&mut iface, timestamp).len(), 1);
+
+        // Looped back packets are delivered to the other sockets only.
+        {
+            let mut socket = socket_set.get::<UdpSocket>(socket_handles[0]);
+            socket.set_multicast_loopback(true);
+            assert_eq!(socket.set_multicast_src_addr(Ipv4Address::new(192, 168, 1, 1)), Ok(()));
+            assert_eq!(socket.send_slice(b"hello", (group_addr, 5353).into()), Ok(()));
+        }
+        iface.socket_multicast_loopback(&mut socket_set, timestamp);
+        iface.socket_multicast_loopback(&mut socket_set, timestamp);
+        assert_eq!(socket_set.get::<UdpSocket>(socket_handles[0]).recv(),
+                   Err(Error::Exhausted));
+        assert_eq!(socket_set.get::<UdpSocket>(socket_handles[1]).recv(),
+                   Ok((&b"hello"[..], (Ipv4Address::new(192, 168, 1, 1), 5353).into())));
+
+        // The group is left once the last socket leaves it.
+        assert!(socket_set.get::<UdpSocket>(socket_handles[0]).leave_multicast_group(group_addr));
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(iface.has_multicast_group(group_addr));
+        assert!(socket_set.get::<UdpSocket>(socket_handles[1]).leave_multicast_group(group_addr));
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(!iface.has_multicast_group(group_addr));
+        let reports = recv_igmp(&mut iface, timestamp);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0.dst_addr, Ipv4Address::MULTICAST_ALL_IGMPV3_ROUTERS);
+    }
+
+    #[test]
+    #[cfg(all(feature = "proto-igmp", feature = "socket-udp"))]
+    fn test_socket_multicast_interleaved() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+
+        let group_addr = Ipv4Address::new(224, 0, 0, 56);
+        let timestamp = Instant::from_secs(0);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+        let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+        assert_eq!(udp_socket.bind(5353), Ok(()));
+        let handle = socket_set.add(udp_socket);
+
+        // A socket leaving a group the application has joined does not leave it.
+        assert_eq!(iface.join_multicast_group(group_addr, timestamp), Ok(true));
+        assert_eq!(socket_set.get::<UdpSocket>(handle).join_multicast_group(group_addr), Ok(true));
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(socket_set.get::<UdpSocket>(handle).leave_multicast_group(group_addr));
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(iface.has_multicast_group(group_addr));
+        assert_eq!(recv_igmp(&mut iface, timestamp).len(), 1);
+
+        // The application leaving a group a socket has joined does not leave it either,
+        assert_eq!(socket_set.get::<UdpSocket>(handle).join_multicast_group(group_addr), Ok(true));
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert_eq!(iface.leave_multicast_group(group_addr, timestamp), Ok(false));
+        assert!(iface.has_multicast_group(group_addr));
+        assert_eq!(recv_igmp(&mut iface, timestamp).len(), 0);
+
+        // but the socket leaving it then does.
+        assert!(socket_set.get::<UdpSocket>(handle).leave_multicast_group(group_addr));
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(!iface.has_multicast_group(group_addr));
+        let reports = recv_igmp(&mut iface, timestamp);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::ChangeToInclude, group_addr, vec![])]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "proto-igmp", feature = "socket-udp"))]
+    fn test_socket_multicast_removed() {
+        use socket::{UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+
+        let group_addr = Ipv4Address::new(224, 0, 0, 56);
+        let timestamp = Instant::from_secs(0);
+        let (mut iface, mut socket_set) = create_loopback();
+
+        let mut socket_handles = vec![];
+        for _ in 0..2 {
+            let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 15]);
+            let mut udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+            assert_eq!(udp_socket.bind(5353), Ok(()));
+            assert_eq!(udp_socket.join_multicast_group(group_addr), Ok(true));
+            socket_handles.push(socket_set.add(udp_socket));
+        }
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert_eq!(recv_igmp(&mut iface, timestamp).len(), 1);
+
+        // A removed socket releases its group,
+        let socket = socket_set.remove(socket_handles[0]);
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(iface.has_multicast_group(group_addr));
+        assert_eq!(recv_igmp(&mut iface, timestamp).len(), 0);
+
+        // and so does a pruned one, which leaves the group.
+        socket_set.release(socket_handles[1]);
+        socket_set.prune();
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(!iface.has_multicast_group(group_addr));
+        let reports = recv_igmp(&mut iface, timestamp);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(parse_igmp_records(&reports[0].1),
+                   vec![(IgmpRecordType::ChangeToInclude, group_addr, vec![])]);
+
+        // A socket added back joins its group again.
+        socket_set.add(socket);
+        iface.socket_multicast_groups(&mut socket_set, timestamp);
+        assert!(iface.has_multicast_group(group_addr));
+        assert_eq!(recv_igmp(&mut iface, timestamp).len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_select_src_addr_ipv6() {
+        let loopback     = IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1);
+        let link_local   = IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let unique_local = IpAddress::v6(0xfdbe, 0, 0, 0, 0, 0, 0, 1);
+        let global       = IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let other_global = IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 1);
+        let mut iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(loopback, 128), IpCidr::new(link_local, 64),
+                           IpCidr::new(unique_local, 64), IpCidr::new(global, 64)])
+            .finalize();
+
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1)),
+                   Some(loopback));
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2)),
+                   Some(link_local));
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0xfdbe, 0, 0, 0, 0, 0, 0, 2)),
+                   Some(unique_local));
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 2)),
+                   Some(global));
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0xff02, 0, 0, 0, 0, 0, 0, 1)),
+                   Some(link_local));
+
+        // A deprecated address is only used if there is no alternative.
+        iface.update_ip_addrs(|addrs| {
+            *addrs = From::from(vec![IpCidr::new(link_local, 64), IpCidr::new(global, 64),
+                                     IpCidr::new(other_global, 64)]);
+        });
+        iface.update_deprecated_ip_addrs(|addrs| {
+            *addrs = From::from(vec![other_global]);
+        });
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 2)),
+                   Some(global));
+        iface.update_deprecated_ip_addrs(|addrs| {
+            *addrs = From::from(vec![global, other_global]);
+        });
+        assert_eq!(iface.select_src_addr(IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 2)),
+                   Some(other_global));
+
+        // Destinations with a source of matching scope are preferred.
+        iface.update_ip_addrs(|addrs| {
+            *addrs = From::from(vec![IpCidr::new(link_local, 64)]);
+        });
+        let mut dst_addrs = [IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                             IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2)];
+        iface.sort_dst_addrs(&mut dst_addrs);
+        assert_eq!(dst_addrs, [IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2),
+                               IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)]);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_select_src_addr_ipv4() {
+        let iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8),
+                           IpCidr::new(IpAddress::v4(10, 0, 0, 1), 8),
+                           IpCidr::new(IpAddress::v4(192, 168, 1, 1), 24)])
+            .finalize();
+
+        assert_eq!(iface.select_src_addr(IpAddress::v4(127, 0, 0, 2)),
+                   Some(IpAddress::v4(127, 0, 0, 1)));
+        assert_eq!(iface.select_src_addr(IpAddress::v4(192, 168, 1, 2)),
+                   Some(IpAddress::v4(192, 168, 1, 1)));
+        assert_eq!(iface.select_src_addr(IpAddress::v4(10, 1, 2, 3)),
+                   Some(IpAddress::v4(10, 0, 0, 1)));
+
+        // Addresses that are being probed are not used.
+        let iface = InterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(IpAddress::v4(10, 0, 0, 1), 8)])
+            .ipv4_conflict_detection(BTreeMap::new())
+            .finalize();
+        assert_eq!(iface.select_src_addr(IpAddress::v4(10, 1, 2, 3)), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "proto-ipv4", feature = "socket-tcp"))]
+    fn test_tx_offload_remote_mss() {
+        let (mut iface, _) = create_loopback();
+        iface.inner.device_capabilities.max_transmission_unit = 1514;
+        iface.inner.device_capabilities.max_segmentation_size = Some(65535);
+
+        let ip_repr = IpRepr::Ipv4(Ipv4Repr {
+            src_addr:      Ipv4Address([127, 0, 0, 1]),
+            dst_addr:      Ipv4Address([127, 0, 0, 2]),
+            protocol:      IpProtocol::Tcp,
+            payload_len:   20 + 4000,
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
+        });
+        // The segments are cut at the MTU...
+        let offload = iface.inner.tx_offload(&ip_repr, Some((20, None)));
+        assert_eq!(offload.segment_size, Some(1460));
+        assert_eq!(offload.header_len, 14 + 20 + 20);
+        // ... or at the MSS of the remote end, if that is smaller.
+        let offload = iface.inner.tx_offload(&ip_repr, Some((20, Some(1000))));
+        assert_eq!(offload.segment_size, Some(1000));
+        let offload = iface.inner.tx_offload(&ip_repr, Some((20, Some(9000))));
+        assert_eq!(offload.segment_size, Some(1460));
+    }
 }
@@ -0,0 +1,436 @@
+// Heads up! Before working on this file you should read, at least,
+// the parts of RFC 3376 that discuss the host side of IGMPv3.
+
+use {Error, Result};
+use wire::{Ipv4Address, IgmpGroupRecord, IgmpRecordType, IgmpVersion};
+use time::{Duration, Instant};
+
+/// The maximum number of sources in the source list of a multicast group membership.
+pub(crate) const MAX_SOURCES: usize = 8;
+
+/// The maximum length of the group records describing a multicast group membership,
+/// or a change of it.
+pub(crate) const MAX_RECORDS_LEN: usize = 2 * (8 + 4 * MAX_SOURCES);
+
+/// The default Robustness Variable, i.e. how many times an unsolicited report
+/// is sent, see RFC 3376 § 8.1.
+const ROBUSTNESS: u8 = 2;
+
+/// The Max Response Time of IGMPv1 queries, which do not specify one,
+/// see RFC 2236 § 4.
+pub(crate) const V1_MAX_RESP_TIME: Duration = Duration { millis: 10_000 };
+
+/// Return the Unsolicited Report Interval for the given IGMP version,
+/// see RFC 2236 § 8.10 and RFC 3376 § 8.11.
+pub(crate) fn unsolicited_report_interval(version: IgmpVersion) -> Duration {
+    match version {
+        IgmpVersion::Version3 => Duration { millis: 1_000 },
+        _ => Duration { millis: 10_000 },
+    }
+}
+
+/// The filter mode of a multicast group membership.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Only datagrams from the sources in the source list are received.
+    Include,
+    /// Datagrams from any source except for the ones in the source list are received.
+    Exclude,
+}
+
+/// A multicast group membership of an interface.
+///
+/// A membership filters the sources whose datagrams sent to the group are received,
+/// as described in RFC 3376 § 3.2. Not being a member of a group is equivalent to
+/// a membership in INCLUDE mode with an empty source list.
+///
+/// The source list holds up to eight addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct Membership {
+    mode:        FilterMode,
+    sources:     [Ipv4Address; MAX_SOURCES],
+    num_sources: usize,
+}
+
+impl Membership {
+    /// Returns a membership receiving datagrams from any source, i.e. EXCLUDE {}.
+    pub fn any_source() -> Membership {
+        Membership {
+            mode:        FilterMode::Exclude,
+            sources:     [Ipv4Address::UNSPECIFIED; MAX_SOURCES],
+            num_sources: 0,
+        }
+    }
+
+    /// Returns a membership not receiving any datagrams, i.e. INCLUDE {}.
+    pub fn none() -> Membership {
+        Membership { mode: FilterMode::Include, ..Membership::any_source() }
+    }
+
+    /// Returns a membership with the given filter mode and source list.
+    ///
+    /// Returns `Err(Error::Exhausted)` if there are more than eight distinct sources.
+    pub fn new(mode: FilterMode, sources: &[Ipv4Address]) -> Result<Membership> {
+        let mut membership = Membership { mode, ..Membership::any_source() };
+        for source in sources {
+            membership.add_source(*source)?;
+        }
+        Ok(membership)
+    }
+
+    /// Return the filter mode.
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    /// Return the source list.
+    pub fn sources(&self) -> &[Ipv4Address] {
+        &self.sources[..self.num_sources]
+    }
+
+    /// Query whether the source list contains the given address.
+    pub fn has_source(&self, addr: Ipv4Address) -> bool {
+        self.sources().contains(&addr)
+    }
+
+    /// Query whether datagrams from the given source are received.
+    pub fn accepts(&self, src_addr: Ipv4Address) -> bool {
+        match self.mode {
+            FilterMode::Include => self.has_source(src_addr),
+            FilterMode::Exclude => !self.has_source(src_addr),
+        }
+    }
+
+    /// Query whether the membership does not receive any datagrams, i.e. is INCLUDE {}.
+    pub fn is_none(&self) -> bool {
+        self.mode == FilterMode::Include && self.num_sources == 0
+    }
+
+    /// Add a source to the source list.
+    ///
+    /// Returns `Ok(false)` if the source list already contains the address,
+    /// and `Err(Error::Exhausted)` if the source list is full.
+    pub fn add_source(&mut self, addr: Ipv4Address) -> Result<bool> {
+        if self.has_source(addr) {
+            return Ok(false)
+        }
+        if self.num_sources == MAX_SOURCES {
+            return Err(Error::Exhausted)
+        }
+        self.sources[self.num_sources] = addr;
+        self.num_sources += 1;
+        Ok(true)
+    }
+
+    /// Remove a source from the source list.
+    ///
+    /// Returns `false` if the source list did not contain the address.
+    pub fn remove_source(&mut self, addr: Ipv4Address) -> bool {
+        match self.sources().iter().position(|source| *source == addr) {
+            Some(index) => {
+                self.num_sources -= 1;
+                self.sources[index] = self.sources[self.num_sources];
+                self.sources[self.num_sources] = Ipv4Address::UNSPECIFIED;
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl PartialEq for Membership {
+    fn eq(&self, other: &Membership) -> bool {
+        self.mode == other.mode && self.num_sources == other.num_sources &&
+            self.sources().iter().all(|source| other.has_source(*source))
+    }
+}
+
+impl Eq for Membership {}
+
+/// The state of a multicast group of an interface.
+///
+/// This is the value type of the multicast group storage of an interface. It holds
+/// the membership of the group, and the timers of the membership reports pending
+/// for it. A group whose membership was just dropped stays in the storage until
+/// its leave report has been retransmitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Group {
+    pub(crate) membership:   Membership,
+    /// When to report the current membership, in response to a query
+    pub(crate) report_at:    Option<Instant>,
+    /// The membership the last state change report is relative to
+    pub(crate) changed_from: Membership,
+    /// When to retransmit the last state change report
+    pub(crate) retransmit_at: Option<Instant>,
+    /// How many retransmissions of the last state change report are left
+    pub(crate) retransmissions_left: u8,
+    /// Whether the group was joined through the interface, rather than only by sockets
+    pub(crate) app_joined:  bool,
+    /// How many sockets have joined the group
+    pub(crate) socket_refs: u16,
+}
+
+impl Group {
+    pub(crate) fn new() -> Group {
+        Group {
+            membership:    Membership::none(),
+            report_at:     None,
+            changed_from:  Membership::none(),
+            retransmit_at: None,
+            retransmissions_left: 0,
+            app_joined:    false,
+            socket_refs:   0,
+        }
+    }
+
+    /// Return the membership, or `None` if the group is not subscribed to.
+    pub fn membership(&self) -> Option<&Membership> {
+        if self.membership.is_none() {
+            None
+        } else {
+            Some(&self.membership)
+        }
+    }
+
+    /// Change the membership, and schedule the retransmissions of the report
+    /// of the state change, the first one at `retransmit_at`.
+    ///
+    /// Unlike described in RFC 3376 § 5.1, the pending retransmissions of the report
+    /// of a previous change are not merged, but dropped.
+    pub(crate) fn change(&mut self, membership: Membership, retransmit_at: Instant) {
+        self.changed_from = self.membership;
+        self.membership = membership;
+        self.retransmit_at = Some(retransmit_at);
+        self.retransmissions_left = ROBUSTNESS - 1;
+        if membership.is_none() {
+            self.report_at = None;
+        }
+    }
+
+    /// Schedule a report of the current membership in response to a query with
+    /// the given Max Response Time, at `report_at` which is randomly picked from it.
+    ///
+    /// A report that is already scheduled is only rescheduled if it is due later
+    /// than the query allows, see RFC 2236 § 3.
+    pub(crate) fn schedule_report(&mut self, timestamp: Instant, max_resp_time: Duration,
+                                  report_at: Instant) {
+        match self.report_at {
+            Some(at) if at <= timestamp + max_resp_time => (),
+            _ => self.report_at = Some(report_at)
+        }
+    }
+
+    /// Return the earliest instant a report is to be sent at.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        self.report_at.into_iter().chain(self.retransmit_at).min()
+    }
+}
+
+/// Emit a group record with the sources of `lhs` that are not in `rhs`, unless
+/// it would be empty and `omit_empty` is set.
+///
+/// Returns the length of the record.
+fn emit_record(buffer: &mut [u8], record_type: IgmpRecordType, group_addr: Ipv4Address,
+               lhs: &Membership, rhs: Option<&Membership>, omit_empty: bool) -> usize {
+    let mut record = IgmpGroupRecord::new_unchecked(buffer);
+    let mut num_sources = 0;
+    for source in lhs.sources() {
+        if rhs.map(|rhs| rhs.has_source(*source)).unwrap_or(false) {
+            continue
+        }
+        record.set_source_addr(num_sources, *source);
+        num_sources += 1;
+    }
+    if num_sources == 0 && omit_empty {
+        return 0
+    }
+
+    record.set_record_type(record_type);
+    record.set_aux_data_len(0);
+    record.set_num_sources(num_sources as u16);
+    record.set_group_addr(group_addr);
+    record.total_len()
+}
+
+/// Emit the group record describing the current state of a membership into `buffer`,
+/// see RFC 3376 § 5.2.
+///
+/// Returns the number of records, and their length.
+pub(crate) fn emit_current_state(buffer: &mut [u8], group_addr: Ipv4Address,
+                                 membership: &Membership) -> (u16, usize) {
+    let record_type = match membership.mode {
+        FilterMode::Include => IgmpRecordType::ModeIsInclude,
+        FilterMode::Exclude => IgmpRecordType::ModeIsExclude,
+    };
+    (1, emit_record(buffer, record_type, group_addr, membership, None, false))
+}
+
+/// Emit the group records describing the change of a membership from `old` to `new`
+/// into `buffer`, see RFC 3376 § 5.1.
+///
+/// Returns the number of records, and their length.
+pub(crate) fn emit_state_change(buffer: &mut [u8], group_addr: Ipv4Address,
+                                old: &Membership, new: &Membership) -> (u16, usize) {
+    match (old.mode, new.mode) {
+        (FilterMode::Include, FilterMode::Exclude) =>
+            (1, emit_record(buffer, IgmpRecordType::ChangeToExclude, group_addr,
+                            new, None, false)),
+        (FilterMode::Exclude, FilterMode::Include) =>
+            (1, emit_record(buffer, IgmpRecordType::ChangeToInclude, group_addr,
+                            new, None, false)),
+        (mode, _) => {
+            // In INCLUDE mode, the new sources are allowed and the old ones blocked;
+            // in EXCLUDE mode, it is the other way around.
+            let (allowed, blocked) = match mode {
+                FilterMode::Include => ((new, old), (old, new)),
+                FilterMode::Exclude => ((old, new), (new, old)),
+            };
+            let mut num_records = 0;
+            let len = emit_record(buffer, IgmpRecordType::AllowNewSources, group_addr,
+                                  allowed.0, Some(allowed.1), true);
+            if len > 0 { num_records += 1 }
+            let block_len = emit_record(&mut buffer[len..], IgmpRecordType::BlockOldSources,
+                                        group_addr, blocked.0, Some(blocked.1), true);
+            if block_len > 0 { num_records += 1 }
+            (num_records, len + block_len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+    use super::*;
+
+    const GROUP: Ipv4Address = Ipv4Address([232, 1, 1, 1]);
+    const SOURCE_A: Ipv4Address = Ipv4Address([10, 0, 0, 1]);
+    const SOURCE_B: Ipv4Address = Ipv4Address([10, 0, 0, 2]);
+
+    fn records(buffer: &[u8], num_records: u16) -> Vec<(IgmpRecordType, Vec<Ipv4Address>)> {
+        let mut offset = 0;
+        let mut records = Vec::new();
+        for _ in 0..num_records {
+            let record = IgmpGroupRecord::new_checked(&buffer[offset..]).unwrap();
+            assert_eq!(record.group_addr(), GROUP);
+            let sources = (0..record.num_sources() as usize)
+                .map(|index| record.source_addr(index))
+                .collect();
+            records.push((record.record_type(), sources));
+            offset += record.total_len();
+        }
+        assert_eq!(offset, buffer.len());
+        records
+    }
+
+    #[test]
+    fn test_membership() {
+        let mut membership = Membership::new(FilterMode::Include, &[SOURCE_A, SOURCE_A]).unwrap();
+        assert_eq!(membership.sources(), &[SOURCE_A]);
+        assert!(membership.accepts(SOURCE_A));
+        assert!(!membership.accepts(SOURCE_B));
+        assert_eq!(membership.add_source(SOURCE_B), Ok(true));
+        assert_eq!(membership, Membership::new(FilterMode::Include, &[SOURCE_B, SOURCE_A]).unwrap());
+        assert!(membership.remove_source(SOURCE_A));
+        assert!(membership.remove_source(SOURCE_B));
+        assert!(!membership.remove_source(SOURCE_B));
+        assert!(membership.is_none());
+        assert_eq!(membership, Membership::none());
+
+        let any_source = Membership::any_source();
+        assert!(any_source.accepts(SOURCE_A));
+        assert!(!any_source.is_none());
+
+        let mut membership = Membership::none();
+        for index in 0..MAX_SOURCES {
+            assert_eq!(membership.add_source(Ipv4Address([10, 0, 1, index as u8])), Ok(true));
+        }
+        assert_eq!(membership.add_source(SOURCE_A), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn test_group() {
+        let mut group = Group::new();
+        assert_eq!(group.membership(), None);
+        assert_eq!(group.poll_at(), None);
+
+        group.change(Membership::any_source(), Instant::from_millis(500));
+        assert_eq!(group.membership(), Some(&Membership::any_source()));
+        assert_eq!(group.changed_from, Membership::none());
+        assert_eq!(group.poll_at(), Some(Instant::from_millis(500)));
+
+        // A change while the previous one is being retransmitted replaces it.
+        let exclude_a = Membership::new(FilterMode::Exclude, &[SOURCE_A]).unwrap();
+        group.change(exclude_a, Instant::from_millis(700));
+        assert_eq!(group.changed_from, Membership::any_source());
+        assert_eq!(group.poll_at(), Some(Instant::from_millis(700)));
+
+        group.schedule_report(Instant::from_millis(0), Duration::from_millis(1000),
+                              Instant::from_millis(600));
+        assert_eq!(group.poll_at(), Some(Instant::from_millis(600)));
+        // A query with a longer Max Response Time does not delay the report,
+        group.schedule_report(Instant::from_millis(100), Duration::from_millis(10_000),
+                              Instant::from_millis(5000));
+        assert_eq!(group.report_at, Some(Instant::from_millis(600)));
+        // but one with a shorter Max Response Time advances it.
+        group.schedule_report(Instant::from_millis(100), Duration::from_millis(100),
+                              Instant::from_millis(150));
+        assert_eq!(group.report_at, Some(Instant::from_millis(150)));
+
+        group.change(Membership::none(), Instant::from_millis(200));
+        assert_eq!(group.changed_from, exclude_a);
+        assert_eq!(group.membership(), None);
+        assert_eq!(group.report_at, None);
+        assert_eq!(group.poll_at(), Some(Instant::from_millis(200)));
+    }
+
+    #[test]
+    fn test_current_state() {
+        let mut buffer = [0; MAX_RECORDS_LEN];
+        let membership = Membership::new(FilterMode::Include, &[SOURCE_A]).unwrap();
+        let (num_records, len) = emit_current_state(&mut buffer, GROUP, &membership);
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::ModeIsInclude, vec![SOURCE_A])]);
+
+        let (num_records, len) = emit_current_state(&mut buffer, GROUP, &Membership::any_source());
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::ModeIsExclude, vec![])]);
+    }
+
+    #[test]
+    fn test_state_change() {
+        let mut buffer = [0; MAX_RECORDS_LEN];
+        let include_a = Membership::new(FilterMode::Include, &[SOURCE_A]).unwrap();
+        let include_b = Membership::new(FilterMode::Include, &[SOURCE_B]).unwrap();
+        let exclude_a = Membership::new(FilterMode::Exclude, &[SOURCE_A]).unwrap();
+
+        let (num_records, len) =
+            emit_state_change(&mut buffer, GROUP, &Membership::none(), &Membership::any_source());
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::ChangeToExclude, vec![])]);
+
+        let (num_records, len) =
+            emit_state_change(&mut buffer, GROUP, &exclude_a, &Membership::none());
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::ChangeToInclude, vec![])]);
+
+        let (num_records, len) =
+            emit_state_change(&mut buffer, GROUP, &Membership::none(), &include_a);
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::AllowNewSources, vec![SOURCE_A])]);
+
+        let (num_records, len) =
+            emit_state_change(&mut buffer, GROUP, &include_a, &include_b);
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::AllowNewSources, vec![SOURCE_B]),
+                        (IgmpRecordType::BlockOldSources, vec![SOURCE_A])]);
+
+        let (num_records, len) =
+            emit_state_change(&mut buffer, GROUP, &Membership::any_source(), &exclude_a);
+        assert_eq!(records(&buffer[..len], num_records),
+                   vec![(IgmpRecordType::BlockOldSources, vec![SOURCE_A])]);
+
+        let (num_records, len) =
+            emit_state_change(&mut buffer, GROUP, &include_a, &include_a);
+        assert_eq!((num_records, len), (0, 0));
+    }
+}
@@ -6,11 +6,24 @@ provides lookup and caching of hardware addresses, and handles management packet
 
 mod neighbor;
 mod route;
+mod select;
+#[cfg(feature = "proto-ipv4")]
+mod conflict;
+#[cfg(feature = "proto-igmp")]
+mod igmp;
 mod ethernet;
 
 pub use self::neighbor::Neighbor as Neighbor;
+pub use self::neighbor::State as NeighborState;
 pub(crate) use self::neighbor::Answer as NeighborAnswer;
 pub use self::neighbor::Cache as NeighborCache;
-pub use self::route::{Route, Routes};
+pub use self::route::{Route, Routes, Iter as RoutesIter};
+#[cfg(feature = "proto-ipv4")]
+pub use self::conflict::{Detector as ConflictDetector, State as ConflictState};
+#[cfg(feature = "proto-igmp")]
+pub use self::igmp::{Group as IgmpGroup, Membership as IgmpMembership,
+                     FilterMode as IgmpFilterMode};
 pub use self::ethernet::{Interface as EthernetInterface,
                          InterfaceBuilder as EthernetInterfaceBuilder};
+#[cfg(feature = "socket-udp")]
+pub use self::ethernet::UdpUnicastPolicy;
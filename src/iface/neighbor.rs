@@ -1,14 +1,25 @@
 // Heads up! Before working on this file you should read, at least,
 // the parts of RFC 1122 that discuss ARP.
 
-use managed::ManagedMap;
+use managed::{ManagedMap, ManagedMapIter};
 
+use {Error, Result};
 use wire::{EthernetAddress, IpAddress};
 use time::{Duration, Instant};
 
 #[cfg(any(feature = "std", feature = "alloc"))]
 use core::mem;
 
+/// The state of a cached neighbor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The mapping was learned from the network, and is discarded past the given timestamp.
+    Dynamic { expires_at: Instant },
+    /// The mapping was configured explicitly, and is never discarded or replaced
+    /// by a mapping learned from the network.
+    Permanent,
+}
+
 /// A cached neighbor.
 ///
 /// A neighbor mapping translates from a protocol address to a hardware address,
@@ -16,7 +27,40 @@ use core::mem;
 #[derive(Debug, Clone, Copy)]
 pub struct Neighbor {
     hardware_addr: EthernetAddress,
-    expires_at:    Instant,
+    state:         State,
+}
+
+impl Neighbor {
+    /// Return the hardware address of the neighbor.
+    pub fn hardware_addr(&self) -> EthernetAddress {
+        self.hardware_addr
+    }
+
+    /// Return the state of the mapping.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Return the timestamp past which the mapping is discarded,
+    /// or `None` if the mapping is permanent.
+    pub fn expires_at(&self) -> Option<Instant> {
+        match self.state {
+            State::Dynamic { expires_at } => Some(expires_at),
+            State::Permanent => None
+        }
+    }
+
+    /// Query whether the mapping is permanent.
+    pub fn is_permanent(&self) -> bool {
+        self.state == State::Permanent
+    }
+
+    fn is_valid(&self, timestamp: Instant) -> bool {
+        match self.state {
+            State::Dynamic { expires_at } => timestamp < expires_at,
+            State::Permanent => true
+        }
+    }
 }
 
 /// An answer to a neighbor cache lookup.
@@ -86,11 +130,28 @@ impl<'a> Cache<'a> {
         Cache { storage, gc_threshold, silent_until: Instant::from_millis(0) }
     }
 
+    /// Insert a mapping learned from the network, which expires after
+    /// a fixed time.
+    ///
+    /// A permanent mapping for the same protocol address is never replaced.
+    /// If a fixed-size cache storage is full, the mapping closest to expiry
+    /// is evicted; permanent mappings are never evicted.
     pub fn fill(&mut self, protocol_addr: IpAddress, hardware_addr: EthernetAddress,
                 timestamp: Instant) {
         debug_assert!(protocol_addr.is_unicast());
         debug_assert!(hardware_addr.is_unicast());
 
+        match self.storage.get(&protocol_addr) {
+            Some(neighbor) if neighbor.is_permanent() => {
+                if neighbor.hardware_addr != hardware_addr {
+                    net_trace!("not replacing permanent {} => {} (with {})",
+                               protocol_addr, neighbor.hardware_addr, hardware_addr);
+                }
+                return
+            }
+            _ => ()
+        }
+
         #[cfg(any(feature = "std", feature = "alloc"))]
         let current_storage_size = self.storage.len();
 
@@ -101,7 +162,7 @@ impl<'a> Cache<'a> {
                 if current_storage_size >= self.gc_threshold {
                     let new_btree_map = map.into_iter()
                         .map(|(key, value)| (*key, *value))
-                        .filter(|(_, v)| timestamp < v.expires_at)
+                        .filter(|(_, v)| v.is_valid(timestamp))
                         .collect();
 
                     mem::replace(map, new_btree_map);
@@ -109,38 +170,104 @@ impl<'a> Cache<'a> {
             }
         };
         let neighbor = Neighbor {
-            expires_at: timestamp + Self::ENTRY_LIFETIME, hardware_addr
+            hardware_addr,
+            state: State::Dynamic { expires_at: timestamp + Self::ENTRY_LIFETIME }
         };
+        let _ = self.insert(protocol_addr, neighbor);
+    }
+
+    /// Insert a permanent mapping, which never expires and is never replaced
+    /// by a mapping learned from the network.
+    ///
+    /// # Errors
+    /// Returns `Err(Error::Exhausted)` if a fixed-size cache storage is full
+    /// of permanent mappings.
+    pub fn fill_permanent(&mut self, protocol_addr: IpAddress,
+                          hardware_addr: EthernetAddress) -> Result<()> {
+        debug_assert!(protocol_addr.is_unicast());
+        debug_assert!(hardware_addr.is_unicast());
+
+        let neighbor = Neighbor { hardware_addr, state: State::Permanent };
+        self.insert(protocol_addr, neighbor)
+    }
+
+    /// Remove the mapping for the given protocol address, whether it is
+    /// permanent or not.
+    ///
+    /// Returns the removed mapping, if any.
+    pub fn remove(&mut self, protocol_addr: &IpAddress) -> Option<Neighbor> {
+        let neighbor = self.storage.remove(protocol_addr);
+        if let Some(_neighbor) = neighbor {
+            net_trace!("removed {} => {}", protocol_addr, _neighbor.hardware_addr);
+        }
+        neighbor
+    }
+
+    /// Remove all mappings learned from the network, keeping the permanent ones.
+    ///
+    /// This also lifts the rate limit on discovery requests.
+    pub fn flush(&mut self) {
+        loop {
+            let dynamic_addr = self.storage.iter()
+                .find(|&(_, neighbor)| !neighbor.is_permanent())
+                .map(|(protocol_addr, _)| *protocol_addr);
+            match dynamic_addr {
+                Some(protocol_addr) => { self.storage.remove(&protocol_addr); }
+                None => break
+            }
+        }
+        self.silent_until = Instant::from_millis(0);
+        net_trace!("flushed");
+    }
+
+    /// Iterate over all mappings, including the ones that have expired
+    /// but were not discarded yet.
+    pub fn iter<'b>(&'b self) -> ManagedMapIter<'b, IpAddress, Neighbor> {
+        self.storage.iter()
+    }
+
+    fn insert(&mut self, protocol_addr: IpAddress, neighbor: Neighbor) -> Result<()> {
+        let hardware_addr = neighbor.hardware_addr;
         match self.storage.insert(protocol_addr, neighbor) {
             Ok(Some(old_neighbor)) => {
                 if old_neighbor.hardware_addr != hardware_addr {
                     net_trace!("replaced {} => {} (was {})",
                                protocol_addr, hardware_addr, old_neighbor.hardware_addr);
                 }
+                Ok(())
             }
             Ok(None) => {
                 net_trace!("filled {} => {} (was empty)", protocol_addr, hardware_addr);
+                Ok(())
             }
             Err((protocol_addr, neighbor)) => {
                 // If we're going down this branch, it means that a fixed-size cache storage
-                // is full, and we need to evict an entry.
+                // is full, and we need to evict an entry. Permanent entries are never evicted.
                 let old_protocol_addr = match self.storage {
                     ManagedMap::Borrowed(ref mut pairs) => {
                         pairs
                             .iter()
-                            .min_by_key(|pair_opt| {
-                                let (_protocol_addr, neighbor) = pair_opt.unwrap();
-                                neighbor.expires_at
+                            .filter_map(|pair_opt| {
+                                let (protocol_addr, neighbor) = pair_opt.unwrap();
+                                neighbor.expires_at().map(|expires_at| (protocol_addr, expires_at))
                             })
-                            .expect("empty neighbor cache storage") // unwraps min_by_key
-                            .unwrap() // unwraps pair
-                            .0
+                            .min_by_key(|&(_protocol_addr, expires_at)| expires_at)
+                            .map(|(protocol_addr, _expires_at)| protocol_addr)
                     }
                     // Owned maps can extend themselves.
                     #[cfg(any(feature = "std", feature = "alloc"))]
                     ManagedMap::Owned(_) => unreachable!()
                 };
 
+                let old_protocol_addr = match old_protocol_addr {
+                    Some(old_protocol_addr) => old_protocol_addr,
+                    None => {
+                        net_trace!("cannot fill {} => {} (only permanent entries)",
+                                   protocol_addr, hardware_addr);
+                        return Err(Error::Exhausted)
+                    }
+                };
+
                 let _old_neighbor =
                     self.storage.remove(&old_protocol_addr).unwrap();
                 match self.storage.insert(protocol_addr, neighbor) {
@@ -148,11 +275,11 @@ impl<'a> Cache<'a> {
                         net_trace!("filled {} => {} (evicted {} => {})",
                                    protocol_addr, hardware_addr,
                                    old_protocol_addr, _old_neighbor.hardware_addr);
+                        Ok(())
                     }
                     // We've covered everything else above.
                     _ => unreachable!()
                 }
-
             }
         }
     }
@@ -164,12 +291,10 @@ impl<'a> Cache<'a> {
         }
 
         match self.storage.get(protocol_addr) {
-            Some(&Neighbor { expires_at, hardware_addr }) => {
-                if timestamp < expires_at {
-                    return Some(hardware_addr)
-                }
+            Some(neighbor) if neighbor.is_valid(timestamp) => {
+                return Some(neighbor.hardware_addr)
             }
-            None => ()
+            _ => ()
         }
 
         None
@@ -193,6 +318,7 @@ impl<'a> Cache<'a> {
 mod test {
     use super::*;
     use std::collections::BTreeMap;
+    use std::vec::Vec;
     use wire::ip::test::{MOCK_IP_ADDR_1, MOCK_IP_ADDR_2, MOCK_IP_ADDR_3, MOCK_IP_ADDR_4};
 
 
@@ -270,6 +396,96 @@ mod test {
         assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_4, Instant::from_millis(1000)), Some(HADDR_D));
     }
 
+    #[test]
+    fn test_permanent() {
+        let mut cache_storage = [Default::default(); 3];
+        let mut cache = Cache::new(&mut cache_storage[..]);
+
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_1, HADDR_A), Ok(()));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_1, Instant::from_millis(0) + Cache::ENTRY_LIFETIME * 2),
+                   Some(HADDR_A));
+
+        // Learned mappings do not replace permanent ones.
+        cache.fill(MOCK_IP_ADDR_1, HADDR_B, Instant::from_millis(0));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_1, Instant::from_millis(0)), Some(HADDR_A));
+
+        // But permanent mappings replace learned ones.
+        cache.fill(MOCK_IP_ADDR_2, HADDR_B, Instant::from_millis(0));
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_2, HADDR_C), Ok(()));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_2, Instant::from_millis(0)), Some(HADDR_C));
+    }
+
+    #[test]
+    fn test_evict_permanent() {
+        let mut cache_storage = [Default::default(); 2];
+        let mut cache = Cache::new(&mut cache_storage[..]);
+
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_1, HADDR_A), Ok(()));
+        cache.fill(MOCK_IP_ADDR_2, HADDR_B, Instant::from_millis(0));
+        cache.fill(MOCK_IP_ADDR_3, HADDR_C, Instant::from_millis(100));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_1, Instant::from_millis(100)), Some(HADDR_A));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_2, Instant::from_millis(100)), None);
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_3, Instant::from_millis(100)), Some(HADDR_C));
+
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_4, HADDR_D), Ok(()));
+        cache.fill(MOCK_IP_ADDR_2, HADDR_B, Instant::from_millis(200));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_2, Instant::from_millis(200)), None);
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_2, HADDR_B), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn test_permanent_gc() {
+        let mut cache = Cache::new_with_limit(BTreeMap::new(), 2);
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_1, HADDR_A), Ok(()));
+        cache.fill(MOCK_IP_ADDR_2, HADDR_B, Instant::from_millis(50));
+        cache.fill(MOCK_IP_ADDR_3, HADDR_C, Instant::from_millis(50) + Cache::ENTRY_LIFETIME * 2);
+
+        assert_eq!(cache.storage.len(), 2);
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_1, Instant::from_millis(50) + Cache::ENTRY_LIFETIME * 2),
+                   Some(HADDR_A));
+    }
+
+    #[test]
+    fn test_remove_flush() {
+        let mut cache_storage = [Default::default(); 3];
+        let mut cache = Cache::new(&mut cache_storage[..]);
+
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_1, HADDR_A), Ok(()));
+        cache.fill(MOCK_IP_ADDR_2, HADDR_B, Instant::from_millis(0));
+        cache.fill(MOCK_IP_ADDR_3, HADDR_C, Instant::from_millis(0));
+
+        assert_eq!(cache.remove(&MOCK_IP_ADDR_3).map(|n| n.hardware_addr()), Some(HADDR_C));
+        assert_eq!(cache.remove(&MOCK_IP_ADDR_3).map(|n| n.hardware_addr()), None);
+
+        assert_eq!(cache.lookup(&MOCK_IP_ADDR_4, Instant::from_millis(0)), Answer::NotFound);
+        cache.flush();
+        assert_eq!(cache.lookup(&MOCK_IP_ADDR_4, Instant::from_millis(100)), Answer::NotFound);
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_1, Instant::from_millis(0)), Some(HADDR_A));
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_2, Instant::from_millis(0)), None);
+
+        assert!(cache.remove(&MOCK_IP_ADDR_1).is_some());
+        assert_eq!(cache.lookup_pure(&MOCK_IP_ADDR_1, Instant::from_millis(0)), None);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut cache_storage = [Default::default(); 3];
+        let mut cache = Cache::new(&mut cache_storage[..]);
+
+        assert_eq!(cache.fill_permanent(MOCK_IP_ADDR_1, HADDR_A), Ok(()));
+        cache.fill(MOCK_IP_ADDR_2, HADDR_B, Instant::from_millis(0));
+
+        let entries = cache.iter()
+            .map(|(addr, neighbor)| (*addr, neighbor.hardware_addr(), neighbor.state()))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![
+            (MOCK_IP_ADDR_1, HADDR_A, State::Permanent),
+            (MOCK_IP_ADDR_2, HADDR_B, State::Dynamic {
+                expires_at: Instant::from_millis(0) + Cache::ENTRY_LIFETIME
+            }),
+        ]);
+    }
+
     #[test]
     fn test_hush() {
         let mut cache_storage = [Default::default(); 3];
@@ -1,135 +1,372 @@
-use managed::ManagedMap;
-use time::Instant;
-use core::ops::Bound;
+use core::slice;
+use byteorder::{ByteOrder, NetworkEndian};
+use managed::ManagedSlice;
+use time::{Duration, Instant};
 
 use {Error, Result};
-use wire::{IpCidr, IpAddress};
+use wire::{IpCidr, IpAddress, IpProtocol};
 #[cfg(feature = "proto-ipv4")]
-use wire::{Ipv4Address, Ipv4Cidr};
+use wire::Ipv4Address;
 #[cfg(feature = "proto-ipv6")]
-use wire::{Ipv6Address, Ipv6Cidr};
+use wire::Ipv6Address;
 
-/// A prefix of addresses that should be routed via a router
-#[derive(Debug, Clone, Copy)]
+/// A route to a prefix of addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Route {
-    pub via_router: IpAddress,
-    /// `None` means "forever".
+    /// The prefix of addresses this route applies to.
+    pub cidr: IpCidr,
+    /// The router to forward packets to; `None` means the prefix is directly
+    /// connected (on-link), and packets are sent to their destination directly.
+    pub via_router: Option<IpAddress>,
+    /// Among routes with the same prefix length, the ones with the lowest
+    /// metric are used.
+    pub metric: u32,
+    /// After this instant the route is deprecated, and is only used if there
+    /// are no preferred routes with the same prefix length. `None` means "forever".
     pub preferred_until: Option<Instant>,
     /// `None` means "forever".
     pub expires_at: Option<Instant>,
 }
 
 impl Route {
-    /// Returns a route to 0.0.0.0/0 via the `gateway`, with no expiry.
-    #[cfg(feature = "proto-ipv4")]
-    pub fn new_ipv4_gateway(gateway: Ipv4Address) -> Route {
+    /// Returns a route to `cidr` via the `router`, with no expiry.
+    pub fn new_via_router(cidr: IpCidr, router: IpAddress) -> Route {
         Route {
-            via_router: gateway.into(),
+            cidr: cidr,
+            via_router: Some(router),
+            metric: 0,
             preferred_until: None,
             expires_at: None,
         }
     }
 
-    /// Returns a route to ::/0 via the `gateway`, with no expiry.
-    #[cfg(feature = "proto-ipv6")]
-    pub fn new_ipv6_gateway(gateway: Ipv6Address) -> Route {
+    /// Returns a route to the directly connected `cidr`, with no expiry.
+    pub fn new_on_link(cidr: IpCidr) -> Route {
         Route {
-            via_router: gateway.into(),
+            cidr: cidr,
+            via_router: None,
+            metric: 0,
             preferred_until: None,
             expires_at: None,
         }
     }
+
+    /// Returns a route to 0.0.0.0/0 via the `gateway`, with no expiry.
+    #[cfg(feature = "proto-ipv4")]
+    pub fn new_ipv4_gateway(gateway: Ipv4Address) -> Route {
+        Route::new_via_router(IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0), gateway.into())
+    }
+
+    /// Returns a route to ::/0 via the `gateway`, with no expiry.
+    #[cfg(feature = "proto-ipv6")]
+    pub fn new_ipv6_gateway(gateway: Ipv6Address) -> Route {
+        Route::new_via_router(IpCidr::new(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 0), 0),
+                              gateway.into())
+    }
+
+    /// Query whether the route has expired at the given instant.
+    pub fn is_expired(&self, timestamp: Instant) -> bool {
+        match self.expires_at {
+            Some(expires_at) => timestamp > expires_at,
+            None => false
+        }
+    }
+
+    /// Query whether the route is deprecated at the given instant.
+    pub fn is_deprecated(&self, timestamp: Instant) -> bool {
+        match self.preferred_until {
+            Some(preferred_until) => timestamp > preferred_until,
+            None => false
+        }
+    }
+
+    /// Return the rank of the route for `addr`, or `None` if it does not apply to `addr`;
+    /// for a given destination, the routes with the highest rank are used.
+    fn rank(&self, addr: &IpAddress, timestamp: Instant) -> Option<(u8, bool, u32)> {
+        if self.is_expired(timestamp) || !self.cidr.contains_addr(addr) {
+            return None
+        }
+        Some((self.cidr.prefix_len(), !self.is_deprecated(timestamp), !self.metric))
+    }
+}
+
+/// The addresses, protocol and ports of the packets of a flow, which are hashed
+/// to choose among equal-cost routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Flow {
+    pub src_addr: IpAddress,
+    pub dst_addr: IpAddress,
+    pub protocol: Option<IpProtocol>,
+    pub ports:    Option<(u16, u16)>,
+}
+
+impl Flow {
+    /// Returns the flow of packets of any protocol from `src_addr` to `dst_addr`.
+    pub fn new(src_addr: IpAddress, dst_addr: IpAddress) -> Flow {
+        Flow { src_addr, dst_addr, protocol: None, ports: None }
+    }
+
+    /// The FNV-1a hash of the flow.
+    fn hash(&self) -> u32 {
+        let protocol = self.protocol.map(u8::from).unwrap_or(0);
+        let (src_port, dst_port) = self.ports.unwrap_or((0, 0));
+        let mut ports = [0; 4];
+        NetworkEndian::write_u16(&mut ports[0..2], src_port);
+        NetworkEndian::write_u16(&mut ports[2..4], dst_port);
+
+        self.src_addr.as_bytes().iter()
+            .chain(self.dst_addr.as_bytes())
+            .chain(&[protocol])
+            .chain(&ports)
+            .fold(0x811c_9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+    }
 }
 
 /// A routing table.
 ///
+/// The route to a destination is chosen by longest prefix match, preferring
+/// routes that are not deprecated, and then routes with a lower metric.
+/// If several routes remain and equal-cost multipath is [enabled], one of them
+/// is chosen based on a hash of the addresses, protocol and ports of the packet; otherwise,
+/// the first one is used.
+///
 /// # Examples
 ///
 /// On systems with heap, this table can be created with:
 ///
 /// ```rust
-/// use std::collections::BTreeMap;
 /// use smoltcp::iface::Routes;
-/// let mut routes = Routes::new(BTreeMap::new());
+/// let mut routes = Routes::new(vec![]);
 /// ```
 ///
 /// On systems without heap, use:
 ///
 /// ```rust
 /// use smoltcp::iface::Routes;
-/// let mut routes_storage = [];
+/// let mut routes_storage = [None; 4];
 /// let mut routes = Routes::new(&mut routes_storage[..]);
 /// ```
+///
+/// [enabled]: #method.set_ecmp
 #[derive(Debug)]
 pub struct Routes<'a> {
-    storage: ManagedMap<'a, IpCidr, Route>,
+    storage: ManagedSlice<'a, Option<Route>>,
+    ecmp:    bool,
 }
 
 impl<'a> Routes<'a> {
+    /// How long a host route installed in response to a redirect is used.
+    pub(crate) const REDIRECT_LIFETIME: Duration = Duration { millis: 300_000 };
+
     /// Creates a routing tables. The backing storage is **not** cleared
     /// upon creation.
     pub fn new<T>(storage: T) -> Routes<'a>
-            where T: Into<ManagedMap<'a, IpCidr, Route>> {
+            where T: Into<ManagedSlice<'a, Option<Route>>> {
         let storage = storage.into();
-        Routes { storage }
+        Routes { storage, ecmp: false }
     }
 
     /// Update the routes of this node.
-    pub fn update<F: FnOnce(&mut ManagedMap<'a, IpCidr, Route>)>(&mut self, f: F) {
+    pub fn update<F: FnOnce(&mut ManagedSlice<'a, Option<Route>>)>(&mut self, f: F) {
         f(&mut self.storage);
     }
 
+    /// Return whether equal-cost multipath routing is enabled.
+    pub fn ecmp(&self) -> bool {
+        self.ecmp
+    }
+
+    /// Enable or disable equal-cost multipath routing.
+    ///
+    /// When enabled, destinations with several equally good routes are spread
+    /// over their next hops by hashing the addresses, protocol and ports of
+    /// each packet, such that all packets of a flow take the same path.
+    pub fn set_ecmp(&mut self, enabled: bool) {
+        self.ecmp = enabled
+    }
+
+    /// Iterate over the routes.
+    pub fn iter<'b>(&'b self) -> Iter<'b> {
+        Iter { lower: self.storage.iter() }
+    }
+
+    /// Add a route.
+    ///
+    /// Several routes to the same prefix may be added, e.g. to use several
+    /// next hops with equal-cost multipath routing.
+    ///
+    /// # Errors
+    /// Returns `Err(Error::Exhausted)` if the storage is fixed-size (not a `Vec`)
+    /// and is full.
+    pub fn add(&mut self, route: Route) -> Result<()> {
+        for slot in self.storage.iter_mut() {
+            if slot.is_none() {
+                *slot = Some(route);
+                return Ok(())
+            }
+        }
+
+        match self.storage {
+            ManagedSlice::Borrowed(_) => Err(Error::Exhausted),
+            #[cfg(any(feature = "std", feature = "alloc"))]
+            ManagedSlice::Owned(ref mut storage) => {
+                storage.push(Some(route));
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove the routes to the given prefix via the given router
+    /// (or on-link, if `via_router` is `None`).
+    ///
+    /// Returns the first removed route, if any.
+    pub fn remove(&mut self, cidr: IpCidr, via_router: Option<IpAddress>) -> Option<Route> {
+        let mut removed = None;
+        for slot in self.storage.iter_mut() {
+            match *slot {
+                Some(route) if route.cidr == cidr && route.via_router == via_router => {
+                    *slot = None;
+                    removed = removed.or(Some(route));
+                }
+                _ => ()
+            }
+        }
+        removed
+    }
+
+    /// Remove the routes that have expired at the given instant.
+    pub fn remove_expired(&mut self, timestamp: Instant) {
+        for slot in self.storage.iter_mut() {
+            match *slot {
+                Some(route) if route.is_expired(timestamp) => *slot = None,
+                _ => ()
+            }
+        }
+    }
+
+    /// Replace all routes to the given prefix with `route`, returning
+    /// the first replaced route, if any.
+    fn replace(&mut self, route: Route) -> Result<Option<Route>> {
+        let mut replaced = None;
+        for slot in self.storage.iter_mut() {
+            match *slot {
+                Some(old_route) if old_route.cidr == route.cidr => {
+                    *slot = None;
+                    replaced = replaced.or(Some(old_route));
+                }
+                _ => ()
+            }
+        }
+        self.add(route)?;
+        Ok(replaced)
+    }
+
+    /// Add a host route to `dst_addr` via `next_hop` (which is on-link if it is
+    /// `dst_addr` itself) in response to a redirect, replacing any previous routes
+    /// to that host.
+    pub(crate) fn add_redirect(&mut self, dst_addr: IpAddress, next_hop: IpAddress,
+                               timestamp: Instant) -> Result<()> {
+        let cidr = match dst_addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(_) => IpCidr::new(dst_addr, 32),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => IpCidr::new(dst_addr, 128),
+            _ => return Err(Error::Unaddressable)
+        };
+        let route = Route {
+            cidr: cidr,
+            via_router: if next_hop == dst_addr { None } else { Some(next_hop) },
+            metric: 0,
+            preferred_until: None,
+            expires_at: Some(timestamp + Self::REDIRECT_LIFETIME),
+        };
+
+        self.remove_expired(timestamp);
+        self.replace(route).map(|_| ())
+    }
+
     /// Add a default ipv4 gateway (ie. "ip route add 0.0.0.0/0 via `gateway`").
     ///
-    /// On success, returns the previous default route, if any.
+    /// Any previous default routes are removed. On success, returns the previous
+    /// default route, if any.
     #[cfg(feature = "proto-ipv4")]
     pub fn add_default_ipv4_route(&mut self, gateway: Ipv4Address) -> Result<Option<Route>> {
-        let cidr = IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0);
-        let route = Route::new_ipv4_gateway(gateway);
-        match self.storage.insert(cidr, route) {
-            Ok(route) => Ok(route),
-            Err((_cidr, _route)) => Err(Error::Exhausted)
-        }
+        self.replace(Route::new_ipv4_gateway(gateway))
     }
 
     /// Add a default ipv6 gateway (ie. "ip -6 route add ::/0 via `gateway`").
     ///
-    /// On success, returns the previous default route, if any.
+    /// Any previous default routes are removed. On success, returns the previous
+    /// default route, if any.
     #[cfg(feature = "proto-ipv6")]
     pub fn add_default_ipv6_route(&mut self, gateway: Ipv6Address) -> Result<Option<Route>> {
-        let cidr = IpCidr::new(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 0), 0);
-        let route = Route::new_ipv6_gateway(gateway);
-        match self.storage.insert(cidr, route) {
-            Ok(route) => Ok(route),
-            Err((_cidr, _route)) => Err(Error::Exhausted)
-        }
+        self.replace(Route::new_ipv6_gateway(gateway))
     }
 
-    pub(crate) fn lookup(&self, addr: &IpAddress, timestamp: Instant) ->
-            Option<IpAddress> {
-        assert!(addr.is_unicast());
+    /// Return the rank of the best routes to `addr`, and how many of them there are.
+    /// Only unicast addresses are routed.
+    fn best_rank(&self, addr: &IpAddress, timestamp: Instant) ->
+            Option<((u8, bool, u32), usize)> {
+        if !addr.is_unicast() { return None }
 
-        let cidr = match addr {
-            #[cfg(feature = "proto-ipv4")]
-            IpAddress::Ipv4(addr) => IpCidr::Ipv4(Ipv4Cidr::new(*addr, 32)),
-            #[cfg(feature = "proto-ipv6")]
-            IpAddress::Ipv6(addr) => IpCidr::Ipv6(Ipv6Cidr::new(*addr, 128)),
-            _ => unimplemented!()
-        };
+        let mut best = None;
+        for rank in self.iter().filter_map(|route| route.rank(addr, timestamp)) {
+            best = match best {
+                Some((best_rank, count)) if rank == best_rank => Some((best_rank, count + 1)),
+                Some((best_rank, _)) if rank < best_rank => best,
+                _ => Some((rank, 1))
+            }
+        }
+        best
+    }
 
-        for (prefix, route) in self.storage.range((Bound::Unbounded, Bound::Included(cidr))).rev() {
-            // TODO: do something with route.preferred_until
-            if let Some(expires_at) = route.expires_at {
-                if timestamp > expires_at {
-                    continue;
-                }
+    /// Return the next hop for the destination of the given flow: either a router,
+    /// or the destination itself if it is on-link.
+    pub(crate) fn lookup(&self, flow: &Flow, timestamp: Instant) -> Option<IpAddress> {
+        let addr = &flow.dst_addr;
+        let (best_rank, best_count) = self.best_rank(addr, timestamp)?;
+        let mut index = if self.ecmp { flow.hash() as usize % best_count } else { 0 };
+        for route in self.iter() {
+            if route.rank(addr, timestamp) != Some(best_rank) {
+                continue
             }
 
-            if prefix.contains_addr(addr) {
-                return Some(route.via_router);
+            if index == 0 {
+                return Some(route.via_router.unwrap_or(*addr))
             }
+            index -= 1;
         }
+        unreachable!()
+    }
+
+    /// Query whether `f` returns true for any of the next hops that packets
+    /// to the given destination may be sent to.
+    pub(crate) fn any_next_hop<F>(&self, addr: &IpAddress, timestamp: Instant, mut f: F) -> bool
+            where F: FnMut(IpAddress) -> bool {
+        match self.best_rank(addr, timestamp) {
+            Some((best_rank, _)) =>
+                self.iter()
+                    .filter(|route| route.rank(addr, timestamp) == Some(best_rank))
+                    .any(|route| f(route.via_router.unwrap_or(*addr))),
+            None => false
+        }
+    }
+}
+
+/// An iterator over the routes of a routing table.
+pub struct Iter<'a> {
+    lower: slice::Iter<'a, Option<Route>>
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Route;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(route_opt) = self.lower.next() {
+            if let Some(route) = route_opt.as_ref() {
+                return Some(route)
+            }
+        }
         None
     }
 }
@@ -140,6 +377,8 @@ mod test {
     #[cfg(feature = "proto-ipv6")]
     mod mock {
         use super::super::*;
+        use wire::Ipv6Cidr;
+
         pub const ADDR_1A: Ipv6Address = Ipv6Address(
                 [0xfe, 0x80, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1]);
         pub const ADDR_1B: Ipv6Address = Ipv6Address(
@@ -150,6 +389,12 @@ mod test {
             Ipv6Cidr::new(Ipv6Address(
                     [0xfe, 0x80, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0]), 64)
         }
+        pub fn cidr_1_host() -> Ipv6Cidr {
+            Ipv6Cidr::new(ADDR_1C, 128)
+        }
+        pub fn cidr_all() -> Ipv6Cidr {
+            Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0)
+        }
 
         pub const ADDR_2A: Ipv6Address = Ipv6Address(
                 [0xfe, 0x80, 0, 0, 0, 0, 51, 100, 0, 0, 0, 0, 0, 0, 0, 1]);
@@ -164,12 +409,20 @@ mod test {
     #[cfg(all(feature = "proto-ipv4", not(feature = "proto-ipv6")))]
     mod mock {
         use super::super::*;
+        use wire::Ipv4Cidr;
+
         pub const ADDR_1A: Ipv4Address = Ipv4Address([192, 0, 2, 1]);
         pub const ADDR_1B: Ipv4Address = Ipv4Address([192, 0, 2, 13]);
         pub const ADDR_1C: Ipv4Address = Ipv4Address([192, 0, 2, 42]);
         pub fn cidr_1() -> Ipv4Cidr {
             Ipv4Cidr::new(Ipv4Address([192, 0, 2, 0]), 24)
         }
+        pub fn cidr_1_host() -> Ipv4Cidr {
+            Ipv4Cidr::new(ADDR_1C, 32)
+        }
+        pub fn cidr_all() -> Ipv4Cidr {
+            Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0)
+        }
 
         pub const ADDR_2A: Ipv4Address = Ipv4Address([198, 51, 100, 1]);
         pub const ADDR_2B: Ipv4Address = Ipv4Address([198, 51, 100, 21]);
@@ -180,50 +433,181 @@ mod test {
 
     use self::mock::*;
 
+    fn flow_to(addr: IpAddress) -> Flow {
+        Flow::new(IpAddress::Unspecified, addr)
+    }
+
     #[test]
     fn test_fill() {
         let mut routes_storage = [None, None, None];
         let mut routes = Routes::new(&mut routes_storage[..]);
 
-        assert_eq!(routes.lookup(&ADDR_1A.into(), Instant::from_millis(0)), None);
-        assert_eq!(routes.lookup(&ADDR_1B.into(), Instant::from_millis(0)), None);
-        assert_eq!(routes.lookup(&ADDR_1C.into(), Instant::from_millis(0)), None);
-        assert_eq!(routes.lookup(&ADDR_2A.into(), Instant::from_millis(0)), None);
-        assert_eq!(routes.lookup(&ADDR_2B.into(), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_1A.into()), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_1B.into()), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(0)), None);
 
-        let route = Route {
-            via_router: ADDR_1A.into(),
-            preferred_until: None, expires_at: None,
-        };
-        routes.update(|storage| {
-            storage.insert(cidr_1().into(), route).unwrap();
-        });
+        let route = Route::new_via_router(cidr_1().into(), ADDR_1A.into());
+        routes.add(route).unwrap();
 
-        assert_eq!(routes.lookup(&ADDR_1A.into(), Instant::from_millis(0)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_1B.into(), Instant::from_millis(0)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_1C.into(), Instant::from_millis(0)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_2A.into(), Instant::from_millis(0)), None);
-        assert_eq!(routes.lookup(&ADDR_2B.into(), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_1A.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1B.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(0)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(0)), None);
 
         let route2 = Route {
-            via_router: ADDR_2A.into(),
             preferred_until: Some(Instant::from_millis(10)),
             expires_at: Some(Instant::from_millis(10)),
+            ..Route::new_via_router(cidr_2().into(), ADDR_2A.into())
         };
-        routes.update(|storage| {
-            storage.insert(cidr_2().into(), route2).unwrap();
-        });
-
-        assert_eq!(routes.lookup(&ADDR_1A.into(), Instant::from_millis(0)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_1B.into(), Instant::from_millis(0)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_1C.into(), Instant::from_millis(0)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_2A.into(), Instant::from_millis(0)), Some(ADDR_2A.into()));
-        assert_eq!(routes.lookup(&ADDR_2B.into(), Instant::from_millis(0)), Some(ADDR_2A.into()));
-
-        assert_eq!(routes.lookup(&ADDR_1A.into(), Instant::from_millis(10)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_1B.into(), Instant::from_millis(10)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_1C.into(), Instant::from_millis(10)), Some(ADDR_1A.into()));
-        assert_eq!(routes.lookup(&ADDR_2A.into(), Instant::from_millis(10)), Some(ADDR_2A.into()));
-        assert_eq!(routes.lookup(&ADDR_2B.into(), Instant::from_millis(10)), Some(ADDR_2A.into()));
+        routes.add(route2).unwrap();
+
+        assert_eq!(routes.lookup(&flow_to(ADDR_1A.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1B.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(0)), Some(ADDR_2A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(0)), Some(ADDR_2A.into()));
+
+        assert_eq!(routes.lookup(&flow_to(ADDR_1A.into()), Instant::from_millis(10)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1B.into()), Instant::from_millis(10)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(10)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(10)), Some(ADDR_2A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(10)), Some(ADDR_2A.into()));
+
+        assert_eq!(routes.lookup(&flow_to(ADDR_1A.into()), Instant::from_millis(11)), Some(ADDR_1A.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(11)), None);
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(11)), None);
+
+        routes.remove_expired(Instant::from_millis(10));
+        assert_eq!(routes.iter().count(), 2);
+        routes.remove_expired(Instant::from_millis(11));
+        assert_eq!(routes.iter().count(), 1);
+        assert_eq!(routes.remove(cidr_1().into(), Some(ADDR_1A.into())), Some(route));
+        assert_eq!(routes.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_longest_prefix_match() {
+        let mut routes_storage = [None, None, None];
+        let mut routes = Routes::new(&mut routes_storage[..]);
+
+        routes.add(Route::new_via_router(cidr_1_host().into(), ADDR_2B.into())).unwrap();
+        routes.add(Route::new_via_router(cidr_all().into(), ADDR_2A.into())).unwrap();
+        routes.add(Route::new_on_link(cidr_1().into())).unwrap();
+
+        assert_eq!(routes.lookup(&flow_to(ADDR_1B.into()), Instant::from_millis(0)), Some(ADDR_1B.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(0)), Some(ADDR_2B.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(0)), Some(ADDR_2A.into()));
+        assert_eq!(routes.add(Route::new_on_link(cidr_2().into())), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn test_metric_and_deprecation() {
+        let mut routes_storage = [None, None, None];
+        let mut routes = Routes::new(&mut routes_storage[..]);
+
+        routes.add(Route {
+            metric: 10,
+            ..Route::new_via_router(cidr_1().into(), ADDR_1A.into())
+        }).unwrap();
+        routes.add(Route {
+            metric: 5,
+            preferred_until: Some(Instant::from_millis(10)),
+            ..Route::new_via_router(cidr_1().into(), ADDR_1B.into())
+        }).unwrap();
+
+        // The lower metric wins, until that route is deprecated.
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(0)), Some(ADDR_1B.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(10)), Some(ADDR_1B.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(11)), Some(ADDR_1A.into()));
+
+        // A deprecated route is still used if there are no other routes.
+        assert!(routes.remove(cidr_1().into(), Some(ADDR_1A.into())).is_some());
+        assert_eq!(routes.lookup(&flow_to(ADDR_1C.into()), Instant::from_millis(11)), Some(ADDR_1B.into()));
+    }
+
+    #[test]
+    fn test_redirect() {
+        let mut routes_storage = [None, None];
+        let mut routes = Routes::new(&mut routes_storage[..]);
+
+        routes.add(Route::new_via_router(cidr_all().into(), ADDR_1A.into())).unwrap();
+        routes.add_redirect(ADDR_2A.into(), ADDR_1B.into(), Instant::from_millis(0)).unwrap();
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(0)), Some(ADDR_1B.into()));
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), Instant::from_millis(0)), Some(ADDR_1A.into()));
+
+        // A further redirect replaces the previous one.
+        routes.add_redirect(ADDR_2A.into(), ADDR_2A.into(), Instant::from_millis(0)).unwrap();
+        assert_eq!(routes.iter().count(), 2);
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), Instant::from_millis(0)), Some(ADDR_2A.into()));
+
+        // Redirects expire, making room for new ones.
+        let expires_at = Instant::from_millis(0) + Routes::REDIRECT_LIFETIME;
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), expires_at), Some(ADDR_2A.into()));
+        let expires_at = expires_at + Duration::from_millis(1);
+        assert_eq!(routes.lookup(&flow_to(ADDR_2A.into()), expires_at), Some(ADDR_1A.into()));
+        routes.add_redirect(ADDR_2B.into(), ADDR_1B.into(), expires_at).unwrap();
+        assert_eq!(routes.lookup(&flow_to(ADDR_2B.into()), expires_at), Some(ADDR_1B.into()));
+    }
+
+    #[test]
+    fn test_non_unicast() {
+        let mut routes_storage = [None];
+        let mut routes = Routes::new(&mut routes_storage[..]);
+        routes.add(Route::new_via_router(cidr_all().into(), ADDR_1A.into())).unwrap();
+
+        #[cfg(feature = "proto-ipv6")]
+        let addr = Ipv6Address::LINK_LOCAL_ALL_NODES.into();
+        #[cfg(all(feature = "proto-ipv4", not(feature = "proto-ipv6")))]
+        let addr = Ipv4Address::BROADCAST.into();
+        for &addr in &[addr, IpAddress::Unspecified] {
+            assert_eq!(routes.lookup(&flow_to(addr), Instant::from_millis(0)), None);
+            assert!(!routes.any_next_hop(&addr, Instant::from_millis(0), |_| true));
+        }
+    }
+
+    #[test]
+    fn test_ecmp() {
+        let mut routes_storage = [None, None];
+        let mut routes = Routes::new(&mut routes_storage[..]);
+
+        routes.add(Route::new_via_router(cidr_all().into(), ADDR_1A.into())).unwrap();
+        routes.add(Route::new_via_router(cidr_all().into(), ADDR_1B.into())).unwrap();
+
+        let mut via_1a = false;
+        let mut via_1b = false;
+        for last_byte in 0..16u8 {
+            let mut addr = ADDR_2A;
+            addr.0[addr.0.len() - 1] = last_byte;
+            let addr = addr.into();
+
+            routes.set_ecmp(false);
+            assert_eq!(routes.lookup(&flow_to(addr), Instant::from_millis(0)), Some(ADDR_1A.into()));
+
+            routes.set_ecmp(true);
+            let next_hop = routes.lookup(&flow_to(addr), Instant::from_millis(0));
+            // The path of a flow is stable.
+            assert_eq!(routes.lookup(&flow_to(addr), Instant::from_millis(0)), next_hop);
+            if next_hop == Some(ADDR_1A.into()) { via_1a = true }
+            if next_hop == Some(ADDR_1B.into()) { via_1b = true }
+        }
+        assert!(via_1a && via_1b);
+
+        // Flows to the same destination are spread over the next hops too.
+        let mut via_1a = false;
+        let mut via_1b = false;
+        for src_port in 49152..49168 {
+            let flow = Flow {
+                protocol: Some(IpProtocol::Udp),
+                ports:    Some((src_port, 53)),
+                ..Flow::new(ADDR_1C.into(), ADDR_2A.into())
+            };
+            let next_hop = routes.lookup(&flow, Instant::from_millis(0));
+            if next_hop == Some(ADDR_1A.into()) { via_1a = true }
+            if next_hop == Some(ADDR_1B.into()) { via_1b = true }
+        }
+        assert!(via_1a && via_1b);
     }
 }
@@ -0,0 +1,376 @@
+// Heads up! Before working on this file you should read RFC 6724,
+// and the parts of RFC 4007 and RFC 4291 that discuss address scopes.
+
+use core::cmp::{self, Ordering};
+use wire::{IpAddress, IpCidr};
+
+/// The scope of link-local unicast addresses; the loopback address
+/// is treated as link-local as well, see RFC 6724 § 3.1.
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+/// The scope of the deprecated IPv6 site-local unicast addresses.
+#[cfg(feature = "proto-ipv6")]
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL:     u8 = 0xe;
+
+/// The default policy table of RFC 6724 § 2.1, as
+/// (prefix, prefix length, precedence, label) tuples.
+#[cfg(feature = "proto-ipv6")]
+const POLICY_TABLE: [([u8; 16], u8, u8, u8); 9] = [
+    ([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1], 128, 50,  0), // ::1/128
+    ([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],   0, 40,  1), // ::/0
+    ([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0, 0],
+                                                      96, 35,  4), // ::ffff:0:0/96
+    ([0x20, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                                                      16, 30,  2), // 2002::/16
+    ([0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                                                      32,  5,  5), // 2001::/32
+    ([0xfc, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],  7,  3, 13), // fc00::/7
+    ([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],  96,  1,  3), // ::/96
+    ([0xfe, 0xc0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                                                      10,  1, 11), // fec0::/10
+    ([0x3f, 0xfe, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                                                      16,  1, 12), // 3ffe::/16
+];
+
+/// A source address that may be selected for a destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    /// The address, and the prefix length of the subnet it belongs to.
+    pub cidr:       IpCidr,
+    /// Whether the address should not be used for new communication.
+    pub deprecated: bool,
+}
+
+/// Return the scope of an address.
+fn scope(addr: &IpAddress) -> u8 {
+    match *addr {
+        #[cfg(feature = "proto-ipv4")]
+        IpAddress::Ipv4(addr) if addr.is_loopback() || addr.is_link_local() =>
+            SCOPE_LINK_LOCAL,
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(addr) if addr.is_multicast() =>
+            addr.as_bytes()[1] & 0x0f,
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(addr) if addr.is_loopback() || addr.is_link_local() =>
+            SCOPE_LINK_LOCAL,
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(addr) if addr.as_bytes()[0] == 0xfe &&
+                                 addr.as_bytes()[1] & 0xc0 == 0xc0 =>
+            SCOPE_SITE_LOCAL,
+        _ => SCOPE_GLOBAL
+    }
+}
+
+/// Return the length of the longest prefix the two byte strings have in common.
+fn common_prefix_len(lhs: &[u8], rhs: &[u8]) -> u8 {
+    let mut len = 0;
+    for (lhs, rhs) in lhs.iter().zip(rhs.iter()) {
+        let diff = lhs ^ rhs;
+        len += diff.leading_zeros() as u8;
+        if diff != 0 { break }
+    }
+    len
+}
+
+/// Return the precedence and the label of an address in the default policy table.
+///
+/// IPv4 addresses are looked up as IPv4-mapped IPv6 addresses.
+fn policy(addr: &IpAddress) -> (u8, u8) {
+    match *addr {
+        #[cfg(feature = "proto-ipv4")]
+        IpAddress::Ipv4(_) => (35, 4),
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(addr) => {
+            let mut best = (0, 40, 1);
+            for &(ref prefix, prefix_len, precedence, label) in POLICY_TABLE.iter() {
+                if prefix_len >= best.0 &&
+                        common_prefix_len(prefix, addr.as_bytes()) >= prefix_len {
+                    best = (prefix_len, precedence, label);
+                }
+            }
+            (best.1, best.2)
+        }
+        _ => (40, 1)
+    }
+}
+
+/// Return the length of the prefix a candidate source address has in common with
+/// the destination address, up to the prefix length of the candidate.
+fn candidate_prefix_len(candidate: &Candidate, dst_addr: &IpAddress) -> u8 {
+    let len = common_prefix_len(candidate.cidr.address().as_bytes(), dst_addr.as_bytes());
+    cmp::min(len, candidate.cidr.prefix_len())
+}
+
+/// Compare two candidate source addresses for a destination, using the rules
+/// of RFC 6724 § 5; `Ordering::Greater` means that `lhs` is preferred.
+///
+/// Rules 4, 5 and 7 (home addresses, outgoing interface and temporary addresses)
+/// do not apply.
+fn compare_sources(lhs: &Candidate, rhs: &Candidate, dst_addr: &IpAddress) -> Ordering {
+    let (lhs_addr, rhs_addr) = (lhs.cidr.address(), rhs.cidr.address());
+
+    // Rule 1: prefer the same address.
+    match ((lhs_addr == *dst_addr), (rhs_addr == *dst_addr)) {
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        _ => ()
+    }
+
+    // Rule 2: prefer appropriate scope.
+    let (lhs_scope, rhs_scope, dst_scope) = (scope(&lhs_addr), scope(&rhs_addr), scope(dst_addr));
+    if lhs_scope < rhs_scope {
+        return if lhs_scope < dst_scope { Ordering::Less } else { Ordering::Greater }
+    }
+    if rhs_scope < lhs_scope {
+        return if rhs_scope < dst_scope { Ordering::Greater } else { Ordering::Less }
+    }
+
+    // Rule 3: avoid deprecated addresses.
+    match (lhs.deprecated, rhs.deprecated) {
+        (false, true) => return Ordering::Greater,
+        (true, false) => return Ordering::Less,
+        _ => ()
+    }
+
+    // Rule 6: prefer matching label.
+    let dst_label = policy(dst_addr).1;
+    match (policy(&lhs_addr).1 == dst_label, policy(&rhs_addr).1 == dst_label) {
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        _ => ()
+    }
+
+    // Rule 8: use longest matching prefix.
+    candidate_prefix_len(lhs, dst_addr).cmp(&candidate_prefix_len(rhs, dst_addr))
+}
+
+/// Select the source address to use for sending to `dst_addr` among `candidates`,
+/// or return `None` if there is no candidate of the same address family.
+///
+/// Candidates that compare equal are selected in the order they are given.
+pub fn select_source<I>(dst_addr: &IpAddress, candidates: I) -> Option<Candidate>
+        where I: IntoIterator<Item = Candidate> {
+    let mut selected: Option<Candidate> = None;
+    for candidate in candidates {
+        if candidate.cidr.address().to_unspecified() != dst_addr.to_unspecified() {
+            continue
+        }
+        selected = match selected {
+            Some(best) if compare_sources(&candidate, &best, dst_addr) != Ordering::Greater =>
+                Some(best),
+            _ => Some(candidate)
+        }
+    }
+    selected
+}
+
+/// Compare two destination addresses, given the source addresses that would be
+/// used for them, using the rules of RFC 6724 § 6; `Ordering::Less` means that
+/// `lhs` is preferred.
+///
+/// Rules 4 and 7 (home addresses and native transport) do not apply.
+fn compare_destinations(lhs: &IpAddress, lhs_src: Option<&Candidate>,
+                        rhs: &IpAddress, rhs_src: Option<&Candidate>) -> Ordering {
+    // Rule 1: avoid unusable destinations.
+    let (lhs_src, rhs_src) = match (lhs_src, rhs_src) {
+        (Some(lhs_src), Some(rhs_src)) => (lhs_src, rhs_src),
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (None, None) => return Ordering::Equal
+    };
+    let (lhs_src_addr, rhs_src_addr) = (lhs_src.cidr.address(), rhs_src.cidr.address());
+
+    // Rule 2: prefer matching scope.
+    match (scope(lhs) == scope(&lhs_src_addr), scope(rhs) == scope(&rhs_src_addr)) {
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        _ => ()
+    }
+
+    // Rule 3: avoid deprecated addresses.
+    match (lhs_src.deprecated, rhs_src.deprecated) {
+        (false, true) => return Ordering::Less,
+        (true, false) => return Ordering::Greater,
+        _ => ()
+    }
+
+    // Rule 5: prefer matching label.
+    let ((lhs_precedence, lhs_label), (rhs_precedence, rhs_label)) = (policy(lhs), policy(rhs));
+    match (policy(&lhs_src_addr).1 == lhs_label, policy(&rhs_src_addr).1 == rhs_label) {
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        _ => ()
+    }
+
+    // Rule 6: prefer higher precedence.
+    match rhs_precedence.cmp(&lhs_precedence) {
+        Ordering::Equal => (),
+        ordering => return ordering
+    }
+
+    // Rule 8: prefer smaller scope.
+    match scope(lhs).cmp(&scope(rhs)) {
+        Ordering::Equal => (),
+        ordering => return ordering
+    }
+
+    // Rule 9: use longest matching prefix, within an address family.
+    if lhs.to_unspecified() == rhs.to_unspecified() {
+        return candidate_prefix_len(rhs_src, rhs).cmp(&candidate_prefix_len(lhs_src, lhs))
+    }
+
+    // Rule 10: otherwise, leave the order unchanged.
+    Ordering::Equal
+}
+
+/// Sort `dst_addrs` so that the most preferred destination address comes first,
+/// using `select_source` to find the source address that would be used for each
+/// of them.
+///
+/// The sort is stable, so that destinations that compare equal keep their order.
+pub fn sort_destinations<F>(dst_addrs: &mut [IpAddress], mut select_source: F)
+        where F: FnMut(&IpAddress) -> Option<Candidate> {
+    // An insertion sort does not need any storage, and lists of destinations are short.
+    for index in 1..dst_addrs.len() {
+        let mut probe = index;
+        while probe > 0 {
+            let (lhs, rhs) = (dst_addrs[probe - 1], dst_addrs[probe]);
+            let (lhs_src, rhs_src) = (select_source(&lhs), select_source(&rhs));
+            match compare_destinations(&lhs, lhs_src.as_ref(), &rhs, rhs_src.as_ref()) {
+                Ordering::Greater => dst_addrs.swap(probe - 1, probe),
+                _ => break
+            }
+            probe -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(cidr: IpCidr) -> Candidate {
+        Candidate { cidr: cidr, deprecated: false }
+    }
+
+    #[test]
+    fn test_common_prefix_len() {
+        assert_eq!(common_prefix_len(&[0x20, 0x01], &[0x20, 0x01]), 16);
+        assert_eq!(common_prefix_len(&[0x20, 0x01], &[0x20, 0x02]), 14);
+        assert_eq!(common_prefix_len(&[0x80, 0x00], &[0x00, 0x00]), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_policy() {
+        assert_eq!(policy(&IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1)), (50, 0));
+        assert_eq!(policy(&IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)), (40, 1));
+        assert_eq!(policy(&IpAddress::v6(0x2001, 0, 0x4136, 0, 0, 0, 0, 1)), (5, 5));
+        assert_eq!(policy(&IpAddress::v6(0x2002, 0xc633, 0x6401, 0, 0, 0, 0, 1)), (30, 2));
+        assert_eq!(policy(&IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1)), (3, 13));
+        assert_eq!(policy(&IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 1)), (40, 1));
+        assert_eq!(policy(&IpAddress::v6(0, 0, 0, 0, 0, 0xffff, 0x0a00, 1)), (35, 4));
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_select_source_ipv6() {
+        let link_local = candidate(IpCidr::new(IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64));
+        let unique_local = candidate(IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1), 64));
+        let global = candidate(IpCidr::new(IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 64));
+        let candidates = [link_local, unique_local, global];
+
+        // Rule 1: prefer the same address.
+        assert_eq!(select_source(&unique_local.cidr.address(), candidates.iter().cloned()),
+                   Some(unique_local));
+        // Rule 2: prefer appropriate scope.
+        assert_eq!(select_source(&IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 1),
+                                 candidates.iter().cloned()),
+                   Some(global));
+        assert_eq!(select_source(&IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2),
+                                 candidates.iter().cloned()),
+                   Some(link_local));
+        assert_eq!(select_source(&IpAddress::v6(0xff02, 0, 0, 0, 0, 0, 0, 1),
+                                 candidates.iter().cloned()),
+                   Some(link_local));
+        // Rule 6: prefer matching label.
+        assert_eq!(select_source(&IpAddress::v6(0xfd12, 0, 0, 0, 0, 0, 0, 1),
+                                 candidates.iter().cloned()),
+                   Some(unique_local));
+        // Rule 8: use longest matching prefix.
+        let other_global = candidate(IpCidr::new(IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 1), 64));
+        assert_eq!(select_source(&IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 2),
+                                 [global, other_global].iter().cloned()),
+                   Some(other_global));
+        // Rule 3: avoid deprecated addresses, even if they have a longer matching prefix.
+        let deprecated = Candidate { deprecated: true, ..other_global };
+        assert_eq!(select_source(&IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 2),
+                                 [global, deprecated].iter().cloned()),
+                   Some(global));
+        assert_eq!(select_source(&IpAddress::v6(0x2001, 0xdb9, 0, 0, 0, 0, 0, 2),
+                                 [deprecated].iter().cloned()),
+                   Some(deprecated));
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_select_source_ipv4() {
+        let link_local = candidate(IpCidr::new(IpAddress::v4(169, 254, 3, 4), 16));
+        let private = candidate(IpCidr::new(IpAddress::v4(192, 168, 1, 2), 24));
+        let other = candidate(IpCidr::new(IpAddress::v4(10, 0, 0, 2), 8));
+        let candidates = [link_local, private, other];
+
+        assert_eq!(select_source(&IpAddress::v4(10, 1, 2, 3), candidates.iter().cloned()),
+                   Some(other));
+        assert_eq!(select_source(&IpAddress::v4(192, 168, 1, 1), candidates.iter().cloned()),
+                   Some(private));
+        assert_eq!(select_source(&IpAddress::v4(169, 254, 1, 1), candidates.iter().cloned()),
+                   Some(link_local));
+        // Outside of the subnets, the longest matching prefix still applies.
+        assert_eq!(select_source(&IpAddress::v4(8, 8, 8, 8), candidates.iter().cloned()),
+                   Some(other));
+        assert_eq!(select_source(&IpAddress::v4(200, 1, 1, 1), candidates.iter().cloned()),
+                   Some(private));
+        assert_eq!(select_source(&IpAddress::v4(8, 8, 8, 8), [link_local].iter().cloned()),
+                   Some(link_local));
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv6")]
+    fn test_sort_destinations() {
+        let link_local = candidate(IpCidr::new(IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64));
+        let global = candidate(IpCidr::new(IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 64));
+        let unique_local = candidate(IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1), 64));
+
+        // Prefer matching scope: with only a link-local address, a global destination
+        // is less preferred than a link-local one.
+        let mut dst_addrs = [IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                             IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2)];
+        sort_destinations(&mut dst_addrs, |dst_addr|
+            select_source(dst_addr, [link_local].iter().cloned()));
+        assert_eq!(dst_addrs, [IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2),
+                               IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)]);
+
+        // Prefer higher precedence: a global destination is preferred over a
+        // unique local one, if both have a source address with a matching label.
+        let mut dst_addrs = [IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 2),
+                             IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)];
+        sort_destinations(&mut dst_addrs, |dst_addr|
+            select_source(dst_addr, [global, unique_local].iter().cloned()));
+        assert_eq!(dst_addrs, [IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                               IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 2)]);
+
+        // Avoid unusable destinations, and keep the order otherwise.
+        let mut dst_addrs = [IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                             IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 3)];
+        sort_destinations(&mut dst_addrs, |dst_addr|
+            select_source(dst_addr, [global].iter().cloned()));
+        assert_eq!(dst_addrs, [IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                               IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 3)]);
+        let mut dst_addrs = [IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2),
+                             IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)];
+        sort_destinations(&mut dst_addrs, |_| None);
+        assert_eq!(dst_addrs, [IpAddress::v6(0xfe80, 0, 0, 0, 0, 0, 0, 2),
+                               IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)]);
+    }
+}
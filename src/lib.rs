@@ -91,7 +91,6 @@ compile_error!("at least one socket needs to be enabled"); */
 // FIXME(dlrobertson): clippy fails with this lint
 #![cfg_attr(feature = "cargo-clippy", allow(if_same_then_else))]
 
-#[cfg(feature = "proto-ipv6")]
 #[macro_use]
 extern crate bitflags;
 extern crate byteorder;
@@ -99,7 +98,8 @@ extern crate managed;
 #[cfg(any(test, feature = "std"))]
 #[macro_use]
 extern crate std;
-#[cfg(any(feature = "phy-raw_socket", feature = "phy-tap_interface"))]
+#[cfg(any(feature = "phy-raw_socket", feature = "phy-tap_interface",
+          feature = "phy-packet_ring", feature = "phy-udp_tunnel"))]
 extern crate libc;
 #[cfg(feature = "alloc")]
 extern crate alloc;
@@ -112,6 +112,8 @@ use core::fmt;
 #[macro_use]
 mod macros;
 mod parsers;
+#[cfg(feature = "proto-ipv4")]
+mod rand;
 
 pub mod storage;
 pub mod phy;
@@ -131,6 +133,9 @@ pub enum Error {
     /// E.g. there was no an Ethernet address corresponding to an IPv4 address in the ARP cache,
     /// or a TCP connection attempt was made to an unspecified endpoint.
     Unaddressable,
+    /// A remote host reported that an endpoint is unreachable.
+    /// E.g. an ICMP port unreachable message was received by a connected UDP socket.
+    Unreachable,
 
     /// An incoming packet could not be parsed because some of its fields were out of bounds
     /// of the received data.
@@ -163,6 +168,7 @@ impl fmt::Display for Error {
             &Error::Exhausted     => write!(f, "buffer space exhausted"),
             &Error::Illegal       => write!(f, "illegal operation"),
             &Error::Unaddressable => write!(f, "unaddressable destination"),
+            &Error::Unreachable   => write!(f, "unreachable destination"),
             &Error::Truncated     => write!(f, "truncated packet"),
             &Error::Checksum      => write!(f, "checksum error"),
             &Error::Unrecognized  => write!(f, "unrecognized packet"),
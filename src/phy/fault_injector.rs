@@ -1,7 +1,11 @@
 use core::cell::RefCell;
+use core::fmt;
+use managed::ManagedSlice;
+#[cfg(all(feature = "std", unix))]
+use std::os::unix::io::RawFd;
 
 use {Error, Result};
-use phy::{self, DeviceCapabilities, Device};
+use phy::{self, Checksum, DeviceCapabilities, Device, Pollable};
 use time::{Duration, Instant};
 
 // We use our own RNG to stay compatible with #![no_std].
@@ -23,18 +27,111 @@ struct Config {
     corrupt_pct: u8,
     drop_pct:    u8,
     reorder_pct: u8,
+    duplicate_pct: u8,
+    burst_enter_pct: u8,
+    burst_leave_pct: u8,
+    burst_drop_pct:  u8,
     max_size:    usize,
     max_tx_rate: u64,
     max_rx_rate: u64,
     interval:    Duration,
+    delay:       Duration,
+    jitter:      Duration,
 }
 
-#[derive(Debug, Clone)]
+/// A packet held back by a fault injector.
+///
+/// See [FaultInjector::set_delay_queue](struct.FaultInjector.html#method.set_delay_queue).
+#[derive(Clone, Copy)]
+pub struct HeldFrame {
+    release_at: Instant,
+    order:      u32,
+    length:     usize,
+    buffer:     [u8; MTU],
+}
+
+impl HeldFrame {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+}
+
+impl fmt::Debug for HeldFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HeldFrame {{ release_at: {}, length: {} }}", self.release_at, self.length)
+    }
+}
+
+struct Queue {
+    frames:     ManagedSlice<'static, Option<HeldFrame>>,
+    next_order: u32,
+}
+
+impl Queue {
+    fn new() -> Queue {
+        Queue {
+            frames:     ManagedSlice::Borrowed(&mut []),
+            next_order: 0,
+        }
+    }
+
+    fn hold(&mut self, release_at: Instant, frame: &[u8]) -> bool {
+        match self.frames.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                let mut held = HeldFrame {
+                    release_at: release_at,
+                    order:      self.next_order,
+                    length:     frame.len(),
+                    buffer:     [0; MTU],
+                };
+                held.buffer[..frame.len()].copy_from_slice(frame);
+                *slot = Some(held);
+                self.next_order = self.next_order.wrapping_add(1);
+                true
+            }
+            None => false
+        }
+    }
+
+    // Frames released at the same time leave in the order they were held.
+    fn next_due(&self, timestamp: Instant) -> Option<usize> {
+        self.frames.iter().enumerate()
+            .filter_map(|(index, slot)|
+                slot.as_ref().map(|held| (index, held.release_at, held.order)))
+            .filter(|&(_, release_at, _)| release_at <= timestamp)
+            .min_by_key(|&(_, release_at, order)|
+                (release_at, order.wrapping_sub(self.next_order)))
+            .map(|(index, _, _)| index)
+    }
+
+    fn next_release(&self) -> Option<Instant> {
+        self.frames.iter()
+            .filter_map(|slot| slot.as_ref().map(|held| held.release_at))
+            .min()
+    }
+
+    fn take(&mut self, index: usize) -> Option<HeldFrame> {
+        self.frames[index].take()
+    }
+}
+
+impl fmt::Debug for Queue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let held = self.frames.iter().filter(|slot| slot.is_some()).count();
+        write!(f, "Queue {{ held: {} }}", held)
+    }
+}
+
+#[derive(Debug)]
 struct State {
     rng_seed:    u32,
     refilled_at: Instant,
     tx_bucket:   u64,
     rx_bucket:   u64,
+    tx_burst:    bool,
+    rx_burst:    bool,
+    now:         Instant,
+    queue:       Queue,
 }
 
 impl State {
@@ -42,6 +139,51 @@ impl State {
         xorshift32(&mut self.rng_seed) % 100 < pct as u32
     }
 
+    // Gilbert-Elliott model: the channel flips between a good state, where packets are lost
+    // with the regular drop chance, and a bad state, where they are lost with the burst
+    // drop chance.
+    fn maybe_burst_drop(&mut self, config: &Config, burst: bool) -> (bool, bool) {
+        let burst =
+            if burst {
+                !self.maybe(config.burst_leave_pct)
+            } else {
+                config.burst_enter_pct > 0 && self.maybe(config.burst_enter_pct)
+            };
+        (burst, burst && self.maybe(config.burst_drop_pct))
+    }
+
+    fn maybe_tx_burst_drop(&mut self, config: &Config) -> bool {
+        let (burst, drop) = { let burst = self.tx_burst; self.maybe_burst_drop(config, burst) };
+        self.tx_burst = burst;
+        drop
+    }
+
+    fn maybe_rx_burst_drop(&mut self, config: &Config) -> bool {
+        let (burst, drop) = { let burst = self.rx_burst; self.maybe_burst_drop(config, burst) };
+        self.rx_burst = burst;
+        drop
+    }
+
+    // Return the instant at which a frame transmitted now should be released,
+    // or `None` if it should be transmitted right away.
+    fn release_at(&mut self, config: &Config, timestamp: Instant) -> Option<Instant> {
+        let zero = Duration::from_millis(0);
+        if config.delay == zero && config.jitter == zero { return None }
+        // Like netem, reorder frames by letting them skip the delay.
+        if config.reorder_pct > 0 && self.maybe(config.reorder_pct) { return None }
+
+        let mut delay = config.delay;
+        if config.jitter > zero {
+            let jitter = xorshift32(&mut self.rng_seed) as u64 % (config.jitter.total_millis() + 1);
+            delay += Duration::from_millis(jitter);
+        }
+        Some(timestamp + delay)
+    }
+
+    fn maybe_duplicate(&mut self, config: &Config) -> bool {
+        config.duplicate_pct > 0 && self.maybe(config.duplicate_pct)
+    }
+
     fn corrupt<T: AsMut<[u8]>>(&mut self, mut buffer: T) {
         let buffer = buffer.as_mut();
         // We introduce a single bitflip, as the most likely, and the hardest to detect, error.
@@ -88,6 +230,14 @@ impl State {
 /// A fault injector is a device that alters packets traversing through it to simulate
 /// adverse network conditions (such as random packet loss or corruption), or software
 /// or hardware limitations (such as a limited number or size of usable network buffers).
+///
+/// Delay, reordering and duplication are applied to transmitted packets, which are held
+/// in a [queue] until they are due; a held packet is released the next time the fault
+/// injector is used at or after that moment, or when it is [released] explicitly.
+/// Place a fault injector on both ends of a link to impair both directions.
+///
+/// [queue]: #method.set_delay_queue
+/// [released]: #method.release
 #[derive(Debug)]
 pub struct FaultInjector<D: for<'a> Device<'a>> {
     inner:      D,
@@ -103,6 +253,10 @@ impl<D: for<'a> Device<'a>> FaultInjector<D> {
             refilled_at: Instant::from_millis(0),
             tx_bucket:   0,
             rx_bucket:   0,
+            tx_burst:    false,
+            rx_burst:    false,
+            now:         Instant::from_millis(0),
+            queue:       Queue::new(),
         };
         FaultInjector {
             inner: inner,
@@ -126,6 +280,41 @@ impl<D: for<'a> Device<'a>> FaultInjector<D> {
         self.config.drop_pct
     }
 
+    /// Return the probability of reordering a packet, in percents.
+    pub fn reorder_chance(&self) -> u8 {
+        self.config.reorder_pct
+    }
+
+    /// Return the probability of duplicating a packet, in percents.
+    pub fn duplicate_chance(&self) -> u8 {
+        self.config.duplicate_pct
+    }
+
+    /// Return the probability of entering a loss burst, in percents per packet.
+    pub fn burst_enter_chance(&self) -> u8 {
+        self.config.burst_enter_pct
+    }
+
+    /// Return the probability of leaving a loss burst, in percents per packet.
+    pub fn burst_leave_chance(&self) -> u8 {
+        self.config.burst_leave_pct
+    }
+
+    /// Return the probability of dropping a packet during a loss burst, in percents.
+    pub fn burst_drop_chance(&self) -> u8 {
+        self.config.burst_drop_pct
+    }
+
+    /// Return the fixed delay of transmitted packets.
+    pub fn delay(&self) -> Duration {
+        self.config.delay
+    }
+
+    /// Return the maximum random delay of transmitted packets, added to the fixed delay.
+    pub fn delay_jitter(&self) -> Duration {
+        self.config.jitter
+    }
+
     /// Return the maximum packet size, in octets.
     pub fn max_packet_size(&self) -> usize {
         self.config.max_size
@@ -164,6 +353,75 @@ impl<D: for<'a> Device<'a>> FaultInjector<D> {
         self.config.drop_pct = pct
     }
 
+    /// Set the probability of reordering a packet, in percents.
+    ///
+    /// Reordered packets are transmitted without the delay, overtaking the packets
+    /// that are held back; hence, reordering has no effect unless a delay is set.
+    ///
+    /// # Panics
+    /// This function panics if the probability is not between 0% and 100%.
+    pub fn set_reorder_chance(&mut self, pct: u8) {
+        if pct > 100 { panic!("percentage out of range") }
+        self.config.reorder_pct = pct
+    }
+
+    /// Set the probability of duplicating a packet, in percents.
+    ///
+    /// Duplicates are held in the [delay queue](#method.set_delay_queue) until
+    /// the fault injector is used again.
+    ///
+    /// # Panics
+    /// This function panics if the probability is not between 0% and 100%.
+    pub fn set_duplicate_chance(&mut self, pct: u8) {
+        if pct > 100 { panic!("percentage out of range") }
+        self.config.duplicate_pct = pct
+    }
+
+    /// Set the probability of entering a loss burst, in percents per packet.
+    ///
+    /// Together with [set_burst_leave_chance](#method.set_burst_leave_chance) and
+    /// [set_burst_drop_chance](#method.set_burst_drop_chance), this configures
+    /// a Gilbert-Elliott model of bursty packet loss. Outside of a burst, packets are
+    /// dropped with the [drop chance](#method.set_drop_chance).
+    ///
+    /// # Panics
+    /// This function panics if the probability is not between 0% and 100%.
+    pub fn set_burst_enter_chance(&mut self, pct: u8) {
+        if pct > 100 { panic!("percentage out of range") }
+        self.config.burst_enter_pct = pct
+    }
+
+    /// Set the probability of leaving a loss burst, in percents per packet.
+    ///
+    /// # Panics
+    /// This function panics if the probability is not between 0% and 100%.
+    pub fn set_burst_leave_chance(&mut self, pct: u8) {
+        if pct > 100 { panic!("percentage out of range") }
+        self.config.burst_leave_pct = pct
+    }
+
+    /// Set the probability of dropping a packet during a loss burst, in percents.
+    ///
+    /// # Panics
+    /// This function panics if the probability is not between 0% and 100%.
+    pub fn set_burst_drop_chance(&mut self, pct: u8) {
+        if pct > 100 { panic!("percentage out of range") }
+        self.config.burst_drop_pct = pct
+    }
+
+    /// Set the fixed delay of transmitted packets.
+    ///
+    /// Delayed packets are held in the [delay queue](#method.set_delay_queue);
+    /// packets that do not fit in it are dropped.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.config.delay = delay
+    }
+
+    /// Set the maximum random delay of transmitted packets, added to the fixed delay.
+    pub fn set_delay_jitter(&mut self, jitter: Duration) {
+        self.config.jitter = jitter
+    }
+
     /// Set the maximum packet size, in octets.
     pub fn set_max_packet_size(&mut self, size: usize) {
         self.config.max_size = size
@@ -184,6 +442,58 @@ impl<D: for<'a> Device<'a>> FaultInjector<D> {
         self.state.borrow_mut().refilled_at = Instant::from_millis(0);
         self.config.interval = interval
     }
+
+    /// Set the storage for the packets held back by delay and duplication, replacing
+    /// any packets already held.
+    ///
+    /// As many packets as there are elements in the storage can be held at once.
+    /// Without a delay queue, delayed and duplicated packets are dropped.
+    pub fn set_delay_queue<S>(&mut self, storage: S)
+            where S: Into<ManagedSlice<'static, Option<HeldFrame>>> {
+        let mut frames = storage.into();
+        for slot in frames.iter_mut() {
+            *slot = None
+        }
+        self.state.borrow_mut().queue.frames = frames
+    }
+
+    /// Return the instant at which the next held packet is due, if any.
+    ///
+    /// The fault injector must be used or [released](#method.release) at that moment
+    /// for the packet to be transmitted, so this should be taken into account when
+    /// waiting for the interface to need polling.
+    pub fn poll_at(&self) -> Option<Instant> {
+        self.state.borrow().queue.next_release()
+    }
+
+    /// Transmit the held packets that are due at the given instant.
+    pub fn release(&mut self, timestamp: Instant) {
+        {
+            let mut state = self.state.borrow_mut();
+            if timestamp > state.now { state.now = timestamp }
+        }
+        self.release_due()
+    }
+
+    // Transmit the held packets that are due, as of the last time the device was used.
+    fn release_due(&mut self) {
+        let &mut Self { ref mut inner, ref state, .. } = self;
+        let mut state = state.borrow_mut();
+        let now = state.now;
+        while let Some(index) = state.queue.next_due(now) {
+            let token = match inner.transmit() {
+                Some(token) => token,
+                None => break
+            };
+            if let Some(held) = state.queue.take(index) {
+                net_trace!("tx: releasing a delayed packet");
+                let _ = phy::TxToken::consume(token, now, held.length, |buffer| {
+                    buffer.copy_from_slice(held.as_ref());
+                    Ok(())
+                });
+            }
+        }
+    }
 }
 
 impl<'a, D> Device<'a> for FaultInjector<D>
@@ -197,10 +507,16 @@ impl<'a, D> Device<'a> for FaultInjector<D>
         if caps.max_transmission_unit > MTU {
             caps.max_transmission_unit = MTU;
         }
+        // Frames are buffered and corrupted here, so the lower device cannot be
+        // asked to finish them.
+        caps.max_segmentation_size = None;
+        if caps.checksum.tcp.partial() { caps.checksum.tcp = Checksum::Both }
+        if caps.checksum.udp.partial() { caps.checksum.udp = Checksum::Both }
         caps
     }
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        self.release_due();
         let &mut Self { ref mut inner, ref state, config } = self;
         inner.receive().map(|(rx_token, tx_token)| {
             let rx = RxToken {
@@ -220,6 +536,7 @@ impl<'a, D> Device<'a> for FaultInjector<D>
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        self.release_due();
         let &mut Self { ref mut inner, ref state, config } = self;
         inner.transmit().map(|token| TxToken {
             state:  &state,
@@ -228,6 +545,23 @@ impl<'a, D> Device<'a> for FaultInjector<D>
             junk:   [0; MTU],
         })
     }
+
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+}
+
+impl<D> Pollable for FaultInjector<D>
+    where D: for<'a> Device<'a> + Pollable,
+{
+    #[cfg(all(feature = "std", unix))]
+    fn pollable_fd(&self) -> Option<RawFd> {
+        self.inner.pollable_fd()
+    }
+
+    fn set_rx_callback(&mut self, callback: fn()) -> bool {
+        self.inner.set_rx_callback(callback)
+    }
 }
 
 #[doc(hidden)]
@@ -242,10 +576,15 @@ impl<'a, Rx: phy::RxToken> phy::RxToken for RxToken<'a, Rx> {
     fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&[u8]) -> Result<R>
     {
+        self.state.borrow_mut().now = timestamp;
         if self.state.borrow_mut().maybe(self.config.drop_pct) {
             net_trace!("rx: randomly dropping a packet");
             return Err(Error::Exhausted)
         }
+        if self.state.borrow_mut().maybe_rx_burst_drop(&self.config) {
+            net_trace!("rx: dropping a packet in a loss burst");
+            return Err(Error::Exhausted)
+        }
         if !self.state.borrow_mut().maybe_receive(&self.config, timestamp) {
             net_trace!("rx: dropping a packet because of rate limiting");
             return Err(Error::Exhausted)
@@ -281,9 +620,13 @@ impl<'a, Tx: phy::TxToken> phy::TxToken for TxToken<'a, Tx> {
     fn consume<R, F>(mut self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
+        self.state.borrow_mut().now = timestamp;
         let drop = if self.state.borrow_mut().maybe(self.config.drop_pct) {
             net_trace!("tx: randomly dropping a packet");
             true
+        } else if self.state.borrow_mut().maybe_tx_burst_drop(&self.config) {
+            net_trace!("tx: dropping a packet in a loss burst");
+            true
         } else if self.config.max_size > 0 && len > self.config.max_size {
             net_trace!("tx: dropping a packet that is too large");
             true
@@ -298,13 +641,176 @@ impl<'a, Tx: phy::TxToken> phy::TxToken for TxToken<'a, Tx> {
             return f(&mut self.junk);
         }
 
-        let Self { token, state, config, .. } = self;
-        token.consume(timestamp, len, |mut buf| {
-            if state.borrow_mut().maybe(config.corrupt_pct) {
-                net_trace!("tx: corrupting a packet");
-                state.borrow_mut().corrupt(&mut buf)
+        let release_at = self.state.borrow_mut().release_at(&self.config, timestamp);
+        let duplicate  = self.state.borrow_mut().maybe_duplicate(&self.config);
+        let Self { token, state, config, mut junk } = self;
+        match release_at {
+            None => {
+                token.consume(timestamp, len, |mut buf| {
+                    if state.borrow_mut().maybe(config.corrupt_pct) {
+                        net_trace!("tx: corrupting a packet");
+                        state.borrow_mut().corrupt(&mut buf)
+                    }
+                    let result = f(buf);
+                    if duplicate && result.is_ok() {
+                        net_trace!("tx: duplicating a packet");
+                        state.borrow_mut().queue.hold(timestamp, buf);
+                    }
+                    result
+                })
             }
-            f(buf)
-        })
+            Some(release_at) => {
+                let buf = &mut junk[..len];
+                let result = f(buf)?;
+                let mut state = state.borrow_mut();
+                if state.maybe(config.corrupt_pct) {
+                    net_trace!("tx: corrupting a packet");
+                    state.corrupt(&mut *buf)
+                }
+                net_trace!("tx: delaying a packet until {}", release_at);
+                if !state.queue.hold(release_at, buf) {
+                    net_trace!("tx: dropping a packet because the delay queue is full");
+                } else if duplicate {
+                    net_trace!("tx: duplicating a packet");
+                    state.queue.hold(release_at, buf);
+                }
+                // Use the transmit opportunity for a held packet that is due, if any.
+                if let Some(held) = state.queue.next_due(timestamp)
+                                         .and_then(|index| state.queue.take(index)) {
+                    token.consume(timestamp, held.length, |buffer| {
+                        buffer.copy_from_slice(held.as_ref());
+                        Ok(())
+                    })?;
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::vec::Vec;
+    use phy::{Loopback, RxToken, TxToken};
+    use super::*;
+
+    fn create_device() -> FaultInjector<Loopback> {
+        let mut device = FaultInjector::new(Loopback::new(), 1);
+        device.set_delay_queue(vec![None; 4]);
+        device
+    }
+
+    fn send(device: &mut FaultInjector<Loopback>, timestamp: i64, value: u8) {
+        let token = device.transmit().unwrap();
+        token.consume(Instant::from_millis(timestamp), 1, |buffer| {
+            buffer[0] = value;
+            Ok(())
+        }).unwrap();
+    }
+
+    fn recv_all(device: &mut FaultInjector<Loopback>, timestamp: i64) -> Vec<u8> {
+        let mut values = Vec::new();
+        while let Some((token, _)) = device.receive() {
+            token.consume(Instant::from_millis(timestamp), |buffer| {
+                values.push(buffer[0]);
+                Ok(())
+            }).unwrap();
+        }
+        values
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut device = create_device();
+        device.set_delay(Duration::from_millis(10));
+        send(&mut device, 0, 1);
+        assert_eq!(recv_all(&mut device, 0), vec![]);
+        send(&mut device, 5, 2);
+        assert_eq!(recv_all(&mut device, 5), vec![]);
+        send(&mut device, 10, 3);
+        assert_eq!(recv_all(&mut device, 10), vec![1]);
+        send(&mut device, 20, 4);
+        assert_eq!(recv_all(&mut device, 20), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_delay_queue_full() {
+        let mut device = create_device();
+        device.set_delay(Duration::from_millis(10));
+        for value in 0..5 {
+            send(&mut device, 0, value);
+        }
+        send(&mut device, 10, 0xff);
+        assert_eq!(recv_all(&mut device, 10), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_delay_without_queue() {
+        let mut device = FaultInjector::new(Loopback::new(), 1);
+        device.set_delay(Duration::from_millis(10));
+        send(&mut device, 0, 1);
+        assert_eq!(device.poll_at(), None);
+        send(&mut device, 10, 2);
+        assert_eq!(recv_all(&mut device, 10), vec![]);
+    }
+
+    #[test]
+    fn test_poll_at_and_release() {
+        let mut device = create_device();
+        device.set_delay(Duration::from_millis(10));
+        device.set_delay_jitter(Duration::from_millis(5));
+        assert_eq!(device.poll_at(), None);
+        send(&mut device, 0, 1);
+        send(&mut device, 0, 2);
+        let release_at = device.poll_at().unwrap();
+        assert!(release_at >= Instant::from_millis(10) && release_at <= Instant::from_millis(15));
+
+        device.release(release_at - Duration::from_millis(1));
+        assert_eq!(device.poll_at(), Some(release_at));
+        device.release(Instant::from_millis(15));
+        assert_eq!(device.poll_at(), None);
+        let mut values = recv_all(&mut device, 15);
+        values.sort();
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut device = create_device();
+        device.set_delay(Duration::from_millis(10));
+        send(&mut device, 0, 1);
+        device.set_reorder_chance(100);
+        send(&mut device, 1, 2);
+        assert_eq!(recv_all(&mut device, 1), vec![2]);
+        device.set_reorder_chance(0);
+        send(&mut device, 10, 3);
+        assert_eq!(recv_all(&mut device, 10), vec![1]);
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let mut device = create_device();
+        device.set_duplicate_chance(100);
+        send(&mut device, 0, 1);
+        assert_eq!(recv_all(&mut device, 0), vec![1, 1]);
+
+        device.set_delay(Duration::from_millis(10));
+        send(&mut device, 0, 2);
+        send(&mut device, 10, 3);
+        assert_eq!(recv_all(&mut device, 10), vec![2, 2]);
+    }
+
+    #[test]
+    fn test_burst_loss() {
+        let mut device = create_device();
+        device.set_burst_enter_chance(100);
+        device.set_burst_drop_chance(100);
+        send(&mut device, 0, 1);
+        send(&mut device, 0, 2);
+        assert_eq!(recv_all(&mut device, 0), vec![]);
+        device.set_burst_enter_chance(0);
+        device.set_burst_leave_chance(100);
+        send(&mut device, 0, 3);
+        assert_eq!(recv_all(&mut device, 0), vec![3]);
     }
 }
@@ -0,0 +1,609 @@
+use core::str::FromStr;
+use managed::ManagedSlice;
+
+use {Error, Result};
+use wire::{EthernetFrame, EthernetProtocol, IpAddress, IpCidr, IpProtocol};
+#[cfg(any(feature = "proto-ipv4", feature = "proto-ipv6"))]
+use wire::{TcpPacket, UdpPacket};
+#[cfg(feature = "proto-ipv4")]
+use wire::Ipv4Packet;
+#[cfg(feature = "proto-ipv6")]
+use wire::Ipv6Packet;
+
+// The maximum depth of the evaluation stack, and of the nesting of parsed expressions.
+const MAX_DEPTH: usize = 32;
+
+bitflags! {
+    /// TCP flags, as matched by a [filter](struct.Filter.html).
+    pub struct FilterTcpFlags: u8 {
+        const FIN = 0x01;
+        const SYN = 0x02;
+        const RST = 0x04;
+        const PSH = 0x08;
+        const ACK = 0x10;
+        const URG = 0x20;
+        const ECE = 0x40;
+        const CWR = 0x80;
+    }
+}
+
+/// The address or port a [filter](struct.Filter.html) operation looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterSide {
+    /// The source address or port.
+    Src,
+    /// The destination address or port.
+    Dst,
+    /// Either the source or the destination address or port.
+    SrcOrDst
+}
+
+/// A [filter](struct.Filter.html) operation.
+///
+/// Every operation that matches a packet field pushes the result of the match
+/// onto the evaluation stack; the logical operations pop their operands from it
+/// and push the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    /// Match frames with the given EtherType.
+    EtherType(EthernetProtocol),
+    /// Match IP packets carrying the given protocol.
+    IpProtocol(IpProtocol),
+    /// Match IP packets with the given address.
+    Host(FilterSide, IpAddress),
+    /// Match IP packets with an address within the given network.
+    Net(FilterSide, IpCidr),
+    /// Match TCP or UDP packets with the given port.
+    Port(FilterSide, u16),
+    /// Match TCP packets with any of the given flags set.
+    TcpFlags(FilterTcpFlags),
+    /// Negate the result of the previous operation.
+    Not,
+    /// Combine the results of the two previous operations, matching if both do.
+    And,
+    /// Combine the results of the two previous operations, matching if either does.
+    Or
+}
+
+impl FilterOp {
+    /// An operation that may be used to fill the storage given to
+    /// [Filter::parse](struct.Filter.html#method.parse).
+    pub const EMPTY: FilterOp = FilterOp::Not;
+}
+
+#[derive(Debug, Default)]
+struct Fields {
+    ethertype: Option<EthernetProtocol>,
+    ip:        Option<(IpAddress, IpAddress, IpProtocol)>,
+    ports:     Option<(u16, u16)>,
+    tcp_flags: Option<FilterTcpFlags>,
+}
+
+impl Fields {
+    fn ethernet(frame: &[u8]) -> Fields {
+        match EthernetFrame::new_checked(frame) {
+            Ok(frame) => Fields::ip(frame.ethertype(), frame.payload()),
+            Err(_) => Fields::default()
+        }
+    }
+
+    fn ip(ethertype: EthernetProtocol, packet: &[u8]) -> Fields {
+        let mut fields = Fields { ethertype: Some(ethertype), ..Fields::default() };
+        match ethertype {
+            #[cfg(feature = "proto-ipv4")]
+            EthernetProtocol::Ipv4 => {
+                if let Ok(packet) = Ipv4Packet::new_checked(packet) {
+                    fields.ip = Some((packet.src_addr().into(), packet.dst_addr().into(),
+                                      packet.protocol()));
+                    // Only the first fragment carries the transport header.
+                    if packet.frag_offset() == 0 {
+                        fields.transport(packet.protocol(), packet.payload())
+                    }
+                }
+            }
+            #[cfg(feature = "proto-ipv6")]
+            EthernetProtocol::Ipv6 => {
+                if let Ok(packet) = Ipv6Packet::new_checked(packet) {
+                    fields.ip = Some((packet.src_addr().into(), packet.dst_addr().into(),
+                                      packet.next_header()));
+                    fields.transport(packet.next_header(), packet.payload())
+                }
+            }
+            _ => {
+                let _ = packet;
+            }
+        }
+        fields
+    }
+
+    #[cfg(any(feature = "proto-ipv4", feature = "proto-ipv6"))]
+    fn transport(&mut self, protocol: IpProtocol, payload: &[u8]) {
+        match protocol {
+            IpProtocol::Tcp => {
+                if let Ok(packet) = TcpPacket::new_checked(payload) {
+                    let mut flags = FilterTcpFlags::empty();
+                    flags.set(FilterTcpFlags::FIN, packet.fin());
+                    flags.set(FilterTcpFlags::SYN, packet.syn());
+                    flags.set(FilterTcpFlags::RST, packet.rst());
+                    flags.set(FilterTcpFlags::PSH, packet.psh());
+                    flags.set(FilterTcpFlags::ACK, packet.ack());
+                    flags.set(FilterTcpFlags::URG, packet.urg());
+                    flags.set(FilterTcpFlags::ECE, packet.ece());
+                    flags.set(FilterTcpFlags::CWR, packet.cwr());
+                    self.ports = Some((packet.src_port(), packet.dst_port()));
+                    self.tcp_flags = Some(flags)
+                }
+            }
+            IpProtocol::Udp => {
+                if let Ok(packet) = UdpPacket::new_checked(payload) {
+                    self.ports = Some((packet.src_port(), packet.dst_port()))
+                }
+            }
+            _ => ()
+        }
+    }
+
+    fn matches<T, F: Fn(T) -> bool>(side: FilterSide, pair: Option<(T, T)>, f: F) -> bool {
+        match (side, pair) {
+            (FilterSide::Src, Some((src, _))) => f(src),
+            (FilterSide::Dst, Some((_, dst))) => f(dst),
+            (FilterSide::SrcOrDst, Some((src, dst))) => f(src) || f(dst),
+            (_, None) => false
+        }
+    }
+
+    fn addrs(&self) -> Option<(IpAddress, IpAddress)> {
+        self.ip.map(|(src_addr, dst_addr, _)| (src_addr, dst_addr))
+    }
+}
+
+/// A packet filter.
+///
+/// A filter is a program of [operations](enum.FilterOp.html) in postfix order,
+/// evaluated against the Ethernet, IP, TCP and UDP headers of a packet, e.g.
+/// `[Port(SrcOrDst, 80), TcpFlags(SYN), And]` matches TCP or UDP packets to or
+/// from port 80 that are TCP packets with the SYN flag set. A filter without
+/// operations matches every packet.
+///
+/// A filter may also be parsed from an expression in the spirit of [tcpdump],
+/// such as `tcp and (port 80 or port 443) and not src net 10.0.0.0/8`.
+/// The following primitives are supported, and may be combined with
+/// `and` (`&&`), `or` (`||`), `not` (`!`) and parentheses:
+///
+///   * `ip`, `ip6`, `arp` and `ether proto <protocol or number>`;
+///   * `tcp`, `udp`, `icmp`, `icmp6`, `igmp` and `proto <protocol or number>`;
+///   * `[src|dst] host <address>`, `[src|dst] net <cidr>` and `[src|dst] port <number>`;
+///   * `tcp-fin`, `tcp-syn`, `tcp-rst`, `tcp-push`, `tcp-ack`, `tcp-urg`, `tcp-ece`
+///     and `tcp-cwr`.
+///
+/// IPv6 extension headers are not skipped, and so e.g. a fragmented IPv6 packet
+/// does not match `tcp`.
+///
+/// [tcpdump]: https://www.tcpdump.org/manpages/pcap-filter.7.html
+#[derive(Debug)]
+pub struct Filter<'a> {
+    ops: ManagedSlice<'a, FilterOp>,
+    len: usize,
+}
+
+impl<'a> Filter<'a> {
+    /// Create a filter from the given operations.
+    ///
+    /// Returns `Err(Error::Malformed)` if the operations do not leave exactly one result
+    /// on the evaluation stack, which is at most 32 results deep.
+    pub fn new<T>(ops: T) -> Result<Filter<'a>>
+            where T: Into<ManagedSlice<'a, FilterOp>> {
+        let ops = ops.into();
+        let len = ops.len();
+        Filter::validate(&ops[..])?;
+        Ok(Filter { ops, len })
+    }
+
+    /// Parse a filter expression into the given storage.
+    ///
+    /// Returns `Err(Error::Malformed)` if the expression is invalid, and
+    /// `Err(Error::Exhausted)` if the storage is too small to hold it, or if it nests
+    /// more than 32 levels of negations and parentheses.
+    pub fn parse<T>(expr: &str, storage: T) -> Result<Filter<'a>>
+            where T: Into<ManagedSlice<'a, FilterOp>> {
+        let mut ops = storage.into();
+        let len = {
+            let mut parser = Parser { tokens: Tokens { rest: expr }, peeked: None,
+                                      ops: &mut ops[..], len: 0, depth: 0 };
+            if parser.peek().is_some() {
+                parser.expr()?;
+                if parser.next().is_some() { return Err(Error::Malformed) }
+            }
+            parser.len
+        };
+        Filter::validate(&ops[..len])?;
+        Ok(Filter { ops, len })
+    }
+
+    fn validate(ops: &[FilterOp]) -> Result<()> {
+        let mut depth = 0;
+        for op in ops {
+            match *op {
+                FilterOp::Not if depth >= 1 => (),
+                FilterOp::And | FilterOp::Or if depth >= 2 => depth -= 1,
+                FilterOp::Not | FilterOp::And | FilterOp::Or => return Err(Error::Malformed),
+                _ if depth < MAX_DEPTH => depth += 1,
+                _ => return Err(Error::Malformed)
+            }
+        }
+        match depth {
+            0 if ops.is_empty() => Ok(()),
+            1 => Ok(()),
+            _ => Err(Error::Malformed)
+        }
+    }
+
+    /// Return the operations of the filter.
+    pub fn ops(&self) -> &[FilterOp] {
+        &self.ops[..self.len]
+    }
+
+    /// Check whether the given Ethernet frame matches the filter.
+    pub fn matches(&self, frame: &[u8]) -> bool {
+        self.len == 0 || self.eval(&Fields::ethernet(frame))
+    }
+
+    /// Check whether the given IPv4 or IPv6 packet matches the filter.
+    pub fn matches_ip(&self, packet: &[u8]) -> bool {
+        if self.len == 0 { return true }
+        let ethertype = match packet.first().map(|byte| byte >> 4) {
+            Some(4) => EthernetProtocol::Ipv4,
+            Some(6) => EthernetProtocol::Ipv6,
+            _ => return self.eval(&Fields::default())
+        };
+        self.eval(&Fields::ip(ethertype, packet))
+    }
+
+    fn eval(&self, fields: &Fields) -> bool {
+        let mut stack = 0u32;
+        for op in self.ops() {
+            let result = match *op {
+                FilterOp::EtherType(ethertype) =>
+                    fields.ethertype == Some(ethertype),
+                FilterOp::IpProtocol(protocol) =>
+                    fields.ip.map(|(_, _, ip_protocol)| ip_protocol == protocol)
+                             .unwrap_or(false),
+                FilterOp::Host(side, addr) =>
+                    Fields::matches(side, fields.addrs(), |ip_addr| ip_addr == addr),
+                FilterOp::Net(side, cidr) =>
+                    Fields::matches(side, fields.addrs(), |ip_addr| cidr.contains_addr(&ip_addr)),
+                FilterOp::Port(side, port) =>
+                    Fields::matches(side, fields.ports, |packet_port| packet_port == port),
+                FilterOp::TcpFlags(flags) =>
+                    fields.tcp_flags.map(|tcp_flags| tcp_flags.intersects(flags))
+                                    .unwrap_or(false),
+                FilterOp::Not => {
+                    stack ^= 1;
+                    continue
+                }
+                FilterOp::And | FilterOp::Or => {
+                    let (rhs, lhs) = (stack & 1 != 0, stack & 2 != 0);
+                    stack >>= 2;
+                    if *op == FilterOp::And { lhs && rhs } else { lhs || rhs }
+                }
+            };
+            stack = (stack << 1) | result as u32;
+        }
+        stack & 1 != 0
+    }
+}
+
+struct Tokens<'s> {
+    rest: &'s str,
+}
+
+impl<'s> Iterator for Tokens<'s> {
+    type Item = &'s str;
+
+    fn next(&mut self) -> Option<&'s str> {
+        let start = self.rest.find(|c: char| !c.is_whitespace()).unwrap_or(self.rest.len());
+        let rest = &self.rest[start..];
+        if rest.is_empty() { return None }
+
+        let len =
+            if rest.starts_with("&&") || rest.starts_with("||") {
+                2
+            } else if rest.starts_with('(') || rest.starts_with(')') || rest.starts_with('!') {
+                1
+            } else {
+                rest.find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                    .unwrap_or(rest.len())
+            };
+        self.rest = &rest[len..];
+        Some(&rest[..len])
+    }
+}
+
+struct Parser<'s, 'o> {
+    tokens: Tokens<'s>,
+    peeked: Option<&'s str>,
+    ops:    &'o mut [FilterOp],
+    len:    usize,
+    depth:  usize,
+}
+
+impl<'s, 'o> Parser<'s, 'o> {
+    fn peek(&mut self) -> Option<&'s str> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next()
+        }
+        self.peeked
+    }
+
+    fn next(&mut self) -> Option<&'s str> {
+        self.peek();
+        self.peeked.take()
+    }
+
+    fn accept(&mut self, words: &[&str]) -> bool {
+        match self.peek() {
+            Some(token) if words.contains(&token) => {
+                self.peeked = None;
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn emit(&mut self, op: FilterOp) -> Result<()> {
+        if self.len == self.ops.len() { return Err(Error::Exhausted) }
+        self.ops[self.len] = op;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<()> {
+        self.and_expr()?;
+        while self.accept(&["or", "||"]) {
+            self.and_expr()?;
+            self.emit(FilterOp::Or)?
+        }
+        Ok(())
+    }
+
+    fn and_expr(&mut self) -> Result<()> {
+        self.unary_expr()?;
+        while self.accept(&["and", "&&"]) {
+            self.unary_expr()?;
+            self.emit(FilterOp::And)?
+        }
+        Ok(())
+    }
+
+    fn unary_expr(&mut self) -> Result<()> {
+        if self.accept(&["not", "!"]) {
+            self.nested(|parser| parser.unary_expr())?;
+            self.emit(FilterOp::Not)
+        } else if self.accept(&["("]) {
+            self.nested(|parser| parser.expr())?;
+            if !self.accept(&[")"]) { return Err(Error::Malformed) }
+            Ok(())
+        } else {
+            let op = self.primitive()?;
+            self.emit(op)
+        }
+    }
+
+    // Bound the recursion, since expressions may come from untrusted input.
+    fn nested<F>(&mut self, f: F) -> Result<()>
+            where F: FnOnce(&mut Self) -> Result<()> {
+        if self.depth == MAX_DEPTH { return Err(Error::Exhausted) }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn value<T: FromStr>(&mut self) -> Result<T> {
+        self.next().and_then(|token| T::from_str(token).ok()).ok_or(Error::Malformed)
+    }
+
+    fn primitive(&mut self) -> Result<FilterOp> {
+        let side =
+            if self.accept(&["src"]) {
+                FilterSide::Src
+            } else if self.accept(&["dst"]) {
+                FilterSide::Dst
+            } else {
+                FilterSide::SrcOrDst
+            };
+        match self.next() {
+            Some("host") => Ok(FilterOp::Host(side, self.value()?)),
+            Some("net")  => Ok(FilterOp::Net(side, self.value()?)),
+            Some("port") => Ok(FilterOp::Port(side, self.value()?)),
+            _ if side != FilterSide::SrcOrDst => Err(Error::Malformed),
+            Some("ether") => {
+                if !self.accept(&["proto"]) { return Err(Error::Malformed) }
+                match self.next() {
+                    Some("ip")  => Ok(FilterOp::EtherType(EthernetProtocol::Ipv4)),
+                    Some("ip6") => Ok(FilterOp::EtherType(EthernetProtocol::Ipv6)),
+                    Some("arp") => Ok(FilterOp::EtherType(EthernetProtocol::Arp)),
+                    Some(token) => u16::from_str(token)
+                        .map(|value| FilterOp::EtherType(EthernetProtocol::from(value)))
+                        .map_err(|_| Error::Malformed),
+                    None => Err(Error::Malformed)
+                }
+            }
+            Some("proto") => {
+                match self.next().and_then(protocol) {
+                    Some(protocol) => Ok(FilterOp::IpProtocol(protocol)),
+                    None => Err(Error::Malformed)
+                }
+            }
+            Some("ip")  => Ok(FilterOp::EtherType(EthernetProtocol::Ipv4)),
+            Some("ip6") => Ok(FilterOp::EtherType(EthernetProtocol::Ipv6)),
+            Some("arp") => Ok(FilterOp::EtherType(EthernetProtocol::Arp)),
+            Some("tcp-fin")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::FIN)),
+            Some("tcp-syn")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::SYN)),
+            Some("tcp-rst")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::RST)),
+            Some("tcp-push") => Ok(FilterOp::TcpFlags(FilterTcpFlags::PSH)),
+            Some("tcp-ack")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::ACK)),
+            Some("tcp-urg")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::URG)),
+            Some("tcp-ece")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::ECE)),
+            Some("tcp-cwr")  => Ok(FilterOp::TcpFlags(FilterTcpFlags::CWR)),
+            Some(token) => match protocol(token) {
+                Some(protocol) if u8::from_str(token).is_err() =>
+                    Ok(FilterOp::IpProtocol(protocol)),
+                _ => Err(Error::Malformed)
+            },
+            None => Err(Error::Malformed)
+        }
+    }
+}
+
+fn protocol(token: &str) -> Option<IpProtocol> {
+    match token {
+        "tcp"   => Some(IpProtocol::Tcp),
+        "udp"   => Some(IpProtocol::Udp),
+        "icmp"  => Some(IpProtocol::Icmp),
+        "icmp6" => Some(IpProtocol::Icmpv6),
+        "igmp"  => Some(IpProtocol::Igmp),
+        _ => u8::from_str(token).ok().map(IpProtocol::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse<'a>(expr: &str, storage: &'a mut [FilterOp]) -> Result<&'a [FilterOp]> {
+        let len = Filter::parse(expr, &mut storage[..])?.ops().len();
+        Ok(&storage[..len])
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut storage = [FilterOp::EMPTY; 8];
+        assert_eq!(parse("", &mut storage), Ok(&[][..]));
+        assert_eq!(parse("tcp and (port 80 || dst port 443) and !tcp-syn", &mut storage),
+                   Ok(&[FilterOp::IpProtocol(IpProtocol::Tcp),
+                        FilterOp::Port(FilterSide::SrcOrDst, 80),
+                        FilterOp::Port(FilterSide::Dst, 443),
+                        FilterOp::Or,
+                        FilterOp::And,
+                        FilterOp::TcpFlags(FilterTcpFlags::SYN),
+                        FilterOp::Not,
+                        FilterOp::And][..]));
+        assert_eq!(parse("arp or ether proto 2048 or proto 17", &mut storage),
+                   Ok(&[FilterOp::EtherType(EthernetProtocol::Arp),
+                        FilterOp::EtherType(EthernetProtocol::Ipv4),
+                        FilterOp::Or,
+                        FilterOp::IpProtocol(IpProtocol::Udp),
+                        FilterOp::Or][..]));
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let mut storage = [FilterOp::EMPTY; 4];
+        assert_eq!(parse("tcp and", &mut storage), Err(Error::Malformed));
+        assert_eq!(parse("(tcp", &mut storage), Err(Error::Malformed));
+        assert_eq!(parse("tcp udp", &mut storage), Err(Error::Malformed));
+        assert_eq!(parse("src tcp", &mut storage), Err(Error::Malformed));
+        assert_eq!(parse("port http", &mut storage), Err(Error::Malformed));
+        assert_eq!(parse("17", &mut storage), Err(Error::Malformed));
+        assert_eq!(parse("tcp or udp or icmp", &mut storage), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let mut storage = [FilterOp::EMPTY; 40];
+        let expr = format!("{}tcp{}", "(".repeat(32), ")".repeat(32));
+        assert_eq!(parse(&expr, &mut storage), Ok(&[FilterOp::IpProtocol(IpProtocol::Tcp)][..]));
+        let expr = format!("{}tcp{}", "(".repeat(33), ")".repeat(33));
+        assert_eq!(parse(&expr, &mut storage), Err(Error::Exhausted));
+        let expr = format!("{}tcp", "not ".repeat(33));
+        assert_eq!(parse(&expr, &mut storage), Err(Error::Exhausted));
+        let expr = format!("{}tcp", "! (".repeat(100_000));
+        assert_eq!(parse(&expr, &mut storage), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn test_new_malformed() {
+        assert!(Filter::new(&mut [][..]).is_ok());
+        assert_eq!(Filter::new(&mut [FilterOp::Not][..]).err(), Some(Error::Malformed));
+        assert_eq!(Filter::new(&mut [FilterOp::IpProtocol(IpProtocol::Tcp),
+                                     FilterOp::And][..]).err(), Some(Error::Malformed));
+        assert_eq!(Filter::new(&mut [FilterOp::IpProtocol(IpProtocol::Tcp); 2][..]).err(),
+                   Some(Error::Malformed));
+    }
+
+    #[cfg(all(feature = "proto-ipv4", feature = "std"))]
+    mod ipv4 {
+        use std::vec::Vec;
+        use wire::{EthernetAddress, Ipv4Address, Ipv4Repr, TcpRepr, TcpControl, TcpSeqNumber};
+        use phy::ChecksumCapabilities;
+        use super::*;
+
+        fn tcp_frame(src_port: u16, dst_port: u16, control: TcpControl) -> Vec<u8> {
+            let tcp_repr = TcpRepr {
+                src_port, dst_port, control,
+                seq_number:   TcpSeqNumber(0),
+                ack_number:   None,
+                window_len:   0,
+                window_scale: None,
+                max_seg_size: None,
+                sack_permitted: false,
+                sack_ranges:  [None, None, None],
+                ece:          false,
+                cwr:          false,
+                payload:      &[]
+            };
+            let ip_repr = Ipv4Repr {
+                src_addr:      Ipv4Address::new(10, 0, 0, 1),
+                dst_addr:      Ipv4Address::new(192, 168, 1, 2),
+                protocol:      IpProtocol::Tcp,
+                payload_len:   tcp_repr.buffer_len(),
+                hop_limit:     64,
+                traffic_class: 0,
+                router_alert:  false
+            };
+            let mut buffer = vec![0; 14 + ip_repr.buffer_len() + tcp_repr.buffer_len()];
+            {
+                let checksum_caps = ChecksumCapabilities::default();
+                let mut frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+                frame.set_src_addr(EthernetAddress([0x02, 0, 0, 0, 0, 1]));
+                frame.set_dst_addr(EthernetAddress([0x02, 0, 0, 0, 0, 2]));
+                frame.set_ethertype(EthernetProtocol::Ipv4);
+                let mut packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+                ip_repr.emit(&mut packet, &checksum_caps);
+                tcp_repr.emit(&mut TcpPacket::new_unchecked(packet.payload_mut()),
+                              &ip_repr.src_addr.into(), &ip_repr.dst_addr.into(),
+                              &checksum_caps);
+            }
+            buffer
+        }
+
+        fn matches(expr: &str, frame: &[u8]) -> bool {
+            let mut storage = [FilterOp::EMPTY; 16];
+            let filter = Filter::parse(expr, &mut storage[..]).unwrap();
+            assert_eq!(filter.matches(frame), filter.matches_ip(&frame[14..]));
+            filter.matches(frame)
+        }
+
+        #[test]
+        fn test_matches() {
+            let syn = tcp_frame(49152, 80, TcpControl::Syn);
+            assert!(matches("", &syn));
+            assert!(matches("ip and tcp", &syn));
+            assert!(!matches("ip6 or udp or arp", &syn));
+            assert!(matches("port 80 and src port 49152 and not dst port 49152", &syn));
+            assert!(matches("src host 10.0.0.1 and dst net 192.168.1.0/24", &syn));
+            assert!(!matches("dst host 10.0.0.1 or src net 192.168.0.0/16", &syn));
+            assert!(matches("tcp-syn and not (tcp-fin or tcp-rst)", &syn));
+            assert!(!matches("tcp-syn", &tcp_frame(49152, 80, TcpControl::Fin)));
+        }
+
+        #[test]
+        fn test_matches_malformed() {
+            let mut storage = [FilterOp::EMPTY; 2];
+            let filter = Filter::parse("not tcp", &mut storage[..]).unwrap();
+            assert!(filter.matches(&[0; 4]));
+            assert!(filter.matches_ip(&[]));
+        }
+    }
+}
@@ -5,10 +5,15 @@ for transmitting and receiving frames, [Device](trait.Device.html)
 and implementations of it:
 
   * the [_loopback_](struct.Loopback.html), for zero dependency testing;
+  * the [_switch_](struct.Switch.html), joining several interfaces within a single process;
+  * the [_pcap replay_](struct.PcapReplay.html), replaying a capture for regression testing;
   * _middleware_ [Tracer](struct.Tracer.html) and
-    [FaultInjector](struct.FaultInjector.html), to facilitate debugging;
-  * _adapters_ [RawSocket](struct.RawSocket.html) and
-    [TapInterface](struct.TapInterface.html), to transmit and receive frames
+    [FaultInjector](struct.FaultInjector.html), to facilitate debugging,
+    with a [Filter](struct.Filter.html) selecting the packets of interest;
+  * _adapters_ [RawSocket](struct.RawSocket.html),
+    [PacketRing](struct.PacketRing.html),
+    [TapInterface](struct.TapInterface.html) and
+    [UdpTunnel](struct.UdpTunnel.html), to transmit and receive frames
     on the host OS.
 
 # Examples
@@ -84,34 +89,57 @@ impl<'a> phy::TxToken for StmPhyTxToken<'a> {
 ```
 */
 
+#[cfg(all(feature = "std", unix))]
+use std::os::unix::io::RawFd;
+
 use Result;
 use time::Instant;
 
-#[cfg(all(any(feature = "phy-raw_socket", feature = "phy-tap_interface"), unix))]
+#[cfg(all(any(feature = "phy-raw_socket", feature = "phy-tap_interface",
+              feature = "phy-packet_ring", feature = "phy-udp_tunnel"), unix))]
 mod sys;
 
 mod tracer;
+mod filter;
 mod fault_injector;
 mod pcap_writer;
 #[cfg(any(feature = "std", feature = "alloc"))]
+mod pcap_replay;
+#[cfg(any(feature = "std", feature = "alloc"))]
 mod loopback;
+#[cfg(any(feature = "std", feature = "alloc"))]
+mod switch;
 #[cfg(all(feature = "phy-raw_socket", unix))]
 mod raw_socket;
 #[cfg(all(feature = "phy-tap_interface", target_os = "linux"))]
 mod tap_interface;
+#[cfg(all(feature = "phy-packet_ring", target_os = "linux"))]
+mod packet_ring;
+#[cfg(all(feature = "phy-udp_tunnel", unix))]
+mod udp_tunnel;
 
-#[cfg(all(any(feature = "phy-raw_socket", feature = "phy-tap_interface"), unix))]
-pub use self::sys::wait;
+#[cfg(all(any(feature = "phy-raw_socket", feature = "phy-tap_interface",
+              feature = "phy-packet_ring", feature = "phy-udp_tunnel"), unix))]
+pub use self::sys::{wait, WaitSet};
 
 pub use self::tracer::Tracer;
-pub use self::fault_injector::FaultInjector;
-pub use self::pcap_writer::{PcapLinkType, PcapMode, PcapSink, PcapWriter};
+pub use self::filter::{Filter, FilterOp, FilterSide, FilterTcpFlags};
+pub use self::fault_injector::{FaultInjector, HeldFrame};
+pub use self::pcap_writer::{PcapDirection, PcapLinkType, PcapMode, PcapSink, PcapWriter};
+#[cfg(any(feature = "std", feature = "alloc"))]
+pub use self::pcap_replay::{PcapReader, PcapReplay};
 #[cfg(any(feature = "std", feature = "alloc"))]
 pub use self::loopback::Loopback;
+#[cfg(any(feature = "std", feature = "alloc"))]
+pub use self::switch::{Switch, SwitchPort};
 #[cfg(all(feature = "phy-raw_socket", unix))]
 pub use self::raw_socket::RawSocket;
 #[cfg(all(feature = "phy-tap_interface", target_os = "linux"))]
 pub use self::tap_interface::TapInterface;
+#[cfg(all(feature = "phy-packet_ring", target_os = "linux"))]
+pub use self::packet_ring::PacketRing;
+#[cfg(all(feature = "phy-udp_tunnel", unix))]
+pub use self::udp_tunnel::UdpTunnel;
 
 /// A tracer device for Ethernet frames.
 pub type EthernetTracer<T> = Tracer<T, super::wire::EthernetFrame<&'static [u8]>>;
@@ -127,6 +155,14 @@ pub enum Checksum {
     Tx,
     /// Ignore checksum completely.
     None,
+    /// Verify checksum when receiving, unless the device reports it as
+    /// [valid](struct.RxOffload.html#structfield.checksum_valid); when sending, fill in
+    /// only the pseudo-header checksum and let the device complete it, in the same way
+    /// as virtio-net does.
+    ///
+    /// Only TCP and UDP can be partially checksummed; every other protocol treats
+    /// this the same as `Both`.
+    Partial,
 }
 
 impl Default for Checksum {
@@ -139,15 +175,26 @@ impl Checksum {
     /// Returns whether checksum should be verified when receiving.
     pub fn rx(&self) -> bool {
         match *self {
-            Checksum::Both | Checksum::Rx => true,
+            Checksum::Both | Checksum::Rx | Checksum::Partial => true,
             _ => false
         }
     }
 
-    /// Returns whether checksum should be verified when sending.
+    /// Returns whether checksum should be computed when sending.
+    ///
+    /// This is also true for `Partial`, since protocols other than TCP and UDP
+    /// cannot be finished by the device; TCP and UDP check `partial()` first.
     pub fn tx(&self) -> bool {
         match *self {
-            Checksum::Both | Checksum::Tx => true,
+            Checksum::Both | Checksum::Tx | Checksum::Partial => true,
+            _ => false
+        }
+    }
+
+    /// Returns whether only the pseudo-header checksum should be computed when sending.
+    pub fn partial(&self) -> bool {
+        match *self {
+            Checksum::Partial => true,
             _ => false
         }
     }
@@ -209,6 +256,14 @@ pub struct DeviceCapabilities {
     /// The set of protocols for which checksum can be computed in hardware.
     pub checksum: ChecksumCapabilities,
 
+    /// Maximum size of a frame carrying a TCP segment that the device can split
+    /// into frames of at most the MTU itself (TCP segmentation offload), if any.
+    ///
+    /// Such a frame is transmitted with a [segment size](struct.TxOffload.html) set,
+    /// and its headers are replicated in every resulting frame. If `None`, every frame
+    /// is at most the MTU.
+    pub max_segmentation_size: Option<usize>,
+
     /// Only present to prevent people from trying to initialize every field of DeviceLimits,
     /// which would not let us add new fields in the future.
     dummy: ()
@@ -236,6 +291,54 @@ pub trait Device<'a> {
 
     /// Get a description of device capabilities.
     fn capabilities(&self) -> DeviceCapabilities;
+
+    /// Send out any frames queued by consumed transmit tokens.
+    ///
+    /// A device may queue transmitted frames to send them in batches; an interface calls
+    /// this function at the end of every poll. The default implementation does nothing.
+    fn flush(&mut self) {}
+}
+
+/// A device that can notify the application once it has frames to receive.
+///
+/// An application may use this to sleep between polls of one or several interfaces,
+/// e.g. with a [WaitSet](struct.WaitSet.html) on a hosted OS, or until an interrupt
+/// on bare metal. Every method has a default implementation that reports no support.
+pub trait Pollable {
+    /// Return a host file descriptor that becomes readable once the device has frames
+    /// to receive, if any.
+    #[cfg(all(feature = "std", unix))]
+    fn pollable_fd(&self) -> Option<RawFd> { None }
+
+    /// Set a function to be called, e.g. from an interrupt handler, once the device
+    /// has frames to receive.
+    ///
+    /// Returns `false` if the device cannot notify the application this way.
+    fn set_rx_callback(&mut self, _callback: fn()) -> bool { false }
+}
+
+/// Offload information accompanying a received frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RxOffload {
+    /// Whether the device has already verified every checksum in the frame,
+    /// e.g. because it coalesced the frame from several received TCP segments,
+    /// which may make the frame larger than the MTU.
+    pub checksum_valid: bool,
+}
+
+/// Offload requests accompanying a transmitted frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxOffload {
+    /// The offset of the first octet covered by a [partial checksum](enum.Checksum.html)
+    /// the device has to complete, if any.
+    pub checksum_start: Option<usize>,
+    /// The offset of the partial checksum field from `checksum_start`.
+    pub checksum_offset: usize,
+    /// The largest payload of every TCP segment the device has to split the frame into,
+    /// if the frame is larger than the MTU.
+    pub segment_size: Option<usize>,
+    /// The length of the link, IP and TCP headers to replicate in every segment.
+    pub header_len: usize,
 }
 
 /// A token to receive a single network packet.
@@ -249,6 +352,13 @@ pub trait RxToken {
     /// arbitrary moment in time, such as system startup.
     fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&[u8]) -> Result<R>;
+
+    /// Return the offload information for the packet this token receives.
+    ///
+    /// The default implementation reports that no offloads were performed.
+    fn offload(&self) -> RxOffload {
+        RxOffload::default()
+    }
 }
 
 /// A token to transmit a single network packet.
@@ -264,4 +374,16 @@ pub trait TxToken {
     /// arbitrary moment in time, such as system startup.
     fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>;
+
+    /// Consumes the token to send a single network packet that requires the given offloads.
+    ///
+    /// This is only used if the [capabilities](struct.DeviceCapabilities.html) of the device
+    /// include the requested offloads. The default implementation ignores them and calls
+    /// [consume](#tymethod.consume).
+    fn consume_with_offload<R, F>(self, timestamp: Instant, len: usize, _offload: TxOffload,
+                                  f: F) -> Result<R>
+        where Self: Sized, F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        self.consume(timestamp, len, f)
+    }
 }
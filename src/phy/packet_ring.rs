@@ -0,0 +1,147 @@
+use std::io;
+use std::os::unix::io::{RawFd, AsRawFd};
+
+use {Error, Result};
+use phy::{self, Pollable, sys, DeviceCapabilities, Device};
+use time::Instant;
+use wire::EthernetFrame;
+
+/// The number of blocks in the RX ring.
+const RX_BLOCKS: usize = 64;
+/// The number of blocks in the TX ring.
+const TX_BLOCKS: usize = 16;
+
+/// A packet socket that exchanges complete frames with the kernel through memory-mapped rings.
+///
+/// This is an alternative to [RawSocket](struct.RawSocket.html) on Linux that uses
+/// the `PACKET_RX_RING` and `PACKET_TX_RING` (TPACKET_V3) facility. Received frames are
+/// lent to the stack straight from the RX ring, and transmitted frames are written straight
+/// into the TX ring, so neither involves a copy or a system call per frame. Transmitted frames
+/// are sent once the TX ring is full or the device is [flushed](trait.Device.html#method.flush),
+/// which an interface does at the end of every poll.
+#[derive(Debug)]
+pub struct PacketRing {
+    rx:     sys::PacketRx,
+    tx:     sys::PacketTx,
+    mtu:    usize,
+    lower:  sys::PacketRingDesc
+}
+
+impl AsRawFd for PacketRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.lower.as_raw_fd()
+    }
+}
+
+impl Pollable for PacketRing {
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
+
+impl PacketRing {
+    /// Creates a packet socket with RX and TX rings, bound to the interface called `name`.
+    ///
+    /// This requires superuser privileges or a corresponding capability bit
+    /// set on the executable.
+    pub fn new(name: &str) -> io::Result<PacketRing> {
+        let mut lower = sys::PacketRingDesc::new(name)?;
+        let mtu = lower.interface_mtu()?;
+        let (rx, tx) = lower.map_rings(EthernetFrame::<&[u8]>::buffer_len(mtu),
+                                       RX_BLOCKS, TX_BLOCKS)?;
+        lower.bind_interface()?;
+        Ok(PacketRing {
+            rx:    rx,
+            tx:    tx,
+            mtu:   mtu,
+            lower: lower
+        })
+    }
+}
+
+impl<'a> Device<'a> for PacketRing {
+    type RxToken = RxToken<'a>;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            max_transmission_unit: self.mtu,
+            ..DeviceCapabilities::default()
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let &mut PacketRing { ref mut rx, ref mut tx, .. } = self;
+        rx.recv().map(move |buffer| {
+            let rx = RxToken { buffer };
+            let tx = TxToken { tx };
+            (rx, tx)
+        })
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        if !self.tx.is_available() {
+            flush(&mut self.tx);
+            if !self.tx.is_available() { return None }
+        }
+        Some(TxToken { tx: &mut self.tx })
+    }
+
+    fn flush(&mut self) {
+        flush(&mut self.tx)
+    }
+}
+
+impl Drop for PacketRing {
+    fn drop(&mut self) {
+        flush(&mut self.tx)
+    }
+}
+
+fn flush(tx: &mut sys::PacketTx) {
+    let pending = tx.pending();
+    match tx.flush() {
+        Ok(()) => (),
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+            net_debug!("packet ring: {} frames left in the ring", pending);
+        }
+        Err(err) => panic!("{}", err)
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<'a> {
+    buffer: &'a [u8]
+}
+
+impl<'a> phy::RxToken for RxToken<'a> {
+    fn consume<R, F: FnOnce(&[u8]) -> Result<R>>(self, _timestamp: Instant, f: F) -> Result<R> {
+        f(self.buffer)
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a> {
+    tx: &'a mut sys::PacketTx
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> Result<R>>(self, _timestamp: Instant, len: usize, f: F)
+        -> Result<R>
+    {
+        let TxToken { tx } = self;
+        if !tx.is_available() {
+            // The ring filled up since this token was handed out along with a received frame.
+            flush(tx);
+            if !tx.is_available() {
+                net_debug!("packet ring: TX ring is full");
+                return Err(Error::Exhausted)
+            }
+        }
+        let result = f(tx.frame_mut(len));
+        if result.is_ok() {
+            tx.commit(len)
+        }
+        result
+    }
+}
@@ -0,0 +1,525 @@
+#[cfg(feature = "std")]
+use std::vec::Vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
+
+use {Error, Result};
+use phy::{self, Device, DeviceCapabilities, PcapLinkType, PcapSink};
+use time::Instant;
+use wire::{EthernetAddress, EthernetFrame, EthernetProtocol};
+
+const GLOBAL_HEADER_LEN: usize = 24;
+const PACKET_HEADER_LEN: usize = 16;
+
+const DEFAULT_PEER_ADDR: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+/// A packet capture reader.
+///
+/// The reader parses a capture in the [libpcap] format, in either byte order and
+/// with either microsecond or nanosecond timestamps, and returns its packets in order.
+///
+/// [libpcap]: https://wiki.wireshark.org/Development/LibpcapFileFormat
+#[derive(Debug)]
+pub struct PcapReader<T: AsRef<[u8]>> {
+    capture:    T,
+    offset:     usize,
+    big_endian: bool,
+    nanos:      bool,
+    link_type:  PcapLinkType,
+}
+
+impl<T: AsRef<[u8]>> PcapReader<T> {
+    /// Create a reader for the given capture.
+    ///
+    /// Returns `Err(Error::Truncated)` if the capture is shorter than the global header,
+    /// and `Err(Error::Unrecognized)` if it does not start with a libpcap magic number.
+    pub fn new(capture: T) -> Result<PcapReader<T>> {
+        let (big_endian, nanos, link_type) = {
+            let data = capture.as_ref();
+            if data.len() < GLOBAL_HEADER_LEN { return Err(Error::Truncated) }
+            let (big_endian, nanos) = match LittleEndian::read_u32(&data[0..4]) {
+                0xa1b2c3d4 => (false, false),
+                0xd4c3b2a1 => (true,  false),
+                0xa1b23c4d => (false, true),
+                0x4d3cb2a1 => (true,  true),
+                _ => return Err(Error::Unrecognized)
+            };
+            let link_type = read_u32(big_endian, &data[20..24]);
+            (big_endian, nanos, PcapLinkType::from(link_type))
+        };
+        Ok(PcapReader {
+            capture:    capture,
+            offset:     GLOBAL_HEADER_LEN,
+            big_endian: big_endian,
+            nanos:      nanos,
+            link_type:  link_type,
+        })
+    }
+
+    /// Return the link-layer header type of the capture.
+    pub fn link_type(&self) -> PcapLinkType {
+        self.link_type
+    }
+
+    /// Return the timestamp of the next packet, or `None` if there are no more packets.
+    pub fn next_timestamp(&self) -> Option<Instant> {
+        self.peek().map(|(timestamp, _, _)| timestamp)
+    }
+
+    /// Return the next packet and its timestamp, or `None` if there are no more packets.
+    ///
+    /// A truncated record at the end of the capture, as left e.g. by an interrupted
+    /// capture, is treated as the end of the capture.
+    pub fn next_packet(&mut self) -> Option<(Instant, &[u8])> {
+        let (timestamp, start, end) = self.peek()?;
+        self.offset = end;
+        Some((timestamp, &self.capture.as_ref()[start..end]))
+    }
+
+    fn peek(&self) -> Option<(Instant, usize, usize)> {
+        let data = &self.capture.as_ref()[self.offset..];
+        if data.len() < PACKET_HEADER_LEN {
+            if data.len() > 0 {
+                net_debug!("pcap: ignoring a truncated packet header");
+            }
+            return None
+        }
+
+        let secs   = read_u32(self.big_endian, &data[0..4]) as i64;
+        let frac   = read_u32(self.big_endian, &data[4..8]) as i64;
+        let length = read_u32(self.big_endian, &data[8..12]) as usize;
+        if data.len() - PACKET_HEADER_LEN < length {
+            net_debug!("pcap: ignoring a truncated packet");
+            return None
+        }
+
+        let millis = if self.nanos { frac / 1_000_000 } else { frac / 1_000 };
+        let start = self.offset + PACKET_HEADER_LEN;
+        Some((Instant::from_millis(secs * 1000 + millis), start, start + length))
+    }
+}
+
+fn read_u32(big_endian: bool, bytes: &[u8]) -> u32 {
+    if big_endian {
+        BigEndian::read_u32(bytes)
+    } else {
+        LittleEndian::read_u32(bytes)
+    }
+}
+
+/// A packet capture replay device.
+///
+/// Every packet in the capture is received through this device once the simulated
+/// clock of the device reaches its timestamp, and every packet transmitted through it
+/// is written (in the [libpcap] format, with the link-layer header type of the capture)
+/// into the provided [sink], where it can be collected, or compared against an expected
+/// capture using a [PcapReader]. This makes it possible to check the behavior of an
+/// interface against recorded traffic, e.g. in regression tests:
+///
+/// ```rust,ignore
+/// while let Some(timestamp) = iface.device().next_timestamp() {
+///     iface.device_mut().advance_to(timestamp);
+///     iface.poll(&mut sockets, timestamp)?;
+/// }
+/// ```
+///
+/// The clock only moves forward, either when [advanced](#method.advance_to) explicitly,
+/// or to the timestamp passed when a token is consumed.
+///
+/// Both Ethernet and IP captures are supported. Packets in an IP capture are received
+/// in an Ethernet frame broadcast from the [peer address](#method.peer_addr), and only
+/// IPv4 and IPv6 packets transmitted through the device are written into the sink,
+/// without their Ethernet header.
+///
+/// [libpcap]: https://wiki.wireshark.org/Development/LibpcapFileFormat
+/// [sink]: trait.PcapSink.html
+/// [PcapReader]: struct.PcapReader.html
+#[derive(Debug)]
+pub struct PcapReplay<T: AsRef<[u8]>, S: PcapSink> {
+    reader:    PcapReader<T>,
+    sink:      S,
+    peer_addr: EthernetAddress,
+    now:       Instant,
+}
+
+impl<T: AsRef<[u8]>, S: PcapSink> PcapReplay<T, S> {
+    /// Create a replay device for the given capture, writing the global header into `sink`.
+    ///
+    /// Returns `Err(Error::Unrecognized)` if the capture is not in the libpcap format
+    /// or has an unsupported link-layer header type, and `Err(Error::Truncated)` if it
+    /// is shorter than the global header.
+    pub fn new(capture: T, sink: S) -> Result<PcapReplay<T, S>> {
+        let reader = PcapReader::new(capture)?;
+        match reader.link_type() {
+            PcapLinkType::Ethernet | PcapLinkType::Ip => (),
+            PcapLinkType::Unknown(_) => return Err(Error::Unrecognized)
+        }
+        sink.global_header(reader.link_type());
+        Ok(PcapReplay {
+            reader:    reader,
+            sink:      sink,
+            peer_addr: DEFAULT_PEER_ADDR,
+            now:       Instant::from_millis(0),
+        })
+    }
+
+    /// Return the link-layer header type of the capture.
+    pub fn link_type(&self) -> PcapLinkType {
+        self.reader.link_type()
+    }
+
+    /// Return the timestamp of the next packet to be received, or `None` if the whole
+    /// capture has been received.
+    pub fn next_timestamp(&self) -> Option<Instant> {
+        self.reader.next_timestamp()
+    }
+
+    /// Return the current time of the simulated clock.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// Advance the simulated clock to the given time, if it is later than the current one.
+    pub fn advance_to(&mut self, timestamp: Instant) {
+        if timestamp > self.now {
+            self.now = timestamp
+        }
+    }
+
+    /// Return the address packets in an IP capture are received from.
+    ///
+    /// The default address is `02-00-00-00-00-01`.
+    pub fn peer_addr(&self) -> EthernetAddress {
+        self.peer_addr
+    }
+
+    /// Set the address packets in an IP capture are received from.
+    ///
+    /// # Panics
+    /// This function panics if the address is not unicast.
+    pub fn set_peer_addr(&mut self, addr: EthernetAddress) {
+        assert!(addr.is_unicast());
+        self.peer_addr = addr
+    }
+
+    /// Return a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Return the sink, consuming the device.
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        let link_type = self.reader.link_type;
+        loop {
+            match self.reader.next_timestamp() {
+                Some(timestamp) if timestamp <= self.now => (),
+                _ => return None
+            }
+            let (_, packet) = self.reader.next_packet()?;
+            if link_type == PcapLinkType::Ethernet {
+                return Some(packet.to_vec())
+            }
+
+            let ethertype = match packet.first().map(|byte| byte >> 4) {
+                Some(4) => EthernetProtocol::Ipv4,
+                Some(6) => EthernetProtocol::Ipv6,
+                _ => {
+                    net_debug!("pcap: ignoring a packet with an unknown IP version");
+                    continue
+                }
+            };
+            let mut buffer = Vec::new();
+            buffer.resize(EthernetFrame::<&[u8]>::buffer_len(packet.len()), 0);
+            {
+                let mut frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+                frame.set_dst_addr(EthernetAddress::BROADCAST);
+                frame.set_src_addr(self.peer_addr);
+                frame.set_ethertype(ethertype);
+                frame.payload_mut().copy_from_slice(packet);
+            }
+            return Some(buffer)
+        }
+    }
+}
+
+impl<'a, T: AsRef<[u8]>, S: PcapSink + 'a> Device<'a> for PcapReplay<T, S> {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a, S>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            max_transmission_unit: 1514,
+            ..DeviceCapabilities::default()
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let buffer = self.next_frame()?;
+        let rx = RxToken { buffer: buffer };
+        let tx = TxToken {
+            sink:      &self.sink,
+            link_type: self.reader.link_type,
+            now:       &mut self.now,
+        };
+        Some((rx, tx))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken {
+            sink:      &self.sink,
+            link_type: self.reader.link_type,
+            now:       &mut self.now,
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken {
+    buffer: Vec<u8>,
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F: FnOnce(&[u8]) -> Result<R>>(self, _timestamp: Instant, f: F) -> Result<R> {
+        f(&self.buffer)
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a, S: PcapSink + 'a> {
+    sink:      &'a S,
+    link_type: PcapLinkType,
+    now:       &'a mut Instant,
+}
+
+impl<'a, S: PcapSink> phy::TxToken for TxToken<'a, S> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        if timestamp > *self.now {
+            *self.now = timestamp
+        }
+
+        let mut buffer = Vec::new();
+        buffer.resize(len, 0);
+        let result = f(&mut buffer)?;
+        match self.link_type {
+            PcapLinkType::Ip => {
+                let frame = EthernetFrame::new_checked(&buffer[..])?;
+                match frame.ethertype() {
+                    EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6 =>
+                        self.sink.packet(timestamp, frame.payload()),
+                    _ => ()
+                }
+            }
+            _ => self.sink.packet(timestamp, &buffer)
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::cell::RefCell;
+    use phy::{RxToken, TxToken};
+    use super::*;
+
+    const ADDR_A: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0xa]);
+    const ADDR_B: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0xb]);
+
+    fn capture(link_type: PcapLinkType, packets: &[(i64, &[u8])]) -> Vec<u8> {
+        let sink = RefCell::new(Vec::new());
+        sink.global_header(link_type);
+        for &(millis, packet) in packets {
+            sink.packet(Instant::from_millis(millis), packet)
+        }
+        sink.into_inner()
+    }
+
+    fn frame(src_addr: EthernetAddress, dst_addr: EthernetAddress) -> Vec<u8> {
+        let mut buffer = vec![0; 14];
+        {
+            let mut frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+            frame.set_src_addr(src_addr);
+            frame.set_dst_addr(dst_addr);
+            frame.set_ethertype(EthernetProtocol::Ipv4);
+        }
+        buffer
+    }
+
+    fn recv<T: AsRef<[u8]>, S: PcapSink>(device: &mut PcapReplay<T, S>) -> Option<Vec<u8>> {
+        let (token, _) = device.receive()?;
+        token.consume(Instant::from_millis(0), |buffer| Ok(buffer.to_vec())).ok()
+    }
+
+    #[test]
+    fn test_reader() {
+        let data = capture(PcapLinkType::Ethernet, &[(1500, &[1, 2, 3]), (2250, &[4])]);
+        let mut reader = PcapReader::new(&data[..]).unwrap();
+        assert_eq!(reader.link_type(), PcapLinkType::Ethernet);
+        assert_eq!(reader.next_timestamp(), Some(Instant::from_millis(1500)));
+        assert_eq!(reader.next_packet(), Some((Instant::from_millis(1500), &[1, 2, 3][..])));
+        assert_eq!(reader.next_packet(), Some((Instant::from_millis(2250), &[4][..])));
+        assert_eq!(reader.next_packet(), None);
+    }
+
+    #[test]
+    fn test_reader_big_endian_nanos() {
+        let data = [
+            0xa1, 0xb2, 0x3c, 0x4d, 0x00, 0x02, 0x00, 0x04,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x65,
+            0x00, 0x00, 0x00, 0x02, 0x1d, 0xcd, 0x65, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+            0x45,
+            // truncated record
+            0x00, 0x00, 0x00, 0x03,
+        ];
+        let mut reader = PcapReader::new(&data[..]).unwrap();
+        assert_eq!(reader.link_type(), PcapLinkType::Ip);
+        assert_eq!(reader.next_packet(), Some((Instant::from_millis(2500), &[0x45][..])));
+        assert_eq!(reader.next_packet(), None);
+    }
+
+    #[test]
+    fn test_reader_malformed() {
+        assert_eq!(PcapReader::new(&[0xa1, 0xb2][..]).err(), Some(Error::Truncated));
+        assert_eq!(PcapReader::new(&[0; 24][..]).err(), Some(Error::Unrecognized));
+        let data = capture(PcapLinkType::Unknown(105), &[]);
+        assert_eq!(PcapReplay::new(&data[..], RefCell::new(Vec::new())).err(),
+                   Some(Error::Unrecognized));
+    }
+
+    #[test]
+    fn test_replay_timing() {
+        let (frame_a, frame_b) = (frame(ADDR_A, ADDR_B), frame(ADDR_B, ADDR_A));
+        let data = capture(PcapLinkType::Ethernet, &[(100, &frame_a), (200, &frame_b)]);
+        let mut device = PcapReplay::new(&data[..], RefCell::new(Vec::new())).unwrap();
+
+        assert_eq!(device.next_timestamp(), Some(Instant::from_millis(100)));
+        assert_eq!(recv(&mut device), None);
+        device.advance_to(Instant::from_millis(150));
+        assert_eq!(recv(&mut device), Some(frame_a));
+        assert_eq!(recv(&mut device), None);
+
+        // Transmitting at a later time advances the clock.
+        device.transmit().unwrap()
+            .consume(Instant::from_millis(250), 14, |buffer| {
+                buffer.copy_from_slice(&frame(ADDR_B, ADDR_B));
+                Ok(())
+            }).unwrap();
+        assert_eq!(device.now(), Instant::from_millis(250));
+        assert_eq!(recv(&mut device), Some(frame_b));
+        assert_eq!(device.next_timestamp(), None);
+
+        let sink = device.into_sink().into_inner();
+        let mut reader = PcapReader::new(&sink[..]).unwrap();
+        assert_eq!(reader.link_type(), PcapLinkType::Ethernet);
+        assert_eq!(reader.next_packet(),
+                   Some((Instant::from_millis(250), &frame(ADDR_B, ADDR_B)[..])));
+        assert_eq!(reader.next_packet(), None);
+    }
+
+    #[test]
+    fn test_replay_ip() {
+        let data = capture(PcapLinkType::Ip, &[(0, &[0x45, 0x00]), (0, &[0x10]),
+                                               (0, &[0x60, 0x00])]);
+        let mut device = PcapReplay::new(&data[..], RefCell::new(Vec::new())).unwrap();
+        device.set_peer_addr(ADDR_A);
+
+        let buffer = recv(&mut device).unwrap();
+        let frame = EthernetFrame::new_checked(&buffer[..]).unwrap();
+        assert_eq!(frame.src_addr(), ADDR_A);
+        assert_eq!(frame.dst_addr(), EthernetAddress::BROADCAST);
+        assert_eq!(frame.ethertype(), EthernetProtocol::Ipv4);
+        assert_eq!(frame.payload(), &[0x45, 0x00]);
+
+        // Packets with an unknown version are skipped.
+        let buffer = recv(&mut device).unwrap();
+        let frame = EthernetFrame::new_checked(&buffer[..]).unwrap();
+        assert_eq!(frame.ethertype(), EthernetProtocol::Ipv6);
+        assert_eq!(recv(&mut device), None);
+
+        // Only IP packets are written, without their Ethernet header.
+        for &ethertype in &[EthernetProtocol::Arp, EthernetProtocol::Ipv4] {
+            device.transmit().unwrap()
+                .consume(Instant::from_millis(0), 15, |buffer| {
+                    let mut frame = EthernetFrame::new_unchecked(buffer);
+                    frame.set_ethertype(ethertype);
+                    frame.payload_mut()[0] = 0x45;
+                    Ok(())
+                }).unwrap();
+        }
+        let sink = device.into_sink().into_inner();
+        let mut reader = PcapReader::new(&sink[..]).unwrap();
+        assert_eq!(reader.link_type(), PcapLinkType::Ip);
+        assert_eq!(reader.next_packet(), Some((Instant::from_millis(0), &[0x45][..])));
+        assert_eq!(reader.next_packet(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_replay_interface() {
+        use std::collections::BTreeMap;
+        use iface::{EthernetInterfaceBuilder, NeighborCache};
+        use socket::SocketSet;
+        use wire::{IpCidr, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr,
+                   Icmpv4Packet, Icmpv4Repr};
+        use phy::ChecksumCapabilities;
+
+        let (local_addr, remote_addr) =
+            (Ipv4Address::new(192, 168, 1, 1), Ipv4Address::new(192, 168, 1, 2));
+        let icmp_repr = Icmpv4Repr::EchoRequest { ident: 1, seq_no: 1, data: b"abcd" };
+        let ip_repr = Ipv4Repr {
+            src_addr:      remote_addr,
+            dst_addr:      local_addr,
+            protocol:      IpProtocol::Icmp,
+            payload_len:   icmp_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
+        };
+        let mut request = vec![0; ip_repr.buffer_len() + icmp_repr.buffer_len()];
+        {
+            let checksum_caps = ChecksumCapabilities::default();
+            let mut packet = Ipv4Packet::new_unchecked(&mut request[..]);
+            ip_repr.emit(&mut packet, &checksum_caps);
+            icmp_repr.emit(&mut Icmpv4Packet::new_unchecked(packet.payload_mut()),
+                           &checksum_caps);
+        }
+
+        let data = capture(PcapLinkType::Ip, &[(1000, &request), (3000, &request)]);
+        let device = PcapReplay::new(data, RefCell::new(Vec::new())).unwrap();
+        let builder = EthernetInterfaceBuilder::new(device)
+            .ethernet_addr(ADDR_B)
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(local_addr.into(), 24)]);
+        #[cfg(feature = "proto-igmp")]
+        let builder = builder.ipv4_multicast_groups(BTreeMap::new());
+        let mut iface = builder.finalize();
+        let mut sockets = SocketSet::new(vec![]);
+
+        while let Some(timestamp) = iface.device().next_timestamp() {
+            iface.device_mut().advance_to(timestamp);
+            let _ = iface.poll(&mut sockets, timestamp);
+        }
+
+        let sink = iface.device().sink().borrow();
+        let mut reader = PcapReader::new(&sink[..]).unwrap();
+        for &millis in &[1000, 3000] {
+            let (timestamp, packet) = reader.next_packet().unwrap();
+            assert_eq!(timestamp, Instant::from_millis(millis));
+            let packet = Ipv4Packet::new_checked(packet).unwrap();
+            assert_eq!(packet.src_addr(), local_addr);
+            assert_eq!(packet.dst_addr(), remote_addr);
+            let icmp_packet = Icmpv4Packet::new_checked(packet.payload()).unwrap();
+            assert_eq!(Icmpv4Repr::parse(&icmp_packet, &ChecksumCapabilities::default()),
+                       Ok(Icmpv4Repr::EchoReply { ident: 1, seq_no: 1, data: b"abcd" }));
+        }
+        assert_eq!(reader.next_packet(), None);
+    }
+}
@@ -2,10 +2,13 @@
 use std::cell::RefCell;
 #[cfg(feature = "std")]
 use std::io::Write;
+#[cfg(all(feature = "std", unix))]
+use std::os::unix::io::RawFd;
+use core::{fmt, str};
 use byteorder::{ByteOrder, NativeEndian};
 
-use Result;
-use phy::{self, DeviceCapabilities, Device};
+use {Error, Result};
+use phy::{self, DeviceCapabilities, Device, Filter, Pollable, RxOffload, TxOffload};
 use time::Instant;
 
 enum_with_unknown! {
@@ -29,6 +32,15 @@ pub enum PcapMode {
     TxOnly
 }
 
+/// Packet direction, as recorded in a pcapng capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcapDirection {
+    /// The packet was received.
+    Inbound,
+    /// The packet was transmitted.
+    Outbound
+}
+
 /// A packet capture sink.
 pub trait PcapSink {
     /// Write data into the sink.
@@ -71,7 +83,7 @@ pub trait PcapSink {
         assert!(length <= 65535);
 
         self.write_u32(timestamp.secs() as u32);   // timestamp seconds
-        self.write_u32(timestamp.millis() as u32);   // timestamp microseconds
+        self.write_u32((timestamp.millis() * 1000) as u32); // timestamp microseconds
         self.write_u32(length  as u32);   // captured length
         self.write_u32(length  as u32);   // original length
     }
@@ -83,6 +95,95 @@ pub trait PcapSink {
         self.packet_header(timestamp, packet.len());
         self.write(packet)
     }
+
+    /// Write the pcapng section header block into the sink.
+    ///
+    /// A pcapng capture starts with a section header, followed by an
+    /// [interface description](#method.interface_description) for every interface
+    /// whose packets are captured.
+    ///
+    /// See also the note for [global_header](#method.global_header).
+    fn section_header(&self) {
+        self.write_u32(0x0a0d0d0a);       // block type
+        self.write_u32(28);               // block total length
+        self.write_u32(0x1a2b3c4d);       // byte-order magic
+        self.write_u16(1);                // major version
+        self.write_u16(0);                // minor version
+        self.write_u32(0xffffffff);       // section length (= unspecified)
+        self.write_u32(0xffffffff);
+        self.write_u32(28);               // block total length
+    }
+
+    /// Write a pcapng interface description block into the sink.
+    ///
+    /// Interfaces are numbered in the order of their descriptions within a section,
+    /// starting from zero. Timestamps of their packets have nanosecond resolution.
+    ///
+    /// See also the note for [global_header](#method.global_header).
+    fn interface_description(&self, link_type: PcapLinkType, name: Option<&str>) {
+        let name_len = name.map(|name| option_len(name.len())).unwrap_or(0);
+        let length = 20 + name_len + option_len(1) + 4;
+        self.write_u32(1);                // block type
+        self.write_u32(length as u32);    // block total length
+        self.write_u16(u32::from(link_type) as u16); // link-layer header type
+        self.write_u16(0);                // reserved
+        self.write_u32(0);                // maximum packet length (= unlimited)
+        if let Some(name) = name {
+            write_option(self, 2, name.as_bytes()); // if_name
+        }
+        write_option(self, 9, &[9]);      // if_tsresol (= nanoseconds)
+        write_option(self, 0, &[]);       // opt_endofopt
+        self.write_u32(length as u32);    // block total length
+    }
+
+    /// Write a pcapng enhanced packet block, containing the packet data, its direction
+    /// and an optional comment, into the sink.
+    ///
+    /// See also the note for [global_header](#method.global_header).
+    fn enhanced_packet(&self, interface_id: u32, timestamp: Instant,
+                       direction: PcapDirection, comment: Option<&str>, packet: &[u8]) {
+        write_enhanced_packet(self, interface_id, timestamp, direction, comment, packet)
+    }
+}
+
+fn option_len(value_len: usize) -> usize {
+    4 + (value_len + 3) / 4 * 4
+}
+
+fn write_option<S: PcapSink + ?Sized>(sink: &S, code: u16, value: &[u8]) {
+    sink.write_u16(code);
+    sink.write_u16(value.len() as u16);
+    sink.write(value);
+    sink.write(&[0; 3][..option_len(value.len()) - 4 - value.len()])
+}
+
+fn write_enhanced_packet<S: PcapSink + ?Sized>(sink: &S, interface_id: u32, timestamp: Instant,
+                                               direction: PcapDirection, comment: Option<&str>,
+                                               packet: &[u8]) {
+    let comment_len = comment.map(|comment| option_len(comment.len())).unwrap_or(0);
+    let length = 28 + option_len(packet.len()) - 4 + option_len(4) + comment_len + 4 + 4;
+    let nanos = timestamp.total_millis() as u64 * 1_000_000;
+    let flags = match direction {
+        PcapDirection::Inbound  => 0b01,
+        PcapDirection::Outbound => 0b10
+    };
+    sink.write_u32(6);                    // block type
+    sink.write_u32(length as u32);        // block total length
+    sink.write_u32(interface_id);         // interface id
+    sink.write_u32((nanos >> 32) as u32); // timestamp (high)
+    sink.write_u32(nanos as u32);         // timestamp (low)
+    sink.write_u32(packet.len() as u32);  // captured length
+    sink.write_u32(packet.len() as u32);  // original length
+    sink.write(packet);
+    sink.write(&[0; 3][..option_len(packet.len()) - 4 - packet.len()]);
+    let mut bytes = [0u8; 4];
+    NativeEndian::write_u32(&mut bytes, flags);
+    write_option(sink, 2, &bytes);        // epb_flags
+    if let Some(comment) = comment {
+        write_option(sink, 1, comment.as_bytes()); // opt_comment
+    }
+    write_option(sink, 0, &[]);           // opt_endofopt
+    sink.write_u32(length as u32);        // block total length
 }
 
 impl<T: AsRef<PcapSink>> PcapSink for T {
@@ -102,35 +203,110 @@ impl<T: Write> PcapSink for RefCell<T> {
         PcapSink::write(self, packet);
         self.borrow_mut().flush().expect("cannot flush")
     }
+
+    fn enhanced_packet(&self, interface_id: u32, timestamp: Instant,
+                       direction: PcapDirection, comment: Option<&str>, packet: &[u8]) {
+        write_enhanced_packet(self, interface_id, timestamp, direction, comment, packet);
+        self.borrow_mut().flush().expect("cannot flush")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Pcap,
+    Pcapng { interface_id: u32 }
+}
+
+struct Comment {
+    buffer: [u8; 256],
+    len:    usize,
+}
+
+impl fmt::Write for Comment {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.buffer.len() - self.len < s.len() { return Err(fmt::Error) }
+        self.buffer[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
 }
 
 /// A packet capture writer device.
 ///
 /// Every packet transmitted or received through this device is timestamped
-/// and written (in the [libpcap] format) using the provided [sink].
+/// and written (in the [libpcap] or [pcapng] format) using the provided [sink].
 /// Note that writes are fine-grained, and buffering is recommended.
 ///
+/// In the pcapng format, the direction of every packet is recorded, and several
+/// devices may share a sink, each being described as a separate interface.
+/// A [comment hook](#method.set_comment_hook) may also annotate every packet.
+///
+/// Only packets matching a [filter](#method.set_filter), if any, are written.
+///
 /// The packet sink should be cheaply cloneable, as it is cloned on every
 /// transmitted packet. For example, `&'a mut Vec<u8>` is cheaply cloneable
 /// but `&std::io::File`
 ///
 /// [libpcap]: https://wiki.wireshark.org/Development/LibpcapFileFormat
+/// [pcapng]: https://github.com/pcapng/pcapng
 /// [sink]: trait.PcapSink.html
 #[derive(Debug)]
 pub struct PcapWriter<D, S>
     where D: for<'a> Device<'a>,
           S: PcapSink + Clone,
 {
-    lower: D,
-    sink:  S,
-    mode:  PcapMode,
+    lower:     D,
+    sink:      S,
+    mode:      PcapMode,
+    format:    Format,
+    link_type: PcapLinkType,
+    comment:   Option<fn(PcapDirection, &[u8], Option<Error>, &mut fmt::Write) -> fmt::Result>,
+    filter:    Option<Filter<'static>>,
 }
 
 impl<D: for<'a> Device<'a>, S: PcapSink + Clone> PcapWriter<D, S> {
     /// Creates a packet capture writer.
     pub fn new(lower: D, sink: S, mode: PcapMode, link_type: PcapLinkType) -> PcapWriter<D, S> {
         sink.global_header(link_type);
-        PcapWriter { lower, sink, mode }
+        PcapWriter { lower, sink, mode, format: Format::Pcap, link_type,
+                     comment: None, filter: None }
+    }
+
+    /// Creates a packet capture writer using the pcapng format.
+    ///
+    /// The device is described as the interface `interface_id`, with an optional `name`.
+    /// Interfaces sharing a sink must be created in order, starting from zero;
+    /// the section header is written along with the interface zero.
+    pub fn new_pcapng(lower: D, sink: S, mode: PcapMode, link_type: PcapLinkType,
+                      interface_id: u32, name: Option<&str>) -> PcapWriter<D, S> {
+        if interface_id == 0 {
+            sink.section_header();
+        }
+        sink.interface_description(link_type, name);
+        PcapWriter { lower, sink, mode, format: Format::Pcapng { interface_id }, link_type,
+                     comment: None, filter: None }
+    }
+
+    /// Set the comment hook.
+    ///
+    /// In the pcapng format, the hook is called for every captured packet, and any text
+    /// it writes (up to 256 octets) is recorded as a comment on that packet. Besides
+    /// the packet, the hook is given the error with which a transmitted packet was
+    /// dropped, if any. Received packets are written before they are processed, so that
+    /// they precede the replies to them, and the hook is given no error for them.
+    /// The hook is not used in the libpcap format.
+    pub fn set_comment_hook(&mut self,
+                            hook: Option<fn(PcapDirection, &[u8], Option<Error>,
+                                            &mut fmt::Write) -> fmt::Result>) {
+        self.comment = hook
+    }
+
+    /// Set the filter selecting the packets to write.
+    ///
+    /// The filter is applied to Ethernet frames or to IP packets,
+    /// depending on the link-layer header type of the capture.
+    pub fn set_filter(&mut self, filter: Option<Filter<'static>>) {
+        self.filter = filter
     }
 }
 
@@ -138,69 +314,240 @@ impl<'a, D, S> Device<'a> for PcapWriter<D, S>
     where D: for<'b> Device<'b>,
           S: PcapSink + Clone + 'a,
 {
-    type RxToken = RxToken<<D as Device<'a>>::RxToken, S>;
-    type TxToken = TxToken<<D as Device<'a>>::TxToken, S>;
+    type RxToken = RxToken<'a, <D as Device<'a>>::RxToken, S>;
+    type TxToken = TxToken<'a, <D as Device<'a>>::TxToken, S>;
 
     fn capabilities(&self) -> DeviceCapabilities { self.lower.capabilities() }
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let &mut Self { ref mut lower, ref sink, mode, .. } = self;
+        let &mut Self { ref mut lower, ref sink, mode, format, link_type, comment, ref filter } =
+            self;
         lower.receive().map(|(rx_token, tx_token)| {
-            let rx = RxToken { token: rx_token, sink: sink.clone(), mode: mode };
-            let tx = TxToken { token: tx_token, sink: sink.clone(), mode: mode };
+            let writer = Writer { sink: sink.clone(), mode, format, link_type, comment,
+                                  filter: filter.as_ref() };
+            let rx = RxToken { token: rx_token, writer: writer.clone() };
+            let tx = TxToken { token: tx_token, writer: writer };
             (rx, tx)
         })
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        let &mut Self { ref mut lower, ref sink, mode } = self;
+        let &mut Self { ref mut lower, ref sink, mode, format, link_type, comment, ref filter } =
+            self;
         lower.transmit().map(|token| {
-            TxToken { token, sink: sink.clone(), mode: mode }
+            let writer = Writer { sink: sink.clone(), mode, format, link_type, comment,
+                                  filter: filter.as_ref() };
+            TxToken { token, writer }
         })
     }
+
+    fn flush(&mut self) {
+        self.lower.flush()
+    }
+}
+
+impl<D, S> Pollable for PcapWriter<D, S>
+    where D: for<'a> Device<'a> + Pollable,
+          S: PcapSink + Clone,
+{
+    #[cfg(all(feature = "std", unix))]
+    fn pollable_fd(&self) -> Option<RawFd> {
+        self.lower.pollable_fd()
+    }
+
+    fn set_rx_callback(&mut self, callback: fn()) -> bool {
+        self.lower.set_rx_callback(callback)
+    }
+}
+
+#[derive(Clone)]
+struct Writer<'a, S: PcapSink> {
+    sink:      S,
+    mode:      PcapMode,
+    format:    Format,
+    link_type: PcapLinkType,
+    comment:   Option<fn(PcapDirection, &[u8], Option<Error>, &mut fmt::Write) -> fmt::Result>,
+    filter:    Option<&'a Filter<'static>>,
+}
+
+impl<'a, S: PcapSink> Writer<'a, S> {
+    fn packet(&self, timestamp: Instant, direction: PcapDirection, packet: &[u8],
+              error: Option<Error>) {
+        match (self.mode, direction) {
+            (PcapMode::RxOnly, PcapDirection::Outbound) |
+            (PcapMode::TxOnly, PcapDirection::Inbound) => return,
+            _ => ()
+        }
+
+        match (self.filter, self.link_type) {
+            (Some(filter), PcapLinkType::Ip) if !filter.matches_ip(packet) => return,
+            (Some(filter), PcapLinkType::Ethernet) if !filter.matches(packet) => return,
+            _ => ()
+        }
+
+        match self.format {
+            Format::Pcap =>
+                self.sink.packet(timestamp, packet),
+            Format::Pcapng { interface_id } => {
+                let mut comment = Comment { buffer: [0; 256], len: 0 };
+                if let Some(hook) = self.comment {
+                    let _ = hook(direction, packet, error, &mut comment);
+                }
+                let comment = match str::from_utf8(&comment.buffer[..comment.len]) {
+                    Ok("") | Err(_) => None,
+                    Ok(comment) => Some(comment)
+                };
+                self.sink.enhanced_packet(interface_id, timestamp, direction, comment, packet)
+            }
+        }
+    }
 }
 
 #[doc(hidden)]
-pub struct RxToken<Rx: phy::RxToken, S: PcapSink> {
-    token: Rx,
-    sink:  S,
-    mode:  PcapMode,
+pub struct RxToken<'a, Rx: phy::RxToken, S: PcapSink> {
+    token:  Rx,
+    writer: Writer<'a, S>,
 }
 
-impl<Rx: phy::RxToken, S: PcapSink> phy::RxToken for RxToken<Rx, S> {
+impl<'a, Rx: phy::RxToken, S: PcapSink> phy::RxToken for RxToken<'a, Rx, S> {
     fn consume<R, F: FnOnce(&[u8]) -> Result<R>>(self, timestamp: Instant, f: F) -> Result<R> {
-        let Self { token, sink, mode } = self;
+        let Self { token, writer } = self;
         token.consume(timestamp, |buffer| {
-            match mode {
-                PcapMode::Both | PcapMode::RxOnly =>
-                    sink.packet(timestamp, buffer.as_ref()),
-                PcapMode::TxOnly => ()
-            }
+            writer.packet(timestamp, PcapDirection::Inbound, buffer.as_ref(), None);
             f(buffer)
         })
     }
+
+    fn offload(&self) -> RxOffload {
+        self.token.offload()
+    }
 }
 
 #[doc(hidden)]
-pub struct TxToken<Tx: phy::TxToken, S: PcapSink> {
-    token: Tx,
-    sink:  S,
-    mode:  PcapMode
+pub struct TxToken<'a, Tx: phy::TxToken, S: PcapSink> {
+    token:  Tx,
+    writer: Writer<'a, S>,
 }
 
-impl<Tx: phy::TxToken, S: PcapSink> phy::TxToken for TxToken<Tx, S> {
+impl<'a, Tx: phy::TxToken, S: PcapSink> phy::TxToken for TxToken<'a, Tx, S> {
     fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
-        let Self { token, sink, mode } = self;
-        token.consume(timestamp, len, |buffer| {
+        self.consume_with_offload(timestamp, len, TxOffload::default(), f)
+    }
+
+    fn consume_with_offload<R, F>(self, timestamp: Instant, len: usize, offload: TxOffload,
+                                  f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        let Self { token, writer } = self;
+        token.consume_with_offload(timestamp, len, offload, |buffer| {
             let result = f(buffer);
-            match mode {
-                PcapMode::Both | PcapMode::TxOnly =>
-                    sink.packet(timestamp, &buffer),
-                PcapMode::RxOnly => ()
-            };
+            writer.packet(timestamp, PcapDirection::Outbound, &buffer,
+                          result.as_ref().err().cloned());
             result
         })
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::rc::Rc;
+    use std::vec::Vec;
+    use phy::{Loopback, RxToken, TxToken};
+    use super::*;
+
+    fn blocks(data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let block_type = NativeEndian::read_u32(&data[offset..]);
+            let length = NativeEndian::read_u32(&data[offset + 4..]) as usize;
+            assert_eq!(NativeEndian::read_u32(&data[offset + length - 4..]) as usize, length);
+            blocks.push((block_type, &data[offset + 8..offset + length - 4]));
+            offset += length;
+        }
+        blocks
+    }
+
+    fn comment_hook(direction: PcapDirection, packet: &[u8], error: Option<Error>,
+                    comment: &mut fmt::Write) -> fmt::Result {
+        match (direction, error) {
+            (_, Some(error)) => write!(comment, "{}", error),
+            (PcapDirection::Inbound, None) => Ok(()),
+            (PcapDirection::Outbound, None) => write!(comment, "sent {} octets", packet.len())
+        }
+    }
+
+    #[test]
+    fn test_pcap_timestamp() {
+        let sink = RefCell::new(Vec::new());
+        sink.packet_header(Instant::from_millis(2500), 0);
+        let data = sink.into_inner();
+        assert_eq!(NativeEndian::read_u32(&data[0..4]), 2);
+        assert_eq!(NativeEndian::read_u32(&data[4..8]), 500_000);
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let mut device = PcapWriter::new_pcapng(Loopback::new(), sink.clone() as Rc<PcapSink>,
+                                                PcapMode::Both, PcapLinkType::Ethernet,
+                                                0, Some("lo"));
+        device.set_comment_hook(Some(comment_hook));
+        device.transmit().unwrap()
+            .consume(Instant::from_millis(1500), 3, |buffer| {
+                buffer.copy_from_slice(&[1, 2, 3]);
+                Ok(())
+            }).unwrap();
+        // A reply sent while processing a received packet is written after it.
+        let (rx_token, tx_token) = device.receive().unwrap();
+        assert_eq!(rx_token.consume(Instant::from_millis(1501), |_| {
+            tx_token.consume(Instant::from_millis(1501), 1, |_| Err::<(), _>(Error::Exhausted))
+        }), Err(Error::Exhausted));
+
+        let data = sink.borrow();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 5);
+
+        let (block_type, section) = blocks[0];
+        assert_eq!(block_type, 0x0a0d0d0a);
+        assert_eq!(NativeEndian::read_u32(&section[0..4]), 0x1a2b3c4d);
+
+        let (block_type, interface) = blocks[1];
+        assert_eq!(block_type, 1);
+        assert_eq!(NativeEndian::read_u16(&interface[0..2]), 1);
+        assert_eq!(NativeEndian::read_u16(&interface[8..10]), 2);
+        assert_eq!(NativeEndian::read_u16(&interface[10..12]), 2);
+        assert_eq!(&interface[12..16], b"lo\0\0");
+        assert_eq!(NativeEndian::read_u16(&interface[16..18]), 9);
+        assert_eq!(interface[20], 9);
+
+        let (block_type, packet) = blocks[2];
+        assert_eq!(block_type, 6);
+        assert_eq!(NativeEndian::read_u32(&packet[0..4]), 0);
+        assert_eq!(NativeEndian::read_u32(&packet[8..12]), 1_500_000_000);
+        assert_eq!(NativeEndian::read_u32(&packet[12..16]), 3);
+        assert_eq!(&packet[20..24], &[1, 2, 3, 0]);
+        assert_eq!(NativeEndian::read_u16(&packet[24..26]), 2);
+        assert_eq!(NativeEndian::read_u32(&packet[28..32]), 0b10);
+        assert_eq!(NativeEndian::read_u16(&packet[32..34]), 1);
+        assert_eq!(NativeEndian::read_u16(&packet[34..36]), 13);
+        assert_eq!(&packet[36..52], b"sent 3 octets\0\0\0");
+        assert_eq!(&packet[52..56], &[0; 4]);
+
+        let (block_type, packet) = blocks[3];
+        assert_eq!(block_type, 6);
+        assert_eq!(NativeEndian::read_u32(&packet[8..12]), 1_501_000_000);
+        assert_eq!(NativeEndian::read_u32(&packet[28..32]), 0b01);
+        assert_eq!(&packet[32..36], &[0; 4]);
+
+        let (block_type, packet) = blocks[4];
+        assert_eq!(block_type, 6);
+        assert_eq!(NativeEndian::read_u32(&packet[8..12]), 1_501_000_000);
+        assert_eq!(NativeEndian::read_u32(&packet[28..32]), 0b10);
+        assert_eq!(NativeEndian::read_u16(&packet[34..36]), 22);
+        assert_eq!(&packet[36..60], b"buffer space exhausted\0\0");
+        assert_eq!(&packet[60..64], &[0; 4]);
+    }
+}
@@ -1,23 +1,36 @@
-use std::cell::RefCell;
-use std::vec::Vec;
-use std::rc::Rc;
 use std::io;
 use std::os::unix::io::{RawFd, AsRawFd};
 
-use Result;
-use phy::{self, sys, DeviceCapabilities, Device};
+use {Error, Result};
+use phy::{self, Pollable, sys, DeviceCapabilities, Device};
 use time::Instant;
 
+/// The number of frames received or transmitted at once.
+const BATCH_LEN: usize = 32;
+
 /// A socket that captures or transmits the complete frame.
+///
+/// Frames are received and transmitted in batches, using a single `recvmmsg` or `sendmmsg`
+/// system call per batch where available, and reusing the same buffers. Transmitted frames
+/// are queued until the batch is full or the device is [flushed](trait.Device.html#method.flush),
+/// which an interface does at the end of every poll.
 #[derive(Debug)]
 pub struct RawSocket {
-    lower:  Rc<RefCell<sys::RawSocketDesc>>,
-    mtu:    usize
+    lower:  sys::RawSocketDesc,
+    mtu:    usize,
+    rx:     sys::Batch,
+    tx:     sys::Batch
 }
 
 impl AsRawFd for RawSocket {
     fn as_raw_fd(&self) -> RawFd {
-        self.lower.borrow().as_raw_fd()
+        self.lower.as_raw_fd()
+    }
+}
+
+impl Pollable for RawSocket {
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
     }
 }
 
@@ -31,17 +44,22 @@ impl RawSocket {
         lower.bind_interface()?;
         let mtu = lower.interface_mtu()?;
         Ok(RawSocket {
-            lower: Rc::new(RefCell::new(lower)),
-            mtu:   mtu
+            lower: lower,
+            mtu:   mtu,
+            rx:    sys::Batch::new(BATCH_LEN, mtu),
+            tx:    sys::Batch::new(BATCH_LEN, mtu)
         })
     }
 }
 
 impl<'a> Device<'a> for RawSocket {
-    type RxToken = RxToken;
-    type TxToken = TxToken;
+    type RxToken = RxToken<'a>;
+    type TxToken = TxToken<'a>;
 
     fn capabilities(&self) -> DeviceCapabilities {
+        // Bursts are not limited by the batches: received frames that do not fit in
+        // a batch wait in the kernel buffers, and a full transmit batch is flushed
+        // right away. So the burst size is left unlimited.
         DeviceCapabilities {
             max_transmission_unit: self.mtu,
             ..DeviceCapabilities::default()
@@ -49,53 +67,89 @@ impl<'a> Device<'a> for RawSocket {
     }
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let mut lower = self.lower.borrow_mut();
-        let mut buffer = vec![0; self.mtu];
-        match lower.recv(&mut buffer[..]) {
-            Ok(size) => {
-                buffer.resize(size, 0);
-                let rx = RxToken { buffer };
-                let tx = TxToken { lower: self.lower.clone() };
-                Some((rx, tx))
-            }
-            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
-                None
+        let &mut RawSocket { ref mut lower, ref mut rx, ref mut tx, .. } = self;
+        if rx.is_drained() {
+            rx.clear();
+            match lower.recv_batch(rx) {
+                Ok(()) => (),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return None
+                }
+                Err(err) => panic!("{}", err)
             }
-            Err(err) => panic!("{}", err)
         }
+        rx.pop().map(move |buffer| {
+            let rx = RxToken { buffer };
+            let tx = TxToken { lower, tx };
+            (rx, tx)
+        })
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
         Some(TxToken {
-            lower: self.lower.clone(),
+            lower: &mut self.lower,
+            tx:    &mut self.tx
         })
     }
+
+    fn flush(&mut self) {
+        flush(&mut self.lower, &mut self.tx)
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        flush(&mut self.lower, &mut self.tx)
+    }
+}
+
+fn flush(lower: &mut sys::RawSocketDesc, tx: &mut sys::Batch) {
+    let mut sent = 0;
+    while sent < tx.len() {
+        match lower.send_batch(tx, sent) {
+            Ok(count) => sent += count,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                net_debug!("raw socket: dropping {} frames", tx.len() - sent);
+                break
+            }
+            Err(err) => panic!("{}", err)
+        }
+    }
+    tx.clear()
 }
 
 #[doc(hidden)]
-pub struct RxToken {
-    buffer: Vec<u8>
+pub struct RxToken<'a> {
+    buffer: &'a mut [u8]
 }
 
-impl phy::RxToken for RxToken {
+impl<'a> phy::RxToken for RxToken<'a> {
     fn consume<R, F: FnOnce(&[u8]) -> Result<R>>(self, _timestamp: Instant, f: F) -> Result<R> {
-        f(&self.buffer[..])
+        f(self.buffer)
     }
 }
 
 #[doc(hidden)]
-pub struct TxToken {
-    lower:  Rc<RefCell<sys::RawSocketDesc>>,
+pub struct TxToken<'a> {
+    lower:  &'a mut sys::RawSocketDesc,
+    tx:     &'a mut sys::Batch
 }
 
-impl phy::TxToken for TxToken {
+impl<'a> phy::TxToken for TxToken<'a> {
     fn consume<R, F: FnOnce(&mut [u8]) -> Result<R>>(self, _timestamp: Instant, len: usize, f: F)
         -> Result<R>
     {
-        let mut lower = self.lower.borrow_mut();
-        let mut buffer = vec![0; len];
-        let result = f(&mut buffer);
-        lower.send(&buffer[..]).unwrap();
+        let TxToken { lower, tx } = self;
+        let result = match tx.push(len) {
+            Some(buffer) => f(buffer),
+            None => {
+                net_debug!("raw socket: cannot send a frame of {} octets", len);
+                return Err(Error::Exhausted)
+            }
+        };
+        if tx.is_full() {
+            flush(lower, tx)
+        }
         result
     }
 }
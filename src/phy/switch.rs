@@ -0,0 +1,339 @@
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+#[cfg(feature = "std")]
+use std::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+#[cfg(feature = "alloc")]
+use alloc::rc::Rc;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(all(feature = "alloc", not(feature = "rust-1_28")))]
+use alloc::collections::VecDeque;
+#[cfg(all(feature = "alloc", feature = "rust-1_28"))]
+use alloc::VecDeque;
+
+use Result;
+use phy::{self, Device, DeviceCapabilities};
+use time::{Duration, Instant};
+use wire::{EthernetAddress, EthernetFrame};
+
+#[derive(Debug)]
+struct State {
+    learning: bool,
+    now:      Instant,
+    queues:   Vec<VecDeque<Vec<u8>>>,
+    table:    Vec<(EthernetAddress, usize)>,
+}
+
+impl State {
+    fn forward(&mut self, port: usize, frame: Vec<u8>) {
+        let (src_addr, dst_addr) = match EthernetFrame::new_checked(&frame[..]) {
+            Ok(frame) => (frame.src_addr(), frame.dst_addr()),
+            Err(_) => {
+                net_debug!("switch: dropping a malformed frame from port {}", port);
+                return
+            }
+        };
+
+        if self.learning && src_addr.is_unicast() {
+            match self.table.iter_mut().find(|&&mut (addr, _)| addr == src_addr) {
+                Some(entry) => entry.1 = port,
+                None => self.table.push((src_addr, port))
+            }
+        }
+
+        let dst_port =
+            if self.learning && dst_addr.is_unicast() {
+                self.table.iter().find(|&&(addr, _)| addr == dst_addr)
+                    .map(|&(_, port)| port)
+            } else {
+                None
+            };
+        match dst_port {
+            Some(dst_port) if dst_port == port => (),
+            Some(dst_port) => self.queues[dst_port].push_back(frame),
+            None => {
+                for (index, queue) in self.queues.iter_mut().enumerate() {
+                    if index != port {
+                        queue.push_back(frame.clone())
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A virtual Ethernet switch.
+///
+/// A switch joins any number of [ports](struct.SwitchPort.html), each of which is a device
+/// that may be given to an interface, so that several interfaces may talk to each other
+/// within a single process, e.g. in tests. Every frame transmitted through a port is
+/// forwarded to the port where its destination address was last seen as a source address,
+/// or to every other port if it is unknown, broadcast or multicast. A hub forwards every
+/// frame to every other port.
+///
+/// A switch also keeps a simulated clock shared by all of its users, which advances only
+/// when requested. Links may be impaired by wrapping ports in
+/// a [FaultInjector](struct.FaultInjector.html).
+#[derive(Debug, Clone)]
+pub struct Switch {
+    state: Rc<RefCell<State>>,
+}
+
+impl Switch {
+    /// Create a switch without ports that learns the addresses behind its ports.
+    pub fn new() -> Switch {
+        Self::with_learning(true)
+    }
+
+    /// Create a hub without ports, that is, a switch that forwards every frame
+    /// to every other port.
+    pub fn hub() -> Switch {
+        Self::with_learning(false)
+    }
+
+    fn with_learning(learning: bool) -> Switch {
+        let state = State {
+            learning: learning,
+            now:      Instant::from_millis(0),
+            queues:   Vec::new(),
+            table:    Vec::new(),
+        };
+        Switch { state: Rc::new(RefCell::new(state)) }
+    }
+
+    /// Add a port to the switch, and return the device attached to it.
+    pub fn port(&self) -> SwitchPort {
+        let mut state = self.state.borrow_mut();
+        state.queues.push(VecDeque::new());
+        SwitchPort {
+            state: self.state.clone(),
+            index: state.queues.len() - 1,
+        }
+    }
+
+    /// Return the number of ports.
+    pub fn port_count(&self) -> usize {
+        self.state.borrow().queues.len()
+    }
+
+    /// Return the port behind which the given address was last seen, if any.
+    pub fn lookup(&self, addr: EthernetAddress) -> Option<usize> {
+        self.state.borrow().table.iter().find(|&&(entry, _)| entry == addr)
+            .map(|&(_, port)| port)
+    }
+
+    /// Return the current time of the simulated clock.
+    pub fn now(&self) -> Instant {
+        self.state.borrow().now
+    }
+
+    /// Advance the simulated clock by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.state.borrow_mut().now += duration
+    }
+}
+
+/// A port of a virtual Ethernet [switch](struct.Switch.html).
+#[derive(Debug)]
+pub struct SwitchPort {
+    state: Rc<RefCell<State>>,
+    index: usize,
+}
+
+impl SwitchPort {
+    /// Return the index of this port in the switch.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Return the current time of the simulated clock of the switch.
+    pub fn now(&self) -> Instant {
+        self.state.borrow().now
+    }
+}
+
+impl<'a> Device<'a> for SwitchPort {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            max_transmission_unit: 1514,
+            ..DeviceCapabilities::default()
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let buffer = self.state.borrow_mut().queues[self.index].pop_front();
+        buffer.map(move |buffer| {
+            let rx = RxToken { buffer: buffer };
+            let tx = TxToken { port: self };
+            (rx, tx)
+        })
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken { port: self })
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken {
+    buffer: Vec<u8>,
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F: FnOnce(&[u8]) -> Result<R>>(self, _timestamp: Instant, f: F) -> Result<R> {
+        f(&self.buffer)
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a> {
+    port: &'a SwitchPort,
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        let mut buffer = Vec::new();
+        buffer.resize(len, 0);
+        let result = f(&mut buffer);
+        self.port.state.borrow_mut().forward(self.port.index, buffer);
+        result
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::vec::Vec;
+    use phy::{RxToken, TxToken};
+    use wire::EthernetProtocol;
+    use super::*;
+
+    const ADDR_A: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0xa]);
+    const ADDR_B: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0xb]);
+
+    fn send(port: &mut SwitchPort, src_addr: EthernetAddress, dst_addr: EthernetAddress) {
+        let token = port.transmit().unwrap();
+        token.consume(Instant::from_millis(0), 14, |buffer| {
+            let mut frame = EthernetFrame::new_unchecked(buffer);
+            frame.set_src_addr(src_addr);
+            frame.set_dst_addr(dst_addr);
+            frame.set_ethertype(EthernetProtocol::Ipv4);
+            Ok(())
+        }).unwrap();
+    }
+
+    fn recv_all(port: &mut SwitchPort) -> Vec<(EthernetAddress, EthernetAddress)> {
+        let mut frames = Vec::new();
+        while let Some((token, _)) = port.receive() {
+            token.consume(Instant::from_millis(0), |buffer| {
+                let frame = EthernetFrame::new_checked(buffer)?;
+                frames.push((frame.src_addr(), frame.dst_addr()));
+                Ok(())
+            }).unwrap();
+        }
+        frames
+    }
+
+    #[test]
+    fn test_switch_learning() {
+        let switch = Switch::new();
+        let (mut port_a, mut port_b, mut port_c) = (switch.port(), switch.port(), switch.port());
+        assert_eq!(switch.port_count(), 3);
+
+        // Unknown destinations are flooded.
+        send(&mut port_a, ADDR_A, ADDR_B);
+        assert_eq!(recv_all(&mut port_a), vec![]);
+        assert_eq!(recv_all(&mut port_b), vec![(ADDR_A, ADDR_B)]);
+        assert_eq!(recv_all(&mut port_c), vec![(ADDR_A, ADDR_B)]);
+        assert_eq!(switch.lookup(ADDR_A), Some(0));
+
+        // Known destinations are not.
+        send(&mut port_b, ADDR_B, ADDR_A);
+        assert_eq!(recv_all(&mut port_a), vec![(ADDR_B, ADDR_A)]);
+        assert_eq!(recv_all(&mut port_c), vec![]);
+
+        // Broadcasts always are.
+        send(&mut port_b, ADDR_B, EthernetAddress::BROADCAST);
+        assert_eq!(recv_all(&mut port_a), vec![(ADDR_B, EthernetAddress::BROADCAST)]);
+        assert_eq!(recv_all(&mut port_b), vec![]);
+        assert_eq!(recv_all(&mut port_c), vec![(ADDR_B, EthernetAddress::BROADCAST)]);
+    }
+
+    #[test]
+    fn test_hub() {
+        let hub = Switch::hub();
+        let (mut port_a, mut port_b, mut port_c) = (hub.port(), hub.port(), hub.port());
+        send(&mut port_b, ADDR_B, ADDR_A);
+        send(&mut port_a, ADDR_A, ADDR_B);
+        assert_eq!(recv_all(&mut port_a), vec![(ADDR_B, ADDR_A)]);
+        assert_eq!(recv_all(&mut port_b), vec![(ADDR_A, ADDR_B)]);
+        assert_eq!(recv_all(&mut port_c), vec![(ADDR_B, ADDR_A), (ADDR_A, ADDR_B)]);
+        assert_eq!(hub.lookup(ADDR_A), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "proto-ipv4", feature = "socket-udp"))]
+    fn test_udp_between_interfaces() {
+        use std::collections::BTreeMap;
+        use iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache};
+        use socket::{SocketSet, UdpSocket, UdpSocketBuffer, UdpPacketMetadata};
+        use wire::{IpCidr, IpEndpoint, Ipv4Address};
+
+        fn interface<'a>(port: SwitchPort, ethernet_addr: EthernetAddress, ip_addr: Ipv4Address)
+                        -> EthernetInterface<'a, 'a, 'a, SwitchPort> {
+            let builder = EthernetInterfaceBuilder::new(port)
+                .ethernet_addr(ethernet_addr)
+                .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+                .ip_addrs(vec![IpCidr::new(ip_addr.into(), 24)]);
+            #[cfg(feature = "proto-igmp")]
+            let builder = builder.ipv4_multicast_groups(BTreeMap::new());
+            builder.finalize()
+        }
+
+        fn socket<'a, 'b>() -> UdpSocket<'a, 'b> {
+            UdpSocket::new(UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 64]),
+                           UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 64]))
+        }
+
+        let switch = Switch::new();
+        let addr_a = Ipv4Address::new(192, 168, 1, 1);
+        let addr_b = Ipv4Address::new(192, 168, 1, 2);
+        let mut iface_a = interface(switch.port(), ADDR_A, addr_a);
+        let mut iface_b = interface(switch.port(), ADDR_B, addr_b);
+
+        let mut sockets_a = SocketSet::new(vec![]);
+        let mut sockets_b = SocketSet::new(vec![]);
+        let handle_a = sockets_a.add(socket());
+        let handle_b = sockets_b.add(socket());
+        sockets_a.get::<UdpSocket>(handle_a).bind(1234).unwrap();
+        sockets_b.get::<UdpSocket>(handle_b).bind(4321).unwrap();
+        sockets_a.get::<UdpSocket>(handle_a)
+            .send_slice(b"hello", IpEndpoint::new(addr_b.into(), 4321)).unwrap();
+
+        for _ in 0..300 {
+            let _ = iface_a.poll(&mut sockets_a, switch.now());
+            let _ = iface_b.poll(&mut sockets_b, switch.now());
+            if sockets_b.get::<UdpSocket>(handle_b).can_recv() { break }
+            switch.advance(Duration::from_millis(10));
+        }
+        assert_eq!(sockets_b.get::<UdpSocket>(handle_b).recv(),
+                   Ok((&b"hello"[..], IpEndpoint::new(addr_a.into(), 1234))));
+        assert_eq!(switch.lookup(ADDR_A), Some(0));
+        assert_eq!(switch.lookup(ADDR_B), Some(1));
+    }
+
+    #[test]
+    fn test_clock() {
+        let switch = Switch::new();
+        let port = switch.port();
+        switch.advance(Duration::from_millis(1500));
+        assert_eq!(port.now(), Instant::from_millis(1500));
+    }
+}
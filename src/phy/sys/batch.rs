@@ -0,0 +1,149 @@
+use std::fmt;
+use std::vec::Vec;
+
+/// A ring of equally sized frame buffers, filled or drained by one system call
+/// per batch where the descriptor supports it.
+pub struct Batch {
+    buffer:   Vec<u8>,
+    capacity: usize,
+    slot_len: usize,
+    lens:     Vec<usize>,
+    next:     usize,
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Batch {{ capacity: {}, slot_len: {}, len: {}, next: {} }}",
+               self.capacity(), self.slot_len, self.lens.len(), self.next)
+    }
+}
+
+impl Batch {
+    pub fn new(capacity: usize, slot_len: usize) -> Batch {
+        Batch {
+            buffer:   vec![0; capacity * slot_len],
+            capacity: capacity,
+            slot_len: slot_len,
+            lens:     Vec::with_capacity(capacity),
+            next:     0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the number of frames in the batch.
+    pub fn len(&self) -> usize {
+        self.lens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lens.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.lens.len() == self.capacity()
+    }
+
+    /// Check whether every frame in the batch has been [popped](#method.pop).
+    pub fn is_drained(&self) -> bool {
+        self.next == self.lens.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.lens.clear();
+        self.next = 0
+    }
+
+    /// Return the whole slot at `index`, e.g. to receive a frame into it.
+    pub fn slot_mut(&mut self, index: usize) -> &mut [u8] {
+        let start = index * self.slot_len;
+        &mut self.buffer[start..start + self.slot_len]
+    }
+
+    /// Record that a frame of `len` octets was received into the next free slot.
+    pub fn commit(&mut self, len: usize) {
+        assert!(!self.is_full() && len <= self.slot_len);
+        self.lens.push(len)
+    }
+
+    /// Append a frame of `len` octets, and return its buffer, or `None` if the batch
+    /// is full or `len` exceeds the slot length.
+    pub fn push(&mut self, len: usize) -> Option<&mut [u8]> {
+        if self.is_full() || len > self.slot_len { return None }
+        let index = self.lens.len();
+        self.commit(len);
+        Some(&mut self.slot_mut(index)[..len])
+    }
+
+    /// Return the frame at `index`.
+    pub fn frame(&self, index: usize) -> &[u8] {
+        let start = index * self.slot_len;
+        &self.buffer[start..start + self.lens[index]]
+    }
+
+    /// Return the next frame that was not popped yet, if any.
+    pub fn pop(&mut self) -> Option<&mut [u8]> {
+        if self.is_drained() { return None }
+        let (index, len) = (self.next, self.lens[self.next]);
+        self.next += 1;
+        Some(&mut self.slot_mut(index)[..len])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_commit_pop() {
+        let mut batch = Batch::new(2, 4);
+        assert!(batch.is_empty() && batch.is_drained());
+        assert_eq!(batch.pop(), None);
+
+        batch.slot_mut(0).copy_from_slice(&[1, 2, 3, 4]);
+        batch.commit(3);
+        batch.slot_mut(1)[..2].copy_from_slice(&[5, 6]);
+        batch.commit(2);
+        assert!(batch.is_full() && !batch.is_drained());
+        assert_eq!(batch.len(), 2);
+
+        assert_eq!(batch.pop(), Some(&mut [1, 2, 3][..]));
+        assert!(!batch.is_drained());
+        assert_eq!(batch.pop(), Some(&mut [5, 6][..]));
+        assert!(batch.is_drained());
+        assert_eq!(batch.pop(), None);
+    }
+
+    #[test]
+    fn test_push_frame() {
+        let mut batch = Batch::new(2, 4);
+        batch.push(2).unwrap().copy_from_slice(&[1, 2]);
+        assert_eq!(batch.push(5), None);
+        batch.push(4).unwrap().copy_from_slice(&[3, 4, 5, 6]);
+        assert!(batch.is_full());
+        assert_eq!(batch.push(1), None);
+
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.frame(0), &[1, 2]);
+        assert_eq!(batch.frame(1), &[3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut batch = Batch::new(2, 4);
+        batch.push(1).unwrap().copy_from_slice(&[1]);
+        batch.push(1).unwrap().copy_from_slice(&[2]);
+        assert_eq!(batch.pop(), Some(&mut [1][..]));
+        assert_eq!(batch.pop(), Some(&mut [2][..]));
+        assert!(batch.is_drained());
+
+        batch.clear();
+        assert!(batch.is_empty() && batch.is_drained());
+        batch.push(2).unwrap().copy_from_slice(&[3, 4]);
+        assert!(!batch.is_drained());
+        assert_eq!(batch.pop(), Some(&mut [3, 4][..]));
+        assert!(batch.is_drained());
+    }
+}
@@ -3,7 +3,7 @@ use std::os::unix::io::{AsRawFd, RawFd};
 
 use libc;
 
-use super::{ifreq, ifreq_for};
+use super::{ifreq, ifreq_for, Batch};
 
 /// set interface
 #[cfg(target_os = "macos")]
@@ -138,6 +138,27 @@ impl BpfDevice {
     }
 }
 
+impl BpfDevice {
+    /// Receive a frame into the next free slot of `batch`.
+    ///
+    /// A BPF device is read in blocking mode, so only a single frame is received.
+    pub fn recv_batch(&mut self, batch: &mut Batch) -> io::Result<()> {
+        let len = {
+            let index = batch.len();
+            self.recv(batch.slot_mut(index))?
+        };
+        batch.commit(len - BPF_HDRLEN);
+        Ok(())
+    }
+
+    /// Send the frame of `batch` at `first`.
+    ///
+    /// Returns the number of frames sent.
+    pub fn send_batch(&mut self, batch: &Batch, first: usize) -> io::Result<usize> {
+        self.send(batch.frame(first)).map(|_| 1)
+    }
+}
+
 impl Drop for BpfDevice {
     fn drop(&mut self) {
         unsafe {
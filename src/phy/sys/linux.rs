@@ -1,11 +1,14 @@
 use libc;
 
 #[cfg(any(feature = "phy-raw_socket",
-          feature = "phy-tap_interface"))]
+          feature = "phy-tap_interface",
+          feature = "phy-packet_ring"))]
 pub const SIOCGIFMTU:   libc::c_ulong = 0x8921;
-#[cfg(any(feature = "phy-raw_socket"))]
+#[cfg(any(feature = "phy-raw_socket",
+          feature = "phy-packet_ring"))]
 pub const SIOCGIFINDEX: libc::c_ulong = 0x8933;
-#[cfg(any(feature = "phy-raw_socket"))]
+#[cfg(any(feature = "phy-raw_socket",
+          feature = "phy-packet_ring"))]
 pub const ETH_P_ALL:    libc::c_short = 0x0003;
 
 #[cfg(feature = "phy-tap_interface")]
@@ -15,3 +18,90 @@ pub const IFF_TAP:      libc::c_int   = 0x0002;
 #[cfg(feature = "phy-tap_interface")]
 pub const IFF_NO_PI:    libc::c_int   = 0x1000;
 
+
+#[cfg(feature = "phy-raw_socket")]
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct mmsghdr {
+    pub msg_hdr: libc::msghdr,
+    pub msg_len: libc::c_uint
+}
+
+#[cfg(feature = "phy-raw_socket")]
+extern "C" {
+    pub fn recvmmsg(sockfd: libc::c_int, msgvec: *mut mmsghdr, vlen: libc::c_uint,
+                    flags: libc::c_int, timeout: *mut libc::timespec) -> libc::c_int;
+    pub fn sendmmsg(sockfd: libc::c_int, msgvec: *mut mmsghdr, vlen: libc::c_uint,
+                    flags: libc::c_int) -> libc::c_int;
+}
+
+#[cfg(feature = "phy-packet_ring")]
+pub const SOL_PACKET:       libc::c_int = 263;
+#[cfg(feature = "phy-packet_ring")]
+pub const PACKET_RX_RING:   libc::c_int = 5;
+#[cfg(feature = "phy-packet_ring")]
+pub const PACKET_VERSION:   libc::c_int = 10;
+#[cfg(feature = "phy-packet_ring")]
+pub const PACKET_TX_RING:   libc::c_int = 13;
+#[cfg(feature = "phy-packet_ring")]
+pub const TPACKET_V3:       libc::c_int = 2;
+
+#[cfg(feature = "phy-packet_ring")]
+pub const TP_STATUS_KERNEL:         u32 = 0;
+#[cfg(feature = "phy-packet_ring")]
+pub const TP_STATUS_USER:           u32 = 1 << 0;
+#[cfg(feature = "phy-packet_ring")]
+pub const TP_STATUS_AVAILABLE:      u32 = 0;
+#[cfg(feature = "phy-packet_ring")]
+pub const TP_STATUS_SEND_REQUEST:   u32 = 1 << 0;
+#[cfg(feature = "phy-packet_ring")]
+pub const TP_STATUS_WRONG_FORMAT:   u32 = 1 << 2;
+
+#[cfg(feature = "phy-packet_ring")]
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct tpacket_req3 {
+    pub tp_block_size:      libc::c_uint,
+    pub tp_block_nr:        libc::c_uint,
+    pub tp_frame_size:      libc::c_uint,
+    pub tp_frame_nr:        libc::c_uint,
+    pub tp_retire_blk_tov:  libc::c_uint,
+    pub tp_sizeof_priv:     libc::c_uint,
+    pub tp_feature_req_word: libc::c_uint
+}
+
+/// The block descriptor at the start of every RX ring block; `struct tpacket_block_desc`
+/// with its `struct tpacket_hdr_v1` flattened.
+#[cfg(feature = "phy-packet_ring")]
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct tpacket_block_desc {
+    pub version:             u32,
+    pub offset_to_priv:      u32,
+    pub block_status:        u32,
+    pub num_pkts:            u32,
+    pub offset_to_first_pkt: u32,
+    pub blk_len:             u32,
+    pub seq_num:             u64,
+    pub ts_first_pkt:        [u32; 2],
+    pub ts_last_pkt:         [u32; 2]
+}
+
+/// The header preceding every frame in the rings; `struct tpacket3_hdr`.
+#[cfg(feature = "phy-packet_ring")]
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct tpacket3_hdr {
+    pub tp_next_offset: u32,
+    pub tp_sec:         u32,
+    pub tp_nsec:        u32,
+    pub tp_snaplen:     u32,
+    pub tp_len:         u32,
+    pub tp_status:      u32,
+    pub tp_mac:         u16,
+    pub tp_net:         u16,
+    pub tp_rxhash:      u32,
+    pub tp_vlan_tci:    u32,
+    pub tp_vlan_tpid:   u16,
+    pub tp_padding:     [u8; 10]
+}
@@ -1,27 +1,38 @@
 #![allow(unsafe_code)]
 
 use libc;
-use std::{mem, ptr, io};
+use std::{cmp, fmt, mem, ptr, io};
+use std::vec::Vec;
 use std::os::unix::io::RawFd;
 use time::Duration;
+use phy::Pollable;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", any(feature = "phy-tap_interface", feature = "phy-raw_socket",
+                                   feature = "phy-packet_ring")))]
 #[path = "linux.rs"]
 mod imp;
 
+#[cfg(all(any(feature = "phy-tap_interface", feature = "phy-raw_socket"), unix))]
+mod batch;
 #[cfg(all(feature = "phy-raw_socket", target_os = "linux"))]
 pub mod raw_socket;
 #[cfg(all(feature = "phy-raw_socket", not(target_os = "linux"), unix))]
 pub mod bpf;
 #[cfg(all(feature = "phy-tap_interface", target_os = "linux"))]
 pub mod tap_interface;
+#[cfg(all(feature = "phy-packet_ring", target_os = "linux"))]
+pub mod packet_ring;
 
+#[cfg(all(any(feature = "phy-tap_interface", feature = "phy-raw_socket"), unix))]
+pub use self::batch::Batch;
 #[cfg(all(feature = "phy-raw_socket", target_os = "linux"))]
 pub use self::raw_socket::RawSocketDesc;
 #[cfg(all(feature = "phy-raw_socket", not(target_os = "linux"), unix))]
 pub use self::bpf::BpfDevice as RawSocketDesc;
 #[cfg(all(feature = "phy-tap_interface", target_os = "linux"))]
 pub use self::tap_interface::TapInterfaceDesc;
+#[cfg(all(feature = "phy-packet_ring", target_os = "linux"))]
+pub use self::packet_ring::{PacketRingDesc, PacketRx, PacketTx};
 
 /// Wait until given file descriptor becomes readable, but no longer than given timeout.
 pub fn wait(fd: RawFd, duration: Option<Duration>) -> io::Result<()> {
@@ -51,7 +62,97 @@ pub fn wait(fd: RawFd, duration: Option<Duration>) -> io::Result<()> {
     }
 }
 
-#[cfg(all(any(feature = "phy-tap_interface", feature = "phy-raw_socket"), unix))]
+/// A set of file descriptors, e.g. those of several devices, to wait on at once.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use smoltcp::phy::{TapInterface, RawSocket, WaitSet};
+/// # use smoltcp::time::Duration;
+/// let tap = TapInterface::new("tap0").unwrap();
+/// let raw = RawSocket::new("eth0").unwrap();
+///
+/// let mut wait_set = WaitSet::new();
+/// let tap_index = wait_set.add(&tap).unwrap();
+/// let raw_index = wait_set.add(&raw).unwrap();
+/// // Sleep until either device has frames to receive, or the earliest of the
+/// // `EthernetInterface::poll_delay` results elapses.
+/// wait_set.wait(Some(Duration::from_millis(100))).unwrap();
+/// if wait_set.is_ready(tap_index) { /* poll the interface using `tap` */ }
+/// if wait_set.is_ready(raw_index) { /* poll the interface using `raw` */ }
+/// ```
+#[derive(Default)]
+pub struct WaitSet {
+    fds: Vec<libc::pollfd>
+}
+
+impl fmt::Debug for WaitSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.fds.iter().map(|pollfd| pollfd.fd)).finish()
+    }
+}
+
+impl WaitSet {
+    /// Create an empty wait set.
+    pub fn new() -> WaitSet {
+        WaitSet { fds: Vec::new() }
+    }
+
+    /// Add the file descriptor `fd`, and return its index in the set.
+    pub fn add_fd(&mut self, fd: RawFd) -> usize {
+        self.fds.push(libc::pollfd { fd: fd, events: libc::POLLIN, revents: 0 });
+        self.fds.len() - 1
+    }
+
+    /// Add the file descriptor of `device`, and return its index in the set,
+    /// or `None` if the device has no [pollable file descriptor](trait.Pollable.html).
+    pub fn add<D: Pollable + ?Sized>(&mut self, device: &D) -> Option<usize> {
+        device.pollable_fd().map(|fd| self.add_fd(fd))
+    }
+
+    /// Return the number of file descriptors in the set.
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    /// Query whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Remove every file descriptor from the set.
+    pub fn clear(&mut self) {
+        self.fds.clear()
+    }
+
+    /// Wait until at least one file descriptor in the set becomes readable, but no longer
+    /// than given timeout, and return the number of readable file descriptors.
+    pub fn wait(&mut self, duration: Option<Duration>) -> io::Result<usize> {
+        let timeout = match duration {
+            Some(duration) => cmp::min(duration.total_millis(), libc::c_int::max_value() as u64)
+                                  as libc::c_int,
+            None => -1
+        };
+
+        for pollfd in self.fds.iter_mut() {
+            pollfd.revents = 0
+        }
+        let res = unsafe {
+            libc::poll(self.fds.as_mut_ptr(), self.fds.len() as libc::nfds_t, timeout)
+        };
+        if res == -1 { return Err(io::Error::last_os_error()) }
+        Ok(res as usize)
+    }
+
+    /// Query whether the file descriptor at `index` became readable, or failed,
+    /// during the last [wait](#method.wait).
+    pub fn is_ready(&self, index: usize) -> bool {
+        self.fds[index].revents & (libc::POLLIN | libc::POLLERR | libc::POLLHUP) != 0
+    }
+}
+
+#[cfg(all(any(feature = "phy-tap_interface", feature = "phy-raw_socket",
+              feature = "phy-packet_ring"), unix))]
 #[repr(C)]
 #[derive(Debug)]
 struct ifreq {
@@ -59,7 +160,8 @@ struct ifreq {
     ifr_data: libc::c_int /* ifr_ifindex or ifr_mtu */
 }
 
-#[cfg(all(any(feature = "phy-tap_interface", feature = "phy-raw_socket"), unix))]
+#[cfg(all(any(feature = "phy-tap_interface", feature = "phy-raw_socket",
+              feature = "phy-packet_ring"), unix))]
 fn ifreq_for(name: &str) -> ifreq {
     let mut ifreq = ifreq {
         ifr_name: [0; libc::IF_NAMESIZE],
@@ -71,7 +173,8 @@ fn ifreq_for(name: &str) -> ifreq {
     ifreq
 }
 
-#[cfg(all(target_os = "linux", any(feature = "phy-tap_interface", feature = "phy-raw_socket")))]
+#[cfg(all(target_os = "linux", any(feature = "phy-tap_interface", feature = "phy-raw_socket",
+                                   feature = "phy-packet_ring")))]
 fn ifreq_ioctl(lower: libc::c_int, ifreq: &mut ifreq,
                cmd: libc::c_ulong) -> io::Result<libc::c_int> {
     unsafe {
@@ -81,3 +184,32 @@ fn ifreq_ioctl(lower: libc::c_int, ifreq: &mut ifreq,
 
     Ok(ifreq.ifr_data)
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::UdpSocket;
+    use std::os::unix::io::AsRawFd;
+    use super::*;
+
+    #[test]
+    fn test_wait_set() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut wait_set = WaitSet::new();
+        assert_eq!(wait_set.add_fd(a.as_raw_fd()), 0);
+        assert_eq!(wait_set.add_fd(b.as_raw_fd()), 1);
+        assert_eq!(wait_set.len(), 2);
+
+        assert_eq!(wait_set.wait(Some(Duration::from_millis(0))).unwrap(), 0);
+        assert!(!wait_set.is_ready(0) && !wait_set.is_ready(1));
+
+        a.send_to(&[1, 2, 3], b.local_addr().unwrap()).unwrap();
+        assert_eq!(wait_set.wait(Some(Duration::from_millis(1000))).unwrap(), 1);
+        assert!(!wait_set.is_ready(0));
+        assert!(wait_set.is_ready(1));
+
+        wait_set.clear();
+        assert!(wait_set.is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use std::{cmp, mem, ptr, slice, io};
+use std::sync::atomic::{fence, Ordering};
+use std::os::unix::io::{RawFd, AsRawFd};
+use libc;
+use super::*;
+
+/// The offset of frame data in a TX ring frame, `TPACKET3_HDRLEN - sizeof(struct sockaddr_ll)`.
+const TX_DATA_OFFSET: usize = 48;
+
+/// The smallest size of a ring block, in octets.
+const MIN_BLOCK_SIZE: usize = 1 << 16;
+
+/// The time after which the kernel hands over a partially filled RX block, in milliseconds.
+const RETIRE_BLOCK_TIMEOUT: u32 = 1;
+
+/// A packet socket with memory-mapped TPACKET_V3 RX and TX rings.
+///
+/// The rings themselves are accessed through [PacketRx](struct.PacketRx.html) and
+/// [PacketTx](struct.PacketTx.html), which must not outlive the descriptor.
+#[derive(Debug)]
+pub struct PacketRingDesc {
+    lower:   libc::c_int,
+    ifreq:   ifreq,
+    map:     *mut u8,
+    map_len: usize
+}
+
+impl AsRawFd for PacketRingDesc {
+    fn as_raw_fd(&self) -> RawFd {
+        self.lower
+    }
+}
+
+/// The RX ring, made of blocks of frames that are handed over by the kernel at once.
+#[derive(Debug)]
+pub struct PacketRx {
+    map:        *mut u8,
+    block_size: usize,
+    block_nr:   usize,
+    block:      usize,
+    in_block:   bool,
+    offset:     usize,
+    remaining:  usize
+}
+
+/// The TX ring, made of fixed size frames that are sent by the kernel on request.
+#[derive(Debug)]
+pub struct PacketTx {
+    lower:      libc::c_int,
+    map:        *mut u8,
+    frame_size: usize,
+    frame_nr:   usize,
+    next:       usize,
+    pending:    usize
+}
+
+impl PacketRingDesc {
+    pub fn new(name: &str) -> io::Result<PacketRingDesc> {
+        let lower = unsafe {
+            let lower = libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_NONBLOCK,
+                                     imp::ETH_P_ALL.to_be() as i32);
+            if lower == -1 { return Err(io::Error::last_os_error()) }
+            lower
+        };
+
+        let mut desc = PacketRingDesc {
+            lower:   lower,
+            ifreq:   ifreq_for(name),
+            map:     ptr::null_mut(),
+            map_len: 0
+        };
+        desc.set_option(imp::PACKET_VERSION, &imp::TPACKET_V3)?;
+        Ok(desc)
+    }
+
+    pub fn interface_mtu(&mut self) -> io::Result<usize> {
+        ifreq_ioctl(self.lower, &mut self.ifreq, imp::SIOCGIFMTU).map(|mtu| mtu as usize)
+    }
+
+    /// Set up and map `rx_blocks` RX blocks and `tx_blocks` TX blocks, sized for frames
+    /// of up to `frame_len` octets.
+    ///
+    /// This must be done before binding the socket, and only once.
+    pub fn map_rings(&mut self, frame_len: usize, rx_blocks: usize, tx_blocks: usize)
+                    -> io::Result<(PacketRx, PacketTx)> {
+        assert!(self.map.is_null());
+
+        let frame_size = (TX_DATA_OFFSET + frame_len).next_power_of_two();
+        let block_size = cmp::max(frame_size, MIN_BLOCK_SIZE);
+        let frames_per_block = block_size / frame_size;
+        let mut req = imp::tpacket_req3 {
+            tp_block_size:       block_size as libc::c_uint,
+            tp_block_nr:         rx_blocks as libc::c_uint,
+            tp_frame_size:       frame_size as libc::c_uint,
+            tp_frame_nr:         (rx_blocks * frames_per_block) as libc::c_uint,
+            tp_retire_blk_tov:   RETIRE_BLOCK_TIMEOUT,
+            tp_sizeof_priv:      0,
+            tp_feature_req_word: 0
+        };
+        self.set_option(imp::PACKET_RX_RING, &req)?;
+        req.tp_block_nr       = tx_blocks as libc::c_uint;
+        req.tp_frame_nr       = (tx_blocks * frames_per_block) as libc::c_uint;
+        req.tp_retire_blk_tov = 0;
+        self.set_option(imp::PACKET_TX_RING, &req)?;
+
+        let rx_len = rx_blocks * block_size;
+        let tx_len = tx_blocks * block_size;
+        unsafe {
+            let map = libc::mmap(ptr::null_mut(), rx_len + tx_len,
+                                 libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED,
+                                 self.lower, 0);
+            if map == libc::MAP_FAILED { return Err(io::Error::last_os_error()) }
+            self.map     = map as *mut u8;
+            self.map_len = rx_len + tx_len;
+        }
+
+        let rx = PacketRx {
+            map:        self.map,
+            block_size: block_size,
+            block_nr:   rx_blocks,
+            block:      0,
+            in_block:   false,
+            offset:     0,
+            remaining:  0
+        };
+        let tx = PacketTx {
+            lower:      self.lower,
+            map:        unsafe { self.map.offset(rx_len as isize) },
+            frame_size: frame_size,
+            frame_nr:   tx_blocks * frames_per_block,
+            next:       0,
+            pending:    0
+        };
+        Ok((rx, tx))
+    }
+
+    pub fn bind_interface(&mut self) -> io::Result<()> {
+        let sockaddr = libc::sockaddr_ll {
+            sll_family:   libc::AF_PACKET as u16,
+            sll_protocol: imp::ETH_P_ALL.to_be() as u16,
+            sll_ifindex:  ifreq_ioctl(self.lower, &mut self.ifreq, imp::SIOCGIFINDEX)?,
+            sll_hatype:   1,
+            sll_pkttype:  0,
+            sll_halen:    6,
+            sll_addr:     [0; 8]
+        };
+
+        unsafe {
+            let res = libc::bind(self.lower,
+                                 &sockaddr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                                 mem::size_of::<libc::sockaddr_ll>() as u32);
+            if res == -1 { return Err(io::Error::last_os_error()) }
+        }
+
+        Ok(())
+    }
+
+    fn set_option<T>(&mut self, name: libc::c_int, value: &T) -> io::Result<()> {
+        unsafe {
+            let res = libc::setsockopt(self.lower, imp::SOL_PACKET, name,
+                                       value as *const T as *const libc::c_void,
+                                       mem::size_of::<T>() as libc::socklen_t);
+            if res == -1 { return Err(io::Error::last_os_error()) }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PacketRingDesc {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.map.is_null() {
+                libc::munmap(self.map as *mut libc::c_void, self.map_len);
+            }
+            libc::close(self.lower);
+        }
+    }
+}
+
+impl PacketRx {
+    fn block_desc(&self) -> *mut imp::tpacket_block_desc {
+        unsafe { self.map.offset((self.block * self.block_size) as isize) as *mut _ }
+    }
+
+    /// Return the next received frame, lent straight from the ring, if any.
+    ///
+    /// Once every frame in a block has been returned, the block is handed back
+    /// to the kernel on the following call, so frames must not be retained.
+    pub fn recv(&mut self) -> Option<&[u8]> {
+        unsafe {
+            if self.remaining == 0 {
+                if self.in_block {
+                    let desc = self.block_desc();
+                    fence(Ordering::Release);
+                    ptr::write_volatile(&mut (*desc).block_status, imp::TP_STATUS_KERNEL);
+                    self.block    = (self.block + 1) % self.block_nr;
+                    self.in_block = false;
+                }
+
+                let desc = self.block_desc();
+                let status = ptr::read_volatile(&(*desc).block_status);
+                fence(Ordering::Acquire);
+                if status & imp::TP_STATUS_USER == 0 { return None }
+
+                self.in_block  = true;
+                self.offset    = (*desc).offset_to_first_pkt as usize;
+                self.remaining = (*desc).num_pkts as usize;
+                if self.remaining == 0 { return None }
+            }
+
+            let block = self.map.offset((self.block * self.block_size) as isize);
+            let header = block.offset(self.offset as isize) as *const imp::tpacket3_hdr;
+            let frame = slice::from_raw_parts((header as *const u8).offset((*header).tp_mac as isize),
+                                              (*header).tp_snaplen as usize);
+            self.offset   += (*header).tp_next_offset as usize;
+            self.remaining -= 1;
+            Some(frame)
+        }
+    }
+}
+
+impl PacketTx {
+    fn header(&self, index: usize) -> *mut imp::tpacket3_hdr {
+        unsafe { self.map.offset((index * self.frame_size) as isize) as *mut _ }
+    }
+
+    /// Return the largest frame that fits into a ring slot.
+    pub fn max_frame_len(&self) -> usize {
+        self.frame_size - TX_DATA_OFFSET
+    }
+
+    /// Check whether the next ring slot is free, i.e. the kernel has sent the frame in it.
+    pub fn is_available(&self) -> bool {
+        unsafe {
+            let status = ptr::read_volatile(&(*self.header(self.next)).tp_status);
+            fence(Ordering::Acquire);
+            status == imp::TP_STATUS_AVAILABLE || status & imp::TP_STATUS_WRONG_FORMAT != 0
+        }
+    }
+
+    /// Return the buffer of the next ring slot, to write a frame of `len` octets into it.
+    ///
+    /// # Panics
+    /// This function panics if `len` exceeds the [maximum frame length](#method.max_frame_len).
+    pub fn frame_mut(&mut self, len: usize) -> &mut [u8] {
+        assert!(len <= self.max_frame_len());
+        unsafe {
+            let data = (self.header(self.next) as *mut u8).offset(TX_DATA_OFFSET as isize);
+            slice::from_raw_parts_mut(data, len)
+        }
+    }
+
+    /// Hand the frame of `len` octets in the next ring slot over to the kernel.
+    /// It is not sent until the ring is [flushed](#method.flush).
+    pub fn commit(&mut self, len: usize) {
+        unsafe {
+            let header = self.header(self.next);
+            (*header).tp_next_offset = 0;
+            (*header).tp_snaplen     = len as u32;
+            (*header).tp_len         = len as u32;
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*header).tp_status, imp::TP_STATUS_SEND_REQUEST);
+        }
+        self.next     = (self.next + 1) % self.frame_nr;
+        self.pending += 1;
+    }
+
+    /// Return the number of frames committed since the last flush.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Ask the kernel to send every committed frame.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending == 0 { return Ok(()) }
+        unsafe {
+            let res = libc::send(self.lower, ptr::null(), 0, libc::MSG_DONTWAIT);
+            if res == -1 { return Err(io::Error::last_os_error()) }
+        }
+        self.pending = 0;
+        Ok(())
+    }
+}
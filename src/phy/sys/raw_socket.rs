@@ -1,8 +1,11 @@
-use std::{mem, io};
+use std::{cmp, mem, ptr, io};
 use std::os::unix::io::{RawFd, AsRawFd};
 use libc;
 use super::*;
 
+/// The maximum number of frames passed to `recvmmsg` or `sendmmsg` at once.
+const MMSG_LEN: usize = 32;
+
 #[derive(Debug)]
 pub struct RawSocketDesc {
     lower: libc::c_int,
@@ -55,21 +58,60 @@ impl RawSocketDesc {
         Ok(())
     }
 
-    pub fn recv(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
-        unsafe {
-            let len = libc::recv(self.lower, buffer.as_mut_ptr() as *mut libc::c_void,
-                                 buffer.len(), 0);
-            if len == -1 { return Err(io::Error::last_os_error()) }
-            Ok(len as usize)
+    /// Receive frames into the free slots of `batch`, using as few system calls as possible.
+    ///
+    /// Returns `Err` with `io::ErrorKind::WouldBlock` if there were no frames to receive.
+    pub fn recv_batch(&mut self, batch: &mut Batch) -> io::Result<()> {
+        while !batch.is_full() {
+            let first = batch.len();
+            let count = cmp::min(batch.capacity() - first, MMSG_LEN);
+            let received = unsafe {
+                let mut iovecs: [libc::iovec; MMSG_LEN] = mem::zeroed();
+                let mut msgs: [imp::mmsghdr; MMSG_LEN] = mem::zeroed();
+                for i in 0..count {
+                    let slot = batch.slot_mut(first + i);
+                    iovecs[i].iov_base = slot.as_mut_ptr() as *mut libc::c_void;
+                    iovecs[i].iov_len  = slot.len();
+                    msgs[i].msg_hdr.msg_iov    = &mut iovecs[i];
+                    msgs[i].msg_hdr.msg_iovlen = 1;
+                }
+
+                let res = imp::recvmmsg(self.lower, msgs.as_mut_ptr(), count as libc::c_uint,
+                                        libc::MSG_DONTWAIT, ptr::null_mut());
+                if res == -1 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::WouldBlock && !batch.is_empty() { break }
+                    return Err(err)
+                }
+                for msg in &msgs[..res as usize] {
+                    batch.commit(msg.msg_len as usize)
+                }
+                res as usize
+            };
+            if received < count { break }
         }
+        Ok(())
     }
 
-    pub fn send(&mut self, buffer: &[u8]) -> io::Result<usize> {
+    /// Send the frames of `batch` starting at `first`, using one system call.
+    ///
+    /// Returns the number of frames sent.
+    pub fn send_batch(&mut self, batch: &Batch, first: usize) -> io::Result<usize> {
+        let count = cmp::min(batch.len() - first, MMSG_LEN);
         unsafe {
-            let len = libc::send(self.lower, buffer.as_ptr() as *const libc::c_void,
-                                 buffer.len(), 0);
-            if len == -1 { Err(io::Error::last_os_error()).unwrap() }
-            Ok(len as usize)
+            let mut iovecs: [libc::iovec; MMSG_LEN] = mem::zeroed();
+            let mut msgs: [imp::mmsghdr; MMSG_LEN] = mem::zeroed();
+            for i in 0..count {
+                let frame = batch.frame(first + i);
+                iovecs[i].iov_base = frame.as_ptr() as *mut libc::c_void;
+                iovecs[i].iov_len  = frame.len();
+                msgs[i].msg_hdr.msg_iov    = &mut iovecs[i];
+                msgs[i].msg_hdr.msg_iovlen = 1;
+            }
+
+            let res = imp::sendmmsg(self.lower, msgs.as_mut_ptr(), count as libc::c_uint, 0);
+            if res == -1 { return Err(io::Error::last_os_error()) }
+            Ok(res as usize)
         }
     }
 }
@@ -49,21 +49,37 @@ impl TapInterfaceDesc {
         mtu
     }
 
-    pub fn recv(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
-        unsafe {
-            let len = libc::read(self.lower, buffer.as_mut_ptr() as *mut libc::c_void,
-                                 buffer.len());
-            if len == -1 { return Err(io::Error::last_os_error()) }
-            Ok(len as usize)
+    /// Receive frames into the free slots of `batch`.
+    ///
+    /// A TAP interface does not support `recvmmsg`, so this takes one system call per frame.
+    /// Returns `Err` with `io::ErrorKind::WouldBlock` if there were no frames to receive.
+    pub fn recv_batch(&mut self, batch: &mut Batch) -> io::Result<()> {
+        while !batch.is_full() {
+            let len = unsafe {
+                let slot = batch.slot_mut(batch.len());
+                libc::read(self.lower, slot.as_mut_ptr() as *mut libc::c_void, slot.len())
+            };
+            if len == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock && !batch.is_empty() { break }
+                return Err(err)
+            }
+            batch.commit(len as usize)
         }
+        Ok(())
     }
 
-    pub fn send(&mut self, buffer: &[u8]) -> io::Result<usize> {
+    /// Send the frame of `batch` at `first`.
+    ///
+    /// A TAP interface does not support `sendmmsg`, so this sends a single frame.
+    /// Returns the number of frames sent.
+    pub fn send_batch(&mut self, batch: &Batch, first: usize) -> io::Result<usize> {
+        let frame = batch.frame(first);
         unsafe {
-            let len = libc::write(self.lower, buffer.as_ptr() as *const libc::c_void,
-                                  buffer.len());
-            if len == -1 { Err(io::Error::last_os_error()).unwrap() }
-            Ok(len as usize)
+            let len = libc::write(self.lower, frame.as_ptr() as *const libc::c_void,
+                                  frame.len());
+            if len == -1 { return Err(io::Error::last_os_error()) }
+            Ok(1)
         }
     }
 }
@@ -1,22 +1,32 @@
+#[cfg(all(feature = "std", unix))]
+use std::os::unix::io::RawFd;
+
 use Result;
 use wire::pretty_print::{PrettyPrint, PrettyPrinter};
-use phy::{self, DeviceCapabilities, Device};
+use phy::{self, DeviceCapabilities, Device, Filter, Pollable, RxOffload, TxOffload};
 use time::Instant;
 
 /// A tracer device.
 ///
 /// A tracer is a device that pretty prints all packets traversing it
+/// (or only those matching a [filter](#method.set_filter))
 /// using the provided writer function, and then passes them to another
 /// device.
 pub struct Tracer<D: for<'a> Device<'a>, P: PrettyPrint> {
     inner:  D,
     writer: fn(Instant, PrettyPrinter<P>),
+    filter: Option<Filter<'static>>,
 }
 
 impl<D: for<'a> Device<'a>, P: PrettyPrint> Tracer<D, P> {
     /// Create a tracer device.
     pub fn new(inner: D, writer: fn(timestamp: Instant, printer: PrettyPrinter<P>)) -> Tracer<D, P> {
-        Tracer { inner, writer }
+        Tracer { inner, writer, filter: None }
+    }
+
+    /// Set the filter selecting the Ethernet frames to print.
+    pub fn set_filter(&mut self, filter: Option<Filter<'static>>) {
+        self.filter = filter
     }
 
     /// Return the underlying device, consuming the tracer.
@@ -29,60 +39,99 @@ impl<'a, D, P> Device<'a> for Tracer<D, P>
     where D: for<'b> Device<'b>,
           P: PrettyPrint + 'a,
 {
-    type RxToken = RxToken<<D as Device<'a>>::RxToken, P>;
-    type TxToken = TxToken<<D as Device<'a>>::TxToken, P>;
+    type RxToken = RxToken<'a, <D as Device<'a>>::RxToken, P>;
+    type TxToken = TxToken<'a, <D as Device<'a>>::TxToken, P>;
 
     fn capabilities(&self) -> DeviceCapabilities { self.inner.capabilities() }
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let &mut Self { ref mut inner, writer, .. } = self;
+        let &mut Self { ref mut inner, writer, ref filter } = self;
         inner.receive().map(|(rx_token, tx_token)| {
-            let rx = RxToken { token: rx_token, writer: writer };
-            let tx = TxToken { token: tx_token, writer: writer };
+            let rx = RxToken { token: rx_token, writer: writer, filter: filter.as_ref() };
+            let tx = TxToken { token: tx_token, writer: writer, filter: filter.as_ref() };
             (rx, tx)
         })
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        let &mut Self { ref mut inner, writer } = self;
+        let &mut Self { ref mut inner, writer, ref filter } = self;
         inner.transmit().map(|tx_token| {
-            TxToken { token: tx_token, writer: writer }
+            TxToken { token: tx_token, writer: writer, filter: filter.as_ref() }
         })
     }
+
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+}
+
+impl<D, P> Pollable for Tracer<D, P>
+    where D: for<'a> Device<'a> + Pollable,
+          P: PrettyPrint,
+{
+    #[cfg(all(feature = "std", unix))]
+    fn pollable_fd(&self) -> Option<RawFd> {
+        self.inner.pollable_fd()
+    }
+
+    fn set_rx_callback(&mut self, callback: fn()) -> bool {
+        self.inner.set_rx_callback(callback)
+    }
+}
+
+fn matches(filter: Option<&Filter>, buffer: &[u8]) -> bool {
+    filter.map(|filter| filter.matches(buffer)).unwrap_or(true)
 }
 
 #[doc(hidden)]
-pub struct RxToken<Rx: phy::RxToken, P: PrettyPrint> {
+pub struct RxToken<'a, Rx: phy::RxToken, P: PrettyPrint> {
     token:     Rx,
-    writer:    fn(Instant, PrettyPrinter<P>)
+    writer:    fn(Instant, PrettyPrinter<P>),
+    filter:    Option<&'a Filter<'static>>
 }
 
-impl<Rx: phy::RxToken, P: PrettyPrint> phy::RxToken for RxToken<Rx, P> {
+impl<'a, Rx: phy::RxToken, P: PrettyPrint> phy::RxToken for RxToken<'a, Rx, P> {
     fn consume<R, F>(self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&[u8]) -> Result<R>
     {
-        let Self { token, writer } = self;
+        let Self { token, writer, filter } = self;
         token.consume(timestamp, |buffer| {
-            writer(timestamp, PrettyPrinter::<P>::new("<- ", &buffer));
+            if matches(filter, buffer) {
+                writer(timestamp, PrettyPrinter::<P>::new("<- ", &buffer));
+            }
             f(buffer)
         })
     }
+
+    fn offload(&self) -> RxOffload {
+        self.token.offload()
+    }
 }
 
 #[doc(hidden)]
-pub struct TxToken<Tx: phy::TxToken, P: PrettyPrint> {
+pub struct TxToken<'a, Tx: phy::TxToken, P: PrettyPrint> {
     token:     Tx,
-    writer:    fn(Instant, PrettyPrinter<P>)
+    writer:    fn(Instant, PrettyPrinter<P>),
+    filter:    Option<&'a Filter<'static>>
 }
 
-impl<Tx: phy::TxToken, P: PrettyPrint> phy::TxToken for TxToken<Tx, P> {
+impl<'a, Tx: phy::TxToken, P: PrettyPrint> phy::TxToken for TxToken<'a, Tx, P> {
     fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
-        let Self { token, writer } = self;
-        token.consume(timestamp, len, |buffer| {
+        self.consume_with_offload(timestamp, len, TxOffload::default(), f)
+    }
+
+    fn consume_with_offload<R, F>(self, timestamp: Instant, len: usize, offload: TxOffload,
+                                  f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        let Self { token, writer, filter } = self;
+        token.consume_with_offload(timestamp, len, offload, |buffer| {
             let result = f(buffer);
-            writer(timestamp, PrettyPrinter::<P>::new("-> ", &buffer));
+            if matches(filter, buffer) {
+                writer(timestamp, PrettyPrinter::<P>::new("-> ", &buffer));
+            }
             result
         })
     }
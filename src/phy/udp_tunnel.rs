@@ -0,0 +1,188 @@
+use std::io;
+use std::vec::Vec;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::unix::io::{RawFd, AsRawFd};
+
+use Result;
+use phy::{self, Pollable, DeviceCapabilities, Device};
+use time::Instant;
+
+/// The largest Ethernet frame carried in a datagram.
+const MTU: usize = 1514;
+
+/// A virtual Ethernet link tunnelled over UDP.
+///
+/// Every frame is sent as a single datagram to a peer, and every datagram received from
+/// that peer is a frame, in the same way as QEMU's `-netdev socket,udp=...` backend does.
+/// Since it only uses an ordinary UDP socket, two processes on one or several hosts can be
+/// connected this way without any special privileges.
+#[derive(Debug)]
+pub struct UdpTunnel {
+    socket:    UdpSocket,
+    rx_buffer: Vec<u8>,
+    tx_buffer: Vec<u8>
+}
+
+impl AsRawFd for UdpTunnel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl Pollable for UdpTunnel {
+    fn pollable_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
+
+impl UdpTunnel {
+    /// Creates a tunnel bound to the `local` address, exchanging frames with
+    /// the `peer` address.
+    ///
+    /// Datagrams from any other address are ignored.
+    pub fn new<A: ToSocketAddrs, B: ToSocketAddrs>(local: A, peer: B) -> io::Result<UdpTunnel> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTunnel {
+            socket:    socket,
+            rx_buffer: vec![0; MTU],
+            tx_buffer: vec![0; MTU]
+        })
+    }
+
+    /// Return the address the tunnel is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Return the address of the peer.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+}
+
+impl<'a> Device<'a> for UdpTunnel {
+    type RxToken = RxToken<'a>;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            max_transmission_unit: MTU,
+            ..DeviceCapabilities::default()
+        }
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let &mut UdpTunnel { ref socket, ref mut rx_buffer, ref mut tx_buffer } = self;
+        match socket.recv(rx_buffer) {
+            Ok(size) => {
+                let rx = RxToken { buffer: &rx_buffer[..size] };
+                let tx = TxToken { socket, buffer: tx_buffer };
+                Some((rx, tx))
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => None,
+            // An ICMP error caused by an earlier datagram, e.g. if the peer is not running yet.
+            Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => None,
+            Err(err) => panic!("{}", err)
+        }
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken {
+            socket: &self.socket,
+            buffer: &mut self.tx_buffer
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<'a> {
+    buffer: &'a [u8]
+}
+
+impl<'a> phy::RxToken for RxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, f: F) -> Result<R>
+        where F: FnOnce(&[u8]) -> Result<R>
+    {
+        f(self.buffer)
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a> {
+    socket: &'a UdpSocket,
+    buffer: &'a mut [u8]
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+        where F: FnOnce(&mut [u8]) -> Result<R>
+    {
+        let buffer = &mut self.buffer[..len];
+        let result = f(buffer);
+        if result.is_err() { return result }
+        match self.socket.send(buffer) {
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::ConnectionRefused => {
+                net_debug!("udp tunnel: dropping a frame: {}", err);
+            }
+            Err(err) => panic!("{}", err)
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use phy::{RxToken, TxToken};
+
+    fn pair() -> (UdpTunnel, UdpTunnel) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        drop((a, b));
+        (UdpTunnel::new(a_addr, b_addr).unwrap(), UdpTunnel::new(b_addr, a_addr).unwrap())
+    }
+
+    fn receive(device: &mut UdpTunnel) -> Vec<u8> {
+        for _ in 0..1000 {
+            if let Some((rx, _tx)) = device.receive() {
+                return rx.consume(Instant::from_millis(0), |buffer| Ok(buffer.to_vec())).unwrap()
+            }
+            ::std::thread::sleep(::std::time::Duration::from_millis(1))
+        }
+        panic!("no frame received")
+    }
+
+    #[test]
+    fn test_exchange() {
+        let (mut a, mut b) = pair();
+        assert_eq!(a.peer_addr().unwrap(), b.local_addr().unwrap());
+
+        a.transmit().unwrap().consume(Instant::from_millis(0), 4, |buffer| {
+            buffer.copy_from_slice(&[1, 2, 3, 4]);
+            Ok(())
+        }).unwrap();
+        assert_eq!(receive(&mut b), vec![1, 2, 3, 4]);
+
+        b.transmit().unwrap().consume(Instant::from_millis(0), MTU, |buffer| {
+            buffer[MTU - 1] = 0xff;
+            Ok(())
+        }).unwrap();
+        let frame = receive(&mut a);
+        assert_eq!(frame.len(), MTU);
+        assert_eq!(frame[MTU - 1], 0xff);
+    }
+
+    #[test]
+    fn test_ignore_others() {
+        let (mut a, _b) = pair();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        other.send_to(&[1, 2, 3], a.local_addr().unwrap()).unwrap();
+        ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        assert!(a.receive().is_none());
+    }
+}
@@ -0,0 +1,61 @@
+use time::Duration;
+
+/// A pseudo-random number generator.
+///
+/// We use our own RNG to stay compatible with #![no_std]. It is not suitable
+/// for anything security-sensitive, only for spreading out protocol timers
+/// and picking addresses.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rand {
+    state: u32,
+}
+
+impl Rand {
+    /// Create a generator from the given seed.
+    pub fn new(seed: u32) -> Rand {
+        // Xorshift never leaves the zero state.
+        let state = if seed == 0 { 0x9e37_79b9 } else { seed };
+        Rand { state }
+    }
+
+    /// Return a random 32-bit number.
+    pub fn rand_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Return a random duration in `min..=max`.
+    ///
+    /// The result has a slight bias, but it doesn't matter.
+    pub fn rand_duration(&mut self, min: Duration, max: Duration) -> Duration {
+        let range = max.total_millis() - min.total_millis();
+        let offset = self.rand_u32() as u64 % (range + 1);
+        min + Duration::from_millis(offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zero_seed() {
+        let mut rand = Rand::new(0);
+        assert!(rand.rand_u32() != 0);
+    }
+
+    #[test]
+    fn test_rand_duration() {
+        let mut rand = Rand::new(0x1234);
+        for _ in 0..100 {
+            let duration = rand.rand_duration(Duration::from_millis(1000),
+                                              Duration::from_millis(2000));
+            assert!(duration >= Duration::from_millis(1000));
+            assert!(duration <= Duration::from_millis(2000));
+        }
+    }
+}
@@ -332,11 +332,13 @@ impl<'a, 'b> IcmpSocket<'a, 'b> {
                     let packet = Icmpv4Packet::new_unchecked(&*packet_buf);
                     let repr = Icmpv4Repr::parse(&packet, &ChecksumCapabilities::ignored())?;
                     let ip_repr = IpRepr::Ipv4(Ipv4Repr {
-                        src_addr:    Ipv4Address::default(),
-                        dst_addr:    ipv4_addr,
-                        protocol:    IpProtocol::Icmp,
-                        payload_len: repr.buffer_len(),
-                        hop_limit:   hop_limit,
+                        src_addr:      Ipv4Address::default(),
+                        dst_addr:      ipv4_addr,
+                        protocol:      IpProtocol::Icmp,
+                        payload_len:   repr.buffer_len(),
+                        hop_limit:     hop_limit,
+                        traffic_class: 0,
+                        router_alert:  false,
                     });
                     emit((ip_repr, IcmpRepr::Ipv4(repr)))
                 },
@@ -346,11 +348,12 @@ impl<'a, 'b> IcmpSocket<'a, 'b> {
                     let src_addr = Ipv6Address::default();
                     let repr = Icmpv6Repr::parse(&src_addr.into(), &ipv6_addr.into(), &packet, &ChecksumCapabilities::ignored())?;
                     let ip_repr = IpRepr::Ipv6(Ipv6Repr {
-                        src_addr:    src_addr,
-                        dst_addr:    ipv6_addr,
-                        next_header: IpProtocol::Icmpv6,
-                        payload_len: repr.buffer_len(),
-                        hop_limit:   hop_limit,
+                        src_addr:      src_addr,
+                        dst_addr:      ipv6_addr,
+                        next_header:   IpProtocol::Icmpv6,
+                        payload_len:   repr.buffer_len(),
+                        hop_limit:     hop_limit,
+                        traffic_class: 0,
                     });
                     emit((ip_repr, IcmpRepr::Ipv6(repr)))
                 },
@@ -419,7 +422,9 @@ mod test_ipv4 {
         dst_addr: REMOTE_IPV4,
         protocol: IpProtocol::Icmp,
         payload_len: 24,
-        hop_limit: 0x40
+        hop_limit: 0x40,
+        traffic_class: 0,
+        router_alert: false
     });
 
     static REMOTE_IPV4_REPR: IpRepr = IpRepr::Ipv4(Ipv4Repr {
@@ -427,7 +432,9 @@ mod test_ipv4 {
         dst_addr: LOCAL_IPV4,
         protocol: IpProtocol::Icmp,
         payload_len: 24,
-        hop_limit: 0x40
+        hop_limit: 0x40,
+        traffic_class: 0,
+        router_alert: false
     });
 
     #[test]
@@ -494,6 +501,8 @@ mod test_ipv4 {
                 protocol: IpProtocol::Icmp,
                 payload_len: ECHOV4_REPR.buffer_len(),
                 hop_limit: 0x2a,
+                traffic_class: 0,
+                router_alert: false,
             }));
             Ok(())
         }), Ok(()));
@@ -567,7 +576,9 @@ mod test_ipv4 {
                 dst_addr: REMOTE_IPV4,
                 protocol: IpProtocol::Icmp,
                 payload_len: 12,
-                hop_limit: 0x40
+                hop_limit: 0x40,
+                traffic_class: 0,
+                router_alert: false
             },
             data: data
         };
@@ -576,7 +587,8 @@ mod test_ipv4 {
             dst_addr: LOCAL_IPV4.into(),
             protocol: IpProtocol::Icmp,
             payload_len: icmp_repr.buffer_len(),
-            hop_limit: 0x40
+            hop_limit: 0x40,
+            traffic_class: 0
         };
 
         assert!(!socket.can_recv());
@@ -618,7 +630,8 @@ mod test_ipv6 {
         dst_addr: REMOTE_IPV6,
         next_header: IpProtocol::Icmpv6,
         payload_len: 24,
-        hop_limit: 0x40
+        hop_limit: 0x40,
+        traffic_class: 0
     });
 
     static REMOTE_IPV6_REPR: IpRepr = IpRepr::Ipv6(Ipv6Repr {
@@ -626,7 +639,8 @@ mod test_ipv6 {
         dst_addr: LOCAL_IPV6,
         next_header: IpProtocol::Icmpv6,
         payload_len: 24,
-        hop_limit: 0x40
+        hop_limit: 0x40,
+        traffic_class: 0
     });
 
     #[test]
@@ -693,6 +707,7 @@ mod test_ipv6 {
                 next_header: IpProtocol::Icmpv6,
                 payload_len: ECHOV6_REPR.buffer_len(),
                 hop_limit: 0x2a,
+                traffic_class: 0,
             }));
             Ok(())
         }), Ok(()));
@@ -766,7 +781,8 @@ mod test_ipv6 {
                 dst_addr: REMOTE_IPV6,
                 next_header: IpProtocol::Icmpv6,
                 payload_len: 12,
-                hop_limit: 0x40
+                hop_limit: 0x40,
+                traffic_class: 0
             },
             data: data
         };
@@ -775,7 +791,8 @@ mod test_ipv6 {
             dst_addr: LOCAL_IPV6.into(),
             protocol: IpProtocol::Icmpv6,
             payload_len: icmp_repr.buffer_len(),
-            hop_limit: 0x40
+            hop_limit: 0x40,
+            traffic_class: 0
         };
 
         assert!(!socket.can_recv());
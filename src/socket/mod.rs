@@ -23,10 +23,14 @@ mod icmp;
 mod udp;
 #[cfg(feature = "socket-tcp")]
 mod tcp;
+#[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+mod multicast;
 mod set;
 mod ref_;
 
 pub(crate) use self::meta::Meta as SocketMeta;
+#[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+pub(crate) use self::multicast::Options as MulticastOptions;
 
 #[cfg(feature = "socket-raw")]
 pub use self::raw::{RawPacketMetadata,
@@ -40,7 +44,8 @@ pub use self::icmp::{IcmpPacketMetadata,
                      IcmpSocket};
 
 #[cfg(feature = "socket-udp")]
-pub use self::udp::{UdpPacketMetadata,
+pub use self::udp::{UdpMetadata,
+                    UdpPacketMetadata,
                     UdpSocketBuffer,
                     UdpSocket};
 
@@ -140,6 +145,30 @@ impl<'a, 'b> Socket<'a, 'b> {
     pub(crate) fn poll_at(&self) -> PollAt {
         dispatch_socket!(self, |socket| socket.poll_at())
     }
+
+    /// Return the multicast options of the socket, if it has any.
+    #[cfg(feature = "proto-igmp")]
+    pub(crate) fn multicast(&self) -> Option<&MulticastOptions> {
+        match self {
+            #[cfg(feature = "socket-raw")]
+            &Socket::Raw(ref socket) => Some(&socket.multicast),
+            #[cfg(feature = "socket-udp")]
+            &Socket::Udp(ref socket) => Some(&socket.multicast),
+            _ => None
+        }
+    }
+
+    /// Return the multicast options of the socket, if it has any, as mutable.
+    #[cfg(feature = "proto-igmp")]
+    pub(crate) fn multicast_mut(&mut self) -> Option<&mut MulticastOptions> {
+        match self {
+            #[cfg(feature = "socket-raw")]
+            &mut Socket::Raw(ref mut socket) => Some(&mut socket.multicast),
+            #[cfg(feature = "socket-udp")]
+            &mut Socket::Udp(ref mut socket) => Some(&mut socket.multicast),
+            _ => None
+        }
+    }
 }
 
 impl<'a, 'b> SocketSession for Socket<'a, 'b> {
@@ -0,0 +1,297 @@
+use {Error, Result};
+use wire::IpAddress;
+
+/// The maximum number of multicast groups a socket can join.
+const MAX_GROUPS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupState {
+    /// The group has been joined, and the interface has not been told yet.
+    Joining,
+    /// The group has been joined.
+    Joined,
+    /// The group has been left, and the interface has not been told yet.
+    Leaving,
+}
+
+/// Multicast options of a socket.
+///
+/// The groups joined by the socket are reference-counted against the multicast groups
+/// of the interface, which learns about joins and leaves the next time it is polled.
+#[derive(Debug)]
+pub(crate) struct Options {
+    /// The time-to-live (IPv4) or hop limit (IPv6) value used in outgoing multicast packets.
+    hop_limit: Option<u8>,
+    /// Whether outgoing multicast packets are looped back to the other sockets.
+    loopback:  bool,
+    /// Whether the packet at the head of the transmit buffer has been looped back.
+    looped:    bool,
+    /// The source address of outgoing multicast packets, if the socket is not bound
+    /// to an address.
+    src_addr:  IpAddress,
+    groups:    [(IpAddress, GroupState); MAX_GROUPS],
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            hop_limit: None,
+            loopback:  false,
+            looped:    false,
+            src_addr:  IpAddress::Unspecified,
+            groups:    [(IpAddress::Unspecified, GroupState::Joined); MAX_GROUPS],
+        }
+    }
+}
+
+impl Options {
+    pub(crate) fn hop_limit(&self) -> Option<u8> {
+        self.hop_limit
+    }
+
+    pub(crate) fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        // A host MUST NOT send a datagram with a hop limit value of 0
+        if let Some(0) = hop_limit {
+            panic!("the time-to-live value of a packet must not be zero")
+        }
+
+        self.hop_limit = hop_limit
+    }
+
+    pub(crate) fn loopback(&self) -> bool {
+        self.loopback
+    }
+
+    pub(crate) fn set_loopback(&mut self, enabled: bool) {
+        self.loopback = enabled
+    }
+
+    pub(crate) fn src_addr(&self) -> IpAddress {
+        self.src_addr
+    }
+
+    pub(crate) fn set_src_addr(&mut self, addr: IpAddress) -> Result<()> {
+        if !addr.is_unspecified() && !addr.is_unicast() { return Err(Error::Unaddressable) }
+
+        self.src_addr = addr;
+        Ok(())
+    }
+
+    /// Return the hop limit to use for a packet sent to `dst_addr`, given the one
+    /// used for unicast packets, which is also used for multicast packets unless
+    /// a multicast hop limit is set.
+    #[cfg(feature = "socket-udp")]
+    pub(crate) fn hop_limit_for(&self, dst_addr: IpAddress, hop_limit: u8) -> u8 {
+        if dst_addr.is_multicast() {
+            self.hop_limit.unwrap_or(hop_limit)
+        } else {
+            hop_limit
+        }
+    }
+
+    /// Return the source address to use for a packet sent to `dst_addr`, given
+    /// the address the socket is bound to.
+    pub(crate) fn src_addr_for(&self, dst_addr: IpAddress, src_addr: IpAddress) -> IpAddress {
+        if dst_addr.is_multicast() && src_addr.is_unspecified() {
+            self.src_addr
+        } else {
+            src_addr
+        }
+    }
+
+    /// Check whether the packet at the head of the transmit buffer, which is sent
+    /// to `dst_addr`, is to be looped back, and mark it as such.
+    pub(crate) fn loop_back(&mut self, dst_addr: IpAddress) -> bool {
+        if !self.loopback || self.looped || !dst_addr.is_multicast() {
+            return false
+        }
+        self.looped = true;
+        true
+    }
+
+    /// Note that the packet at the head of the transmit buffer has been dequeued.
+    pub(crate) fn dequeued(&mut self) {
+        self.looped = false
+    }
+
+    fn find(&self, addr: IpAddress) -> Option<usize> {
+        self.groups.iter().position(|&(group_addr, _)| group_addr == addr)
+    }
+
+    pub(crate) fn has_group(&self, addr: IpAddress) -> bool {
+        if !addr.is_multicast() { return false }
+
+        match self.find(addr) {
+            Some(index) => self.groups[index].1 != GroupState::Leaving,
+            None => false
+        }
+    }
+
+    pub(crate) fn join(&mut self, addr: IpAddress) -> Result<bool> {
+        if !addr.is_multicast() { return Err(Error::Unaddressable) }
+
+        match self.find(addr) {
+            Some(index) => match self.groups[index].1 {
+                GroupState::Leaving => {
+                    self.groups[index].1 = GroupState::Joined;
+                    Ok(true)
+                }
+                _ => Ok(false)
+            },
+            None => {
+                let index = self.find(IpAddress::Unspecified).ok_or(Error::Exhausted)?;
+                self.groups[index] = (addr, GroupState::Joining);
+                Ok(true)
+            }
+        }
+    }
+
+    pub(crate) fn leave(&mut self, addr: IpAddress) -> bool {
+        match self.find(addr) {
+            Some(index) => match self.groups[index].1 {
+                GroupState::Joining => {
+                    self.groups[index].0 = IpAddress::Unspecified;
+                    true
+                }
+                GroupState::Joined => {
+                    self.groups[index].1 = GroupState::Leaving;
+                    true
+                }
+                GroupState::Leaving => false
+            },
+            None => false
+        }
+    }
+
+    /// Return a group that has been joined (`true`) or left (`false`), and whose
+    /// change the interface has not been told about yet.
+    #[cfg(feature = "proto-igmp")]
+    pub(crate) fn pending_change(&self) -> Option<(IpAddress, bool)> {
+        self.groups.iter().filter_map(|&(addr, state)| {
+            match state {
+                _ if addr.is_unspecified() => None,
+                GroupState::Joining => Some((addr, true)),
+                GroupState::Leaving => Some((addr, false)),
+                GroupState::Joined  => None,
+            }
+        }).next()
+    }
+
+    /// Note that the socket has been removed from the interface it was used with,
+    /// which releases its groups. The groups that remain joined are joined again
+    /// when the socket is used with an interface next time.
+    #[cfg(feature = "proto-igmp")]
+    pub(crate) fn detach(&mut self) {
+        for &mut (ref mut addr, ref mut state) in self.groups.iter_mut() {
+            match *state {
+                GroupState::Joining => (),
+                GroupState::Joined  => *state = GroupState::Joining,
+                GroupState::Leaving => *addr = IpAddress::Unspecified
+            }
+        }
+    }
+
+    /// Note that the interface has been told about the change of a group, successfully
+    /// or not. A group that could not be joined is forgotten.
+    #[cfg(feature = "proto-igmp")]
+    pub(crate) fn change_done(&mut self, addr: IpAddress, succeeded: bool) {
+        if let Some(index) = self.find(addr) {
+            match self.groups[index].1 {
+                GroupState::Joining if succeeded => self.groups[index].1 = GroupState::Joined,
+                GroupState::Joining | GroupState::Leaving =>
+                    self.groups[index].0 = IpAddress::Unspecified,
+                GroupState::Joined => ()
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "proto-igmp"))]
+mod test {
+    use wire::Ipv4Address;
+    use super::*;
+
+    const GROUP_A: IpAddress = IpAddress::Ipv4(Ipv4Address([224, 0, 0, 251]));
+    const GROUP_B: IpAddress = IpAddress::Ipv4(Ipv4Address([239, 1, 2, 3]));
+
+    #[test]
+    fn test_hop_limit_and_src_addr() {
+        let mut options = Options::default();
+        let unicast = IpAddress::v4(192, 168, 1, 1);
+        let src_addr = IpAddress::v4(192, 168, 1, 2);
+        #[cfg(feature = "socket-udp")]
+        {
+            assert_eq!(options.hop_limit_for(GROUP_A, 64), 64);
+            assert_eq!(options.hop_limit_for(unicast, 64), 64);
+            options.set_hop_limit(Some(16));
+            assert_eq!(options.hop_limit_for(GROUP_A, 64), 16);
+        }
+
+        assert_eq!(options.set_src_addr(GROUP_B), Err(Error::Unaddressable));
+        assert_eq!(options.set_src_addr(src_addr), Ok(()));
+        assert_eq!(options.src_addr_for(GROUP_A, IpAddress::Unspecified), src_addr);
+        assert_eq!(options.src_addr_for(unicast, IpAddress::Unspecified),
+                   IpAddress::Unspecified);
+        assert_eq!(options.src_addr_for(GROUP_A, unicast), unicast);
+    }
+
+    #[test]
+    fn test_loop_back() {
+        let mut options = Options::default();
+        assert!(!options.loop_back(GROUP_A));
+        options.set_loopback(true);
+        assert!(!options.loop_back(IpAddress::v4(192, 168, 1, 1)));
+        assert!(options.loop_back(GROUP_A));
+        assert!(!options.loop_back(GROUP_A));
+        options.dequeued();
+        assert!(options.loop_back(GROUP_A));
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut options = Options::default();
+        assert_eq!(options.join(IpAddress::v4(192, 168, 1, 1)), Err(Error::Unaddressable));
+        assert_eq!(options.join(GROUP_A), Ok(true));
+        assert_eq!(options.join(GROUP_A), Ok(false));
+        assert!(options.has_group(GROUP_A));
+        assert_eq!(options.pending_change(), Some((GROUP_A, true)));
+        options.change_done(GROUP_A, true);
+        assert_eq!(options.pending_change(), None);
+
+        // Leaving a group that the interface has not been told about is not a change.
+        assert_eq!(options.join(GROUP_B), Ok(true));
+        assert!(options.leave(GROUP_B));
+        assert_eq!(options.pending_change(), None);
+
+        assert!(options.leave(GROUP_A));
+        assert!(!options.leave(GROUP_A));
+        assert!(!options.has_group(GROUP_A));
+        assert_eq!(options.pending_change(), Some((GROUP_A, false)));
+        options.change_done(GROUP_A, true);
+        assert_eq!(options.pending_change(), None);
+
+        // A group that could not be joined is forgotten.
+        assert_eq!(options.join(GROUP_A), Ok(true));
+        options.change_done(GROUP_A, false);
+        assert!(!options.has_group(GROUP_A));
+
+        // A detached socket joins its groups again, and forgets those it has left.
+        assert_eq!(options.join(GROUP_A), Ok(true));
+        options.change_done(GROUP_A, true);
+        assert_eq!(options.join(GROUP_B), Ok(true));
+        options.change_done(GROUP_B, true);
+        assert!(options.leave(GROUP_B));
+        options.detach();
+        assert!(options.has_group(GROUP_A));
+        assert_eq!(options.pending_change(), Some((GROUP_A, true)));
+        options.change_done(GROUP_A, true);
+        assert_eq!(options.pending_change(), None);
+        assert!(options.leave(GROUP_A));
+        options.change_done(GROUP_A, true);
+
+        for index in 0..MAX_GROUPS {
+            assert_eq!(options.join(IpAddress::v4(239, 0, 0, index as u8)), Ok(true));
+        }
+        assert_eq!(options.join(GROUP_A), Err(Error::Exhausted));
+    }
+}
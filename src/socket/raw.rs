@@ -1,10 +1,12 @@
 use core::cmp::min;
+use byteorder::{ByteOrder, NetworkEndian};
 
 use {Error, Result};
 use phy::ChecksumCapabilities;
-use socket::{Socket, SocketMeta, SocketHandle, PollAt};
+use socket::{Socket, SocketMeta, SocketHandle, PollAt, MulticastOptions};
 use storage::{PacketBuffer, PacketMetadata};
-use wire::{IpVersion, IpRepr, IpProtocol};
+use wire::{IpVersion, IpRepr, IpProtocol, IpAddress};
+use wire::ip::checksum;
 #[cfg(feature = "proto-ipv4")]
 use wire::{Ipv4Repr, Ipv4Packet};
 #[cfg(feature = "proto-ipv6")]
@@ -27,6 +29,7 @@ pub struct RawSocket<'a, 'b: 'a> {
     ip_protocol: IpProtocol,
     rx_buffer:   RawSocketBuffer<'a, 'b>,
     tx_buffer:   RawSocketBuffer<'a, 'b>,
+    pub(crate) multicast: MulticastOptions,
 }
 
 impl<'a, 'b> RawSocket<'a, 'b> {
@@ -41,6 +44,7 @@ impl<'a, 'b> RawSocket<'a, 'b> {
             ip_protocol,
             rx_buffer,
             tx_buffer,
+            multicast: MulticastOptions::default(),
         }
     }
 
@@ -62,6 +66,79 @@ impl<'a, 'b> RawSocket<'a, 'b> {
         self.ip_protocol
     }
 
+    /// Return the time-to-live (IPv4) or hop limit (IPv6) value used in outgoing
+    /// multicast packets.
+    ///
+    /// See also the [set_multicast_hop_limit](#method.set_multicast_hop_limit) method
+    pub fn multicast_hop_limit(&self) -> Option<u8> {
+        self.multicast.hop_limit()
+    }
+
+    /// Set the time-to-live (IPv4) or hop limit (IPv6) value used in outgoing
+    /// multicast packets. When set, it replaces the value in the IP header of
+    /// the packets sent.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if a hop limit value of 0 is given. See [RFC 1122 § 3.2.1.7].
+    ///
+    /// [RFC 1122 § 3.2.1.7]: https://tools.ietf.org/html/rfc1122#section-3.2.1.7
+    pub fn set_multicast_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.multicast.set_hop_limit(hop_limit)
+    }
+
+    /// Return whether outgoing multicast packets are looped back to the other sockets.
+    ///
+    /// See also the [set_multicast_loopback](#method.set_multicast_loopback) method
+    pub fn multicast_loopback(&self) -> bool {
+        self.multicast.loopback()
+    }
+
+    /// Enable or disable looping back outgoing multicast packets to the other raw
+    /// sockets in the socket set, if the interface is a member of the group.
+    /// Unlike with BSD sockets, this is disabled by default.
+    pub fn set_multicast_loopback(&mut self, enabled: bool) {
+        self.multicast.set_loopback(enabled)
+    }
+
+    /// Return the source address of outgoing multicast packets.
+    ///
+    /// See also the [set_multicast_src_addr](#method.set_multicast_src_addr) method
+    pub fn multicast_src_addr(&self) -> IpAddress {
+        self.multicast.src_addr()
+    }
+
+    /// Set the source address of outgoing multicast packets whose IP header has
+    /// the unspecified source address.
+    ///
+    /// This function returns `Err(Error::Unaddressable)` if the address is not unicast.
+    pub fn set_multicast_src_addr<T: Into<IpAddress>>(&mut self, addr: T) -> Result<()> {
+        self.multicast.set_src_addr(addr.into())
+    }
+
+    /// Join a multicast group on the interface the socket is used with.
+    ///
+    /// See [UdpSocket::join_multicast_group].
+    ///
+    /// [UdpSocket::join_multicast_group]: struct.UdpSocket.html#method.join_multicast_group
+    pub fn join_multicast_group<T: Into<IpAddress>>(&mut self, addr: T) -> Result<bool> {
+        self.multicast.join(addr.into())
+    }
+
+    /// Leave a multicast group joined with [join_multicast_group].
+    ///
+    /// Returns `false` if the socket has not joined the group.
+    ///
+    /// [join_multicast_group]: #method.join_multicast_group
+    pub fn leave_multicast_group<T: Into<IpAddress>>(&mut self, addr: T) -> bool {
+        self.multicast.leave(addr.into())
+    }
+
+    /// Check whether the socket has joined the given multicast group.
+    pub fn has_multicast_group<T: Into<IpAddress>>(&self, addr: T) -> bool {
+        self.multicast.has_group(addr.into())
+    }
+
     /// Check whether the transmit buffer is full.
     #[inline]
     pub fn can_send(&self) -> bool {
@@ -154,12 +231,27 @@ impl<'a, 'b> RawSocket<'a, 'b> {
                              Result<()>
             where F: FnOnce((IpRepr, &[u8])) -> Result<()> {
         fn prepare<'a>(protocol: IpProtocol, buffer: &'a mut [u8],
-                   _checksum_caps: &ChecksumCapabilities) -> Result<(IpRepr, &'a [u8])> {
+                   _checksum_caps: &ChecksumCapabilities,
+                   _multicast: &MulticastOptions) -> Result<(IpRepr, &'a [u8])> {
             match IpVersion::of_packet(buffer.as_ref())? {
                 #[cfg(feature = "proto-ipv4")]
                 IpVersion::Ipv4 => {
                     let mut packet = Ipv4Packet::new_checked(buffer.as_mut())?;
                     if packet.protocol() != protocol { return Err(Error::Unaddressable) }
+                    let dst_addr = IpAddress::from(packet.dst_addr());
+                    match _multicast.hop_limit() {
+                        Some(hop_limit) if dst_addr.is_multicast() =>
+                            packet.set_hop_limit(hop_limit),
+                        _ => ()
+                    }
+                    if let IpAddress::Ipv4(src_addr) =
+                            _multicast.src_addr_for(dst_addr, packet.src_addr().into()) {
+                        if src_addr != packet.src_addr() {
+                            packet.set_src_addr(src_addr);
+                            update_src_addr_checksum(protocol, packet.payload_mut(),
+                                                     src_addr.as_bytes())?;
+                        }
+                    }
                     if _checksum_caps.ipv4.tx() {
                         packet.fill_checksum();
                     } else {
@@ -176,6 +268,20 @@ impl<'a, 'b> RawSocket<'a, 'b> {
                 IpVersion::Ipv6 => {
                     let mut packet = Ipv6Packet::new_checked(buffer.as_mut())?;
                     if packet.next_header() != protocol { return Err(Error::Unaddressable) }
+                    let dst_addr = IpAddress::from(packet.dst_addr());
+                    match _multicast.hop_limit() {
+                        Some(hop_limit) if dst_addr.is_multicast() =>
+                            packet.set_hop_limit(hop_limit),
+                        _ => ()
+                    }
+                    if let IpAddress::Ipv6(src_addr) =
+                            _multicast.src_addr_for(dst_addr, packet.src_addr().into()) {
+                        if src_addr != packet.src_addr() {
+                            packet.set_src_addr(src_addr);
+                            update_src_addr_checksum(protocol, packet.payload_mut(),
+                                                     src_addr.as_bytes())?;
+                        }
+                    }
                     let packet = Ipv6Packet::new_unchecked(&*packet.into_inner());
                     let ipv6_repr = Ipv6Repr::parse(&packet)?;
                     Ok((IpRepr::Ipv6(ipv6_repr), packet.payload()))
@@ -185,11 +291,34 @@ impl<'a, 'b> RawSocket<'a, 'b> {
             }
         }
 
+        /// Update the checksum of a TCP, UDP or ICMPv6 packet, whose pseudo-header
+        /// source address changed from the unspecified address to `src_addr`,
+        /// see RFC 1624 § 3.
+        fn update_src_addr_checksum(protocol: IpProtocol, payload: &mut [u8],
+                                    src_addr: &[u8]) -> Result<()> {
+            let offset = match protocol {
+                IpProtocol::Tcp    => 16,
+                IpProtocol::Udp    => 6,
+                IpProtocol::Icmpv6 => 2,
+                _ => return Ok(())
+            };
+            if payload.len() < offset + 2 { return Err(Error::Truncated) }
+            let field = &mut payload[offset..offset + 2];
+            let old_checksum = NetworkEndian::read_u16(field);
+            // A zero UDP checksum means that none was computed.
+            if protocol == IpProtocol::Udp && old_checksum == 0 { return Ok(()) }
+            let mut checksum = !checksum::combine(&[!old_checksum, checksum::data(src_addr)]);
+            if protocol == IpProtocol::Udp && checksum == 0 { checksum = 0xffff }
+            NetworkEndian::write_u16(field, checksum);
+            Ok(())
+        }
+
         let handle      = self.meta.handle;
         let ip_protocol = self.ip_protocol;
         let ip_version  = self.ip_version;
-        self.tx_buffer.dequeue_with(|&mut (), packet_buf| {
-            match prepare(ip_protocol, packet_buf.as_mut(), &checksum_caps) {
+        let multicast   = &self.multicast;
+        let result = self.tx_buffer.dequeue_with(|&mut (), packet_buf| {
+            match prepare(ip_protocol, packet_buf.as_mut(), &checksum_caps, multicast) {
                 Ok((ip_repr, raw_packet)) => {
                     net_trace!("{}:{}:{}: sending {} octets",
                                handle, ip_version, ip_protocol,
@@ -204,7 +333,60 @@ impl<'a, 'b> RawSocket<'a, 'b> {
                     Ok(())
                 }
             }
-        })
+        });
+        if result.is_ok() {
+            self.multicast.dequeued();
+        }
+        result
+    }
+
+    /// Call `f` with the multicast packet at the head of the transmit buffer,
+    /// if it is to be looped back and has not been yet. Malformed packets are not
+    /// looped back, as they are dropped by [dispatch](#method.dispatch).
+    pub(crate) fn loop_back<F>(&mut self, f: F)
+            where F: FnOnce(&IpRepr, &[u8]) {
+        fn parse<'a>(protocol: IpProtocol, buffer: &'a [u8]) -> Result<(IpRepr, &'a [u8])> {
+            let _checksum_caps = ChecksumCapabilities::ignored();
+            match IpVersion::of_packet(buffer)? {
+                #[cfg(feature = "proto-ipv4")]
+                IpVersion::Ipv4 => {
+                    let packet = Ipv4Packet::new_checked(buffer)?;
+                    if packet.protocol() != protocol { return Err(Error::Unaddressable) }
+                    let ipv4_repr = Ipv4Repr::parse(&packet, &_checksum_caps)?;
+                    Ok((IpRepr::Ipv4(ipv4_repr), packet.payload()))
+                }
+                #[cfg(feature = "proto-ipv6")]
+                IpVersion::Ipv6 => {
+                    let packet = Ipv6Packet::new_checked(buffer)?;
+                    if packet.next_header() != protocol { return Err(Error::Unaddressable) }
+                    let ipv6_repr = Ipv6Repr::parse(&packet)?;
+                    Ok((IpRepr::Ipv6(ipv6_repr), packet.payload()))
+                }
+                IpVersion::Unspecified => unreachable!(),
+                IpVersion::__Nonexhaustive => unreachable!()
+            }
+        }
+
+        let ip_protocol = self.ip_protocol;
+        let multicast   = &mut self.multicast;
+        if let Ok((&(), packet_buf)) = self.tx_buffer.peek() {
+            let (ip_repr, payload) = match parse(ip_protocol, packet_buf) {
+                Ok(parsed) => parsed,
+                Err(_) => return
+            };
+            let dst_addr = ip_repr.dst_addr();
+            if !multicast.loop_back(dst_addr) { return }
+
+            let ip_repr = IpRepr::Unspecified {
+                src_addr:      multicast.src_addr_for(dst_addr, ip_repr.src_addr()),
+                dst_addr:      dst_addr,
+                protocol:      ip_protocol,
+                payload_len:   payload.len(),
+                hop_limit:     multicast.hop_limit().unwrap_or(ip_repr.hop_limit()),
+                traffic_class: ip_repr.traffic_class(),
+            };
+            f(&ip_repr, payload)
+        }
     }
 
     pub(crate) fn poll_at(&self) -> PollAt {
@@ -252,7 +434,9 @@ mod test {
             dst_addr: Ipv4Address([10, 0, 0, 2]),
             protocol: IpProtocol::Unknown(IP_PROTO),
             payload_len: 4,
-            hop_limit: 64
+            hop_limit: 64,
+            traffic_class: 0,
+            router_alert: false
         });
         pub const PACKET_BYTES: [u8; 24] = [
             0x45, 0x00, 0x00, 0x18,
@@ -286,7 +470,8 @@ mod test {
                                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02]),
             next_header: IpProtocol::Unknown(IP_PROTO),
             payload_len: 4,
-            hop_limit: 64
+            hop_limit: 64,
+            traffic_class: 0
         });
 
         pub const PACKET_BYTES: [u8; 44] = [
@@ -426,6 +611,42 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_multicast_src_addr_checksum() {
+        use wire::{UdpPacket, UdpRepr};
+
+        let checksum_caps = &ChecksumCapabilities::default();
+        let src_addr = Ipv4Address([10, 0, 0, 1]);
+        let dst_addr = Ipv4Address([224, 0, 0, 251]);
+        let mut socket = RawSocket::new(IpVersion::Ipv4, IpProtocol::Udp, buffer(0), buffer(1));
+        assert_eq!(socket.set_multicast_src_addr(src_addr), Ok(()));
+
+        let ip_repr = Ipv4Repr {
+            src_addr:      Ipv4Address::UNSPECIFIED,
+            dst_addr:      dst_addr,
+            protocol:      IpProtocol::Udp,
+            payload_len:   8 + 4,
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
+        };
+        let udp_repr = UdpRepr { src_port: 5353, dst_port: 5353, payload: &[0xaa; 4] };
+        let mut bytes = [0; 20 + 8 + 4];
+        ip_repr.emit(&mut Ipv4Packet::new_unchecked(&mut bytes[..]), checksum_caps);
+        udp_repr.emit(&mut UdpPacket::new_unchecked(&mut bytes[20..]),
+                      &Ipv4Address::UNSPECIFIED.into(), &dst_addr.into(), checksum_caps);
+
+        // The source address is filled in, and the UDP checksum updated accordingly.
+        assert_eq!(socket.send_slice(&bytes[..]), Ok(()));
+        assert_eq!(socket.dispatch(checksum_caps, |(ip_repr, payload)| {
+            assert_eq!(ip_repr.src_addr(), src_addr.into());
+            assert!(UdpPacket::new_checked(payload).unwrap()
+                        .verify_checksum(&src_addr.into(), &dst_addr.into()));
+            Ok(())
+        }), Ok(()));
+    }
+
     #[test]
     fn test_recv_process() {
         #[cfg(feature = "proto-ipv4")]
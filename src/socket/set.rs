@@ -1,4 +1,6 @@
 use core::{fmt, slice};
+#[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+use core::iter;
 use managed::ManagedSlice;
 
 use super::{Socket, SocketRef, AnySocket};
@@ -97,11 +99,22 @@ impl<'a, 'b: 'a, 'c: 'a + 'b> Set<'a, 'b, 'c> {
 
     /// Remove a socket from the set, without changing its state.
     ///
+    /// The multicast groups the socket has joined are left on the interface the next
+    /// time it is polled, and joined again if the socket is added back to a set.
+    ///
     /// # Panics
     /// This function may panic if the handle does not belong to this socket set.
     pub fn remove(&mut self, handle: Handle) -> Socket<'b, 'c> {
         net_trace!("[{}]: removing", handle.0);
         match self.sockets[handle.0].take() {
+            #[cfg(feature = "proto-igmp")]
+            Some(Item { mut socket, .. }) => {
+                if let Some(multicast) = socket.multicast_mut() {
+                    multicast.detach()
+                }
+                socket
+            }
+            #[cfg(not(feature = "proto-igmp"))]
             Some(item) => item.socket,
             None => panic!("handle does not refer to a valid socket")
         }
@@ -176,8 +189,27 @@ impl<'a, 'b: 'a, 'c: 'a + 'b> Set<'a, 'b, 'c> {
     pub fn iter_mut<'d>(&'d mut self) -> IterMut<'d, 'b, 'c> {
         IterMut { lower: self.sockets.iter_mut() }
     }
+
+    /// Call `f` with every socket in this set, and an iterator over the other sockets.
+    #[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+    pub(crate) fn for_each_with_others<F>(&mut self, mut f: F)
+            where F: for<'d> FnMut(&'d mut Socket<'b, 'c>, Others<'d, 'b, 'c>) {
+        for index in 0..self.sockets.len() {
+            let (before, rest) = self.sockets.split_at_mut(index);
+            let (item, after) = rest.split_first_mut().unwrap();
+            if let Some(ref mut item) = *item {
+                f(&mut item.socket, IterMut { lower: before.iter_mut() }
+                                        .chain(IterMut { lower: after.iter_mut() }))
+            }
+        }
+    }
 }
 
+/// Iterator over the sockets other than the one given to the closure of
+/// [for_each_with_others](struct.SocketSet.html#method.for_each_with_others).
+#[cfg(any(feature = "socket-raw", feature = "socket-udp"))]
+pub(crate) type Others<'a, 'b, 'c> = iter::Chain<IterMut<'a, 'b, 'c>, IterMut<'a, 'b, 'c>>;
+
 /// Immutable socket set iterator.
 ///
 /// This struct is created by the [iter](struct.SocketSet.html#method.iter)
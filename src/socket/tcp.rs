@@ -9,7 +9,8 @@ use phy::DeviceCapabilities;
 use time::{Duration, Instant};
 use socket::{Socket, SocketMeta, SocketHandle, PollAt};
 use storage::{Assembler, RingBuffer};
-use wire::{IpProtocol, IpRepr, IpAddress, IpEndpoint, TcpSeqNumber, TcpRepr, TcpControl};
+use wire::{IpProtocol, IpRepr, IpAddress, IpEndpoint, IpEcn,
+           TcpSeqNumber, TcpRepr, TcpControl};
 
 /// A TCP socket ring buffer.
 pub type SocketBuffer<'a> = RingBuffer<'a, u8>;
@@ -221,6 +222,8 @@ pub struct TcpSocket<'a> {
     /// The last sequence number sent.
     /// I.e. in an idle socket, local_seq_no+tx_buffer.len().
     remote_last_seq: TcpSeqNumber,
+    /// The highest sequence number sent, used to tell new data from retransmitted data.
+    remote_max_seq:  TcpSeqNumber,
     /// The last acknowledgement number sent.
     /// I.e. in an idle socket, remote_seq_no+rx_buffer.len().
     remote_last_ack: Option<TcpSeqNumber>,
@@ -247,6 +250,20 @@ pub struct TcpSocket<'a> {
     /// The number of packets recived directly after
     /// each other which have the same ACK number.
     local_rx_dup_acks: u8,
+    /// Whether Explicit Congestion Notification is offered or accepted on new connections.
+    ecn_enabled:     bool,
+    /// Whether Explicit Congestion Notification was negotiated with the remote.
+    ecn_negotiated:  bool,
+    /// Whether a segment marked as Congestion Experienced was received, and the ECE flag
+    /// must be set on outgoing segments until the remote responds with the CWR flag.
+    ecn_echo:        bool,
+    /// Whether the CWR flag must be set on the next segment carrying new data.
+    ecn_cwr:         bool,
+    /// The sequence number that has to be acknowledged before the congestion window
+    /// may be reduced again. I.e. the congestion window is reduced at most once per window.
+    ecn_recover:     Option<TcpSeqNumber>,
+    /// The maximum number of octets in flight, or `None` if it was never reduced.
+    congestion_window: Option<usize>,
 }
 
 const DEFAULT_MSS: usize = 536;
@@ -285,6 +302,7 @@ impl<'a> TcpSocket<'a> {
             local_seq_no:    TcpSeqNumber::default(),
             remote_seq_no:   TcpSeqNumber::default(),
             remote_last_seq: TcpSeqNumber::default(),
+            remote_max_seq:  TcpSeqNumber::default(),
             remote_last_ack: None,
             remote_last_win: 0,
             remote_win_len:  0,
@@ -296,6 +314,12 @@ impl<'a> TcpSocket<'a> {
             local_rx_last_ack: None,
             local_rx_last_seq: None,
             local_rx_dup_acks: 0,
+            ecn_enabled:     false,
+            ecn_negotiated:  false,
+            ecn_echo:        false,
+            ecn_cwr:         false,
+            ecn_recover:     None,
+            congestion_window: None,
         }
     }
 
@@ -392,6 +416,36 @@ impl<'a> TcpSocket<'a> {
         self.hop_limit = hop_limit
     }
 
+    /// Return whether Explicit Congestion Notification is enabled.
+    ///
+    /// See also the [set_ecn_enabled](#method.set_ecn_enabled) method.
+    pub fn ecn_enabled(&self) -> bool {
+        self.ecn_enabled
+    }
+
+    /// Enable or disable Explicit Congestion Notification, as described in [RFC 3168].
+    ///
+    /// A socket with ECN enabled offers it in the SYN when connecting, and accepts it
+    /// when the connecting remote offers it. Once ECN is negotiated, outgoing data segments
+    /// are marked as ECN-capable, segments that arrive marked as Congestion Experienced
+    /// are echoed to the remote using the ECE flag, and an ECE flag from the remote
+    /// halves the amount of data the socket keeps in flight.
+    ///
+    /// The setting takes effect when the next connection is established, and is
+    /// kept across connections. ECN is disabled by default.
+    ///
+    /// [RFC 3168]: https://tools.ietf.org/html/rfc3168
+    pub fn set_ecn_enabled(&mut self, enabled: bool) {
+        self.ecn_enabled = enabled
+    }
+
+    /// Return whether Explicit Congestion Notification was negotiated for the current
+    /// connection.
+    #[inline]
+    pub fn ecn_negotiated(&self) -> bool {
+        self.ecn_negotiated
+    }
+
     /// Return the local endpoint.
     #[inline]
     pub fn local_endpoint(&self) -> IpEndpoint {
@@ -428,6 +482,7 @@ impl<'a> TcpSocket<'a> {
         self.local_seq_no    = TcpSeqNumber::default();
         self.remote_seq_no   = TcpSeqNumber::default();
         self.remote_last_seq = TcpSeqNumber::default();
+        self.remote_max_seq  = TcpSeqNumber::default();
         self.remote_last_ack = None;
         self.remote_last_win = 0;
         self.remote_win_len  = 0;
@@ -435,6 +490,11 @@ impl<'a> TcpSocket<'a> {
         self.remote_win_shift = rx_cap_log2.saturating_sub(16) as u8;
         self.remote_mss      = DEFAULT_MSS;
         self.remote_last_ts  = None;
+        self.ecn_negotiated  = false;
+        self.ecn_echo        = false;
+        self.ecn_cwr         = false;
+        self.ecn_recover     = None;
+        self.congestion_window = None;
     }
 
     /// Start listening on the given endpoint.
@@ -497,6 +557,7 @@ impl<'a> TcpSocket<'a> {
         self.remote_endpoint = remote_endpoint;
         self.local_seq_no    = local_seq_no;
         self.remote_last_seq = local_seq_no;
+        self.remote_max_seq  = local_seq_no;
         self.set_state(State::SynSent);
         Ok(())
     }
@@ -808,14 +869,17 @@ impl<'a> TcpSocket<'a> {
             max_seg_size: None,
             sack_permitted: false,
             sack_ranges:  [None, None, None],
+            ece:          false,
+            cwr:          false,
             payload:      &[]
         };
         let ip_reply_repr = IpRepr::Unspecified {
-            src_addr:    ip_repr.dst_addr(),
-            dst_addr:    ip_repr.src_addr(),
-            protocol:    IpProtocol::Tcp,
-            payload_len: reply_repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      ip_repr.dst_addr(),
+            dst_addr:      ip_repr.src_addr(),
+            protocol:      IpProtocol::Tcp,
+            payload_len:   reply_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0
         };
         (ip_reply_repr, reply_repr)
     }
@@ -852,6 +916,11 @@ impl<'a> TcpSocket<'a> {
         reply_repr.window_len = self.scaled_window();
         self.remote_last_win = reply_repr.window_len;
 
+        // From RFC 3168:
+        // [...] the TCP receiver sets the ECN-Echo flag in a series of ACK packets [...]
+        // until it receives a CWR packet.
+        reply_repr.ece = self.ecn_echo;
+
         // If the remote supports selective acknowledgement, add the option to the outgoing
         // segment.
         if self.remote_has_sack {
@@ -1077,6 +1146,7 @@ impl<'a> TcpSocket<'a> {
                            self.meta.handle, self.local_endpoint, self.remote_endpoint);
                 self.local_endpoint.addr = self.listen_address;
                 self.remote_endpoint     = IpEndpoint::default();
+                self.ecn_negotiated      = false;
                 self.set_state(State::Listen);
                 return Ok(None)
             }
@@ -1101,7 +1171,10 @@ impl<'a> TcpSocket<'a> {
                 self.local_seq_no    = TcpSeqNumber(-repr.seq_number.0);
                 self.remote_seq_no   = repr.seq_number + 1;
                 self.remote_last_seq = self.local_seq_no;
+                self.remote_max_seq  = self.local_seq_no;
                 self.remote_has_sack = repr.sack_permitted;
+                // RFC 3168: an ECN-setup SYN packet has both the ECE and CWR flags set.
+                self.ecn_negotiated  = self.ecn_enabled && repr.ece && repr.cwr;
                 if let Some(max_seg_size) = repr.max_seg_size {
                     self.remote_mss = max_seg_size as usize
                 }
@@ -1140,6 +1213,9 @@ impl<'a> TcpSocket<'a> {
                 if let Some(max_seg_size) = repr.max_seg_size {
                     self.remote_mss = max_seg_size as usize;
                 }
+                // RFC 3168: an ECN-setup SYN-ACK packet has the ECE flag set
+                // but the CWR flag not set.
+                self.ecn_negotiated  = self.ecn_enabled && repr.ece && !repr.cwr;
                 self.set_state(State::Established);
                 self.timer.set_for_idle(timestamp, self.keep_alive);
             }
@@ -1221,6 +1297,19 @@ impl<'a> TcpSocket<'a> {
         // Update remote state.
         self.remote_last_ts = Some(timestamp);
 
+        if self.ecn_negotiated && control != TcpControl::Syn {
+            // RFC 3168: the CWR flag acknowledges the ECE flags we've sent, but a Congestion
+            // Experienced mark on the same packet has to be echoed again.
+            if repr.cwr {
+                self.ecn_echo = false;
+            }
+            if ip_repr.ecn() == IpEcn::Ce {
+                net_trace!("{}:{}:{}: received a congestion experienced mark",
+                           self.meta.handle, self.local_endpoint, self.remote_endpoint);
+                self.ecn_echo = true;
+            }
+        }
+
         // RFC 1323: The window field (SEG.WND) in the header of every incoming segment, with the
         // exception of SYN segments, is left-shifted by Snd.Wind.Scale bits before updating SND.WND.
         self.remote_win_len = (repr.window_len as usize) << (self.remote_win_scale.unwrap_or(0) as usize);
@@ -1273,6 +1362,29 @@ impl<'a> TcpSocket<'a> {
                     self.local_rx_last_ack = Some(ack_number);
                 }
             };
+
+            // RFC 3168: respond to the ECE flag as if a single packet was dropped, i.e. halve
+            // the congestion window, but do so at most once per window of data.
+            if self.ecn_negotiated && repr.ece && control != TcpControl::Syn {
+                if self.ecn_recover.map(|recover| ack_number > recover).unwrap_or(true) {
+                    let flight_size = self.remote_last_seq - self.local_seq_no;
+                    let window = cmp::max(flight_size / 2, 2 * self.remote_mss);
+                    net_debug!("{}:{}:{}: received ECE, reducing congestion window to {}",
+                               self.meta.handle, self.local_endpoint, self.remote_endpoint,
+                               window);
+                    self.congestion_window = Some(window);
+                    self.ecn_recover = Some(self.remote_last_seq);
+                    self.ecn_cwr = true;
+                }
+            } else if let Some(window) = self.congestion_window {
+                // Grow a reduced congestion window by about a segment per window of data
+                // acknowledged, as in the congestion avoidance phase of RFC 5681.
+                if ack_len > 0 {
+                    let increment = cmp::max(self.remote_mss * self.remote_mss / window, 1);
+                    self.congestion_window = Some(window + increment);
+                }
+            }
+
             // We've processed everything in the incoming segment, so advance the local
             // sequence number past it.
             self.local_seq_no = ack_number;
@@ -1360,13 +1472,22 @@ impl<'a> TcpSocket<'a> {
             _ => control = TcpControl::None
         }
 
-        if self.remote_win_len > 0 {
+        if self.remote_win_len > 0 && self.congestion_window_available() > 0 {
             self.remote_last_seq < self.local_seq_no + self.tx_buffer.len() + control.len()
         } else {
             false
         }
     }
 
+    fn congestion_window_available(&self) -> usize {
+        match self.congestion_window {
+            Some(window) =>
+                window.saturating_sub(self.remote_last_seq - self.local_seq_no),
+            None =>
+                usize::max_value()
+        }
+    }
+
     fn ack_to_transmit(&self) -> bool {
         if let Some(remote_last_ack) = self.remote_last_ack {
             remote_last_ack < self.remote_seq_no + self.rx_buffer.len()
@@ -1450,11 +1571,12 @@ impl<'a> TcpSocket<'a> {
         // Construct the lowered IP representation.
         // We might need this to calculate the MSS, so do it early.
         let mut ip_repr = IpRepr::Unspecified {
-            src_addr:     self.local_endpoint.addr,
-            dst_addr:     self.remote_endpoint.addr,
-            protocol:     IpProtocol::Tcp,
-            hop_limit:    self.hop_limit.unwrap_or(64),
-            payload_len:  0
+            src_addr:      self.local_endpoint.addr,
+            dst_addr:      self.remote_endpoint.addr,
+            protocol:      IpProtocol::Tcp,
+            hop_limit:     self.hop_limit.unwrap_or(64),
+            traffic_class: 0,
+            payload_len:   0
         }.lower(&[])?;
 
         // Construct the basic TCP representation, an empty ACK packet.
@@ -1470,6 +1592,8 @@ impl<'a> TcpSocket<'a> {
             max_seg_size: None,
             sack_permitted: false,
             sack_ranges:  [None, None, None],
+            ece:          self.ecn_echo,
+            cwr:          false,
             payload:      &[]
         };
 
@@ -1491,10 +1615,13 @@ impl<'a> TcpSocket<'a> {
                     repr.ack_number = None;
                     repr.window_scale = Some(self.remote_win_shift);
                     repr.sack_permitted = true;
+                    repr.ece = self.ecn_enabled;
+                    repr.cwr = self.ecn_enabled;
                 } else {
                     repr.sack_permitted = self.remote_has_sack;
                     repr.window_scale = self.remote_win_scale.map(
                         |_| self.remote_win_shift);
+                    repr.ece = self.ecn_negotiated;
                 }
             }
 
@@ -1505,7 +1632,9 @@ impl<'a> TcpSocket<'a> {
                 // Extract as much data as the remote side can receive in this packet
                 // from the transmit buffer.
                 let offset = self.remote_last_seq - self.local_seq_no;
-                let size = cmp::min(self.remote_win_len, self.remote_mss);
+                let max_send_len = self.max_send_len(&ip_repr, repr.header_len(), caps);
+                let size = cmp::min(cmp::min(self.remote_win_len, max_send_len),
+                                    self.congestion_window_available());
                 repr.payload = self.tx_buffer.get_allocated(offset, size);
                 // If we've sent everything we had in the buffer, follow it with the PSH or FIN
                 // flags, depending on whether the transmit half of the connection is open.
@@ -1541,6 +1670,16 @@ impl<'a> TcpSocket<'a> {
             is_keep_alive = false;
         }
 
+        // RFC 3168: only segments carrying new data are sent as ECN-capable, and the first
+        // of them after a reduction of the congestion window carries the CWR flag.
+        let is_new_data = self.ecn_negotiated && !is_keep_alive &&
+                          repr.payload.len() > 0 && repr.seq_number >= self.remote_max_seq;
+        if is_new_data {
+            let traffic_class = ip_repr.traffic_class();
+            ip_repr.set_traffic_class(IpEcn::Ect0.with_traffic_class(traffic_class));
+            repr.cwr = self.ecn_cwr;
+        }
+
         // Trace a summary of what will be sent.
         if is_keep_alive {
             net_trace!("{}:{}:{}: sending a keep-alive",
@@ -1596,6 +1735,12 @@ impl<'a> TcpSocket<'a> {
         self.remote_last_seq = repr.seq_number + repr.segment_len();
         self.remote_last_ack = repr.ack_number;
         self.remote_last_win = repr.window_len;
+        if self.remote_max_seq < self.remote_last_seq {
+            self.remote_max_seq = self.remote_last_seq;
+        }
+        if is_new_data {
+            self.ecn_cwr = false;
+        }
 
         if !self.seq_to_transmit() && repr.segment_len() > 0 {
             // If we've transmitted all data we could (and there was something at all,
@@ -1612,6 +1757,30 @@ impl<'a> TcpSocket<'a> {
         Ok(())
     }
 
+    /// Return the largest payload to send in a single segment, which exceeds the MSS
+    /// if the device can split such a segment into several ones itself.
+    fn max_send_len(&self, ip_repr: &IpRepr, tcp_header_len: usize,
+                    caps: &DeviceCapabilities) -> usize {
+        let header_len = ip_repr.buffer_len() + tcp_header_len;
+        // An IP packet is never longer than 65535 octets, whatever the device can segment.
+        let max_segmentation_size = match caps.max_segmentation_size {
+            Some(size) if size > caps.max_transmission_unit => cmp::min(size, 65535),
+            _ => return self.remote_mss
+        };
+        // The device cuts the segment at the MTU, and the pieces must not be larger
+        // than what the remote end accepts.
+        let local_mss = caps.max_transmission_unit.saturating_sub(header_len);
+        let mss = cmp::min(self.remote_mss, local_mss);
+        // The remote end may advertise an MSS of zero.
+        if mss == 0 { return self.remote_mss }
+        max_segmentation_size.saturating_sub(header_len) / mss * mss
+    }
+
+    /// Return the maximum segment size the remote end accepts.
+    pub(crate) fn remote_mss(&self) -> usize {
+        self.remote_mss
+    }
+
     pub(crate) fn poll_at(&self) -> PollAt {
         // The logic here mirrors the beginning of dispatch() closely.
         if !self.remote_endpoint.is_specified() {
@@ -1683,7 +1852,8 @@ mod test {
     const SEND_IP_TEMPL: IpRepr = IpRepr::Unspecified {
         src_addr: MOCK_IP_ADDR_1, dst_addr: MOCK_IP_ADDR_2,
         protocol: IpProtocol::Tcp, payload_len: 20,
-        hop_limit: 64
+        hop_limit: 64,
+        traffic_class: 0
     };
     const SEND_TEMPL: TcpRepr<'static> = TcpRepr {
         src_port: REMOTE_PORT, dst_port: LOCAL_PORT,
@@ -1693,12 +1863,14 @@ mod test {
         max_seg_size: None,
         sack_permitted: false,
         sack_ranges: [None, None, None],
+        ece: false, cwr: false,
         payload: &[]
     };
     const _RECV_IP_TEMPL: IpRepr = IpRepr::Unspecified {
         src_addr: MOCK_IP_ADDR_1, dst_addr: MOCK_IP_ADDR_2,
         protocol: IpProtocol::Tcp, payload_len: 20,
-        hop_limit: 64
+        hop_limit: 64,
+        traffic_class: 0
     };
     const RECV_TEMPL:  TcpRepr<'static> = TcpRepr {
         src_port: LOCAL_PORT, dst_port: REMOTE_PORT,
@@ -1708,6 +1880,7 @@ mod test {
         max_seg_size: None,
         sack_permitted: false,
         sack_ranges: [None, None, None],
+        ece: false, cwr: false,
         payload: &[]
     };
 
@@ -1722,12 +1895,18 @@ mod test {
 
     fn send(socket: &mut TcpSocket, timestamp: Instant, repr: &TcpRepr) ->
            Result<Option<TcpRepr<'static>>> {
+        send_with_ecn(socket, timestamp, IpEcn::NotEct, repr)
+    }
+
+    fn send_with_ecn(socket: &mut TcpSocket, timestamp: Instant, ecn: IpEcn, repr: &TcpRepr) ->
+           Result<Option<TcpRepr<'static>>> {
         let ip_repr = IpRepr::Unspecified {
-            src_addr:    MOCK_IP_ADDR_2,
-            dst_addr:    MOCK_IP_ADDR_1,
-            protocol:    IpProtocol::Tcp,
-            payload_len: repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      MOCK_IP_ADDR_2,
+            dst_addr:      MOCK_IP_ADDR_1,
+            protocol:      IpProtocol::Tcp,
+            payload_len:   repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: ecn.with_traffic_class(0)
         };
         net_trace!("send: {}", repr);
 
@@ -4404,6 +4583,310 @@ mod test {
         s.set_hop_limit(Some(0));
     }
 
+    // =========================================================================================//
+    // Tests for segmentation offload.
+    // =========================================================================================//
+
+    fn recv_segment_lens(socket: &mut TcpSocket, caps: &DeviceCapabilities) -> Vec<usize> {
+        let mut lens = Vec::new();
+        while socket.dispatch(Instant::from_millis(0), caps, |(_, repr)| {
+            lens.push(repr.payload.len());
+            Ok(())
+        }).is_ok() {}
+        lens
+    }
+
+    #[test]
+    fn test_segmentation_offload() {
+        let mut s = socket_established_with_buffer_sizes(8000, 64);
+        s.remote_mss     = BASE_MSS as usize;
+        s.remote_win_len = 8000;
+        s.send_slice(&[0xaa; 8000][..]).unwrap();
+
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = 1520;
+        caps.max_segmentation_size = Some(6000);
+        // 4 full-sized segments at once, which the device splits itself.
+        let mss = BASE_MSS as usize;
+        assert_eq!(recv_segment_lens(&mut s, &caps), vec![4 * mss, 8000 - 4 * mss]);
+    }
+
+    #[test]
+    fn test_segmentation_offload_small_remote_mss() {
+        let mut s = socket_established_with_buffer_sizes(3000, 64);
+        s.remote_mss     = 1000;
+        s.remote_win_len = 3000;
+        s.send_slice(&[0xaa; 3000][..]).unwrap();
+
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = 1520;
+        caps.max_segmentation_size = Some(6000);
+        // The device splits the segment at the remote MSS rather than at the MTU.
+        assert_eq!(recv_segment_lens(&mut s, &caps), vec![3000]);
+    }
+
+    #[test]
+    fn test_segmentation_offload_zero_remote_mss() {
+        let mut s = socket_established_with_buffer_sizes(3000, 64);
+        s.remote_mss     = 0;
+        s.remote_win_len = 3000;
+        s.send_slice(&[0xaa; 3000][..]).unwrap();
+
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = 1520;
+        caps.max_segmentation_size = Some(6000);
+        // Nothing can be sent, but that does not bring the socket down.
+        assert_eq!(s.dispatch(Instant::from_millis(0), &caps, |(_, repr)| {
+            assert_eq!(repr.payload.len(), 0);
+            Ok(())
+        }), Ok(()));
+    }
+
+    // =========================================================================================//
+    // Tests for Explicit Congestion Notification.
+    // =========================================================================================//
+
+    fn socket_established_ecn() -> TcpSocket<'static> {
+        let mut s = socket_established_with_buffer_sizes(2000, 64);
+        s.ecn_enabled     = true;
+        s.ecn_negotiated  = true;
+        s.remote_max_seq  = LOCAL_SEQ + 1;
+        s.remote_mss      = 100;
+        s.remote_win_len  = 2000;
+        s
+    }
+
+    fn recv_ecn(socket: &mut TcpSocket, timestamp: Instant) ->
+               Vec<(IpEcn, TcpSeqNumber, usize, bool)> {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = 1520;
+        let mut segments = Vec::new();
+        while socket.dispatch(timestamp, &caps, |(ip_repr, repr)| {
+            segments.push((ip_repr.ecn(), repr.seq_number, repr.payload.len(), repr.cwr));
+            Ok(())
+        }).is_ok() {}
+        segments
+    }
+
+    #[test]
+    fn test_ecn_syn_sent_negotiate() {
+        let mut s = socket_syn_sent();
+        s.set_ecn_enabled(true);
+        recv!(s, [TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: LOCAL_SEQ,
+            ack_number: None,
+            max_seg_size: Some(BASE_MSS),
+            window_scale: Some(0),
+            sack_permitted: true,
+            ece:        true,
+            cwr:        true,
+            ..RECV_TEMPL
+        }]);
+        send!(s, TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: REMOTE_SEQ,
+            ack_number: Some(LOCAL_SEQ + 1),
+            max_seg_size: Some(BASE_MSS - 80),
+            ece:        true,
+            ..SEND_TEMPL
+        });
+        assert_eq!(s.state, State::Established);
+        assert!(s.ecn_negotiated());
+    }
+
+    #[test]
+    fn test_ecn_syn_sent_not_negotiated() {
+        let mut s = socket_syn_sent();
+        s.set_ecn_enabled(true);
+        send!(s, TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: REMOTE_SEQ,
+            ack_number: Some(LOCAL_SEQ + 1),
+            ..SEND_TEMPL
+        });
+        assert_eq!(s.state, State::Established);
+        assert!(!s.ecn_negotiated());
+    }
+
+    #[test]
+    fn test_ecn_listen_negotiate() {
+        let mut s = socket_listen();
+        s.set_ecn_enabled(true);
+        send!(s, TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: REMOTE_SEQ,
+            ack_number: None,
+            ece:        true,
+            cwr:        true,
+            ..SEND_TEMPL
+        });
+        assert!(s.ecn_negotiated());
+        recv!(s, [TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: LOCAL_SEQ,
+            ack_number: Some(REMOTE_SEQ + 1),
+            max_seg_size: Some(BASE_MSS),
+            ece:        true,
+            ..RECV_TEMPL
+        }]);
+    }
+
+    #[test]
+    fn test_ecn_listen_disabled() {
+        let mut s = socket_listen();
+        send!(s, TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: REMOTE_SEQ,
+            ack_number: None,
+            ece:        true,
+            cwr:        true,
+            ..SEND_TEMPL
+        });
+        assert!(!s.ecn_negotiated());
+        recv!(s, [TcpRepr {
+            control:    TcpControl::Syn,
+            seq_number: LOCAL_SEQ,
+            ack_number: Some(REMOTE_SEQ + 1),
+            max_seg_size: Some(BASE_MSS),
+            ..RECV_TEMPL
+        }]);
+    }
+
+    #[test]
+    fn test_ecn_mark_new_data() {
+        let mut s = socket_established_ecn();
+        s.send_slice(&[0; 150][..]).unwrap();
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(0)), vec![
+            (IpEcn::Ect0, LOCAL_SEQ + 1,   100, false),
+            (IpEcn::Ect0, LOCAL_SEQ + 101, 50,  false),
+        ]);
+        // Retransmitted data is not ECN-capable.
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(1000)), vec![
+            (IpEcn::NotEct, LOCAL_SEQ + 1,   100, false),
+            (IpEcn::NotEct, LOCAL_SEQ + 101, 50,  false),
+        ]);
+        // Neither are pure acknowledgements.
+        send!(s, TcpRepr {
+            seq_number: REMOTE_SEQ + 1,
+            ack_number: Some(LOCAL_SEQ + 151),
+            payload:    &b"abc"[..],
+            ..SEND_TEMPL
+        });
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(1000)), vec![
+            (IpEcn::NotEct, LOCAL_SEQ + 151, 0, false),
+        ]);
+    }
+
+    #[test]
+    fn test_ecn_echo_congestion_experienced() {
+        let mut s = socket_established_ecn();
+        send_with_ecn(&mut s, Instant::from_millis(0), IpEcn::Ce, &TcpRepr {
+            seq_number: REMOTE_SEQ + 1,
+            ack_number: Some(LOCAL_SEQ + 1),
+            payload:    &b"abc"[..],
+            ..SEND_TEMPL
+        }).unwrap();
+        recv!(s, [TcpRepr {
+            seq_number: LOCAL_SEQ + 1,
+            ack_number: Some(REMOTE_SEQ + 1 + 3),
+            window_len: 61,
+            ece:        true,
+            ..RECV_TEMPL
+        }]);
+        // The ECE flag is repeated until the remote sends CWR.
+        send!(s, TcpRepr {
+            seq_number: REMOTE_SEQ + 1 + 3,
+            ack_number: Some(LOCAL_SEQ + 1),
+            payload:    &b"def"[..],
+            ..SEND_TEMPL
+        });
+        recv!(s, [TcpRepr {
+            seq_number: LOCAL_SEQ + 1,
+            ack_number: Some(REMOTE_SEQ + 1 + 6),
+            window_len: 58,
+            ece:        true,
+            ..RECV_TEMPL
+        }]);
+        send!(s, TcpRepr {
+            seq_number: REMOTE_SEQ + 1 + 6,
+            ack_number: Some(LOCAL_SEQ + 1),
+            payload:    &b"ghi"[..],
+            cwr:        true,
+            ..SEND_TEMPL
+        });
+        recv!(s, [TcpRepr {
+            seq_number: LOCAL_SEQ + 1,
+            ack_number: Some(REMOTE_SEQ + 1 + 9),
+            window_len: 55,
+            ..RECV_TEMPL
+        }]);
+    }
+
+    #[test]
+    fn test_ecn_echo_not_negotiated() {
+        let mut s = socket_established();
+        send_with_ecn(&mut s, Instant::from_millis(0), IpEcn::Ce, &TcpRepr {
+            seq_number: REMOTE_SEQ + 1,
+            ack_number: Some(LOCAL_SEQ + 1),
+            payload:    &b"abc"[..],
+            ..SEND_TEMPL
+        }).unwrap();
+        recv!(s, [TcpRepr {
+            seq_number: LOCAL_SEQ + 1,
+            ack_number: Some(REMOTE_SEQ + 1 + 3),
+            window_len: 61,
+            ..RECV_TEMPL
+        }]);
+    }
+
+    #[test]
+    fn test_ecn_reduce_window() {
+        let mut s = socket_established_ecn();
+        s.send_slice(&[0; 1000][..]).unwrap();
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(0)).len(), 10);
+
+        // The first ECE halves the congestion window...
+        send!(s, TcpRepr {
+            seq_number: REMOTE_SEQ + 1,
+            ack_number: Some(LOCAL_SEQ + 1 + 100),
+            window_len: 2000,
+            ece:        true,
+            ..SEND_TEMPL
+        });
+        assert_eq!(s.congestion_window, Some(500));
+        s.send_slice(&[0; 500][..]).unwrap();
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(0)), vec![]);
+
+        // ... but further ECEs in the same window do not reduce it further.
+        send!(s, TcpRepr {
+            seq_number: REMOTE_SEQ + 1,
+            ack_number: Some(LOCAL_SEQ + 1 + 600),
+            window_len: 2000,
+            ece:        true,
+            ..SEND_TEMPL
+        });
+        assert_eq!(s.congestion_window, Some(500));
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(0)), vec![
+            (IpEcn::Ect0, LOCAL_SEQ + 1 + 1000, 100, true),
+        ]);
+
+        // Once the reduction is acknowledged, the window grows again.
+        send!(s, TcpRepr {
+            seq_number: REMOTE_SEQ + 1,
+            ack_number: Some(LOCAL_SEQ + 1 + 1100),
+            window_len: 2000,
+            ..SEND_TEMPL
+        });
+        assert_eq!(s.congestion_window, Some(520));
+        assert_eq!(recv_ecn(&mut s, Instant::from_millis(0)), vec![
+            (IpEcn::Ect0, LOCAL_SEQ + 1 + 1100, 100, false),
+            (IpEcn::Ect0, LOCAL_SEQ + 1 + 1200, 100, false),
+            (IpEcn::Ect0, LOCAL_SEQ + 1 + 1300, 100, false),
+            (IpEcn::Ect0, LOCAL_SEQ + 1 + 1400, 100, false),
+        ]);
+    }
+
     // =========================================================================================//
     // Tests for reassembly.
     // =========================================================================================//
@@ -4531,29 +5014,32 @@ mod test {
         };
 
         let ip_repr = IpRepr::Unspecified {
-            src_addr:    MOCK_IP_ADDR_2,
-            dst_addr:    MOCK_IP_ADDR_1,
-            protocol:    IpProtocol::Tcp,
-            payload_len: tcp_repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      MOCK_IP_ADDR_2,
+            dst_addr:      MOCK_IP_ADDR_1,
+            protocol:      IpProtocol::Tcp,
+            payload_len:   tcp_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0
         };
         assert!(s.accepts(&ip_repr, &tcp_repr));
 
         let ip_repr_wrong_src = IpRepr::Unspecified {
-            src_addr:    MOCK_IP_ADDR_3,
-            dst_addr:    MOCK_IP_ADDR_1,
-            protocol:    IpProtocol::Tcp,
-            payload_len: tcp_repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      MOCK_IP_ADDR_3,
+            dst_addr:      MOCK_IP_ADDR_1,
+            protocol:      IpProtocol::Tcp,
+            payload_len:   tcp_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0
         };
         assert!(!s.accepts(&ip_repr_wrong_src, &tcp_repr));
 
         let ip_repr_wrong_dst = IpRepr::Unspecified {
-            src_addr:    MOCK_IP_ADDR_2,
-            dst_addr:    MOCK_IP_ADDR_3,
-            protocol:    IpProtocol::Tcp,
-            payload_len: tcp_repr.buffer_len(),
-            hop_limit:   64
+            src_addr:      MOCK_IP_ADDR_2,
+            dst_addr:      MOCK_IP_ADDR_3,
+            protocol:      IpProtocol::Tcp,
+            payload_len:   tcp_repr.buffer_len(),
+            hop_limit:     64,
+            traffic_class: 0
         };
         assert!(!s.accepts(&ip_repr_wrong_dst, &tcp_repr));
     }
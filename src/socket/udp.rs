@@ -1,28 +1,62 @@
 use core::cmp::min;
 
 use {Error, Result};
-use socket::{Socket, SocketMeta, SocketHandle, PollAt};
+use socket::{Socket, SocketMeta, SocketHandle, PollAt, MulticastOptions};
 use storage::{PacketBuffer, PacketMetadata};
-use wire::{IpProtocol, IpRepr, IpEndpoint, UdpRepr};
+use time::Instant;
+use wire::{IpProtocol, IpRepr, IpAddress, IpEndpoint, UdpRepr};
+
+/// Metadata of a UDP datagram.
+///
+/// The metadata of a received datagram describes it completely. In the metadata of
+/// a datagram to send, every field other than the remote endpoint is an optional override
+/// of what the socket would use otherwise.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UdpMetadata {
+    /// The remote endpoint: the source of a received datagram, or the destination
+    /// of a datagram to send.
+    pub endpoint:      IpEndpoint,
+    /// The local address: the destination of a received datagram, which may be
+    /// a broadcast or multicast address, or the source of a datagram to send,
+    /// if it is specified.
+    pub local_addr:    IpAddress,
+    /// The time-to-live (IPv4) or hop limit (IPv6) value.
+    pub hop_limit:     Option<u8>,
+    /// The traffic class: the DSCP in the upper six bits, and the ECN codepoint
+    /// in the lower two bits.
+    pub traffic_class: Option<u8>,
+    /// The time a received datagram was processed at; ignored when sending.
+    pub timestamp:     Option<Instant>,
+}
+
+impl From<IpEndpoint> for UdpMetadata {
+    fn from(endpoint: IpEndpoint) -> UdpMetadata {
+        UdpMetadata { endpoint, ..UdpMetadata::default() }
+    }
+}
 
 /// A UDP packet metadata.
-pub type UdpPacketMetadata = PacketMetadata<IpEndpoint>;
+pub type UdpPacketMetadata = PacketMetadata<UdpMetadata>;
 
 /// A UDP packet ring buffer.
-pub type UdpSocketBuffer<'a, 'b> = PacketBuffer<'a, 'b, IpEndpoint>;
+pub type UdpSocketBuffer<'a, 'b> = PacketBuffer<'a, 'b, UdpMetadata>;
 
 /// A User Datagram Protocol socket.
 ///
 /// A UDP socket is bound to a specific endpoint, and owns transmit and receive
-/// packet buffers.
+/// packet buffers. It can also be connected to a remote endpoint.
 #[derive(Debug)]
 pub struct UdpSocket<'a, 'b: 'a> {
     pub(crate) meta: SocketMeta,
     endpoint:  IpEndpoint,
+    remote_endpoint: IpEndpoint,
+    /// An error reported by the remote endpoint, and not yet returned to the user.
+    error:     Option<Error>,
     rx_buffer: UdpSocketBuffer<'a, 'b>,
     tx_buffer: UdpSocketBuffer<'a, 'b>,
     /// The time-to-live (IPv4) or hop limit (IPv6) value used in outgoing packets.
-    hop_limit: Option<u8>
+    hop_limit: Option<u8>,
+    pub(crate) multicast: MulticastOptions,
 }
 
 impl<'a, 'b> UdpSocket<'a, 'b> {
@@ -32,9 +66,12 @@ impl<'a, 'b> UdpSocket<'a, 'b> {
         UdpSocket {
             meta:      SocketMeta::default(),
             endpoint:  IpEndpoint::default(),
+            remote_endpoint: IpEndpoint::default(),
+            error:     None,
             rx_buffer: rx_buffer,
             tx_buffer: tx_buffer,
-            hop_limit: None
+            hop_limit: None,
+            multicast: MulticastOptions::default(),
         }
     }
 
@@ -50,6 +87,13 @@ impl<'a, 'b> UdpSocket<'a, 'b> {
         self.endpoint
     }
 
+    /// Return the connected remote endpoint, or an unspecified endpoint if the socket
+    /// is not connected.
+    #[inline]
+    pub fn remote_endpoint(&self) -> IpEndpoint {
+        self.remote_endpoint
+    }
+
     /// Return the time-to-live (IPv4) or hop limit (IPv6) value used in outgoing packets.
     ///
     /// See also the [set_hop_limit](#method.set_hop_limit) method
@@ -77,6 +121,88 @@ impl<'a, 'b> UdpSocket<'a, 'b> {
         self.hop_limit = hop_limit
     }
 
+    /// Return the time-to-live (IPv4) or hop limit (IPv6) value used in outgoing
+    /// multicast packets.
+    ///
+    /// See also the [set_multicast_hop_limit](#method.set_multicast_hop_limit) method
+    pub fn multicast_hop_limit(&self) -> Option<u8> {
+        self.multicast.hop_limit()
+    }
+
+    /// Set the time-to-live (IPv4) or hop limit (IPv6) value used in outgoing
+    /// multicast packets, instead of the [hop limit](#method.set_hop_limit).
+    ///
+    /// A socket without an explicitly set multicast hop limit value uses its
+    /// [hop limit](#method.hop_limit) for multicast packets as well.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if a hop limit value of 0 is given. See [RFC 1122 § 3.2.1.7].
+    ///
+    /// [RFC 1122 § 3.2.1.7]: https://tools.ietf.org/html/rfc1122#section-3.2.1.7
+    pub fn set_multicast_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.multicast.set_hop_limit(hop_limit)
+    }
+
+    /// Return whether outgoing multicast packets are looped back to the other sockets.
+    ///
+    /// See also the [set_multicast_loopback](#method.set_multicast_loopback) method
+    pub fn multicast_loopback(&self) -> bool {
+        self.multicast.loopback()
+    }
+
+    /// Enable or disable looping back outgoing multicast packets to the other UDP
+    /// sockets in the socket set, if the interface is a member of the group.
+    /// Unlike with BSD sockets, this is disabled by default.
+    pub fn set_multicast_loopback(&mut self, enabled: bool) {
+        self.multicast.set_loopback(enabled)
+    }
+
+    /// Return the source address of outgoing multicast packets.
+    ///
+    /// See also the [set_multicast_src_addr](#method.set_multicast_src_addr) method
+    pub fn multicast_src_addr(&self) -> IpAddress {
+        self.multicast.src_addr()
+    }
+
+    /// Set the source address of outgoing multicast packets, if the socket is
+    /// not bound to an address. With the unspecified address (the default), the
+    /// interface selects the source address.
+    ///
+    /// This function returns `Err(Error::Unaddressable)` if the address is not unicast.
+    pub fn set_multicast_src_addr<T: Into<IpAddress>>(&mut self, addr: T) -> Result<()> {
+        self.multicast.set_src_addr(addr.into())
+    }
+
+    /// Join a multicast group on the interface the socket is used with.
+    ///
+    /// The interface joins the group the next time it is polled, unless another
+    /// socket has joined it already, and leaves it when the last socket leaves it.
+    /// A socket can join up to four groups.
+    ///
+    /// Returns `Ok(false)` if the socket has already joined the group. This function
+    /// returns `Err(Error::Unaddressable)` if the address is not multicast, and
+    /// `Err(Error::Exhausted)` if the socket has joined too many groups.
+    ///
+    /// **Note:** Leave the groups before removing the socket from the socket set.
+    pub fn join_multicast_group<T: Into<IpAddress>>(&mut self, addr: T) -> Result<bool> {
+        self.multicast.join(addr.into())
+    }
+
+    /// Leave a multicast group joined with [join_multicast_group].
+    ///
+    /// Returns `false` if the socket has not joined the group.
+    ///
+    /// [join_multicast_group]: #method.join_multicast_group
+    pub fn leave_multicast_group<T: Into<IpAddress>>(&mut self, addr: T) -> bool {
+        self.multicast.leave(addr.into())
+    }
+
+    /// Check whether the socket has joined the given multicast group.
+    pub fn has_multicast_group<T: Into<IpAddress>>(&self, addr: T) -> bool {
+        self.multicast.has_group(addr.into())
+    }
+
     /// Bind the socket to the given endpoint.
     ///
     /// This function returns `Err(Error::Illegal)` if the socket was open
@@ -92,61 +218,132 @@ impl<'a, 'b> UdpSocket<'a, 'b> {
         Ok(())
     }
 
+    /// Connect the bound socket to the given remote endpoint.
+    ///
+    /// A connected socket only receives datagrams sent from the remote endpoint,
+    /// sends datagrams to it unless another endpoint is given, and reports an ICMP
+    /// port unreachable message from it as an `Err(Error::Unreachable)` returned from
+    /// [recv](#method.recv). The socket can be connected again to another endpoint.
+    ///
+    /// This function returns `Err(Error::Illegal)` if the socket is not open,
+    /// and `Err(Error::Unaddressable)` if the remote endpoint is unspecified.
+    pub fn connect<T: Into<IpEndpoint>>(&mut self, remote_endpoint: T) -> Result<()> {
+        let remote_endpoint = remote_endpoint.into();
+        if !self.is_open() { return Err(Error::Illegal) }
+        if !remote_endpoint.is_specified() { return Err(Error::Unaddressable) }
+
+        self.remote_endpoint = remote_endpoint;
+        self.error = None;
+        Ok(())
+    }
+
+    /// Disconnect the socket from the remote endpoint, if it is connected.
+    pub fn disconnect(&mut self) {
+        self.remote_endpoint = IpEndpoint::default();
+        self.error = None;
+    }
+
     /// Check whether the socket is open.
     #[inline]
     pub fn is_open(&self) -> bool {
         self.endpoint.port != 0
     }
 
+    /// Check whether the socket is connected to a remote endpoint.
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.remote_endpoint.is_specified()
+    }
+
     /// Check whether the transmit buffer is full.
     #[inline]
     pub fn can_send(&self) -> bool {
         !self.tx_buffer.is_full()
     }
 
-    /// Check whether the receive buffer is not empty.
+    /// Check whether the receive buffer is not empty, or an error is to be returned
+    /// from [recv](#method.recv).
     #[inline]
     pub fn can_recv(&self) -> bool {
-        !self.rx_buffer.is_empty()
+        !self.rx_buffer.is_empty() || self.error.is_some()
     }
 
     /// Enqueue a packet to be sent to a given remote endpoint, and return a pointer
-    /// to its payload.
+    /// to its payload. If the socket is connected, an unspecified endpoint
+    /// (`IpEndpoint::default()`) stands for the connected remote endpoint.
     ///
     /// This function returns `Err(Error::Exhausted)` if the transmit buffer is full,
     /// `Err(Error::Unaddressable)` if local or remote port, or remote address are unspecified,
     /// and `Err(Error::Truncated)` if there is not enough transmit buffer capacity
     /// to ever send this packet.
     pub fn send(&mut self, size: usize, endpoint: IpEndpoint) -> Result<&mut [u8]> {
+        self.send_with_meta(size, endpoint.into())
+    }
+
+    /// Enqueue a packet to be sent to a given remote endpoint, and fill it from a slice.
+    ///
+    /// See also [send](#method.send).
+    pub fn send_slice(&mut self, data: &[u8], endpoint: IpEndpoint) -> Result<()> {
+        self.send(data.len(), endpoint)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Enqueue a packet to be sent with the given metadata, and return a pointer
+    /// to its payload. The metadata can override the source address, the hop limit,
+    /// and the traffic class of the packet.
+    ///
+    /// This function returns `Err(Error::Unaddressable)` if the source address
+    /// is not unicast, `Err(Error::Illegal)` if the hop limit is zero, and otherwise
+    /// behaves identically to [send](#method.send).
+    pub fn send_with_meta(&mut self, size: usize, meta: UdpMetadata) -> Result<&mut [u8]> {
+        let mut meta = meta;
+        if meta.endpoint == IpEndpoint::default() {
+            meta.endpoint = self.remote_endpoint
+        }
         if self.endpoint.port == 0 { return Err(Error::Unaddressable) }
-        if !endpoint.is_specified() { return Err(Error::Unaddressable) }
+        if !meta.endpoint.is_specified() { return Err(Error::Unaddressable) }
+        if !meta.local_addr.is_unspecified() &&
+            !meta.local_addr.is_unicast() { return Err(Error::Unaddressable) }
+        if let Some(0) = meta.hop_limit { return Err(Error::Illegal) }
 
-        let payload_buf = self.tx_buffer.enqueue(size, endpoint)?;
+        let payload_buf = self.tx_buffer.enqueue(size, meta)?;
 
         net_trace!("{}:{}:{}: buffer to send {} octets",
-                   self.meta.handle, self.endpoint, endpoint, size);
+                   self.meta.handle, self.endpoint, meta.endpoint, size);
         Ok(payload_buf)
     }
 
-    /// Enqueue a packet to be sent to a given remote endpoint, and fill it from a slice.
+    /// Enqueue a packet to be sent with the given metadata, and fill it from a slice.
     ///
-    /// See also [send](#method.send).
-    pub fn send_slice(&mut self, data: &[u8], endpoint: IpEndpoint) -> Result<()> {
-        self.send(data.len(), endpoint)?.copy_from_slice(data);
+    /// See also [send_with_meta](#method.send_with_meta).
+    pub fn send_slice_with_meta(&mut self, data: &[u8], meta: UdpMetadata) -> Result<()> {
+        self.send_with_meta(data.len(), meta)?.copy_from_slice(data);
         Ok(())
     }
 
     /// Dequeue a packet received from a remote endpoint, and return the endpoint as well
     /// as a pointer to the payload.
     ///
-    /// This function returns `Err(Error::Exhausted)` if the receive buffer is empty.
+    /// This function returns `Err(Error::Exhausted)` if the receive buffer is empty, and
+    /// `Err(Error::Unreachable)`, once, if the connected remote endpoint has reported
+    /// its port unreachable since the last call.
     pub fn recv(&mut self) -> Result<(&[u8], IpEndpoint)> {
-        let (endpoint, payload_buf) = self.rx_buffer.dequeue()?;
+        self.recv_with_meta().map(|(payload_buf, meta)| (payload_buf, meta.endpoint))
+    }
+
+    /// Dequeue a packet received from a remote endpoint, and return its metadata
+    /// as well as a pointer to the payload.
+    ///
+    /// See also [recv](#method.recv).
+    pub fn recv_with_meta(&mut self) -> Result<(&[u8], UdpMetadata)> {
+        if let Some(error) = self.error.take() { return Err(error) }
+
+        let (meta, payload_buf) = self.rx_buffer.dequeue()?;
 
         net_trace!("{}:{}:{}: receive {} buffered octets",
                    self.meta.handle, self.endpoint,
-                   endpoint, payload_buf.len());
-        Ok((payload_buf, endpoint))
+                   meta.endpoint, payload_buf.len());
+        Ok((payload_buf, meta))
     }
 
     /// Dequeue a packet received from a remote endpoint, copy the payload into the given slice,
@@ -164,15 +361,18 @@ impl<'a, 'b> UdpSocket<'a, 'b> {
     /// as a pointer to the payload without removing the packet from the receive buffer.
     /// This function otherwise behaves identically to [recv](#method.recv).
     ///
-    /// It returns `Err(Error::Exhausted)` if the receive buffer is empty.
+    /// It returns `Err(Error::Exhausted)` if the receive buffer is empty, and the error
+    /// that [recv](#method.recv) would return, without clearing it, if there is one.
     pub fn peek(&mut self) -> Result<(&[u8], &IpEndpoint)> {
+        if let Some(error) = self.error { return Err(error) }
+
         let handle = self.meta.handle;
         let endpoint = self.endpoint;
-        self.rx_buffer.peek().map(|(remote_endpoint, payload_buf)| {
+        self.rx_buffer.peek().map(|(meta, payload_buf)| {
             net_trace!("{}:{}:{}: peek {} buffered octets",
                        handle, endpoint,
-                       remote_endpoint, payload_buf.len());
-           (payload_buf, remote_endpoint)
+                       meta.endpoint, payload_buf.len());
+           (payload_buf, &meta.endpoint)
         })
     }
 
@@ -195,49 +395,115 @@ impl<'a, 'b> UdpSocket<'a, 'b> {
             self.endpoint.addr != ip_repr.dst_addr() &&
             !ip_repr.dst_addr().is_broadcast() &&
             !ip_repr.dst_addr().is_multicast() { return false }
+        if self.is_connected() &&
+            (self.remote_endpoint.addr != ip_repr.src_addr() ||
+             self.remote_endpoint.port != repr.src_port) { return false }
+
+        true
+    }
+
+    /// Report that the port of a remote endpoint is unreachable, as learned from
+    /// an ICMP message about a datagram sent from the local endpoint.
+    ///
+    /// Returns whether the socket is connected to the remote endpoint, and records
+    /// the error if it is.
+    pub(crate) fn process_unreachable(&mut self, local_endpoint: IpEndpoint,
+                                      remote_endpoint: IpEndpoint) -> bool {
+        if !self.is_connected() || self.remote_endpoint != remote_endpoint { return false }
+        if self.endpoint.port != local_endpoint.port { return false }
+        if !self.endpoint.addr.is_unspecified() &&
+            self.endpoint.addr != local_endpoint.addr { return false }
 
+        net_debug!("{}:{}:{}: port unreachable",
+                   self.meta.handle, self.endpoint, remote_endpoint);
+        self.error = Some(Error::Unreachable);
         true
     }
 
-    pub(crate) fn process(&mut self, ip_repr: &IpRepr, repr: &UdpRepr) -> Result<()> {
+    pub(crate) fn process(&mut self, ip_repr: &IpRepr, repr: &UdpRepr,
+                          timestamp: Instant) -> Result<()> {
         debug_assert!(self.accepts(ip_repr, repr));
 
         let size = repr.payload.len();
 
-        let endpoint = IpEndpoint { addr: ip_repr.src_addr(), port: repr.src_port };
-        self.rx_buffer.enqueue(size, endpoint)?.copy_from_slice(repr.payload);
+        let meta = UdpMetadata {
+            endpoint:      IpEndpoint { addr: ip_repr.src_addr(), port: repr.src_port },
+            local_addr:    ip_repr.dst_addr(),
+            hop_limit:     Some(ip_repr.hop_limit()),
+            traffic_class: Some(ip_repr.traffic_class()),
+            timestamp:     Some(timestamp),
+        };
+        self.rx_buffer.enqueue(size, meta)?.copy_from_slice(repr.payload);
 
         net_trace!("{}:{}:{}: receiving {} octets",
                    self.meta.handle, self.endpoint,
-                   endpoint, size);
+                   meta.endpoint, size);
         Ok(())
     }
 
+    /// Return the headers of a packet to send with the given metadata.
+    fn packet_repr<'p>(endpoint: IpEndpoint, hop_limit: Option<u8>, multicast: &MulticastOptions,
+                       meta: &UdpMetadata, payload: &'p [u8]) -> (IpRepr, UdpRepr<'p>) {
+        let remote_endpoint = meta.endpoint;
+        let repr = UdpRepr {
+            src_port: endpoint.port,
+            dst_port: remote_endpoint.port,
+            payload:  payload,
+        };
+        let src_addr = if meta.local_addr.is_unspecified() {
+            multicast.src_addr_for(remote_endpoint.addr, endpoint.addr)
+        } else {
+            meta.local_addr
+        };
+        let hop_limit = meta.hop_limit.unwrap_or_else(|| {
+            multicast.hop_limit_for(remote_endpoint.addr, hop_limit.unwrap_or(64))
+        });
+        let ip_repr = IpRepr::Unspecified {
+            src_addr:      src_addr,
+            dst_addr:      remote_endpoint.addr,
+            protocol:      IpProtocol::Udp,
+            payload_len:   repr.buffer_len(),
+            hop_limit:     hop_limit,
+            traffic_class: meta.traffic_class.unwrap_or(0),
+        };
+        (ip_repr, repr)
+    }
+
     pub(crate) fn dispatch<F>(&mut self, emit: F) -> Result<()>
             where F: FnOnce((IpRepr, UdpRepr)) -> Result<()> {
         let handle    = self.handle();
         let endpoint  = self.endpoint;
-        let hop_limit = self.hop_limit.unwrap_or(64);
+        let hop_limit = self.hop_limit;
+        let multicast = &self.multicast;
 
-        self.tx_buffer.dequeue_with(|remote_endpoint, payload_buf| {
+        let result = self.tx_buffer.dequeue_with(|meta, payload_buf| {
             net_trace!("{}:{}:{}: sending {} octets",
                         handle, endpoint,
-                        endpoint, payload_buf.len());
-
-            let repr = UdpRepr {
-                src_port: endpoint.port,
-                dst_port: remote_endpoint.port,
-                payload:  payload_buf,
-            };
-            let ip_repr = IpRepr::Unspecified {
-                src_addr:    endpoint.addr,
-                dst_addr:    remote_endpoint.addr,
-                protocol:    IpProtocol::Udp,
-                payload_len: repr.buffer_len(),
-                hop_limit:   hop_limit,
-            };
-            emit((ip_repr, repr))
-        })
+                        meta.endpoint, payload_buf.len());
+
+            emit(Self::packet_repr(endpoint, hop_limit, multicast, meta, payload_buf))
+        });
+        if result.is_ok() {
+            self.multicast.dequeued();
+        }
+        result
+    }
+
+    /// Call `f` with the multicast packet at the head of the transmit buffer,
+    /// if it is to be looped back and has not been yet.
+    pub(crate) fn loop_back<F>(&mut self, f: F)
+            where F: FnOnce(&IpRepr, &UdpRepr) {
+        let endpoint  = self.endpoint;
+        let hop_limit = self.hop_limit;
+        let multicast = &mut self.multicast;
+
+        if let Ok((meta, payload_buf)) = self.tx_buffer.peek() {
+            if !multicast.loop_back(meta.endpoint.addr) { return }
+
+            let (ip_repr, repr) = Self::packet_repr(endpoint, hop_limit, multicast,
+                                                    meta, payload_buf);
+            f(&ip_repr, &repr)
+        }
     }
 
     pub(crate) fn poll_at(&self) -> PollAt {
@@ -287,6 +553,7 @@ mod test {
         protocol: IpProtocol::Udp,
         payload_len: 8 + 6,
         hop_limit: 64,
+        traffic_class: 0,
     };
 
     const LOCAL_UDP_REPR: UdpRepr = UdpRepr {
@@ -309,7 +576,9 @@ mod test {
                 dst_addr: dst,
                 protocol: IpProtocol::Udp,
                 payload_len: 8 + 6,
-                hop_limit: 64
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
             }),
             #[cfg(feature = "proto-ipv6")]
             (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => IpRepr::Ipv6(Ipv6Repr {
@@ -317,7 +586,8 @@ mod test {
                 dst_addr: dst,
                 next_header: IpProtocol::Udp,
                 payload_len: 8 + 6,
-                hop_limit: 64
+                hop_limit: 64,
+                traffic_class: 0
             }),
             _ => unreachable!()
         }
@@ -394,12 +664,12 @@ mod test {
         assert_eq!(socket.recv(), Err(Error::Exhausted));
 
         assert!(socket.accepts(&remote_ip_repr(), &REMOTE_UDP_REPR));
-        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR),
+        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR, Instant::from_millis(0)),
                    Ok(()));
         assert!(socket.can_recv());
 
         assert!(socket.accepts(&remote_ip_repr(), &REMOTE_UDP_REPR));
-        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR),
+        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR, Instant::from_millis(0)),
                    Err(Error::Exhausted));
         assert_eq!(socket.recv(), Ok((&b"abcdef"[..], REMOTE_END)));
         assert!(!socket.can_recv());
@@ -412,7 +682,7 @@ mod test {
 
         assert_eq!(socket.peek(), Err(Error::Exhausted));
 
-        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR),
+        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR, Instant::from_millis(0)),
                    Ok(()));
         assert_eq!(socket.peek(), Ok((&b"abcdef"[..], &REMOTE_END)));
         assert_eq!(socket.recv(), Ok((&b"abcdef"[..], REMOTE_END)));
@@ -425,7 +695,7 @@ mod test {
         assert_eq!(socket.bind(LOCAL_PORT), Ok(()));
 
         assert!(socket.accepts(&remote_ip_repr(), &REMOTE_UDP_REPR));
-        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR),
+        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR, Instant::from_millis(0)),
                    Ok(()));
 
         let mut slice = [0; 4];
@@ -438,7 +708,7 @@ mod test {
         let mut socket = socket(buffer(1), buffer(0));
         assert_eq!(socket.bind(LOCAL_PORT), Ok(()));
 
-        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR),
+        assert_eq!(socket.process(&remote_ip_repr(), &REMOTE_UDP_REPR, Instant::from_millis(0)),
                    Ok(()));
 
         let mut slice = [0; 4];
@@ -463,11 +733,68 @@ mod test {
                 protocol: IpProtocol::Udp,
                 payload_len: 8 + 6,
                 hop_limit: 0x2a,
+                traffic_class: 0,
             });
             Ok(())
         }), Ok(()));
     }
 
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_multicast_dispatch() {
+        use wire::Ipv4Address;
+
+        let group = IpEndpoint::new(Ipv4Address::new(224, 0, 0, 251).into(), 5353);
+        let mut s = socket(buffer(0), buffer(2));
+        assert_eq!(s.bind(LOCAL_PORT), Ok(()));
+        s.set_hop_limit(Some(0x2a));
+        s.set_multicast_hop_limit(Some(4));
+        assert_eq!(s.set_multicast_src_addr(MOCK_IP_ADDR_3), Ok(()));
+        s.set_multicast_loopback(true);
+
+        // Multicast packets use the multicast hop limit and source address,
+        assert_eq!(s.send_slice(b"abcdef", group), Ok(()));
+        let expected_ip_repr = IpRepr::Unspecified {
+            src_addr: MOCK_IP_ADDR_3,
+            dst_addr: group.addr,
+            protocol: IpProtocol::Udp,
+            payload_len: 8 + 6,
+            hop_limit: 4,
+            traffic_class: 0,
+        };
+        // and are looped back once.
+        let mut looped = 0;
+        for _ in 0..2 {
+            s.loop_back(|ip_repr, udp_repr| {
+                assert_eq!(*ip_repr, expected_ip_repr);
+                assert_eq!(udp_repr.payload, b"abcdef");
+                looped += 1;
+            });
+        }
+        assert_eq!(looped, 1);
+        assert_eq!(s.dispatch(|(ip_repr, _)| {
+            assert_eq!(ip_repr, expected_ip_repr);
+            Ok(())
+        }), Ok(()));
+
+        // Unicast packets do not.
+        assert_eq!(s.send_slice(b"abcdef", REMOTE_END), Ok(()));
+        s.loop_back(|_, _| unreachable!());
+        assert_eq!(s.dispatch(|(ip_repr, _)| {
+            assert_eq!(ip_repr.src_addr(), IpAddress::Unspecified);
+            assert_eq!(ip_repr.hop_limit(), 0x2a);
+            Ok(())
+        }), Ok(()));
+
+        // Without a multicast hop limit, multicast packets use the hop limit.
+        s.set_multicast_hop_limit(None);
+        assert_eq!(s.send_slice(b"abcdef", group), Ok(()));
+        assert_eq!(s.dispatch(|(ip_repr, _)| {
+            assert_eq!(ip_repr.hop_limit(), 0x2a);
+            Ok(())
+        }), Ok(()));
+    }
+
     #[test]
     fn test_doesnt_accept_wrong_port() {
         let mut socket = socket(buffer(1), buffer(0));
@@ -489,7 +816,9 @@ mod test {
                     dst_addr: dst,
                     protocol: IpProtocol::Udp,
                     payload_len: 8 + 6,
-                    hop_limit: 64
+                    hop_limit: 64,
+                    traffic_class: 0,
+                    router_alert: false
                 }),
                 #[cfg(feature = "proto-ipv6")]
                 (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => IpRepr::Ipv6(Ipv6Repr {
@@ -497,7 +826,8 @@ mod test {
                     dst_addr: dst,
                     next_header: IpProtocol::Udp,
                     payload_len: 8 + 6,
-                    hop_limit: 64
+                    hop_limit: 64,
+                    traffic_class: 0
                 }),
                 _ => unreachable!()
             }
@@ -534,7 +864,118 @@ mod test {
             dst_port: LOCAL_PORT,
             payload: &[]
         };
-        assert_eq!(socket.process(&remote_ip_repr(), &repr), Ok(()));
+        assert_eq!(socket.process(&remote_ip_repr(), &repr, Instant::from_millis(0)), Ok(()));
         assert_eq!(socket.recv(), Ok((&[][..], REMOTE_END)));
     }
+
+    #[test]
+    fn test_connect() {
+        let mut socket = socket(buffer(1), buffer(1));
+        assert_eq!(socket.connect(REMOTE_END), Err(Error::Illegal));
+        assert_eq!(socket.bind(LOCAL_PORT), Ok(()));
+        assert_eq!(socket.connect(IpEndpoint { port: 0, ..REMOTE_END }),
+                   Err(Error::Unaddressable));
+        assert_eq!(socket.connect(REMOTE_END), Ok(()));
+        assert!(socket.is_connected());
+        assert_eq!(socket.remote_endpoint(), REMOTE_END);
+
+        // Only datagrams from the remote endpoint are accepted,
+        assert!(socket.accepts(&remote_ip_repr(), &REMOTE_UDP_REPR));
+        assert!(!socket.accepts(&remote_ip_repr(),
+                                &UdpRepr { src_port: REMOTE_PORT + 1, ..REMOTE_UDP_REPR }));
+        let other_ip_repr = IpRepr::Unspecified {
+            src_addr: MOCK_IP_ADDR_3,
+            dst_addr: MOCK_IP_ADDR_1,
+            protocol: IpProtocol::Udp,
+            payload_len: 8 + 6,
+            hop_limit: 64,
+            traffic_class: 0,
+        };
+        assert!(!socket.accepts(&other_ip_repr, &REMOTE_UDP_REPR));
+
+        // and datagrams are sent to it by default.
+        assert_eq!(socket.send_slice(b"abcdef", IpEndpoint::default()), Ok(()));
+        assert_eq!(socket.dispatch(|(ip_repr, _)| {
+            assert_eq!(ip_repr.dst_addr(), MOCK_IP_ADDR_2);
+            Ok(())
+        }), Ok(()));
+
+        socket.disconnect();
+        assert!(!socket.is_connected());
+        assert!(socket.accepts(&other_ip_repr, &REMOTE_UDP_REPR));
+        assert_eq!(socket.send_slice(b"abcdef", IpEndpoint::default()),
+                   Err(Error::Unaddressable));
+    }
+
+    #[test]
+    fn test_connect_unreachable() {
+        let mut socket = socket(buffer(1), buffer(0));
+        assert_eq!(socket.bind(LOCAL_END), Ok(()));
+        assert!(!socket.process_unreachable(LOCAL_END, REMOTE_END));
+
+        assert_eq!(socket.connect(REMOTE_END), Ok(()));
+        assert!(!socket.process_unreachable(IpEndpoint { port: LOCAL_PORT + 1, ..LOCAL_END },
+                                            REMOTE_END));
+        assert!(!socket.can_recv());
+        assert!(socket.process_unreachable(LOCAL_END, REMOTE_END));
+        assert!(socket.can_recv());
+
+        // The error is returned once.
+        assert_eq!(socket.peek(), Err(Error::Unreachable));
+        assert_eq!(socket.recv(), Err(Error::Unreachable));
+        assert_eq!(socket.recv(), Err(Error::Exhausted));
+    }
+
+    #[test]
+    fn test_recv_with_meta() {
+        let mut socket = socket(buffer(1), buffer(0));
+        assert_eq!(socket.bind(LOCAL_PORT), Ok(()));
+
+        let ip_repr = IpRepr::Unspecified {
+            src_addr:      MOCK_IP_ADDR_2,
+            dst_addr:      MOCK_IP_ADDR_1,
+            protocol:      IpProtocol::Udp,
+            payload_len:   8 + 6,
+            hop_limit:     32,
+            traffic_class: 0xb8,
+        };
+        assert_eq!(socket.process(&ip_repr, &REMOTE_UDP_REPR, Instant::from_millis(100)),
+                   Ok(()));
+        assert_eq!(socket.recv_with_meta(), Ok((&b"abcdef"[..], UdpMetadata {
+            endpoint:      REMOTE_END,
+            local_addr:    MOCK_IP_ADDR_1,
+            hop_limit:     Some(32),
+            traffic_class: Some(0xb8),
+            timestamp:     Some(Instant::from_millis(100)),
+        })));
+    }
+
+    #[test]
+    fn test_send_with_meta() {
+        let mut socket = socket(buffer(0), buffer(1));
+        assert_eq!(socket.bind(LOCAL_PORT), Ok(()));
+
+        let meta = UdpMetadata {
+            local_addr:    MOCK_IP_ADDR_3,
+            hop_limit:     Some(32),
+            traffic_class: Some(0xb8),
+            ..REMOTE_END.into()
+        };
+        assert_eq!(socket.send_slice_with_meta(b"abcdef",
+                                               UdpMetadata { hop_limit: Some(0), ..meta }),
+                   Err(Error::Illegal));
+        assert_eq!(socket.send_slice_with_meta(b"abcdef", meta), Ok(()));
+        assert_eq!(socket.dispatch(|(ip_repr, udp_repr)| {
+            assert_eq!(ip_repr, IpRepr::Unspecified {
+                src_addr:      MOCK_IP_ADDR_3,
+                dst_addr:      MOCK_IP_ADDR_2,
+                protocol:      IpProtocol::Udp,
+                payload_len:   8 + 6,
+                hop_limit:     32,
+                traffic_class: 0xb8,
+            });
+            assert_eq!(udp_repr, LOCAL_UDP_REPR);
+            Ok(())
+        }), Ok(()));
+    }
 }
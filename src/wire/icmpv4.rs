@@ -4,7 +4,7 @@ use byteorder::{ByteOrder, NetworkEndian};
 use {Error, Result};
 use phy::ChecksumCapabilities;
 use super::ip::checksum;
-use super::{Ipv4Packet, Ipv4Repr};
+use super::{Ipv4Address, Ipv4Packet, Ipv4Repr};
 
 enum_with_unknown! {
     /// Internet protocol control message type.
@@ -143,6 +143,23 @@ enum_with_unknown! {
     }
 }
 
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Redirect::Net =>
+                write!(f, "redirect for network"),
+            &Redirect::Host =>
+                write!(f, "redirect for host"),
+            &Redirect::NetToS =>
+                write!(f, "redirect for type of service and network"),
+            &Redirect::HostToS =>
+                write!(f, "redirect for type of service and host"),
+            &Redirect::Unknown(id) =>
+                write!(f, "{}", id)
+        }
+    }
+}
+
 enum_with_unknown! {
     /// Internet protocol control message subtype for type "Time Exceeded".
     pub doc enum TimeExceeded(u8) {
@@ -183,6 +200,8 @@ mod field {
     pub const ECHO_IDENT: Field = 4..6;
     pub const ECHO_SEQNO: Field = 6..8;
 
+    pub const REDIRECT_GATEWAY: Field = 4..8;
+
     pub const HEADER_END: usize = 8;
 }
 
@@ -263,6 +282,16 @@ impl<T: AsRef<[u8]>> Packet<T> {
         NetworkEndian::read_u16(&data[field::ECHO_SEQNO])
     }
 
+    /// Return the gateway address field (for redirect packets).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not a redirect packet.
+    #[inline]
+    pub fn redirect_gateway(&self) -> Ipv4Address {
+        let data = self.buffer.as_ref();
+        Ipv4Address::from_bytes(&data[field::REDIRECT_GATEWAY])
+    }
+
     /// Return the header length.
     /// The result depends on the value of the message type field.
     pub fn header_len(&self) -> usize {
@@ -270,6 +299,7 @@ impl<T: AsRef<[u8]>> Packet<T> {
             Message::EchoRequest    => field::ECHO_SEQNO.end,
             Message::EchoReply      => field::ECHO_SEQNO.end,
             Message::DstUnreachable => field::UNUSED.end,
+            Message::Redirect       => field::REDIRECT_GATEWAY.end,
             _ => field::UNUSED.end // make a conservative assumption
         }
     }
@@ -337,6 +367,16 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         NetworkEndian::write_u16(&mut data[field::ECHO_SEQNO], value)
     }
 
+    /// Set the gateway address field (for redirect packets).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not a redirect packet.
+    #[inline]
+    pub fn set_redirect_gateway(&mut self, value: Ipv4Address) {
+        let data = self.buffer.as_mut();
+        data[field::REDIRECT_GATEWAY].copy_from_slice(value.as_bytes())
+    }
+
     /// Compute and fill in the header checksum.
     pub fn fill_checksum(&mut self) {
         self.set_checksum(0);
@@ -382,6 +422,12 @@ pub enum Repr<'a> {
         header: Ipv4Repr,
         data:   &'a [u8]
     },
+    Redirect {
+        reason:  Redirect,
+        gateway: Ipv4Address,
+        header:  Ipv4Repr,
+        data:    &'a [u8]
+    },
     #[doc(hidden)]
     __Nonexhaustive
 }
@@ -427,7 +473,32 @@ impl<'a> Repr<'a> {
                         dst_addr: ip_packet.dst_addr(),
                         protocol: ip_packet.protocol(),
                         payload_len: payload.len(),
-                        hop_limit: ip_packet.hop_limit()
+                        hop_limit: ip_packet.hop_limit(),
+                        traffic_class: ip_packet.dscp() << 2 | ip_packet.ecn(),
+                        router_alert: false
+                    },
+                    data: payload
+                })
+            }
+
+            (Message::Redirect, code) => {
+                let ip_packet = Ipv4Packet::new_checked(packet.data())?;
+
+                let payload = &packet.data()[ip_packet.header_len() as usize..];
+                // RFC 792 requires exactly eight bytes to be returned.
+                if payload.len() < 8 { return Err(Error::Truncated) }
+
+                Ok(Repr::Redirect {
+                    reason:  Redirect::from(code),
+                    gateway: packet.redirect_gateway(),
+                    header:  Ipv4Repr {
+                        src_addr: ip_packet.src_addr(),
+                        dst_addr: ip_packet.dst_addr(),
+                        protocol: ip_packet.protocol(),
+                        payload_len: payload.len(),
+                        hop_limit: ip_packet.hop_limit(),
+                        traffic_class: ip_packet.dscp() << 2 | ip_packet.ecn(),
+                        router_alert: false
                     },
                     data: payload
                 })
@@ -446,6 +517,9 @@ impl<'a> Repr<'a> {
             &Repr::DstUnreachable { header, data, .. } => {
                 field::UNUSED.end + header.buffer_len() + data.len()
             }
+            &Repr::Redirect { header, data, .. } => {
+                field::REDIRECT_GATEWAY.end + header.buffer_len() + data.len()
+            }
             &Repr::__Nonexhaustive => unreachable!()
         }
     }
@@ -484,6 +558,17 @@ impl<'a> Repr<'a> {
                 payload.copy_from_slice(&data[..])
             }
 
+            &Repr::Redirect { reason, gateway, header, data } => {
+                packet.set_msg_type(Message::Redirect);
+                packet.set_msg_code(reason.into());
+                packet.set_redirect_gateway(gateway);
+
+                let mut ip_packet = Ipv4Packet::new_unchecked(packet.data_mut());
+                header.emit(&mut ip_packet, checksum_caps);
+                let payload = &mut ip_packet.into_inner()[header.buffer_len()..];
+                payload.copy_from_slice(&data[..])
+            }
+
             &Repr::__Nonexhaustive => unreachable!()
         }
 
@@ -507,6 +592,8 @@ impl<'a, T: AsRef<[u8]> + ?Sized> fmt::Display for Packet<&'a T> {
                 match self.msg_type() {
                     Message::DstUnreachable =>
                         write!(f, " code={:?}", DstUnreachable::from(self.msg_code())),
+                    Message::Redirect =>
+                        write!(f, " code={:?}", Redirect::from(self.msg_code())),
                     _ => write!(f, " code={}", self.msg_code())
                 }
             }
@@ -526,6 +613,9 @@ impl<'a> fmt::Display for Repr<'a> {
             &Repr::DstUnreachable { reason, .. } =>
                 write!(f, "ICMPv4 destination unreachable ({})",
                        reason),
+            &Repr::Redirect { reason, gateway, header, .. } =>
+                write!(f, "ICMPv4 {} dst={} gw={}",
+                       reason, header.dst_addr, gateway),
             &Repr::__Nonexhaustive => unreachable!()
         }
     }
@@ -543,7 +633,7 @@ impl<T: AsRef<[u8]>> PrettyPrint for Packet<T> {
         write!(f, "{}{}", indent, packet)?;
 
         match packet.msg_type() {
-            Message::DstUnreachable => {
+            Message::DstUnreachable | Message::Redirect => {
                 indent.increase(f)?;
                 super::Ipv4Packet::<&[u8]>::pretty_print(&packet.data(), f, indent)
             }
@@ -613,6 +703,51 @@ mod test {
         assert_eq!(&packet.into_inner()[..], &ECHO_PACKET_BYTES[..]);
     }
 
+    static REDIRECT_PACKET_BYTES: [u8; 36] =
+        [0x05, 0x01, 0xd0, 0x47,
+         0xc0, 0x00, 0x02, 0x02,
+         0x45, 0x00, 0x00, 0x1c,
+         0x00, 0x00, 0x40, 0x00,
+         0x40, 0x11, 0x4e, 0x92,
+         0xc0, 0x00, 0x02, 0x0a,
+         0xc6, 0x33, 0x64, 0x01,
+         0x12, 0x34, 0x56, 0x78,
+         0x00, 0x08, 0x00, 0x00];
+
+    fn redirect_packet_repr() -> Repr<'static> {
+        Repr::Redirect {
+            reason: Redirect::Host,
+            gateway: Ipv4Address([192, 0, 2, 2]),
+            header: Ipv4Repr {
+                src_addr: Ipv4Address([192, 0, 2, 10]),
+                dst_addr: Ipv4Address([198, 51, 100, 1]),
+                protocol: ::wire::IpProtocol::Udp,
+                payload_len: 8,
+                hop_limit: 64,
+                traffic_class: 0,
+                router_alert: false
+            },
+            data: &REDIRECT_PACKET_BYTES[28..]
+        }
+    }
+
+    #[test]
+    fn test_redirect_parse() {
+        let packet = Packet::new_unchecked(&REDIRECT_PACKET_BYTES[..]);
+        assert_eq!(packet.redirect_gateway(), Ipv4Address([192, 0, 2, 2]));
+        let repr = Repr::parse(&packet, &ChecksumCapabilities::default()).unwrap();
+        assert_eq!(repr, redirect_packet_repr());
+    }
+
+    #[test]
+    fn test_redirect_emit() {
+        let repr = redirect_packet_repr();
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        let mut packet = Packet::new_unchecked(&mut bytes);
+        repr.emit(&mut packet, &ChecksumCapabilities::default());
+        assert_eq!(&packet.into_inner()[..], &REDIRECT_PACKET_BYTES[..]);
+    }
+
     #[test]
     fn test_check_len() {
         let bytes = [0x0b, 0x00, 0x00, 0x00,
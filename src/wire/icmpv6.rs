@@ -562,7 +562,8 @@ impl<'a> Repr<'a> {
                 dst_addr: ip_packet.dst_addr(),
                 next_header: ip_packet.next_header(),
                 payload_len: payload.len(),
-                hop_limit: ip_packet.hop_limit()
+                hop_limit: ip_packet.hop_limit(),
+                traffic_class: ip_packet.traffic_class()
             };
             Ok((payload, repr))
         }
@@ -804,7 +805,8 @@ mod test {
                                        0x00, 0x00, 0x00, 0x02]),
                 next_header: IpProtocol::Udp,
                 payload_len: 12,
-                hop_limit: 0x40
+                hop_limit: 0x40,
+                traffic_class: 0
             },
             data: &PKT_TOO_BIG_UDP_PAYLOAD,
         }
@@ -8,7 +8,7 @@ use time::Duration;
 use wire::Ipv4Address;
 
 enum_with_unknown! {
-    /// Internet Group Management Protocol v1/v2 message version/type.
+    /// Internet Group Management Protocol v1/v2/v3 message version/type.
     pub doc enum Message(u8) {
         /// Membership Query
         MembershipQuery = 0x11,
@@ -17,11 +17,31 @@ enum_with_unknown! {
         /// Leave Group
         LeaveGroup = 0x17,
         /// Version 1 Membership Report
-        MembershipReportV1 = 0x12
+        MembershipReportV1 = 0x12,
+        /// Version 3 Membership Report
+        MembershipReportV3 = 0x22
     }
 }
 
-/// A read/write wrapper around an Internet Group Management Protocol v1/v2 packet buffer.
+enum_with_unknown! {
+    /// Internet Group Management Protocol v3 group record type.
+    pub doc enum RecordType(u8) {
+        /// Current state: the sources of the group are filtered in INCLUDE mode
+        ModeIsInclude = 1,
+        /// Current state: the sources of the group are filtered in EXCLUDE mode
+        ModeIsExclude = 2,
+        /// Filter mode change to INCLUDE
+        ChangeToInclude = 3,
+        /// Filter mode change to EXCLUDE
+        ChangeToExclude = 4,
+        /// Source list change: the sources are now received
+        AllowNewSources = 5,
+        /// Source list change: the sources are not received anymore
+        BlockOldSources = 6
+    }
+}
+
+/// A read/write wrapper around an Internet Group Management Protocol v1/v2/v3 packet buffer.
 #[derive(Debug)]
 pub struct Packet<T: AsRef<[u8]>> {
     buffer: T,
@@ -34,6 +54,23 @@ mod field {
     pub const MAX_RESP_CODE: usize = 1;
     pub const CHECKSUM: Field = 2..4;
     pub const GROUP_ADDRESS: Field = 4..8;
+
+    // IGMPv3 membership query.
+    pub const QUERY_FLAGS: usize = 8;
+    pub const QQIC: usize = 9;
+    pub const NUM_SOURCES: Field = 10..12;
+    pub const SOURCE_ADDRESSES: Rest = 12..;
+
+    // IGMPv3 membership report.
+    pub const NUM_GROUP_RECORDS: Field = 6..8;
+    pub const GROUP_RECORDS: Rest = 8..;
+
+    // IGMPv3 group record.
+    pub const RECORD_TYPE: usize = 0;
+    pub const AUX_DATA_LEN: usize = 1;
+    pub const RECORD_NUM_SOURCES: Field = 2..4;
+    pub const RECORD_GROUP_ADDRESS: Field = 4..8;
+    pub const RECORD_SOURCE_ADDRESSES: Rest = 8..;
 }
 
 impl fmt::Display for Message {
@@ -43,14 +80,31 @@ impl fmt::Display for Message {
             &Message::MembershipReportV2 => write!(f, "version 2 membership report"),
             &Message::LeaveGroup => write!(f, "leave group"),
             &Message::MembershipReportV1 => write!(f, "version 1 membership report"),
+            &Message::MembershipReportV3 => write!(f, "version 3 membership report"),
             &Message::Unknown(id) => write!(f, "{}", id),
         }
     }
 }
 
-/// Internet Group Management Protocol v1/v2 defined in [RFC 2236].
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RecordType::ModeIsInclude => write!(f, "IS_IN"),
+            &RecordType::ModeIsExclude => write!(f, "IS_EX"),
+            &RecordType::ChangeToInclude => write!(f, "TO_IN"),
+            &RecordType::ChangeToExclude => write!(f, "TO_EX"),
+            &RecordType::AllowNewSources => write!(f, "ALLOW"),
+            &RecordType::BlockOldSources => write!(f, "BLOCK"),
+            &RecordType::Unknown(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// Internet Group Management Protocol v1/v2 defined in [RFC 2236],
+/// and v3 defined in [RFC 3376].
 ///
 /// [RFC 2236]: https://tools.ietf.org/html/rfc2236
+/// [RFC 3376]: https://tools.ietf.org/html/rfc3376
 impl<T: AsRef<[u8]>> Packet<T> {
     /// Imbue a raw octet buffer with IGMPv2 packet structure.
     pub fn new_unchecked(buffer: T) -> Packet<T> {
@@ -69,10 +123,16 @@ impl<T: AsRef<[u8]>> Packet<T> {
 
     /// Ensure that no accessor method will panic if called.
     /// Returns `Err(Error::Truncated)` if the buffer is too short.
+    ///
+    /// The source addresses of an IGMPv3 membership query are checked as well;
+    /// the group records of an IGMPv3 membership report are not.
     pub fn check_len(&self) -> Result<()> {
         let len = self.buffer.as_ref().len();
         if len < field::GROUP_ADDRESS.end as usize {
             Err(Error::Truncated)
+        } else if self.is_v3_query() &&
+                len < field::SOURCE_ADDRESSES.start + self.num_sources() as usize * 4 {
+            Err(Error::Truncated)
         } else {
             Ok(())
         }
@@ -114,6 +174,49 @@ impl<T: AsRef<[u8]>> Packet<T> {
         Ipv4Address::from_bytes(&data[field::GROUP_ADDRESS])
     }
 
+    /// Query whether the packet is an IGMPv3 membership query, i.e. a membership
+    /// query at least 12 octets long, see [RFC 3376] § 7.1.
+    pub fn is_v3_query(&self) -> bool {
+        let data = self.buffer.as_ref();
+        self.msg_type() == Message::MembershipQuery &&
+            data.len() >= field::SOURCE_ADDRESSES.start
+    }
+
+    /// Return the suppress router-side processing flag of an IGMPv3 membership query.
+    #[inline]
+    pub fn suppress_router_flag(&self) -> bool {
+        let data = self.buffer.as_ref();
+        data[field::QUERY_FLAGS] & 0x08 != 0
+    }
+
+    /// Return the querier's robustness variable of an IGMPv3 membership query.
+    #[inline]
+    pub fn robustness(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[field::QUERY_FLAGS] & 0x07
+    }
+
+    /// Return the querier's query interval code of an IGMPv3 membership query.
+    #[inline]
+    pub fn query_interval_code(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[field::QQIC]
+    }
+
+    /// Return the number of source addresses of an IGMPv3 membership query.
+    #[inline]
+    pub fn num_sources(&self) -> u16 {
+        let data = self.buffer.as_ref();
+        NetworkEndian::read_u16(&data[field::NUM_SOURCES])
+    }
+
+    /// Return the number of group records of an IGMPv3 membership report.
+    #[inline]
+    pub fn num_group_records(&self) -> u16 {
+        let data = self.buffer.as_ref();
+        NetworkEndian::read_u16(&data[field::NUM_GROUP_RECORDS])
+    }
+
     /// Validate the header checksum.
     ///
     /// # Fuzzing
@@ -128,6 +231,23 @@ impl<T: AsRef<[u8]>> Packet<T> {
     }
 }
 
+impl<'a, T: AsRef<[u8]> + ?Sized> Packet<&'a T> {
+    /// Return a pointer to the source addresses of an IGMPv3 membership query.
+    #[inline]
+    pub fn source_addrs(&self) -> &'a [u8] {
+        let data = self.buffer.as_ref();
+        let len = self.num_sources() as usize * 4;
+        &data[field::SOURCE_ADDRESSES][..len]
+    }
+
+    /// Return a pointer to the group records of an IGMPv3 membership report.
+    #[inline]
+    pub fn group_records(&self) -> &'a [u8] {
+        let data = self.buffer.as_ref();
+        &data[field::GROUP_RECORDS]
+    }
+}
+
 impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
     /// Set the message type field.
     #[inline]
@@ -158,6 +278,52 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         data[field::GROUP_ADDRESS].copy_from_slice(addr.as_bytes());
     }
 
+    /// Set the suppress router-side processing flag and the querier's robustness
+    /// variable of an IGMPv3 membership query, clearing the reserved bits.
+    #[inline]
+    pub fn set_query_flags(&mut self, suppress_router: bool, robustness: u8) {
+        let data = self.buffer.as_mut();
+        let flag = if suppress_router { 0x08 } else { 0x00 };
+        data[field::QUERY_FLAGS] = flag | (robustness & 0x07);
+    }
+
+    /// Set the querier's query interval code of an IGMPv3 membership query.
+    #[inline]
+    pub fn set_query_interval_code(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[field::QQIC] = value;
+    }
+
+    /// Set the number of source addresses of an IGMPv3 membership query.
+    #[inline]
+    pub fn set_num_sources(&mut self, value: u16) {
+        let data = self.buffer.as_mut();
+        NetworkEndian::write_u16(&mut data[field::NUM_SOURCES], value)
+    }
+
+    /// Set the number of group records of an IGMPv3 membership report,
+    /// clearing the reserved field.
+    #[inline]
+    pub fn set_num_group_records(&mut self, value: u16) {
+        let data = self.buffer.as_mut();
+        NetworkEndian::write_u16(&mut data[field::GROUP_ADDRESS.start..field::NUM_GROUP_RECORDS.start], 0);
+        NetworkEndian::write_u16(&mut data[field::NUM_GROUP_RECORDS], value)
+    }
+
+    /// Return a mutable pointer to the source addresses of an IGMPv3 membership query.
+    #[inline]
+    pub fn source_addrs_mut(&mut self) -> &mut [u8] {
+        let data = self.buffer.as_mut();
+        &mut data[field::SOURCE_ADDRESSES]
+    }
+
+    /// Return a mutable pointer to the group records of an IGMPv3 membership report.
+    #[inline]
+    pub fn group_records_mut(&mut self) -> &mut [u8] {
+        let data = self.buffer.as_mut();
+        &mut data[field::GROUP_RECORDS]
+    }
+
     /// Compute and fill in the header checksum.
     pub fn fill_checksum(&mut self) {
         self.set_checksum(0);
@@ -169,18 +335,166 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
     }
 }
 
-/// A high-level representation of an Internet Group Management Protocol v1/v2 header.
+/// A read/write wrapper around an Internet Group Management Protocol v3
+/// group record buffer, contained in a membership report.
+#[derive(Debug)]
+pub struct GroupRecord<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> GroupRecord<T> {
+    /// Imbue a raw octet buffer with IGMPv3 group record structure.
+    pub fn new_unchecked(buffer: T) -> GroupRecord<T> {
+        GroupRecord { buffer }
+    }
+
+    /// Shorthand for a combination of [new_unchecked] and [check_len].
+    ///
+    /// [new_unchecked]: #method.new_unchecked
+    /// [check_len]: #method.check_len
+    pub fn new_checked(buffer: T) -> Result<GroupRecord<T>> {
+        let record = Self::new_unchecked(buffer);
+        record.check_len()?;
+        Ok(record)
+    }
+
+    /// Ensure that no accessor method will panic if called.
+    /// Returns `Err(Error::Truncated)` if the buffer is too short.
+    pub fn check_len(&self) -> Result<()> {
+        let len = self.buffer.as_ref().len();
+        if len < field::RECORD_GROUP_ADDRESS.end || len < self.total_len() {
+            Err(Error::Truncated)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Consume the group record, returning the underlying buffer.
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    /// Return the record type field.
+    #[inline]
+    pub fn record_type(&self) -> RecordType {
+        let data = self.buffer.as_ref();
+        RecordType::from(data[field::RECORD_TYPE])
+    }
+
+    /// Return the auxiliary data length field, in units of 32-bit words.
+    #[inline]
+    pub fn aux_data_len(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[field::AUX_DATA_LEN]
+    }
+
+    /// Return the number of sources field.
+    #[inline]
+    pub fn num_sources(&self) -> u16 {
+        let data = self.buffer.as_ref();
+        NetworkEndian::read_u16(&data[field::RECORD_NUM_SOURCES])
+    }
+
+    /// Return the multicast address field.
+    #[inline]
+    pub fn group_addr(&self) -> Ipv4Address {
+        let data = self.buffer.as_ref();
+        Ipv4Address::from_bytes(&data[field::RECORD_GROUP_ADDRESS])
+    }
+
+    /// Return the source address with the given index.
+    ///
+    /// # Panics
+    /// This function panics if the index is not less than the number of sources.
+    #[inline]
+    pub fn source_addr(&self, index: usize) -> Ipv4Address {
+        assert!(index < self.num_sources() as usize);
+        let data = self.buffer.as_ref();
+        let start = field::RECORD_SOURCE_ADDRESSES.start + index * 4;
+        Ipv4Address::from_bytes(&data[start..start + 4])
+    }
+
+    /// Return the length of the group record, including the source addresses
+    /// and the auxiliary data.
+    #[inline]
+    pub fn total_len(&self) -> usize {
+        field::RECORD_SOURCE_ADDRESSES.start + self.num_sources() as usize * 4 +
+            self.aux_data_len() as usize * 4
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> GroupRecord<T> {
+    /// Set the record type field.
+    #[inline]
+    pub fn set_record_type(&mut self, value: RecordType) {
+        let data = self.buffer.as_mut();
+        data[field::RECORD_TYPE] = value.into()
+    }
+
+    /// Set the auxiliary data length field, in units of 32-bit words.
+    #[inline]
+    pub fn set_aux_data_len(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[field::AUX_DATA_LEN] = value
+    }
+
+    /// Set the number of sources field.
+    #[inline]
+    pub fn set_num_sources(&mut self, value: u16) {
+        let data = self.buffer.as_mut();
+        NetworkEndian::write_u16(&mut data[field::RECORD_NUM_SOURCES], value)
+    }
+
+    /// Set the multicast address field.
+    #[inline]
+    pub fn set_group_addr(&mut self, addr: Ipv4Address) {
+        let data = self.buffer.as_mut();
+        data[field::RECORD_GROUP_ADDRESS].copy_from_slice(addr.as_bytes());
+    }
+
+    /// Set the source address with the given index.
+    #[inline]
+    pub fn set_source_addr(&mut self, index: usize, addr: Ipv4Address) {
+        let data = self.buffer.as_mut();
+        let start = field::RECORD_SOURCE_ADDRESSES.start + index * 4;
+        data[start..start + 4].copy_from_slice(addr.as_bytes());
+    }
+}
+
+/// A high-level representation of an Internet Group Management Protocol v1/v2/v3 header.
+///
+/// The variable length parts of IGMPv3 messages are represented as raw octets:
+/// `source_addrs` contains consecutive IPv4 addresses, and `records` contains
+/// consecutive group records that can be accessed with [GroupRecord].
+///
+/// [GroupRecord]: struct.GroupRecord.html
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Repr {
+pub enum Repr<'a> {
+    /// An IGMPv1 or IGMPv2 membership query.
     MembershipQuery {
         max_resp_time: Duration,
         group_addr: Ipv4Address,
         version: IgmpVersion,
     },
+    /// An IGMPv3 membership query.
+    MembershipQueryV3 {
+        max_resp_time: Duration,
+        group_addr: Ipv4Address,
+        suppress_router: bool,
+        robustness: u8,
+        query_interval: Duration,
+        source_addrs: &'a [u8],
+    },
+    /// An IGMPv1 or IGMPv2 membership report.
     MembershipReport {
         group_addr: Ipv4Address,
         version: IgmpVersion,
     },
+    /// An IGMPv3 membership report.
+    MembershipReportV3 {
+        num_records: u16,
+        records: &'a [u8],
+    },
     LeaveGroup {
         group_addr: Ipv4Address,
     },
@@ -193,25 +507,41 @@ pub enum IgmpVersion {
     Version1,
     /// IGMPv2
     Version2,
+    /// IGMPv3
+    Version3,
 }
 
-impl Repr {
-    /// Parse an Internet Group Management Protocol v1/v2 packet and return
+impl<'a> Repr<'a> {
+    /// Parse an Internet Group Management Protocol v1/v2/v3 packet and return
     /// a high-level representation.
-    pub fn parse<T>(packet: &Packet<&T>) -> Result<Repr>
+    pub fn parse<T>(packet: &Packet<&'a T>) -> Result<Repr<'a>>
         where T: AsRef<[u8]> + ?Sized
     {
         // Check if the address is 0.0.0.0 or multicast
         let addr = packet.group_addr();
-        if !addr.is_unspecified() && !addr.is_multicast() {
+        if packet.msg_type() != Message::MembershipReportV3 &&
+                !addr.is_unspecified() && !addr.is_multicast() {
             return Err(Error::Malformed);
         }
 
         // construct a packet based on the Type field
         match packet.msg_type() {
+            Message::MembershipQuery if packet.is_v3_query() => {
+                Ok(Repr::MembershipQueryV3 {
+                    max_resp_time: max_resp_code_to_duration(packet.max_resp_code()),
+                    group_addr: addr,
+                    suppress_router: packet.suppress_router_flag(),
+                    robustness: packet.robustness(),
+                    query_interval:
+                        Duration::from_secs(decode_code(packet.query_interval_code())),
+                    source_addrs: packet.source_addrs(),
+                })
+            }
+            // See RFC 3376: 7.1. Query Version Distinctions
+            Message::MembershipQuery if packet.buffer.as_ref().len() > field::GROUP_ADDRESS.end =>
+                Err(Error::Malformed),
             Message::MembershipQuery => {
                 let max_resp_time = max_resp_code_to_duration(packet.max_resp_code());
-                // See RFC 3376: 7.1. Query Version Distinctions
                 let version = if packet.max_resp_code() == 0 {
                     IgmpVersion::Version1
                 } else {
@@ -237,20 +567,48 @@ impl Repr {
                     version: IgmpVersion::Version1,
                 })
             }
+            Message::MembershipReportV3 => {
+                let num_records = packet.num_group_records();
+                let records = packet.group_records();
+                let mut offset = 0;
+                for _ in 0..num_records {
+                    let record = GroupRecord::new_checked(&records[offset..])?;
+                    offset += record.total_len();
+                }
+                Ok(Repr::MembershipReportV3 {
+                    num_records,
+                    records: &records[..offset],
+                })
+            }
             _ => Err(Error::Unrecognized),
         }
     }
 
     /// Return the length of a packet that will be emitted from this high-level representation.
     pub fn buffer_len(&self) -> usize {
-        // always 8 bytes
-        field::GROUP_ADDRESS.end
+        match self {
+            &Repr::MembershipQueryV3 { source_addrs, .. } =>
+                field::SOURCE_ADDRESSES.start + source_addrs.len(),
+            &Repr::MembershipReportV3 { records, .. } =>
+                field::GROUP_RECORDS.start + records.len(),
+            _ => field::GROUP_ADDRESS.end
+        }
     }
 
-    /// Emit a high-level representation into an Internet Group Management Protocol v2 packet.
-    pub fn emit<T>(&self, packet: &mut Packet<&mut T>)
+    /// Emit a high-level representation into an Internet Group Management Protocol packet.
+    ///
+    /// Returns `Err(Error::Malformed)` if the version of a membership query or report is
+    /// `IgmpVersion::Version3`, since IGMPv3 messages are represented by separate variants.
+    pub fn emit<T>(&self, packet: &mut Packet<&mut T>) -> Result<()>
         where T: AsRef<[u8]> + AsMut<[u8]> + ?Sized
     {
+        match self {
+            &Repr::MembershipQuery { version: IgmpVersion::Version3, .. } |
+            &Repr::MembershipReport { version: IgmpVersion::Version3, .. } =>
+                return Err(Error::Malformed),
+            _ => ()
+        }
+
         match self {
             &Repr::MembershipQuery {
                 max_resp_time,
@@ -263,9 +621,26 @@ impl Repr {
                         packet.set_max_resp_code(0),
                     IgmpVersion::Version2 =>
                         packet.set_max_resp_code(duration_to_max_resp_code(max_resp_time)),
+                    IgmpVersion::Version3 => unreachable!(),
                 }
                 packet.set_group_address(group_addr);
             }
+            &Repr::MembershipQueryV3 {
+                max_resp_time,
+                group_addr,
+                suppress_router,
+                robustness,
+                query_interval,
+                source_addrs,
+            } => {
+                packet.set_msg_type(Message::MembershipQuery);
+                packet.set_max_resp_code(duration_to_max_resp_code(max_resp_time));
+                packet.set_group_address(group_addr);
+                packet.set_query_flags(suppress_router, robustness);
+                packet.set_query_interval_code(encode_code(query_interval.secs()));
+                packet.set_num_sources((source_addrs.len() / 4) as u16);
+                packet.source_addrs_mut()[..source_addrs.len()].copy_from_slice(source_addrs);
+            }
             &Repr::MembershipReport {
                 group_addr,
                 version,
@@ -273,38 +648,46 @@ impl Repr {
                 match version {
                     IgmpVersion::Version1 => packet.set_msg_type(Message::MembershipReportV1),
                     IgmpVersion::Version2 => packet.set_msg_type(Message::MembershipReportV2),
+                    IgmpVersion::Version3 => unreachable!(),
                 };
                 packet.set_max_resp_code(0);
                 packet.set_group_address(group_addr);
             }
+            &Repr::MembershipReportV3 { num_records, records } => {
+                packet.set_msg_type(Message::MembershipReportV3);
+                packet.set_max_resp_code(0);
+                packet.set_num_group_records(num_records);
+                packet.group_records_mut()[..records.len()].copy_from_slice(records);
+            }
             &Repr::LeaveGroup { group_addr } => {
                 packet.set_msg_type(Message::LeaveGroup);
                 packet.set_group_address(group_addr);
             }
         }
 
-        packet.fill_checksum()
+        packet.fill_checksum();
+        Ok(())
     }
 }
 
-fn max_resp_code_to_duration(value: u8) -> Duration {
+/// Decode a Max Resp Code or a QQIC, see [RFC 3376] § 4.1.1 and § 4.1.7.
+fn decode_code(value: u8) -> u64 {
     let value: u64 = value.into();
-    let centisecs = if value < 128 {
+    if value < 128 {
         value
     } else {
         let mant = value & 0xF;
         let exp = (value >> 4) & 0x7;
         (mant | 0x10) << (exp + 3)
-    };
-    Duration::from_millis(centisecs * 100)
+    }
 }
 
-fn duration_to_max_resp_code(duration: Duration) -> u8 {
-    let centisecs = duration.total_millis() / 100;
-    if centisecs < 128 {
-        centisecs as u8
-    } else if centisecs < 31744 {
-        let mut mant = centisecs >> 3;
+/// Encode a Max Resp Code or a QQIC, saturating at the largest representable value.
+fn encode_code(value: u64) -> u8 {
+    if value < 128 {
+        value as u8
+    } else if value < 31744 {
+        let mut mant = value >> 3;
         let mut exp = 0u8;
         while mant > 0x1F && exp < 0x8 {
             mant >>= 1;
@@ -316,6 +699,14 @@ fn duration_to_max_resp_code(duration: Duration) -> u8 {
     }
 }
 
+fn max_resp_code_to_duration(value: u8) -> Duration {
+    Duration::from_millis(decode_code(value) * 100)
+}
+
+fn duration_to_max_resp_code(duration: Duration) -> u8 {
+    encode_code(duration.total_millis() / 100)
+}
+
 impl<'a, T: AsRef<[u8]> + ?Sized> fmt::Display for Packet<&'a T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match Repr::parse(self) {
@@ -325,7 +716,7 @@ impl<'a, T: AsRef<[u8]> + ?Sized> fmt::Display for Packet<&'a T> {
     }
 }
 
-impl<'a> fmt::Display for Repr {
+impl<'a> fmt::Display for Repr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &Repr::MembershipQuery {
@@ -339,6 +730,20 @@ impl<'a> fmt::Display for Repr {
                        group_addr,
                        version)
             }
+            &Repr::MembershipQueryV3 {
+                max_resp_time,
+                group_addr,
+                source_addrs,
+                ..
+            } => {
+                write!(f,
+                       "IGMP membership query max_resp_time={} group_addr={} version={:?} \
+                        num_sources={}",
+                       max_resp_time,
+                       group_addr,
+                       IgmpVersion::Version3,
+                       source_addrs.len() / 4)
+            }
             &Repr::MembershipReport {
                 group_addr,
                 version,
@@ -348,6 +753,20 @@ impl<'a> fmt::Display for Repr {
                        group_addr,
                        version)
             }
+            &Repr::MembershipReportV3 { num_records, records } => {
+                write!(f, "IGMP membership report version={:?}", IgmpVersion::Version3)?;
+                let mut offset = 0;
+                for _ in 0..num_records {
+                    let record = GroupRecord::new_unchecked(&records[offset..]);
+                    write!(f, " {}({}", record.record_type(), record.group_addr())?;
+                    for index in 0..record.num_sources() as usize {
+                        write!(f, " {}", record.source_addr(index))?;
+                    }
+                    write!(f, ")")?;
+                    offset += record.total_len();
+                }
+                Ok(())
+            }
             &Repr::LeaveGroup { group_addr } => {
                 write!(f, "IGMP leave group group_addr={})", group_addr)
             }
@@ -377,6 +796,28 @@ mod test {
     static LEAVE_PACKET_BYTES: [u8; 8] = [0x17, 0x00, 0x02, 0x69, 0xe0, 0x00, 0x06, 0x96];
     static REPORT_PACKET_BYTES: [u8; 8] = [0x16, 0x00, 0x08, 0xda, 0xe1, 0x00, 0x00, 0x25];
 
+    static QUERY_V3_PACKET_BYTES: [u8; 16] =
+        [0x11, 0x64, 0xf9, 0x19, 0xe8, 0x01, 0x01, 0x01,
+         0x02, 0x7d, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x01];
+
+    static REPORT_V3_PACKET_BYTES: [u8; 20] =
+        [0x22, 0x00, 0xe9, 0xf9, 0x00, 0x00, 0x00, 0x01,
+         0x01, 0x00, 0x00, 0x01, 0xe8, 0x01, 0x01, 0x01,
+         0x0a, 0x00, 0x00, 0x01];
+
+    static SOURCE_ADDR_BYTES: [u8; 4] = [0x0a, 0x00, 0x00, 0x01];
+
+    fn query_v3_repr() -> Repr<'static> {
+        Repr::MembershipQueryV3 {
+            max_resp_time: Duration::from_secs(10),
+            group_addr: Ipv4Address::new(232, 1, 1, 1),
+            suppress_router: false,
+            robustness: 2,
+            query_interval: Duration::from_secs(125),
+            source_addrs: &SOURCE_ADDR_BYTES,
+        }
+    }
+
     #[test]
     fn test_leave_group_deconstruct() {
         let packet = Packet::new_unchecked(&LEAVE_PACKET_BYTES[..]);
@@ -421,6 +862,99 @@ mod test {
         assert_eq!(&packet.into_inner()[..], &REPORT_PACKET_BYTES[..]);
     }
 
+    #[test]
+    fn test_query_v3_deconstruct() {
+        let packet = Packet::new_checked(&QUERY_V3_PACKET_BYTES[..]).unwrap();
+        assert_eq!(packet.msg_type(), Message::MembershipQuery);
+        assert!(packet.is_v3_query());
+        assert_eq!(packet.max_resp_code(), 100);
+        assert_eq!(packet.group_addr(), Ipv4Address::new(232, 1, 1, 1));
+        assert_eq!(packet.suppress_router_flag(), false);
+        assert_eq!(packet.robustness(), 2);
+        assert_eq!(packet.query_interval_code(), 125);
+        assert_eq!(packet.num_sources(), 1);
+        assert_eq!(packet.source_addrs(), &SOURCE_ADDR_BYTES[..]);
+        assert_eq!(packet.verify_checksum(), true);
+        assert_eq!(Repr::parse(&packet), Ok(query_v3_repr()));
+    }
+
+    #[test]
+    fn test_query_v3_construct() {
+        let repr = query_v3_repr();
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        assert_eq!(repr.emit(&mut Packet::new_unchecked(&mut bytes)), Ok(()));
+        assert_eq!(&bytes[..], &QUERY_V3_PACKET_BYTES[..]);
+    }
+
+    #[test]
+    fn test_query_v3_truncated() {
+        assert_eq!(Packet::new_checked(&QUERY_V3_PACKET_BYTES[..14]).err(),
+                   Some(Error::Truncated));
+        // Queries between 9 and 11 octets long are neither v2 nor v3 queries.
+        let packet = Packet::new_checked(&QUERY_V3_PACKET_BYTES[..10]).unwrap();
+        assert_eq!(Repr::parse(&packet), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn test_report_v3_deconstruct() {
+        let packet = Packet::new_checked(&REPORT_V3_PACKET_BYTES[..]).unwrap();
+        assert_eq!(packet.msg_type(), Message::MembershipReportV3);
+        assert_eq!(packet.num_group_records(), 1);
+        assert_eq!(packet.verify_checksum(), true);
+        let record = GroupRecord::new_checked(packet.group_records()).unwrap();
+        assert_eq!(record.record_type(), RecordType::ModeIsInclude);
+        assert_eq!(record.aux_data_len(), 0);
+        assert_eq!(record.num_sources(), 1);
+        assert_eq!(record.group_addr(), Ipv4Address::new(232, 1, 1, 1));
+        assert_eq!(record.source_addr(0), Ipv4Address::new(10, 0, 0, 1));
+        assert_eq!(record.total_len(), 12);
+        assert_eq!(Repr::parse(&packet), Ok(Repr::MembershipReportV3 {
+            num_records: 1,
+            records: &REPORT_V3_PACKET_BYTES[8..],
+        }));
+    }
+
+    #[test]
+    fn test_report_v3_construct() {
+        let mut records = vec![0xa5; 12];
+        {
+            let mut record = GroupRecord::new_unchecked(&mut records);
+            record.set_record_type(RecordType::ModeIsInclude);
+            record.set_aux_data_len(0);
+            record.set_num_sources(1);
+            record.set_group_addr(Ipv4Address::new(232, 1, 1, 1));
+            record.set_source_addr(0, Ipv4Address::new(10, 0, 0, 1));
+        }
+        let repr = Repr::MembershipReportV3 { num_records: 1, records: &records };
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        assert_eq!(repr.emit(&mut Packet::new_unchecked(&mut bytes)), Ok(()));
+        assert_eq!(&bytes[..], &REPORT_V3_PACKET_BYTES[..]);
+    }
+
+    #[test]
+    fn test_emit_v3_in_v1_v2_repr() {
+        let mut bytes = vec![0; 8];
+        let repr = Repr::MembershipReport {
+            group_addr: Ipv4Address::new(224, 0, 6, 150),
+            version:    IgmpVersion::Version3,
+        };
+        assert_eq!(repr.emit(&mut Packet::new_unchecked(&mut bytes)), Err(Error::Malformed));
+        let repr = Repr::MembershipQuery {
+            max_resp_time: Duration::from_secs(1),
+            group_addr:    Ipv4Address::UNSPECIFIED,
+            version:       IgmpVersion::Version3,
+        };
+        assert_eq!(repr.emit(&mut Packet::new_unchecked(&mut bytes)), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn test_report_v3_truncated() {
+        let mut bytes = REPORT_V3_PACKET_BYTES.to_vec();
+        bytes[7] = 2;
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(Repr::parse(&packet), Err(Error::Truncated));
+    }
+
     #[test]
     fn max_resp_time_to_duration_and_back() {
         for i in 0..256usize {
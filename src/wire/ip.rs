@@ -83,6 +83,61 @@ impl fmt::Display for Protocol {
     }
 }
 
+/// Explicit Congestion Notification codepoint, as defined in RFC 3168.
+///
+/// The codepoint occupies the two low-order bits of the IPv4 type of service
+/// and the IPv6 traffic class fields.
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Ecn {
+    /// Not ECN-capable transport.
+    NotEct = 0b00,
+    /// ECN-capable transport, codepoint ECT(1).
+    Ect1   = 0b01,
+    /// ECN-capable transport, codepoint ECT(0).
+    Ect0   = 0b10,
+    /// Congestion experienced.
+    Ce     = 0b11
+}
+
+impl Ecn {
+    /// Extract the ECN codepoint from a traffic class value.
+    pub fn from_traffic_class(traffic_class: u8) -> Ecn {
+        match traffic_class & 0b11 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _    => Ecn::Ce
+        }
+    }
+
+    /// Return the given traffic class value with its ECN codepoint replaced by this one.
+    pub fn with_traffic_class(&self, traffic_class: u8) -> u8 {
+        (traffic_class & !0b11) | *self as u8
+    }
+
+    /// Query whether the codepoint indicates an ECN-capable transport.
+    pub fn is_capable(&self) -> bool {
+        *self != Ecn::NotEct
+    }
+}
+
+impl Default for Ecn {
+    fn default() -> Ecn {
+        Ecn::NotEct
+    }
+}
+
+impl fmt::Display for Ecn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Ecn::NotEct => write!(f, "Not-ECT"),
+            &Ecn::Ect1   => write!(f, "ECT(1)"),
+            &Ecn::Ect0   => write!(f, "ECT(0)"),
+            &Ecn::Ce     => write!(f, "CE")
+        }
+    }
+}
+
 /// An internetworking address.
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum Address {
@@ -439,11 +494,12 @@ impl<T: Into<Address>> From<(T, u16)> for Endpoint {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Repr {
     Unspecified {
-        src_addr:    Address,
-        dst_addr:    Address,
-        protocol:    Protocol,
-        payload_len: usize,
-        hop_limit:   u8
+        src_addr:      Address,
+        dst_addr:      Address,
+        protocol:      Protocol,
+        payload_len:   usize,
+        hop_limit:     u8,
+        traffic_class: u8
     },
     #[cfg(feature = "proto-ipv4")]
     Ipv4(Ipv4Repr),
@@ -543,6 +599,21 @@ impl Repr {
         }
     }
 
+    /// Set the traffic class, i.e. the DSCP and the ECN codepoint.
+    pub fn set_traffic_class(&mut self, value: u8) {
+        match self {
+            &mut Repr::Unspecified { ref mut traffic_class, .. } =>
+                *traffic_class = value,
+            #[cfg(feature = "proto-ipv4")]
+            &mut Repr::Ipv4(Ipv4Repr { ref mut traffic_class, .. }) =>
+                *traffic_class = value,
+            #[cfg(feature = "proto-ipv6")]
+            &mut Repr::Ipv6(Ipv6Repr { ref mut traffic_class, .. }) =>
+                *traffic_class = value,
+            &mut Repr::__Nonexhaustive => unreachable!()
+        }
+    }
+
     /// Return the TTL value.
     pub fn hop_limit(&self) -> u8 {
         match self {
@@ -555,6 +626,23 @@ impl Repr {
         }
     }
 
+    /// Return the traffic class, i.e. the DSCP and the ECN codepoint.
+    pub fn traffic_class(&self) -> u8 {
+        match self {
+            &Repr::Unspecified { traffic_class, .. }    => traffic_class,
+            #[cfg(feature = "proto-ipv4")]
+            &Repr::Ipv4(Ipv4Repr { traffic_class, .. }) => traffic_class,
+            #[cfg(feature = "proto-ipv6")]
+            &Repr::Ipv6(Ipv6Repr { traffic_class, ..})  => traffic_class,
+            &Repr::__Nonexhaustive => unreachable!()
+        }
+    }
+
+    /// Return the ECN codepoint carried in the traffic class.
+    pub fn ecn(&self) -> Ecn {
+        Ecn::from_traffic_class(self.traffic_class())
+    }
+
     /// Convert an unspecified representation into a concrete one, or return
     /// `Err(Error::Unaddressable)` if not possible.
     ///
@@ -586,12 +674,12 @@ impl Repr {
             &Repr::Unspecified {
                 src_addr: src_addr @ Address::Unspecified,
                 dst_addr: Address::Ipv4(dst_addr),
-                protocol, payload_len, hop_limit
+                protocol, payload_len, hop_limit, traffic_class
             } |
             &Repr::Unspecified {
                 src_addr: src_addr @ Address::Ipv4(_),
                 dst_addr: Address::Ipv4(dst_addr),
-                protocol, payload_len, hop_limit
+                protocol, payload_len, hop_limit, traffic_class
             } if src_addr.is_unspecified() => {
                 let mut src_addr = if let Address::Ipv4(src_ipv4_addr) = src_addr {
                     Some(src_ipv4_addr)
@@ -606,7 +694,8 @@ impl Repr {
                 }
                 Ok(Repr::Ipv4(Ipv4Repr {
                     src_addr:    src_addr.ok_or(Error::Unaddressable)?,
-                    dst_addr, protocol, payload_len, hop_limit
+                    dst_addr, protocol, payload_len, hop_limit, traffic_class,
+                    router_alert: false
                 }))
             }
 
@@ -614,12 +703,12 @@ impl Repr {
             &Repr::Unspecified {
                 src_addr: src_addr @ Address::Unspecified,
                 dst_addr: Address::Ipv6(dst_addr),
-                protocol, payload_len, hop_limit
+                protocol, payload_len, hop_limit, traffic_class
             } |
             &Repr::Unspecified {
                 src_addr: src_addr @ Address::Ipv6(_),
                 dst_addr: Address::Ipv6(dst_addr),
-                protocol, payload_len, hop_limit
+                protocol, payload_len, hop_limit, traffic_class
             } if src_addr.is_unspecified() => {
                 let mut src_addr = if let Address::Ipv6(src_ipv6_addr) = src_addr {
                     Some(src_ipv6_addr)
@@ -635,7 +724,7 @@ impl Repr {
                 Ok(Repr::Ipv6(Ipv6Repr {
                     src_addr:    src_addr.ok_or(Error::Unaddressable)?,
                     next_header: protocol,
-                    dst_addr, payload_len, hop_limit
+                    dst_addr, payload_len, hop_limit, traffic_class
                 }))
             }
 
@@ -643,13 +732,14 @@ impl Repr {
             &Repr::Unspecified {
                 src_addr: Address::Ipv4(src_addr),
                 dst_addr: Address::Ipv4(dst_addr),
-                protocol, payload_len, hop_limit
+                protocol, payload_len, hop_limit, traffic_class
             } => {
                 Ok(Repr::Ipv4(Ipv4Repr {
                     src_addr:    src_addr,
                     dst_addr:    dst_addr,
                     protocol:    protocol,
-                    payload_len: payload_len, hop_limit
+                    payload_len: payload_len, hop_limit, traffic_class,
+                    router_alert: false
                 }))
             }
 
@@ -657,14 +747,15 @@ impl Repr {
             &Repr::Unspecified {
                 src_addr: Address::Ipv6(src_addr),
                 dst_addr: Address::Ipv6(dst_addr),
-                protocol, payload_len, hop_limit
+                protocol, payload_len, hop_limit, traffic_class
             } => {
                 Ok(Repr::Ipv6(Ipv6Repr {
-                    src_addr:    src_addr,
-                    dst_addr:    dst_addr,
-                    next_header: protocol,
-                    payload_len: payload_len,
-                    hop_limit:   hop_limit
+                    src_addr:      src_addr,
+                    dst_addr:      dst_addr,
+                    next_header:   protocol,
+                    payload_len:   payload_len,
+                    hop_limit:     hop_limit,
+                    traffic_class: traffic_class
                 }))
             }
 
@@ -926,7 +1017,7 @@ pub(crate) mod test {
     macro_rules! generate_common_tests {
         ($name:ident, $repr:ident, $ip_repr:path, $ip_addr:path,
          $addr_from:path, $nxthdr:ident, $bytes_a:expr, $bytes_b:expr,
-         $unspecified:expr) => {
+         $unspecified:expr, [$($extra:tt)*]) => {
             mod $name {
                 use super::*;
 
@@ -939,27 +1030,30 @@ pub(crate) mod test {
 
                     assert_eq!(
                         Repr::Unspecified{
-                            src_addr:  $ip_addr(ip_addr_a),
-                            dst_addr:  $ip_addr(ip_addr_b),
-                            protocol:  proto,
-                            hop_limit: 0x2a,
+                            src_addr:      $ip_addr(ip_addr_a),
+                            dst_addr:      $ip_addr(ip_addr_b),
+                            protocol:      proto,
+                            hop_limit:     0x2a,
+                            traffic_class: 0,
                             payload_len,
                         }.lower(&[]),
-                        Ok($ip_repr($repr{
-                            src_addr:  ip_addr_a,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 0x2a,
+                        Ok($ip_repr($repr{ $($extra)*
+                            src_addr:      ip_addr_a,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     0x2a,
+                            traffic_class: 0,
                             payload_len
                         }))
                     );
 
                     assert_eq!(
                         Repr::Unspecified{
-                            src_addr:  IpAddress::Unspecified,
-                            dst_addr:  $ip_addr(ip_addr_b),
-                            protocol:  proto,
-                            hop_limit: 64,
+                            src_addr:      IpAddress::Unspecified,
+                            dst_addr:      $ip_addr(ip_addr_b),
+                            protocol:      proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }.lower(&[]),
                         Err(Error::Unaddressable)
@@ -967,96 +1061,107 @@ pub(crate) mod test {
 
                     assert_eq!(
                         Repr::Unspecified{
-                            src_addr:  IpAddress::Unspecified,
-                            dst_addr:  $ip_addr(ip_addr_b),
-                            protocol:  proto,
-                            hop_limit: 64,
+                            src_addr:      IpAddress::Unspecified,
+                            dst_addr:      $ip_addr(ip_addr_b),
+                            protocol:      proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }.lower(&[IpCidr::new($ip_addr(ip_addr_a), 24)]),
-                        Ok($ip_repr($repr{
-                            src_addr:  ip_addr_a,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 64,
+                        Ok($ip_repr($repr{ $($extra)*
+                            src_addr:      ip_addr_a,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }))
                     );
 
                     assert_eq!(
                         Repr::Unspecified{
-                            src_addr:  $ip_addr($unspecified),
-                            dst_addr:  $ip_addr(ip_addr_b),
-                            protocol:  proto,
-                            hop_limit: 64,
+                            src_addr:      $ip_addr($unspecified),
+                            dst_addr:      $ip_addr(ip_addr_b),
+                            protocol:      proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }.lower(&[IpCidr::new($ip_addr(ip_addr_a), 24)]),
-                        Ok($ip_repr($repr{
-                            src_addr:  ip_addr_a,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 64,
+                        Ok($ip_repr($repr{ $($extra)*
+                            src_addr:      ip_addr_a,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }))
                     );
 
                     assert_eq!(
                         Repr::Unspecified{
-                            src_addr:  $ip_addr($unspecified),
-                            dst_addr:  $ip_addr(ip_addr_b),
-                            protocol:  proto,
-                            hop_limit: 64,
+                            src_addr:      $ip_addr($unspecified),
+                            dst_addr:      $ip_addr(ip_addr_b),
+                            protocol:      proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }.lower(&[]),
-                        Ok($ip_repr($repr{
-                            src_addr:  $unspecified,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 64,
+                        Ok($ip_repr($repr{ $($extra)*
+                            src_addr:      $unspecified,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }))
                     );
 
                     assert_eq!(
-                        $ip_repr($repr{
-                            src_addr:  ip_addr_a,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 255,
+                        $ip_repr($repr{ $($extra)*
+                            src_addr:      ip_addr_a,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     255,
+                            traffic_class: 0,
                             payload_len
                         }).lower(&[]),
-                        Ok($ip_repr($repr{
-                            src_addr:  ip_addr_a,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 255,
+                        Ok($ip_repr($repr{ $($extra)*
+                            src_addr:      ip_addr_a,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     255,
+                            traffic_class: 0,
                             payload_len
                         }))
                     );
 
                     assert_eq!(
-                        $ip_repr($repr{
-                            src_addr:  $unspecified,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 255,
+                        $ip_repr($repr{ $($extra)*
+                            src_addr:      $unspecified,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     255,
+                            traffic_class: 0,
                             payload_len
                         }).lower(&[]),
                         Err(Error::Unaddressable)
                     );
 
                     assert_eq!(
-                        $ip_repr($repr{
-                            src_addr:  $unspecified,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 64,
+                        $ip_repr($repr{ $($extra)*
+                            src_addr:      $unspecified,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }).lower(&[IpCidr::new($ip_addr(ip_addr_a), 24)]),
-                        Ok($ip_repr($repr{
-                            src_addr:  ip_addr_a,
-                            dst_addr:  ip_addr_b,
-                            $nxthdr:   proto,
-                            hop_limit: 64,
+                        Ok($ip_repr($repr{ $($extra)*
+                            src_addr:      ip_addr_a,
+                            dst_addr:      ip_addr_b,
+                            $nxthdr:       proto,
+                            hop_limit:     64,
+                            traffic_class: 0,
                             payload_len
                         }))
                     );
@@ -1066,12 +1171,13 @@ pub(crate) mod test {
         (ipv4 $addr_bytes_a:expr, $addr_bytes_b:expr) => {
             generate_common_tests!(ipv4, Ipv4Repr, Repr::Ipv4, IpAddress::Ipv4,
                                    Ipv4Address::from_bytes, protocol, $addr_bytes_a,
-                                   $addr_bytes_b, Ipv4Address::UNSPECIFIED);
+                                   $addr_bytes_b, Ipv4Address::UNSPECIFIED,
+                                   [router_alert: false,]);
         };
         (ipv6 $addr_bytes_a:expr, $addr_bytes_b:expr) => {
             generate_common_tests!(ipv6, Ipv6Repr, Repr::Ipv6, IpAddress::Ipv6,
                                    Ipv6Address::from_bytes, next_header, $addr_bytes_a,
-                                   $addr_bytes_b, Ipv6Address::UNSPECIFIED);
+                                   $addr_bytes_b, Ipv6Address::UNSPECIFIED, []);
         }
     }
 
@@ -1090,11 +1196,12 @@ pub(crate) mod test {
     #[should_panic(expected = "source and destination IP address families do not match")]
     fn test_lower_between_families() {
         Repr::Unspecified {
-            src_addr:  Address::Ipv6(Ipv6Address::UNSPECIFIED),
-            dst_addr:  Address::Ipv4(Ipv4Address::UNSPECIFIED),
-            protocol:  IpProtocol::Icmpv6,
-            hop_limit: 0xff,
-            payload_len: 0
+            src_addr:      Address::Ipv6(Ipv6Address::UNSPECIFIED),
+            dst_addr:      Address::Ipv4(Ipv4Address::UNSPECIFIED),
+            protocol:      IpProtocol::Icmpv6,
+            hop_limit:     0xff,
+            traffic_class: 0,
+            payload_len:   0
         }.lower(&[]);
     }
 
@@ -1171,4 +1278,16 @@ pub(crate) mod test {
     fn to_prefix_len_ipv6_error() {
         assert_eq!(None, IpAddress::from(Ipv6Address::new(0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0, 1)).to_prefix_len());
     }
+
+    #[test]
+    fn test_ecn_traffic_class() {
+        assert_eq!(Ecn::from_traffic_class(0xb8), Ecn::NotEct);
+        assert_eq!(Ecn::from_traffic_class(0xba), Ecn::Ect0);
+        assert_eq!(Ecn::from_traffic_class(0xb9), Ecn::Ect1);
+        assert_eq!(Ecn::from_traffic_class(0xbb), Ecn::Ce);
+        assert_eq!(Ecn::Ce.with_traffic_class(0xb8), 0xbb);
+        assert_eq!(Ecn::NotEct.with_traffic_class(0xba), 0xb8);
+        assert!(Ecn::Ect0.is_capable());
+        assert!(!Ecn::NotEct.is_capable());
+    }
 }
@@ -38,6 +38,9 @@ impl Address {
     /// All multicast-capable routers
     pub const MULTICAST_ALL_ROUTERS: Address = Address([224, 0, 0, 2]);
 
+    /// All IGMPv3-capable multicast routers
+    pub const MULTICAST_ALL_IGMPV3_ROUTERS: Address = Address([224, 0, 0, 22]);
+
     /// Construct an IPv4 address from parts.
     pub fn new(a0: u8, a1: u8, a2: u8, a3: u8) -> Address {
         Address([a0, a1, a2, a3])
@@ -245,6 +248,11 @@ mod field {
     pub const DST_ADDR: Field = 16..20;
 }
 
+/// The IP Router Alert option, see [RFC 2113].
+///
+/// [RFC 2113]: https://tools.ietf.org/html/rfc2113
+const ROUTER_ALERT_OPTION: [u8; 4] = [0x94, 0x04, 0x00, 0x00];
+
 impl<T: AsRef<[u8]>> Packet<T> {
     /// Imbue a raw octet buffer with IPv4 packet structure.
     pub fn new_unchecked(buffer: T) -> Packet<T> {
@@ -397,6 +405,26 @@ impl<T: AsRef<[u8]>> Packet<T> {
         let data = self.buffer.as_ref();
         checksum::data(&data[..self.header_len() as usize]) == !0
     }
+
+    /// Query whether the options include the IP Router Alert option.
+    pub fn router_alert(&self) -> bool {
+        let data = self.buffer.as_ref();
+        let mut options = &data[field::DST_ADDR.end..self.header_len() as usize];
+        while let Some(&kind) = options.first() {
+            let len = match kind {
+                // End of Option List
+                0x00 => return false,
+                // No Operation
+                0x01 => 1,
+                _ if options.len() >= 2 && options[1] >= 2 => options[1] as usize,
+                _ => return false
+            };
+            if len > options.len() { return false }
+            if &options[..len] == &ROUTER_ALERT_OPTION[..] { return true }
+            options = &options[len..];
+        }
+        false
+    }
 }
 
 impl<'a, T: AsRef<[u8]> + ?Sized> Packet<&'a T> {
@@ -549,11 +577,16 @@ impl<T: AsRef<[u8]>> AsRef<[u8]> for Packet<T> {
 /// A high-level representation of an Internet Protocol version 4 packet header.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Repr {
-    pub src_addr:    Address,
-    pub dst_addr:    Address,
-    pub protocol:    Protocol,
-    pub payload_len: usize,
-    pub hop_limit:   u8
+    pub src_addr:      Address,
+    pub dst_addr:      Address,
+    pub protocol:      Protocol,
+    pub payload_len:   usize,
+    pub hop_limit:     u8,
+    /// The DSCP in the upper six bits, and the ECN codepoint in the lower two bits.
+    pub traffic_class: u8,
+    /// Whether the header carries the IP Router Alert option, which is the only
+    /// option ever emitted.
+    pub router_alert:  bool
 }
 
 impl Repr {
@@ -574,26 +607,31 @@ impl Repr {
         // All ECN values are acceptable, since ECN requires opt-in from both endpoints.
         // All TTL values are acceptable, since we do not perform routing.
         Ok(Repr {
-            src_addr:    packet.src_addr(),
-            dst_addr:    packet.dst_addr(),
-            protocol:    packet.protocol(),
-            payload_len: payload_len,
-            hop_limit:   packet.hop_limit()
+            src_addr:      packet.src_addr(),
+            dst_addr:      packet.dst_addr(),
+            protocol:      packet.protocol(),
+            payload_len:   payload_len,
+            hop_limit:     packet.hop_limit(),
+            traffic_class: packet.dscp() << 2 | packet.ecn(),
+            router_alert:  packet.router_alert()
         })
     }
 
     /// Return the length of a header that will be emitted from this high-level representation.
     pub fn buffer_len(&self) -> usize {
-        // We never emit any options.
-        field::DST_ADDR.end
+        if self.router_alert {
+            field::DST_ADDR.end + ROUTER_ALERT_OPTION.len()
+        } else {
+            field::DST_ADDR.end
+        }
     }
 
     /// Emit a high-level representation into an Internet Protocol version 4 packet.
     pub fn emit<T: AsRef<[u8]> + AsMut<[u8]>>(&self, packet: &mut Packet<T>, checksum_caps: &ChecksumCapabilities) {
         packet.set_version(4);
-        packet.set_header_len(field::DST_ADDR.end as u8);
-        packet.set_dscp(0);
-        packet.set_ecn(0);
+        packet.set_header_len(self.buffer_len() as u8);
+        packet.set_dscp(self.traffic_class >> 2);
+        packet.set_ecn(self.traffic_class & 0x03);
         let total_len = packet.header_len() as u16 + self.payload_len as u16;
         packet.set_total_len(total_len);
         packet.set_ident(0);
@@ -605,6 +643,10 @@ impl Repr {
         packet.set_protocol(self.protocol);
         packet.set_src_addr(self.src_addr);
         packet.set_dst_addr(self.dst_addr);
+        if self.router_alert {
+            let data = packet.buffer.as_mut();
+            data[field::DST_ADDR.end..self.buffer_len()].copy_from_slice(&ROUTER_ALERT_OPTION);
+        }
 
         if checksum_caps.ipv4.tx() {
             packet.fill_checksum();
@@ -692,6 +734,7 @@ impl<T: AsRef<[u8]>> PrettyPrint for Packet<T> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use phy::Checksum;
 
     static PACKET_BYTES: [u8; 30] =
         [0x45, 0x00, 0x00, 0x1e,
@@ -787,11 +830,13 @@ mod test {
 
     fn packet_repr() -> Repr {
         Repr {
-            src_addr:    Address([0x11, 0x12, 0x13, 0x14]),
-            dst_addr:    Address([0x21, 0x22, 0x23, 0x24]),
-            protocol:    Protocol::Icmp,
-            payload_len: 4,
-            hop_limit:   64
+            src_addr:      Address([0x11, 0x12, 0x13, 0x14]),
+            dst_addr:      Address([0x21, 0x22, 0x23, 0x24]),
+            protocol:      Protocol::Icmp,
+            payload_len:   4,
+            hop_limit:     64,
+            traffic_class: 0,
+            router_alert:  false
         }
     }
 
@@ -830,6 +875,59 @@ mod test {
         assert_eq!(&packet.into_inner()[..], &REPR_PACKET_BYTES[..]);
     }
 
+    #[test]
+    fn test_emit_partial_checksum() {
+        // Only TCP and UDP are partially checksummed; the IP header checksum is complete.
+        let mut checksum_caps = ChecksumCapabilities::default();
+        checksum_caps.ipv4 = Checksum::Partial;
+        let repr = packet_repr();
+        let mut bytes = vec![0xa5; repr.buffer_len() + REPR_PAYLOAD_BYTES.len()];
+        let mut packet = Packet::new_unchecked(&mut bytes);
+        repr.emit(&mut packet, &checksum_caps);
+        packet.payload_mut().copy_from_slice(&REPR_PAYLOAD_BYTES);
+        assert_eq!(&packet.into_inner()[..], &REPR_PACKET_BYTES[..]);
+    }
+
+    #[test]
+    fn test_traffic_class() {
+        let repr = Repr { traffic_class: 0xb9, ..packet_repr() };
+        let mut bytes = vec![0; repr.buffer_len() + REPR_PAYLOAD_BYTES.len()];
+        let mut packet = Packet::new_unchecked(&mut bytes);
+        repr.emit(&mut packet, &ChecksumCapabilities::default());
+        assert_eq!(packet.dscp(), 0x2e);
+        assert_eq!(packet.ecn(), 0x01);
+        let packet = Packet::new_unchecked(&*packet.into_inner());
+        assert_eq!(Repr::parse(&packet, &ChecksumCapabilities::default()), Ok(repr));
+    }
+
+    #[test]
+    fn test_router_alert() {
+        let repr = Repr { router_alert: true, ..packet_repr() };
+        assert_eq!(repr.buffer_len(), 24);
+        let mut bytes = vec![0; repr.buffer_len() + REPR_PAYLOAD_BYTES.len()];
+        let mut packet = Packet::new_unchecked(&mut bytes);
+        repr.emit(&mut packet, &ChecksumCapabilities::default());
+        assert_eq!(packet.header_len(), 24);
+        assert_eq!(packet.total_len(), 28);
+        assert_eq!(&packet.as_ref()[20..24], &[0x94, 0x04, 0x00, 0x00]);
+        let packet = Packet::new_unchecked(&*packet.into_inner());
+        assert_eq!(Repr::parse(&packet, &ChecksumCapabilities::default()), Ok(repr));
+
+        // The option is found after other ones, but not after the end of the option list.
+        fn router_alert(options: &[u8]) -> bool {
+            let mut bytes = vec![0; 28];
+            bytes[..20].copy_from_slice(&REPR_PACKET_BYTES[..20]);
+            bytes[20..].copy_from_slice(options);
+            let mut packet = Packet::new_unchecked(&mut bytes);
+            packet.set_header_len(28);
+            packet.router_alert()
+        }
+        assert!(router_alert(&[0x01, 0x01, 0x01, 0x01, 0x94, 0x04, 0x00, 0x00]));
+        assert!(router_alert(&[0x07, 0x03, 0x04, 0x94, 0x04, 0x00, 0x00, 0x00]));
+        assert!(!router_alert(&[0x00, 0x00, 0x00, 0x00, 0x94, 0x04, 0x00, 0x00]));
+        assert!(!router_alert(&[0x07, 0x01, 0x94, 0x04, 0x00, 0x00, 0x00, 0x00]));
+    }
+
     #[test]
     fn test_unspecified() {
         assert!(Address::UNSPECIFIED.is_unspecified());
@@ -303,11 +303,7 @@ impl Cidr {
     /// Query whether the subnetwork described by this IPv6 CIDR block contains
     /// the given address.
     pub fn contains_addr(&self, addr: &Address) -> bool {
-        // right shift by 128 is not legal
-        if self.prefix_len == 0 { return true }
-
-        let shift = 128 - self.prefix_len;
-        self.address.mask(shift) == addr.mask(shift)
+        self.address.mask(self.prefix_len) == addr.mask(self.prefix_len)
     }
 
     /// Query whether the subnetwork described by this IPV6 CIDR block contains
@@ -593,15 +589,18 @@ impl<T: AsRef<[u8]>> AsRef<[u8]> for Packet<T> {
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Repr {
     /// IPv6 address of the source node.
-    pub src_addr:    Address,
+    pub src_addr:      Address,
     /// IPv6 address of the destination node.
-    pub dst_addr:    Address,
+    pub dst_addr:      Address,
     /// Protocol contained in the next header.
-    pub next_header: Protocol,
+    pub next_header:   Protocol,
     /// Length of the payload including the extension headers.
-    pub payload_len: usize,
+    pub payload_len:   usize,
     /// The 8-bit hop limit field.
-    pub hop_limit:   u8
+    pub hop_limit:     u8,
+    /// The 8-bit traffic class field: the DSCP in the upper six bits,
+    /// and the ECN codepoint in the lower two bits.
+    pub traffic_class: u8
 }
 
 impl Repr {
@@ -611,11 +610,12 @@ impl Repr {
         packet.check_len()?;
         if packet.version() != 6 { return Err(Error::Malformed); }
         Ok(Repr {
-            src_addr:    packet.src_addr(),
-            dst_addr:    packet.dst_addr(),
-            next_header: packet.next_header(),
-            payload_len: packet.payload_len() as usize,
-            hop_limit:   packet.hop_limit()
+            src_addr:      packet.src_addr(),
+            dst_addr:      packet.dst_addr(),
+            next_header:   packet.next_header(),
+            payload_len:   packet.payload_len() as usize,
+            hop_limit:     packet.hop_limit(),
+            traffic_class: packet.traffic_class()
         })
     }
 
@@ -630,7 +630,7 @@ impl Repr {
         // Make no assumptions about the original state of the packet buffer.
         // Make sure to set every byte.
         packet.set_version(6);
-        packet.set_traffic_class(0);
+        packet.set_traffic_class(self.traffic_class);
         packet.set_flow_label(0);
         packet.set_payload_len(self.payload_len as u16);
         packet.set_hop_limit(self.hop_limit);
@@ -884,6 +884,14 @@ mod test {
 
         let cidr_without_prefix = Cidr::new(LINK_LOCAL_ADDR, 0);
         assert!(cidr_without_prefix.contains_addr(&Address::LOOPBACK));
+
+        let cidr_host = Cidr::new(LINK_LOCAL_ADDR, 128);
+        assert!(cidr_host.contains_addr(&LINK_LOCAL_ADDR));
+        assert!(!cidr_host.contains_addr(&Address::from_bytes(&inside_subnet[0])));
+
+        let cidr_odd_prefix = Cidr::new(LINK_LOCAL_ADDR, 12);
+        assert!(cidr_odd_prefix.contains_addr(&Address::new(0xfe8f, 0, 0, 0, 0, 0, 0, 1)));
+        assert!(!cidr_odd_prefix.contains_addr(&Address::new(0xfe90, 0, 0, 0, 0, 0, 0, 1)));
     }
 
     #[test]
@@ -917,14 +925,15 @@ mod test {
 
     fn packet_repr() -> Repr {
         Repr {
-            src_addr:    Address([0xfe, 0x80, 0x00, 0x00,
-                                  0x00, 0x00, 0x00, 0x00,
-                                  0x00, 0x00, 0x00, 0x00,
-                                  0x00, 0x00, 0x00, 0x01]),
-            dst_addr:    Address::LINK_LOCAL_ALL_NODES,
-            next_header: Protocol::Udp,
-            payload_len: 12,
-            hop_limit:   64
+            src_addr:      Address([0xfe, 0x80, 0x00, 0x00,
+                                    0x00, 0x00, 0x00, 0x00,
+                                    0x00, 0x00, 0x00, 0x00,
+                                    0x00, 0x00, 0x00, 0x01]),
+            dst_addr:      Address::LINK_LOCAL_ALL_NODES,
+            next_header:   Protocol::Udp,
+            payload_len:   12,
+            hop_limit:     64,
+            traffic_class: 0
         }
     }
 
@@ -1052,6 +1061,17 @@ mod test {
         assert_eq!(&packet.into_inner()[..], &REPR_PACKET_BYTES[..]);
     }
 
+    #[test]
+    fn test_repr_traffic_class() {
+        let repr = Repr { traffic_class: 0xb9, ..packet_repr() };
+        let mut bytes = vec![0; repr.buffer_len() + REPR_PAYLOAD_BYTES.len()];
+        let mut packet = Packet::new_unchecked(&mut bytes);
+        repr.emit(&mut packet);
+        assert_eq!(packet.traffic_class(), 0xb9);
+        let packet = Packet::new_unchecked(&*packet.into_inner());
+        assert_eq!(Repr::parse(&packet), Ok(repr));
+    }
+
     #[test]
     fn test_pretty_print() {
         assert_eq!(format!("{}", PrettyPrinter::<Packet<&'static [u8]>>::new("\n", &&REPR_PACKET_BYTES[..])),
@@ -48,11 +48,13 @@ To emit an IP packet header into an octet buffer, and then parse it back:
 use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::wire::*;
 let repr = Ipv4Repr {
-    src_addr:    Ipv4Address::new(10, 0, 0, 1),
-    dst_addr:    Ipv4Address::new(10, 0, 0, 2),
-    protocol:    IpProtocol::Tcp,
-    payload_len: 10,
-    hop_limit:   64
+    src_addr:      Ipv4Address::new(10, 0, 0, 1),
+    dst_addr:      Ipv4Address::new(10, 0, 0, 2),
+    protocol:      IpProtocol::Tcp,
+    payload_len:   10,
+    hop_limit:     64,
+    traffic_class: 0,
+    router_alert:  false
 };
 let mut buffer = vec![0; repr.buffer_len() + repr.payload_len];
 { // emission
@@ -127,6 +129,7 @@ pub use self::arp::{Hardware as ArpHardware,
 
 pub use self::ip::{Version as IpVersion,
                    Protocol as IpProtocol,
+                   Ecn as IpEcn,
                    Address as IpAddress,
                    Endpoint as IpEndpoint,
                    Repr as IpRepr,
@@ -176,6 +179,8 @@ pub use self::icmpv4::{Message as Icmpv4Message,
 #[cfg(feature = "proto-igmp")]
 pub use self::igmp::{Packet as IgmpPacket,
                      Repr as IgmpRepr,
+                     GroupRecord as IgmpGroupRecord,
+                     RecordType as IgmpRecordType,
                      IgmpVersion};
 
 #[cfg(feature = "proto-ipv6")]
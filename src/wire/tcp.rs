@@ -533,6 +533,17 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         self.set_checksum(checksum)
     }
 
+    /// Fill in the pseudo-header checksum only, for a device to complete the checksum
+    /// over the rest of the packet.
+    ///
+    /// # Panics
+    /// This function panics unless `src_addr` and `dst_addr` belong to the same family,
+    /// and that family is IPv4 or IPv6.
+    pub fn fill_partial_checksum(&mut self, src_addr: &IpAddress, dst_addr: &IpAddress) {
+        let len = self.buffer.as_ref().len() as u32;
+        self.set_checksum(checksum::pseudo_header(src_addr, dst_addr, IpProtocol::Tcp, len))
+    }
+
     /// Return a pointer to the options.
     #[inline]
     pub fn options_mut(&mut self) -> &mut [u8] {
@@ -749,6 +760,8 @@ pub struct Repr<'a> {
     pub max_seg_size: Option<u16>,
     pub sack_permitted: bool,
     pub sack_ranges:  [Option<(u32, u32)>; 3],
+    pub ece:          bool,
+    pub cwr:          bool,
     pub payload:      &'a [u8]
 }
 
@@ -828,6 +841,8 @@ impl<'a> Repr<'a> {
             max_seg_size: max_seg_size,
             sack_permitted: sack_permitted,
             sack_ranges:   sack_ranges,
+            ece:          packet.ece(),
+            cwr:          packet.cwr(),
             payload:      packet.payload()
         })
     }
@@ -891,6 +906,8 @@ impl<'a> Repr<'a> {
             Control::Rst  => packet.set_rst(true)
         }
         packet.set_ack(self.ack_number.is_some());
+        packet.set_ece(self.ece);
+        packet.set_cwr(self.cwr);
         {
             let mut options = packet.options_mut();
             if let Some(value) = self.window_scale {
@@ -912,7 +929,9 @@ impl<'a> Repr<'a> {
         packet.set_urgent_at(0);
         packet.payload_mut()[..self.payload.len()].copy_from_slice(self.payload);
 
-        if checksum_caps.tcp.tx() {
+        if checksum_caps.tcp.partial() {
+            packet.fill_partial_checksum(src_addr, dst_addr)
+        } else if checksum_caps.tcp.tx() {
             packet.fill_checksum(src_addr, dst_addr)
         } else {
             // make sure we get a consistently zeroed checksum,
@@ -996,6 +1015,8 @@ impl<'a> fmt::Display for Repr<'a> {
             Control::Psh => write!(f, " psh")?,
             Control::None => ()
         }
+        if self.ece { write!(f, " ece")? }
+        if self.cwr { write!(f, " cwr")? }
         write!(f, " seq={}", self.seq_number)?;
         if let Some(ack_number) = self.ack_number {
             write!(f, " ack={}", ack_number)?;
@@ -1025,6 +1046,8 @@ impl<T: AsRef<[u8]>> PrettyPrint for Packet<T> {
 mod test {
     #[cfg(feature = "proto-ipv4")]
     use wire::Ipv4Address;
+    #[cfg(feature = "proto-ipv4")]
+    use phy::Checksum;
     use super::*;
 
     #[cfg(feature = "proto-ipv4")]
@@ -1136,6 +1159,8 @@ mod test {
             max_seg_size: None,
             sack_permitted: false,
             sack_ranges:  [None, None, None],
+            ece:          false,
+            cwr:          false,
             payload:      &PAYLOAD_BYTES
         }
     }
@@ -1158,6 +1183,21 @@ mod test {
         assert_eq!(&packet.into_inner()[..], &SYN_PACKET_BYTES[..]);
     }
 
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_emit_partial_checksum() {
+        let repr = packet_repr();
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        let mut caps = ChecksumCapabilities::default();
+        caps.tcp = Checksum::Partial;
+        repr.emit(&mut Packet::new_unchecked(&mut bytes), &SRC_ADDR.into(), &DST_ADDR.into(),
+                  &caps);
+        // Complete the checksum the way a device would.
+        let checksum = !checksum::data(&bytes[..]);
+        Packet::new_unchecked(&mut bytes).set_checksum(checksum);
+        assert_eq!(&bytes[..], &SYN_PACKET_BYTES[..]);
+    }
+
     #[test]
     #[cfg(feature = "proto-ipv4")]
     fn test_header_len_multiple_of_4() {
@@ -1166,6 +1206,26 @@ mod test {
         assert_eq!(repr.header_len() % 4, 0); // Should e.g. be 28 instead of 27.
     }
 
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_ecn_flags() {
+        let mut repr = packet_repr();
+        repr.ece = true;
+        repr.cwr = true;
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        {
+            let mut packet = Packet::new_unchecked(&mut bytes);
+            repr.emit(&mut packet, &SRC_ADDR.into(), &DST_ADDR.into(),
+                      &ChecksumCapabilities::default());
+            assert_eq!(packet.ece(), true);
+            assert_eq!(packet.cwr(), true);
+            assert_eq!(packet.syn(), true);
+        }
+        let packet = Packet::new_unchecked(&bytes[..]);
+        assert_eq!(Repr::parse(&packet, &SRC_ADDR.into(), &DST_ADDR.into(),
+                               &ChecksumCapabilities::default()), Ok(repr));
+    }
+
     macro_rules! assert_option_parses {
         ($opt:expr, $data:expr) => ({
             assert_eq!(TcpOption::parse($data), Ok((&[][..], $opt)));
@@ -181,6 +181,17 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         self.set_checksum(if checksum == 0 { 0xffff } else { checksum })
     }
 
+    /// Fill in the pseudo-header checksum only, for a device to complete the checksum
+    /// over the rest of the packet.
+    ///
+    /// # Panics
+    /// This function panics unless `src_addr` and `dst_addr` belong to the same family,
+    /// and that family is IPv4 or IPv6.
+    pub fn fill_partial_checksum(&mut self, src_addr: &IpAddress, dst_addr: &IpAddress) {
+        let len = self.len() as u32;
+        self.set_checksum(checksum::pseudo_header(src_addr, dst_addr, IpProtocol::Udp, len))
+    }
+
     /// Return a mutable pointer to the payload.
     #[inline]
     pub fn payload_mut(&mut self) -> &mut [u8] {
@@ -249,7 +260,9 @@ impl<'a> Repr<'a> {
         packet.set_len((field::CHECKSUM.end + self.payload.len()) as u16);
         packet.payload_mut().copy_from_slice(self.payload);
 
-        if checksum_caps.udp.tx() {
+        if checksum_caps.udp.partial() {
+            packet.fill_partial_checksum(src_addr, dst_addr)
+        } else if checksum_caps.udp.tx() {
             packet.fill_checksum(src_addr, dst_addr)
         } else {
             // make sure we get a consistently zeroed checksum,
@@ -290,6 +303,8 @@ impl<T: AsRef<[u8]>> PrettyPrint for Packet<T> {
 mod test {
     #[cfg(feature = "proto-ipv4")]
     use wire::Ipv4Address;
+    #[cfg(feature = "proto-ipv4")]
+    use phy::Checksum;
     use super::*;
 
     #[cfg(feature = "proto-ipv4")]
@@ -381,4 +396,19 @@ mod test {
                   &ChecksumCapabilities::default());
         assert_eq!(&packet.into_inner()[..], &PACKET_BYTES[..]);
     }
+
+    #[test]
+    #[cfg(feature = "proto-ipv4")]
+    fn test_emit_partial_checksum() {
+        let repr = packet_repr();
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        let mut caps = ChecksumCapabilities::default();
+        caps.udp = Checksum::Partial;
+        repr.emit(&mut Packet::new_unchecked(&mut bytes), &SRC_ADDR.into(), &DST_ADDR.into(),
+                  &caps);
+        // Complete the checksum the way a device would.
+        let checksum = !checksum::data(&bytes[..]);
+        Packet::new_unchecked(&mut bytes).set_checksum(checksum);
+        assert_eq!(&bytes[..], &PACKET_BYTES[..]);
+    }
 }